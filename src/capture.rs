@@ -0,0 +1,287 @@
+//! Reading the clipboard on a thread of its own, so that reading a large copy doesn't hold up hotkeys or the tray icon
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+
+/// How many reads can wait to be started. Each read sees whatever is on the clipboard once it starts, so a read asked
+/// for while others are still waiting would see the same as them.
+const QUEUE_LENGTH: usize = 4;
+
+/// Does reads on a thread of its own, in the order they're asked for, and hands back what they read. Only the thread
+/// that asked for the reads uses what they read, so it can keep the stack to itself.
+pub struct Reader<Request, Read> {
+   requests: Option<SyncSender<Request>>,
+   reads: Receiver<Read>,
+   /// How many reads have been asked for and not yet handed back
+   in_flight: usize,
+   thread: Option<JoinHandle<()>>,
+}
+
+impl<Request: Send + 'static, Read: Send + 'static> Reader<Request, Read> {
+   /// Starts the thread, which calls `read` for each request and then `notify`, to tell the thread that asked that
+   /// there's something to hand back
+   pub fn start<F, N>(mut read: F, notify: N) -> io::Result<Reader<Request, Read>>
+   where
+      F: FnMut(Request) -> Read + Send + 'static,
+      N: Fn() + Send + 'static,
+   {
+      let (requests, thread_requests) = mpsc::sync_channel::<Request>(QUEUE_LENGTH);
+      let (thread_reads, reads) = mpsc::channel();
+      let thread = thread::Builder::new().name(String::from("capture")).spawn(move || {
+         for request in thread_requests {
+            if thread_reads.send(read(request)).is_err() {
+               return;
+            }
+            notify();
+         }
+      })?;
+      Ok(Reader {
+         requests: Some(requests),
+         reads,
+         in_flight: 0,
+         thread: Some(thread),
+      })
+   }
+
+   /// Asks for a read. Returns false if the queue is full, in which case a read already waiting will see the same
+   /// as this one would have.
+   pub fn request(&mut self, request: Request) -> bool {
+      match self.requests.as_ref().map(|x| x.try_send(request)) {
+         Some(Ok(())) => {
+            self.in_flight += 1;
+            true
+         }
+         Some(Err(TrySendError::Full(_))) | Some(Err(TrySendError::Disconnected(_))) | None => false,
+      }
+   }
+
+   /// Whether any reads asked for haven't been handed back yet
+   pub fn is_busy(&self) -> bool {
+      self.in_flight > 0
+   }
+
+   /// Hands back the reads that are done, without waiting for the rest
+   pub fn ready(&mut self) -> Vec<Read> {
+      let mut done = Vec::new();
+      while self.in_flight > 0 {
+         match self.reads.try_recv() {
+            Ok(read) => {
+               self.in_flight -= 1;
+               done.push(read);
+            }
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+               self.in_flight = 0;
+               break;
+            }
+         }
+      }
+      done
+   }
+
+   /// Waits for every read asked for so far and hands them back, so that whatever happens next comes after them
+   pub fn finish(&mut self) -> Vec<Read> {
+      let mut done = Vec::with_capacity(self.in_flight);
+      while self.in_flight > 0 {
+         match self.reads.recv() {
+            Ok(read) => {
+               self.in_flight -= 1;
+               done.push(read);
+            }
+            Err(_) => self.in_flight = 0,
+         }
+      }
+      done
+   }
+}
+
+impl<Request, Read> Drop for Reader<Request, Read> {
+   fn drop(&mut self) {
+      // Ends the thread once it's done with what it's been asked for
+      self.requests = None;
+      if let Some(thread) = self.thread.take() {
+         let _ = thread.join();
+      }
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use crate::config::Config;
+   use crate::stack::{push_copy, Kind, Push, Size, Stack, Text};
+   use crate::watch::{CaptureGate, Gate};
+   use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+   use std::sync::{Arc, Mutex};
+   use std::time::{Duration, Instant};
+
+   /// A stand-in for the clipboard: a number that goes up with each copy, which is slow to read every so often
+   fn slow_clipboard() -> (Arc<AtomicU32>, impl FnMut(()) -> u32 + Send + 'static) {
+      let clipboard = Arc::new(AtomicU32::new(0));
+      let thread_clipboard = Arc::clone(&clipboard);
+      let mut reads = 0;
+      let read = move |()| {
+         reads += 1;
+         if reads % 5 == 0 {
+            thread::sleep(Duration::from_millis(1));
+         }
+         thread_clipboard.load(Ordering::SeqCst)
+      };
+      (clipboard, read)
+   }
+
+   /// A copy read from the stand-in clipboard, to go on the stack
+   #[derive(Clone, Copy, Debug, PartialEq)]
+   struct Copied(u32);
+
+   impl Size for Copied {
+      fn size(&self) -> usize {
+         4
+      }
+   }
+
+   impl Text for Copied {
+      fn to_text(&self) -> String {
+         self.0.to_string()
+      }
+
+      fn kind(&self) -> Kind {
+         Kind::Text
+      }
+   }
+
+   #[test]
+   fn pops_see_every_copy_before_them() {
+      // The stand-in clipboard's sequence number and what's on it
+      let clipboard = Arc::new(Mutex::new((0u32, 0u32)));
+      let thread_clipboard = Arc::clone(&clipboard);
+      let mut gate = CaptureGate::default();
+      let mut reads = 0;
+      // Read as the capture thread reads the clipboard, through the capture gate
+      let read = move |()| {
+         reads += 1;
+         if reads % 5 == 0 {
+            thread::sleep(Duration::from_millis(1));
+         }
+         let (sequence_number, contents) = *thread_clipboard.lock().unwrap();
+         if gate.check_sequence(sequence_number) == Gate::Unchanged {
+            return None;
+         }
+         if gate.check_contents(u64::from(contents), Instant::now(), Duration::from_secs(60)) == Gate::SameContents {
+            return None;
+         }
+         Some(Copied(contents))
+      };
+      let notified = Arc::new(AtomicUsize::new(0));
+      let thread_notified = Arc::clone(&notified);
+      let mut reader = Reader::start(read, move || {
+         thread_notified.fetch_add(1, Ordering::SeqCst);
+      })
+      .unwrap();
+      let config = Config {
+         max_stack_size: None,
+         duplicate_window_ms: 60_000,
+         ..Config::default()
+      };
+      let mut stack = Stack::with_capacity(0);
+      let mut last_push = None;
+      let mut push = |stack: &mut Stack<Copied>, read: Option<Copied>| {
+         if let Some(copy) = read {
+            assert_eq!(
+               push_copy(stack, copy, &mut last_push, Instant::now(), &config),
+               Push::Pushed
+            );
+         }
+      };
+      let mut pops = 0;
+      for copy in 1..=2000 {
+         {
+            let mut clipboard = clipboard.lock().unwrap();
+            *clipboard = (clipboard.0 + 1, copy);
+         }
+         reader.request(());
+         // Some programs announce one copy twice, and some place the same copy on the clipboard again
+         reader.request(());
+         if copy % 3 == 0 {
+            let mut clipboard = clipboard.lock().unwrap();
+            clipboard.0 += 1;
+         }
+         reader.request(());
+         for read in reader.ready() {
+            push(&mut stack, read);
+         }
+         if copy % 7 == 0 {
+            // A pop waits for the copies before it, so it always takes the latest
+            for read in reader.finish() {
+               push(&mut stack, read);
+            }
+            assert!(!reader.is_busy());
+            assert_eq!(stack.pop_back(), Some(Copied(copy)));
+            pops += 1;
+         }
+      }
+      for read in reader.finish() {
+         push(&mut stack, read);
+      }
+      assert_eq!(stack.back(), Some(&Copied(2000)));
+      assert_eq!(pops, 2000 / 7);
+      // Copies only go on in order, once each, and popped ones never come back
+      let copies: Vec<u32> = stack.iter().map(|x| x.0).collect();
+      assert!(copies.windows(2).all(|x| x[0] < x[1]));
+      assert!(copies.iter().all(|x| x % 7 != 0));
+      assert!(notified.load(Ordering::SeqCst) > 0);
+   }
+
+   #[test]
+   fn full_queue_drops_requests() {
+      let (clipboard, mut read) = slow_clipboard();
+      let (started, thread_started) = mpsc::channel();
+      let (release, thread_release) = mpsc::channel::<()>();
+      // Holds the thread on its first read, so that the rest wait in the queue
+      let mut reader = Reader::start(
+         move |()| {
+            let _ = started.send(());
+            let _ = thread_release.recv();
+            read(())
+         },
+         || (),
+      )
+      .unwrap();
+      assert!(reader.request(()));
+      thread_started.recv().unwrap();
+      for _ in 0..QUEUE_LENGTH {
+         assert!(reader.request(()));
+      }
+      assert!(!reader.request(()));
+      clipboard.store(3, Ordering::SeqCst);
+      for _ in 0..=QUEUE_LENGTH {
+         release.send(()).unwrap();
+      }
+      let reads = reader.finish();
+      assert_eq!(reads.len(), QUEUE_LENGTH + 1);
+      // Every read, even the one that was asked for first, started after the last copy
+      assert!(reads.iter().all(|x| *x == 3));
+   }
+
+   #[test]
+   fn drop_waits_for_reads() {
+      let (_, mut read) = slow_clipboard();
+      let count = Arc::new(AtomicUsize::new(0));
+      let thread_count = Arc::clone(&count);
+      {
+         let mut reader = Reader::start(
+            move |()| {
+               thread_count.fetch_add(1, Ordering::SeqCst);
+               read(())
+            },
+            || (),
+         )
+         .unwrap();
+         for _ in 0..3 {
+            assert!(reader.request(()));
+         }
+      }
+      assert_eq!(count.load(Ordering::SeqCst), 3);
+   }
+}
@@ -1,42 +1,296 @@
+use crate::entry::Format;
 use crate::win;
 use dirs;
+use log::LevelFilter;
+use regex::Regex;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Write};
 use std::num::ParseIntError;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use std::usize;
 
-const DEFAULT_CONFIG: &[u8] = b"\
-max_stack_size = 100
-show_tray_icon = true
-pop_keybinding = Control + Shift + C
-swap_keybinding = None
-clear_keybinding = None
-prevent_duplicate_push = false
-";
-
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Config {
+   pub mode: Mode,
    pub max_stack_size: Option<usize>,
+   pub stack_full_policy: StackFullPolicy,
+   pub max_stack_memory: Option<usize>,
+   pub max_entry_age: Option<Duration>,
+   pub max_item_size: Option<usize>,
+   pub oversize_action: OversizeAction,
+   pub normalize_line_endings: LineEndings,
+   pub transform: Vec<Transform>,
+   pub capture_images: bool,
+   pub max_image_pixels: Option<usize>,
+   pub image_oversize_action: ImageOversizeAction,
+   pub capture_html: bool,
+   pub capture_rtf: bool,
+   pub capture_formats: Vec<Format>,
+   pub plain_text_only: bool,
+   pub delayed_render: bool,
+   pub respect_exclusion_formats: bool,
+   pub ignore_remote_clipboard: bool,
+   pub ignored_applications: Vec<IgnoredApplication>,
+   pub persist_stack: bool,
+   pub persist_encrypted: bool,
+   pub clear_on_lock: bool,
+   pub clear_clipboard_on_lock: bool,
+   pub pause_in_fullscreen: bool,
+   pub pause_hotkeys_in_fullscreen: bool,
    pub show_tray_icon: bool,
-   pub pop_keybinding: Option<Hotkey>,
-   pub clear_keybinding: Option<Hotkey>,
-   pub swap_keybinding: Option<Hotkey>,
+   pub tray_menu_entries: usize,
+   pub tray_tooltip_preview: bool,
+   pub tray_badge: bool,
+   pub pop_keybinding: Vec<Hotkey>,
+   pub pop_removes_entry: bool,
+   pub paste_after_pop: bool,
+   pub paste_keystroke: Hotkey,
+   pub clear_keybinding: Vec<Hotkey>,
+   pub swap_keybinding: Vec<Hotkey>,
+   pub swap_behavior: SwapBehavior,
+   pub push_keybinding: Vec<Hotkey>,
+   pub peek_keybinding: Vec<Hotkey>,
+   pub pause_keybinding: Vec<Hotkey>,
+   pub rotate_keybinding: Vec<Hotkey>,
+   pub drop_keybinding: Vec<Hotkey>,
+   pub pop_all_keybinding: Vec<Hotkey>,
+   pub join_separator: String,
+   pub join_order: JoinOrder,
+   pub duplicate_top_keybinding: Vec<Hotkey>,
+   pub undo_pop_keybinding: Vec<Hotkey>,
+   pub show_stack_keybinding: Vec<Hotkey>,
+   pub reverse_stack_keybinding: Vec<Hotkey>,
+   pub move_to_bottom_keybinding: Vec<Hotkey>,
+   pub copy_all_keybinding: Vec<Hotkey>,
+   pub copy_all_max_bytes: usize,
+   pub save_snapshot_keybinding: Vec<Hotkey>,
+   pub restore_snapshot_keybinding: Vec<Hotkey>,
+   pub indexed_pop_modifiers: Option<win::Modifiers>,
+   pub paste_second_keybinding: Vec<Hotkey>,
+   pub export_keybinding: Vec<Hotkey>,
+   pub export_path: Option<PathBuf>,
+   pub import_keybinding: Vec<Hotkey>,
+   pub import_path: Option<PathBuf>,
+   pub import_append: bool,
+   pub history_file: Option<PathBuf>,
+   pub history_max_bytes: Option<usize>,
+   pub pin_top_keybinding: Vec<Hotkey>,
+   pub undo_clear_keybinding: Vec<Hotkey>,
+   pub undo_clear_timeout: Option<Duration>,
+   pub hotkey_no_repeat: bool,
+   pub use_keyboard_hook: bool,
    pub prevent_duplicate_push: bool,
+   pub dedupe_scope: DedupeScope,
+   pub dedupe_action: DedupeAction,
+   pub dedupe_trim_whitespace: bool,
+   pub dedupe_ignore_case: bool,
+   pub dedupe_formatting: bool,
+   pub duplicate_window_ms: u64,
+   pub capture_debounce_ms: u32,
+   pub clipboard_retry_ms: u32,
+   pub log_level: LevelFilter,
+   pub log_file: Option<PathBuf>,
+   pub log_skipped_updates: bool,
+}
+
+impl Config {
+   /// Every format to keep from a copy besides its main contents: `capture_formats`, along with HTML and RTF when
+   /// `capture_html` and `capture_rtf` ask for them. There are none if `plain_text_only` is set.
+   pub fn captured_formats(&self) -> Vec<Format> {
+      let mut formats = Vec::new();
+      if self.plain_text_only {
+         return formats;
+      }
+      if self.capture_html {
+         formats.push(Format::html());
+      }
+      if self.capture_rtf {
+         formats.push(Format::rtf());
+      }
+      for format in self.capture_formats.iter() {
+         if !formats.contains(format) {
+            formats.push(format.clone());
+         }
+      }
+      formats
+   }
+
+   /// Every keybinding option, with its name
+   pub fn keybindings(&self) -> [(&'static str, &[Hotkey]); KEYBINDING_COUNT] {
+      [
+         ("pop_keybinding", &self.pop_keybinding),
+         ("clear_keybinding", &self.clear_keybinding),
+         ("swap_keybinding", &self.swap_keybinding),
+         ("push_keybinding", &self.push_keybinding),
+         ("peek_keybinding", &self.peek_keybinding),
+         ("pause_keybinding", &self.pause_keybinding),
+         ("rotate_keybinding", &self.rotate_keybinding),
+         ("drop_keybinding", &self.drop_keybinding),
+         ("pop_all_keybinding", &self.pop_all_keybinding),
+         ("duplicate_top_keybinding", &self.duplicate_top_keybinding),
+         ("undo_pop_keybinding", &self.undo_pop_keybinding),
+         ("show_stack_keybinding", &self.show_stack_keybinding),
+         ("reverse_stack_keybinding", &self.reverse_stack_keybinding),
+         ("move_to_bottom_keybinding", &self.move_to_bottom_keybinding),
+         ("copy_all_keybinding", &self.copy_all_keybinding),
+         ("save_snapshot_keybinding", &self.save_snapshot_keybinding),
+         ("restore_snapshot_keybinding", &self.restore_snapshot_keybinding),
+         ("paste_second_keybinding", &self.paste_second_keybinding),
+         ("export_keybinding", &self.export_keybinding),
+         ("import_keybinding", &self.import_keybinding),
+         ("pin_top_keybinding", &self.pin_top_keybinding),
+         ("undo_clear_keybinding", &self.undo_clear_keybinding),
+      ]
+   }
+
+   /// Looks up every hotkey whose key depends on the keyboard layout, in order, in `layout`. When the keyboard
+   /// layout changes, the hotkeys only need registering again if this does too.
+   pub fn layout_mapping(&self, layout: &impl KeyboardLayout) -> Vec<Result<(win::Modifiers, u32), ResolveError>> {
+      IntoIterator::into_iter(self.keybindings())
+         .flat_map(|(_, hotkeys)| hotkeys.iter())
+         .filter(|hotkey| hotkey.key.is_layout_dependent())
+         .map(|hotkey| hotkey.resolve(layout))
+         .collect()
+   }
+
+   /// Checks for problems that span several options, which parsing one option at a time can't catch
+   pub fn validate(&self) -> Result<(), ValidationError> {
+      let mut seen: Vec<(&'static str, Hotkey)> = Vec::new();
+      let indexed_pop = self.indexed_pop_modifiers.iter().flat_map(|modifiers| {
+         INDEXED_POP_KEYS.iter().map(move |key| Hotkey {
+            key: Key::Virtual(*key),
+            modifiers: *modifiers,
+            then: None,
+         })
+      });
+      let hotkeys = IntoIterator::into_iter(self.keybindings())
+         .flat_map(|(option, hotkeys)| hotkeys.iter().map(move |hotkey| (option, *hotkey)))
+         .chain(indexed_pop.map(|hotkey| ("indexed_pop_modifiers", hotkey)));
+      for (option, hotkey) in hotkeys {
+         if let Some((first, _)) = seen.iter().find(|(_, x)| x.conflicts_with(&hotkey)) {
+            return Err(ValidationError::ConflictingHotkeys {
+               hotkey,
+               first,
+               second: option,
+            });
+         }
+         seen.push((option, hotkey));
+      }
+      Ok(())
+   }
 }
 
+/// How many keybinding options there are, as listed by `Config::keybindings`
+pub const KEYBINDING_COUNT: usize = 22;
+
+/// The keys that `indexed_pop_modifiers` applies to, in order
+pub const INDEXED_POP_KEYS: [win::VirtualKey; 9] = [
+   win::VirtualKey::One,
+   win::VirtualKey::Two,
+   win::VirtualKey::Three,
+   win::VirtualKey::Four,
+   win::VirtualKey::Five,
+   win::VirtualKey::Six,
+   win::VirtualKey::Seven,
+   win::VirtualKey::Eight,
+   win::VirtualKey::Nine,
+];
+
 impl Default for Config {
    fn default() -> Config {
       Config {
+         mode: Mode::Stack,
          max_stack_size: Some(100),
+         stack_full_policy: StackFullPolicy::DropOldest,
+         max_stack_memory: None,
+         max_entry_age: None,
+         max_item_size: None,
+         oversize_action: OversizeAction::Skip,
+         normalize_line_endings: LineEndings::Keep,
+         transform: Vec::new(),
+         capture_images: true,
+         max_image_pixels: None,
+         image_oversize_action: ImageOversizeAction::Skip,
+         capture_html: true,
+         capture_rtf: false,
+         capture_formats: Vec::new(),
+         plain_text_only: false,
+         delayed_render: false,
+         respect_exclusion_formats: true,
+         ignore_remote_clipboard: false,
+         ignored_applications: Vec::new(),
+         persist_stack: false,
+         persist_encrypted: true,
+         clear_on_lock: false,
+         clear_clipboard_on_lock: false,
+         pause_in_fullscreen: false,
+         pause_hotkeys_in_fullscreen: false,
          show_tray_icon: true,
-         pop_keybinding: Some(Hotkey {
-            key: win::VirtualKey::C,
+         tray_menu_entries: 10,
+         tray_tooltip_preview: true,
+         tray_badge: true,
+         pop_keybinding: vec![Hotkey {
+            key: Key::Virtual(win::VirtualKey::C),
             modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
-         }),
-         clear_keybinding: None,
-         swap_keybinding: None,
+            then: None,
+         }],
+         pop_removes_entry: true,
+         paste_after_pop: false,
+         paste_keystroke: Hotkey {
+            key: Key::Virtual(win::VirtualKey::V),
+            modifiers: win::Modifiers::CONTROL,
+            then: None,
+         },
+         clear_keybinding: Vec::new(),
+         swap_keybinding: Vec::new(),
+         swap_behavior: SwapBehavior::TopTwo,
+         push_keybinding: Vec::new(),
+         peek_keybinding: Vec::new(),
+         pause_keybinding: Vec::new(),
+         rotate_keybinding: Vec::new(),
+         drop_keybinding: Vec::new(),
+         pop_all_keybinding: Vec::new(),
+         join_separator: String::from("\r\n"),
+         join_order: JoinOrder::BottomFirst,
+         duplicate_top_keybinding: Vec::new(),
+         undo_pop_keybinding: Vec::new(),
+         show_stack_keybinding: Vec::new(),
+         reverse_stack_keybinding: Vec::new(),
+         move_to_bottom_keybinding: Vec::new(),
+         copy_all_keybinding: Vec::new(),
+         copy_all_max_bytes: 16 * 1024 * 1024,
+         save_snapshot_keybinding: Vec::new(),
+         restore_snapshot_keybinding: Vec::new(),
+         indexed_pop_modifiers: None,
+         paste_second_keybinding: Vec::new(),
+         export_keybinding: Vec::new(),
+         export_path: None,
+         import_keybinding: Vec::new(),
+         import_path: None,
+         import_append: false,
+         history_file: None,
+         history_max_bytes: Some(10 * 1024 * 1024),
+         pin_top_keybinding: Vec::new(),
+         undo_clear_keybinding: Vec::new(),
+         undo_clear_timeout: Some(Duration::from_secs(5 * 60)),
+         hotkey_no_repeat: true,
+         use_keyboard_hook: false,
          prevent_duplicate_push: false,
+         dedupe_scope: DedupeScope::Consecutive,
+         dedupe_action: DedupeAction::Skip,
+         dedupe_trim_whitespace: false,
+         dedupe_ignore_case: false,
+         dedupe_formatting: false,
+         duplicate_window_ms: 0,
+         capture_debounce_ms: 50,
+         clipboard_retry_ms: 200,
+         log_level: LevelFilter::Info,
+         log_file: None,
+         log_skipped_updates: false,
       }
    }
 }
@@ -46,10 +300,42 @@ pub enum LineError {
    Malformed,
    UnknownOption(String),
    UnknownModifier(String),
+   DuplicateModifier(String),
    UnknownKey(String),
    ExpectedBool(String),
    ExpectedInt(ParseIntError),
+   ExpectedLogLevel(String),
+   ExpectedDedupeScope(String),
+   ExpectedDedupeAction(String),
+   ExpectedStackFullPolicy(String),
+   ExpectedOversizeAction(String),
+   ExpectedLineEndings(String),
+   ExpectedImageOversizeAction(String),
+   ExpectedSwapBehavior(String),
+   ExpectedJoinOrder(String),
+   ExpectedFormat(String),
+   ExpectedApplication(String),
+   HandleFormat(u32),
+   BadTransform(String),
+   ExpectedMode(String),
+   ExpectedByteSize(String),
+   ExpectedDuration(String),
+   ZeroStackSize,
+   ZeroItemSize,
+   ZeroImagePixels,
+   ZeroStackMemory,
+   ZeroHistorySize,
+   ZeroEntryAge,
    ModifierWithNoKey,
+   NoneInHotkeyList,
+   InHotkeyList { element: usize, error: Box<LineError> },
+   NoneInSequence,
+   ExpectedKeystroke,
+   BadSequenceKey(String),
+   BadScancode(String),
+   UnterminatedString,
+   UnknownEscape(char),
+   IncludeFailed(PathBuf, io::Error),
 }
 
 impl fmt::Display for LineError {
@@ -61,6 +347,7 @@ impl fmt::Display for LineError {
          ),
          LineError::UnknownOption(got) => write!(f, "Unknown option `{}`", got),
          LineError::UnknownModifier(got) => write!(f, "Unknown modifier `{}`", got),
+         LineError::DuplicateModifier(got) => write!(f, "`{}` is already part of this hotkey", got),
          LineError::UnknownKey(got) => write!(f, "Unknown key `{}`", got),
          LineError::ExpectedBool(got) => write!(f, "Expected value to be one of `true` or `false`, got {}", got),
          LineError::ExpectedInt(err) => write!(
@@ -69,12 +356,266 @@ impl fmt::Display for LineError {
             usize::MAX,
             err
          ),
+         LineError::ExpectedLogLevel(got) => write!(
+            f,
+            "Expected value to be one of `error`, `warn`, `info`, `debug` or `trace`, got {}",
+            got
+         ),
+         LineError::ExpectedDedupeScope(got) => {
+            write!(f, "Expected value to be one of `consecutive` or `stack`, got {}", got)
+         }
+         LineError::ExpectedDedupeAction(got) => {
+            write!(f, "Expected value to be one of `skip` or `promote`, got {}", got)
+         }
+         LineError::ExpectedStackFullPolicy(got) => {
+            write!(
+               f,
+               "Expected value to be one of `drop_oldest` or `reject_new`, got {}",
+               got
+            )
+         }
+         LineError::ExpectedOversizeAction(got) => {
+            write!(f, "Expected value to be one of `skip` or `truncate`, got {}", got)
+         }
+         LineError::ExpectedImageOversizeAction(got) => {
+            write!(f, "Expected value to be one of `skip` or `downscale`, got {}", got)
+         }
+         LineError::ExpectedLineEndings(got) => {
+            write!(f, "Expected value to be one of `none`, `crlf` or `lf`, got {}", got)
+         }
+         LineError::ExpectedSwapBehavior(got) => {
+            write!(f, "Expected value to be one of `top_two` or `clipboard`, got {}", got)
+         }
+         LineError::ExpectedJoinOrder(got) => {
+            write!(
+               f,
+               "Expected value to be one of `top_first` or `bottom_first`, got {}",
+               got
+            )
+         }
+         LineError::ExpectedFormat(got) => {
+            write!(f, "Expected the name or number of a clipboard format, got `{}`", got)
+         }
+         LineError::ExpectedApplication(got) => write!(
+            f,
+            "Expected a program's file name, such as `keepass.exe`, or its full path, which may end in `*`, got `{}`",
+            got
+         ),
+         LineError::HandleFormat(got) => write!(
+            f,
+            "Clipboard format {} holds a handle to something rather than data, so it can't be kept",
+            got
+         ),
+         LineError::BadTransform(message) => write!(f, "Bad transform: {}", message),
+         LineError::ExpectedMode(got) => {
+            write!(f, "Expected value to be one of `stack` or `queue`, got {}", got)
+         }
+         LineError::ExpectedByteSize(got) => write!(
+            f,
+            "Expected value to be a number of bytes, optionally followed by `kb`, `mb` or `gb`, got {}",
+            got
+         ),
+         LineError::ExpectedDuration(got) => write!(
+            f,
+            "Expected value to be a number followed by `s`, `m`, `h` or `d`, such as `30m`, got {}",
+            got
+         ),
+         LineError::ZeroStackSize => write!(
+            f,
+            "A max_stack_size of 0 would keep nothing on the stack. Use `none` or `unlimited` for no limit"
+         ),
+         LineError::ZeroItemSize => write!(
+            f,
+            "A max_item_size of 0 would keep nothing of any copy. Use `none` or `unlimited` for no limit"
+         ),
+         LineError::ZeroImagePixels => write!(
+            f,
+            "A max_image_pixels of 0 would keep no image. Use `none` or `unlimited` for no limit"
+         ),
+         LineError::ZeroStackMemory => write!(
+            f,
+            "A max_stack_memory of 0 would keep nothing on the stack. Use `none` or `unlimited` for no limit"
+         ),
+         LineError::ZeroHistorySize => write!(
+            f,
+            "A history_max_bytes of 0 would start a new history file for every entry. Use `none` or `unlimited` \
+             for no limit"
+         ),
+         LineError::ZeroEntryAge => write!(
+            f,
+            "A max_entry_age of 0 would expire everything right away. Use `none` for no limit"
+         ),
          LineError::ModifierWithNoKey => write!(
             f,
             "It doesn't make sense to have an empty key (None) with any modifiers, or other tokens"
          ),
+         LineError::NoneInHotkeyList => write!(f, "`None` can't be combined with other hotkeys"),
+         LineError::InHotkeyList { element, error } => write!(f, "In hotkey {} of the list: {}", element, error),
+         LineError::NoneInSequence => write!(f, "`None` can't be part of a sequence"),
+         LineError::ExpectedKeystroke => write!(
+            f,
+            "Expected a single keystroke, such as Control + V, rather than None, a mouse button or a sequence"
+         ),
+         LineError::BadScancode(got) => write!(
+            f,
+            "Expected a scancode such as `scancode:0x2E`, or a letter or number such as `scancode:c`, got `{}`",
+            got
+         ),
+         LineError::BadSequenceKey(got) => write!(
+            f,
+            "`{}` can't be the second step of a sequence, which must be a single key other than Escape",
+            got
+         ),
+         LineError::UnterminatedString => write!(f, "Quoted value is missing its closing quote"),
+         LineError::UnknownEscape(got) => write!(
+            f,
+            "Unknown escape `\\{}`. Quoted values may use `\\n`, `\\t`, `\\r`, `\\\"`, and `\\\\` for a backslash",
+            got
+         ),
+         LineError::IncludeFailed(path, err) => {
+            write!(f, "Unable to read included file {}: {}", path.display(), err)
+         }
+      }
+   }
+}
+
+/// Formats whose data is a handle to a bitmap, palette or metafile, which isn't something that can be copied
+const HANDLE_FORMATS: &[u32] = &[2, 3, 9, 14, 0x82, 0x83, 0x8E];
+
+/// Parses a list of clipboard formats separated by commas, or None for no formats
+fn parse_formats(value: &str) -> Result<Vec<Format>, LineError> {
+   if value == "none" {
+      return Ok(Vec::new());
+   }
+   let mut formats: Vec<Format> = Vec::new();
+   for format in value.split(',') {
+      let format = format
+         .parse()
+         .map_err(|_| LineError::ExpectedFormat(format.trim().to_owned()))?;
+      match format {
+         Format::Id(id) if HANDLE_FORMATS.contains(&id) => return Err(LineError::HandleFormat(id)),
+         format if !formats.contains(&format) => formats.push(format),
+         _ => (),
+      }
+   }
+   Ok(formats)
+}
+
+/// A program whose copies are left off the stack: the file name of its executable, such as `keepass.exe`, or the
+/// full path to it. Either may end in `*` to match anything that starts with what comes before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IgnoredApplication {
+   /// Lowercase, with `/` made `\`
+   pattern: String,
+}
+
+impl IgnoredApplication {
+   fn new(pattern: &str) -> IgnoredApplication {
+      IgnoredApplication {
+         pattern: pattern.to_lowercase().replace('/', "\\"),
+      }
+   }
+
+   /// Whether this matches the program at `path`, the full path to its executable
+   pub fn matches(&self, path: &str) -> bool {
+      let path = path.to_lowercase().replace('/', "\\");
+      let target = if self.pattern.contains('\\') {
+         &path[..]
+      } else {
+         path.rsplit('\\').next().unwrap_or(&path)
+      };
+      match self.pattern.strip_suffix('*') {
+         Some(prefix) => target.starts_with(prefix),
+         None => target == self.pattern,
+      }
+   }
+}
+
+impl fmt::Display for IgnoredApplication {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "{}", self.pattern)
+   }
+}
+
+/// Parses a list of programs separated by commas, or None for no programs
+fn parse_applications(value: &str) -> Result<Vec<IgnoredApplication>, LineError> {
+   if value == "none" {
+      return Ok(Vec::new());
+   }
+   let mut applications: Vec<IgnoredApplication> = Vec::new();
+   for application in value.split(',') {
+      let application = application.trim();
+      // Only a trailing `*` is understood, so one anywhere else is more likely a mistake than part of a name, and
+      // `*` alone would ignore every copy
+      let name = application.strip_suffix('*').unwrap_or(application);
+      if name.is_empty() || name.contains('*') {
+         return Err(LineError::ExpectedApplication(application.to_owned()));
+      }
+      let application = IgnoredApplication::new(application);
+      if !applications.contains(&application) {
+         applications.push(application);
+      }
+   }
+   Ok(applications)
+}
+
+/// A rewrite applied to the text of each copy: every match of `regex` is replaced with `replacement`, which can
+/// refer to capture groups as `$1` or `${name}`
+#[derive(Clone, Debug)]
+pub struct Transform {
+   pub regex: Regex,
+   pub replacement: String,
+}
+
+impl PartialEq for Transform {
+   fn eq(&self, other: &Transform) -> bool {
+      self.regex.as_str() == other.regex.as_str() && self.replacement == other.replacement
+   }
+}
+
+/// Reads the part of a transform up to the next unescaped `/`, where `\/` stands for `/`. Other escapes are left for
+/// the regex. Returns the part, along with what follows the `/`.
+fn split_transform_part(value: &str) -> Option<(String, &str)> {
+   let mut part = String::new();
+   let mut chars = value.char_indices();
+   while let Some((i, c)) = chars.next() {
+      match c {
+         '/' => return Some((part, &value[i + 1..])),
+         '\\' => match chars.next() {
+            Some((_, '/')) => part.push('/'),
+            Some((_, c)) => {
+               part.push('\\');
+               part.push(c);
+            }
+            None => part.push('\\'),
+         },
+         c => part.push(c),
       }
    }
+   None
+}
+
+/// Parses a transform written as `/pattern/replacement/`
+fn parse_transform(value: &str) -> Result<Transform, LineError> {
+   let malformed = || LineError::BadTransform(String::from("expected /pattern/replacement/"));
+   let value = value.strip_prefix('/').ok_or_else(malformed)?;
+   let (pattern, rest) = split_transform_part(value).ok_or_else(malformed)?;
+   let (replacement, rest) = split_transform_part(rest).ok_or_else(malformed)?;
+   if !rest.trim().is_empty() {
+      return Err(malformed());
+   }
+   let regex = Regex::new(&pattern).map_err(|e| LineError::BadTransform(e.to_string()))?;
+   Ok(Transform { regex, replacement })
+}
+
+/// Writes a transform as `parse_transform` reads it
+fn transform_value(transform: &Transform) -> String {
+   let escape = |x: &str| x.replace('/', "\\/");
+   quote(&format!(
+      "/{}/{}/",
+      escape(transform.regex.as_str()),
+      escape(&transform.replacement)
+   ))
 }
 
 impl From<win::ParseVirtualKeyError> for LineError {
@@ -93,10 +634,122 @@ impl From<win::ParseModifierError> for LineError {
    }
 }
 
+/// Where a line came from. `file` is `None` when parsing from something other than a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+   pub file: Option<PathBuf>,
+   pub line: usize,
+}
+
+impl fmt::Display for Location {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match &self.file {
+         Some(file) => write!(f, "line {} of {}", self.line + 1, file.display()),
+         None => write!(f, "line {}", self.line + 1),
+      }
+   }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigWarning {
+   DuplicateOption { option: String, first: Location },
+   ModifierInKeyPosition(String),
+   NoModifiers(String),
+   DeprecatedOption { option: String, replacement: &'static str },
+}
+
+impl fmt::Display for ConfigWarning {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ConfigWarning::DuplicateOption { option, first } => write!(
+            f,
+            "`{}` was already set at {}; the value on this line takes precedence",
+            option, first
+         ),
+         ConfigWarning::ModifierInKeyPosition(key) => write!(
+            f,
+            "Encountered a modifier key `{}` in key position while parsing hotkey. Is this intended?",
+            key
+         ),
+         ConfigWarning::NoModifiers(key) => write!(
+            f,
+            "Hotkey `{}` has no modifiers, so that key won't work normally in any other application",
+            key
+         ),
+         ConfigWarning::DeprecatedOption { option, replacement } => {
+            write!(f, "`{}` has been renamed to `{}`", option, replacement)
+         }
+      }
+   }
+}
+
+/// An error in an option's value, narrowed down to the part of the value at fault if possible
+#[derive(Debug)]
+struct ValueError {
+   error: LineError,
+   /// Byte range within the value
+   span: Option<Range<usize>>,
+}
+
+impl From<LineError> for ValueError {
+   fn from(error: LineError) -> ValueError {
+      ValueError { error, span: None }
+   }
+}
+
+/// A line that failed to parse
+#[derive(Debug)]
+pub struct BadLine {
+   pub error: LineError,
+   pub location: Location,
+   /// The line as written, minus surrounding whitespace
+   pub text: String,
+   /// Byte range within `text` of the part at fault
+   pub span: Range<usize>,
+}
+
+impl fmt::Display for BadLine {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let column = self.text[..self.span.start].chars().count();
+      let width = std::cmp::max(1, self.text[self.span.clone()].chars().count());
+      writeln!(f, "Error at {}: {}", self.location, self.error)?;
+      writeln!(f, "  {}", self.text)?;
+      write!(f, "  {}{}", " ".repeat(column), "^".repeat(width))
+   }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+   ConflictingHotkeys {
+      hotkey: Hotkey,
+      first: &'static str,
+      second: &'static str,
+   },
+}
+
+impl fmt::Display for ValidationError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ValidationError::ConflictingHotkeys { hotkey, first, second } if first == second => {
+            write!(f, "`{}` has {} more than once", first, hotkey)
+         }
+         ValidationError::ConflictingHotkeys { hotkey, first, second } => {
+            write!(
+               f,
+               "`{}` and `{}` both have {}; each hotkey can only do one thing",
+               first, second, hotkey
+            )
+         }
+      }
+   }
+}
+
 #[derive(Debug)]
 pub enum ParseError {
    Io(io::Error),
-   Line(LineError, usize),
+   Lines(Vec<BadLine>),
+   IncludeCycle { including: PathBuf, included: PathBuf },
+   Invalid(ValidationError),
 }
 
 impl From<io::Error> for ParseError {
@@ -109,182 +762,3893 @@ impl fmt::Display for ParseError {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
       match self {
          ParseError::Io(e) => write!(f, "I/O Error: {}", e),
-         ParseError::Line(e, index) => write!(f, "Error at line {}: {}", index + 1, e),
+         ParseError::Lines(errors) => {
+            for (i, bad_line) in errors.iter().enumerate() {
+               if i > 0 {
+                  writeln!(f)?;
+               }
+               write!(f, "{}", bad_line)?;
+            }
+            Ok(())
+         }
+         ParseError::IncludeCycle { including, included } => write!(
+            f,
+            "{} includes {}, which is already being included",
+            including.display(),
+            included.display()
+         ),
+         ParseError::Invalid(e) => write!(f, "{}", e),
       }
    }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Hotkey {
-   pub key: win::VirtualKey,
-   pub modifiers: win::Modifiers,
+/// Which end of the stack copies go on, relative to the end pop and peek take from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+   /// Last in, first out: a copy goes on top, to be popped first
+   Stack,
+   /// First in, first out: a copy goes on the bottom, to be popped after everything copied before it
+   Queue,
 }
 
-fn parse_hotkey(hotkey: &str) -> Result<Option<Hotkey>, LineError> {
-   let mut tokens_iter = hotkey.split('+').rev();
-   let raw_key = tokens_iter.next().unwrap().trim();
-   if raw_key == "none" {
-      if tokens_iter.next() != None {
-         return Err(LineError::ModifierWithNoKey);
+/// What to do with a new entry when the stack is already at `max_stack_size`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackFullPolicy {
+   /// Push the new entry, discarding the bottom of the stack
+   DropOldest,
+   /// Discard the new entry, leaving the stack as it is
+   RejectNew,
+}
+
+/// What to do with a copy larger than `max_item_size`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OversizeAction {
+   /// Leave the copy off the stack
+   Skip,
+   /// Push the start of the copy, as much as fits
+   Truncate,
+}
+
+/// What to do with an image larger than `max_image_pixels`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageOversizeAction {
+   /// Leave the image off the stack
+   Skip,
+   /// Push the image shrunk to fit
+   Downscale,
+}
+
+/// What line endings copied text is given
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEndings {
+   /// Leave the text as it was copied
+   Keep,
+   /// Windows line endings, `\r\n`
+   Crlf,
+   /// Unix line endings, `\n`
+   Lf,
+}
+
+/// Which entries a copy is compared against to find duplicates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupeScope {
+   /// Only the top of the stack, and only if `prevent_duplicate_push` is set
+   Consecutive,
+   /// Every entry on the stack
+   Stack,
+}
+
+/// Which end of the stack comes first when entries are joined together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinOrder {
+   TopFirst,
+   BottomFirst,
+}
+
+/// What `swap_keybinding` swaps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwapBehavior {
+   /// The top two entries of the stack, putting the new top on the clipboard
+   TopTwo,
+   /// Text on the clipboard that isn't on the stack, with the top of the stack
+   Clipboard,
+}
+
+/// What to do with a copy that's already on the stack
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupeAction {
+   /// Leave the stack as it is
+   Skip,
+   /// Move the entry already on the stack to the top
+   Promote,
+}
+
+/// The key of a hotkey
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+   Virtual(win::VirtualKey),
+   /// A key by its position on the keyboard, whichever virtual key the keyboard layout gives it
+   Scancode(u16),
+   /// Whichever key types this character in the keyboard layout
+   Character(char),
+}
+
+/// Looks up keys in a keyboard layout
+pub trait KeyboardLayout {
+   /// The virtual key code at `scancode`, if the layout has a key there
+   fn scancode_key(&self, scancode: u16) -> Option<u32>;
+   /// The virtual key code that types `c`, along with the modifiers that have to be held for it,
+   /// if the layout has a key for it
+   fn character_key(&self, c: char) -> Option<(u32, win::Modifiers)>;
+}
+
+/// Why a key couldn't be found in the keyboard layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResolveError {
+   NotOnLayout,
+   /// The character is typed with AltGr, which hotkeys can't tell apart from Control + Alt
+   NeedsAltGr,
+}
+
+impl fmt::Display for ResolveError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ResolveError::NotOnLayout => write!(f, "isn't on the current keyboard layout"),
+         ResolveError::NeedsAltGr => write!(
+            f,
+            "is typed with AltGr on the current keyboard layout, which hotkeys can't use"
+         ),
       }
-      return Ok(None);
-   }
-   let key: win::VirtualKey = raw_key.parse()?;
-   if key.is_modifier() {
-      warn!(
-         "Encountered a modifier key `{}` in key position while parsing hotkey. Is this intended?",
-         raw_key
-      );
-   }
-   let mut modifiers = win::Modifiers::empty();
-   for modifier in tokens_iter {
-      let modifier: win::Modifiers = modifier.trim().parse()?;
-      modifiers |= modifier;
    }
-   Ok(Some(Hotkey { key, modifiers }))
 }
 
-pub fn parse_config<R>(input: R) -> Result<Config, ParseError>
-where
-   R: BufRead,
-{
-   let mut config = Config::default();
-   for (i, line) in input.lines().enumerate() {
-      let mut line = line?;
-      line.make_ascii_lowercase();
-      let line = line.trim();
-      if line.is_empty() {
-         continue;
-      }
-      let pieces: Vec<_> = line.split('=').collect();
-      if pieces.len() != 2 {
-         return Err(ParseError::Line(LineError::Malformed, i));
-      }
-      match pieces[0].trim() {
-         "max_stack_size" => {
-            let opt_value = pieces[1].trim();
-            config.max_stack_size = if opt_value == "none" {
-               None
-            } else {
-               match opt_value.parse::<usize>() {
-                  Ok(value) => Some(value),
-                  Err(e) => return Err(ParseError::Line(LineError::ExpectedInt(e), i)),
-               }
-            }
-         }
-         "show_tray_icon" => match pieces[1].trim() {
-            "true" => {
-               config.show_tray_icon = true;
-            }
-            "false" => {
-               config.show_tray_icon = false;
-            }
-            x => return Err(ParseError::Line(LineError::ExpectedBool(x.to_owned()), i)),
-         },
-         "prevent_duplicate_push" => match pieces[1].trim() {
-            "true" => {
-               config.prevent_duplicate_push = true;
-            }
-            "false" => {
-               config.prevent_duplicate_push = false;
+impl Key {
+   /// The virtual key code of this key in `layout`, with the modifiers needed to type it
+   pub fn resolve(self, layout: &impl KeyboardLayout) -> Result<(u32, win::Modifiers), ResolveError> {
+      match self {
+         Key::Virtual(key) => Ok((key as u32, win::Modifiers::empty())),
+         Key::Scancode(scancode) => layout
+            .scancode_key(scancode)
+            .map(|code| (code, win::Modifiers::empty()))
+            .ok_or(ResolveError::NotOnLayout),
+         Key::Character(c) => match layout.character_key(c) {
+            Some((_, modifiers)) if modifiers.contains(win::Modifiers::CONTROL | win::Modifiers::ALT) => {
+               Err(ResolveError::NeedsAltGr)
             }
-            x => return Err(ParseError::Line(LineError::ExpectedBool(x.to_owned()), i)),
+            Some(resolved) => Ok(resolved),
+            None => Err(ResolveError::NotOnLayout),
          },
-         "pop_keybinding" => {
-            config.pop_keybinding = match parse_hotkey(pieces[1].trim()) {
-               Ok(binding) => binding,
-               Err(e) => return Err(ParseError::Line(e, i)),
-            }
-         }
-         "clear_keybinding" => {
-            config.clear_keybinding = match parse_hotkey(pieces[1].trim()) {
-               Ok(binding) => binding,
-               Err(e) => return Err(ParseError::Line(e, i)),
-            }
-         }
-         "swap_keybinding" => {
-            config.swap_keybinding = match parse_hotkey(pieces[1].trim()) {
-               Ok(binding) => binding,
-               Err(e) => return Err(ParseError::Line(e, i)),
-            }
-         }
-         x => return Err(ParseError::Line(LineError::UnknownOption(x.to_owned()), i)),
       }
    }
-   Ok(config)
+
+   /// Whether what this key is depends on the keyboard layout
+   pub fn is_layout_dependent(self) -> bool {
+      !matches!(self, Key::Virtual(_))
+   }
+
+   pub fn is_mouse_button(self) -> bool {
+      match self {
+         Key::Virtual(key) => key.is_mouse_button(),
+         _ => false,
+      }
+   }
 }
 
-pub fn load_config() -> Result<Config, ParseError> {
-   let path_opt = dirs::config_dir();
-   if let Some(mut path) = path_opt {
-      path.push("ripclip");
-      // Maybe it already exists, maybe not.
-      // We ignore errors because it will be handled when we try to
-      // write/read the configuration
-      let _ = fs::create_dir(&path);
-      path.push("ripclip.conf");
-      if let Ok(file) = File::open(&path) {
-         let config = parse_config(BufReader::new(file))?;
-         info!("Read configuration from {:#?}", path);
-         Ok(config)
-      } else {
-         match File::create(&path) {
-            Ok(mut file) => {
-               if let Err(e) = file.write_all(DEFAULT_CONFIG) {
-                  warn!("Unable to write default configuration to {:#?}.\n Error: {}", path, e);
-               } else {
-                  info!("Wrote default configuration to {:#?}", path);
-               }
-            }
+impl fmt::Display for Key {
+   /// Writes the key the same way it would be written in the configuration
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         Key::Virtual(key) => write!(f, "{}", key),
+         Key::Scancode(scancode) => write!(f, "Scancode:{:#04X}", scancode),
+         Key::Character(c) => write!(f, "{}", c),
+      }
+   }
+}
+
+/// Where each letter and number is on a US keyboard, so that `scancode:c` can stand for wherever C is there
+const SCANCODE_POSITIONS: &[(&str, u16)] = &[
+   ("1", 0x02),
+   ("2", 0x03),
+   ("3", 0x04),
+   ("4", 0x05),
+   ("5", 0x06),
+   ("6", 0x07),
+   ("7", 0x08),
+   ("8", 0x09),
+   ("9", 0x0A),
+   ("0", 0x0B),
+   ("q", 0x10),
+   ("w", 0x11),
+   ("e", 0x12),
+   ("r", 0x13),
+   ("t", 0x14),
+   ("y", 0x15),
+   ("u", 0x16),
+   ("i", 0x17),
+   ("o", 0x18),
+   ("p", 0x19),
+   ("a", 0x1E),
+   ("s", 0x1F),
+   ("d", 0x20),
+   ("f", 0x21),
+   ("g", 0x22),
+   ("h", 0x23),
+   ("j", 0x24),
+   ("k", 0x25),
+   ("l", 0x26),
+   ("z", 0x2C),
+   ("x", 0x2D),
+   ("c", 0x2E),
+   ("v", 0x2F),
+   ("b", 0x30),
+   ("n", 0x31),
+   ("m", 0x32),
+];
+
+const SCANCODE_PREFIX: &str = "scancode:";
+
+/// Parses a key, which is either a virtual key like `c`, a scancode like `scancode:0x2e` or `scancode:c`,
+/// or any other character that the keyboard layout can type, like `é`
+fn parse_key(raw_key: &str) -> Result<Key, LineError> {
+   let raw_scancode = match raw_key.strip_prefix(SCANCODE_PREFIX) {
+      Some(raw_scancode) => raw_scancode.trim(),
+      None => {
+         return match raw_key.parse() {
+            Ok(key) => Ok(Key::Virtual(key)),
             Err(e) => {
-               warn!("Unable to write default configuration to {:#?}.\n Error: {}", path, e);
+               let mut chars = raw_key.chars();
+               match (chars.next(), chars.next()) {
+                  (Some(c), None) if !c.is_control() && !c.is_whitespace() => Ok(Key::Character(c)),
+                  _ => Err(e.into()),
+               }
             }
          }
-         Ok(Config::default())
       }
-   } else {
-      warn!("Unable to determine configuration directory; Falling back to default");
-      Ok(Config::default())
+   };
+   let parsed = match raw_scancode.strip_prefix("0x") {
+      Some(hex) => u16::from_str_radix(hex, 16).ok(),
+      None => SCANCODE_POSITIONS
+         .iter()
+         .find(|(name, _)| *name == raw_scancode)
+         .map(|(_, scancode)| *scancode),
+   };
+   match parsed {
+      Some(scancode) if scancode != 0 => Ok(Key::Scancode(scancode)),
+      _ => Err(LineError::BadScancode(raw_scancode.to_owned())),
    }
 }
 
-#[cfg(test)]
-mod test {
-   use super::*;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hotkey {
+   pub key: Key,
+   pub modifiers: win::Modifiers,
+   /// For a sequence, the key pressed after the rest, as in `Control + Shift + R then P`
+   pub then: Option<win::VirtualKey>,
+}
 
-   #[test]
-   fn ignores_case() {
-      let config: &[u8] = b"
-         max_STACK_size = nonE
-         clear_keybinding = CTRL + shift + c
-      ";
-      let parsed_cfg = parse_config(config);
-      assert!(parsed_cfg.is_ok());
-      let parsed_cfg = parsed_cfg.unwrap();
-      assert!(parsed_cfg.max_stack_size.is_none());
-      assert_eq!(parsed_cfg.clear_keybinding, Some(Hotkey {
-         modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
-         key: win::VirtualKey::C,
-      }));
+impl Hotkey {
+   /// The modifiers and virtual key code to register for this hotkey in `layout`.
+   /// Any modifiers needed to type a character are added to the ones given.
+   pub fn resolve(&self, layout: &impl KeyboardLayout) -> Result<(win::Modifiers, u32), ResolveError> {
+      let (code, implied) = self.key.resolve(layout)?;
+      Ok((self.modifiers | implied, code))
    }
 
-   #[test]
-   fn parses_default_config() {
-      assert_eq!(parse_config(DEFAULT_CONFIG).unwrap(), Config::default());
+   /// Whether pressing one could trigger the other. A sequence starting with a hotkey conflicts with that hotkey,
+   /// but sequences sharing a first step are fine as long as their second keys differ.
+   pub fn conflicts_with(&self, other: &Hotkey) -> bool {
+      self.key == other.key
+         && self.modifiers == other.modifiers
+         && (self.then.is_none() || other.then.is_none() || self.then == other.then)
    }
 
-   #[test]
-   fn ignores_blank_lines() {
-      let config_blank_lines: &[u8] = b"
+   /// The part of the hotkey pressed first, which is all of it unless it's a sequence
+   pub fn leader(&self) -> Hotkey {
+      Hotkey { then: None, ..*self }
+   }
+}
+
+impl fmt::Display for Hotkey {
+   /// Writes the hotkey the same way it would be written in the configuration
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      if self.modifiers.is_empty() {
+         write!(f, "{}", self.key)?;
+      } else {
+         write!(f, "{} + {}", self.modifiers, self.key)?;
+      }
+      match self.then {
+         Some(then) => write!(f, " then {}", then),
+         None => Ok(()),
+      }
+   }
+}
+
+/// A piece of a value, along with its byte range in that value
+type Token<'a> = (&'a str, Range<usize>);
 
+/// Splits `s` on any of `separators`, yielding each trimmed piece with its byte range in `s`
+fn split_with_spans<'a>(s: &'a str, separators: &[char]) -> Vec<Token<'a>> {
+   s.split(separators)
+      .map(|piece| {
+         let trimmed = piece.trim();
+         let offset = trimmed.as_ptr() as usize - s.as_ptr() as usize;
+         (trimmed, offset..offset + trimmed.len())
+      })
+      .collect()
+}
 
+/// Either of these can go between the parts of a hotkey, as in `Control + C` or `Ctrl-C`
+const HOTKEY_SEPARATORS: &[char] = &['+', '-'];
 
-      max_stack_size = 100
+/// Splits a hotkey into its modifiers and its key, each with its byte range in `hotkey`.
+/// A separator at the very end is the key itself, as in `Control + -`.
+fn split_hotkey(hotkey: &str) -> (Vec<Token<'_>>, Token<'_>) {
+   let trimmed = hotkey.trim_end();
+   match trimmed.chars().last() {
+      Some(last) if HOTKEY_SEPARATORS.contains(&last) => {
+         let key_start = trimmed.len() - last.len_utf8();
+         let rest = trimmed[..key_start].trim_end();
+         let rest = rest.strip_suffix(HOTKEY_SEPARATORS).unwrap_or(rest);
+         let modifiers = if rest.trim().is_empty() {
+            Vec::new()
+         } else {
+            split_with_spans(rest, HOTKEY_SEPARATORS)
+         };
+         (modifiers, (&trimmed[key_start..], key_start..trimmed.len()))
+      }
+      _ => {
+         let mut tokens = split_with_spans(hotkey, HOTKEY_SEPARATORS);
+         let key = tokens.pop().unwrap();
+         (tokens, key)
+      }
+   }
+}
+
+/// Separates the two steps of a sequence, as in `Control + Shift + R then P`
+const SEQUENCE_SEPARATOR: &str = " then ";
+
+/// Parses the second step of a sequence, which is a single key. `span` is where `raw_key` is in the hotkey.
+fn parse_sequence_key(raw_key: &str, span: Range<usize>) -> Result<win::VirtualKey, ValueError> {
+   let bad_key = || ValueError {
+      error: LineError::BadSequenceKey(raw_key.to_owned()),
+      span: Some(span.clone()),
+   };
+   if raw_key == "none" {
+      return Err(LineError::NoneInSequence.into());
+   }
+   if !split_hotkey(raw_key).0.is_empty() {
+      return Err(bad_key());
+   }
+   let key: win::VirtualKey = raw_key.parse().map_err(|e: win::ParseVirtualKeyError| ValueError {
+      error: e.into(),
+      span: Some(span.clone()),
+   })?;
+   // Escape cancels a sequence, and the sequence is only watched for on the keyboard
+   if key.is_modifier() || key == win::VirtualKey::Escape || key.is_mouse_button() {
+      return Err(bad_key());
+   }
+   Ok(key)
+}
+
+fn parse_hotkey(hotkey: &str, warnings: &mut Vec<ConfigWarning>) -> Result<Option<Hotkey>, ValueError> {
+   if let Some(at) = hotkey.find(SEQUENCE_SEPARATOR) {
+      let rest = &hotkey[at + SEQUENCE_SEPARATOR.len()..];
+      let raw_then = rest.trim();
+      let then_start = raw_then.as_ptr() as usize - hotkey.as_ptr() as usize;
+      let then = parse_sequence_key(raw_then, then_start..then_start + raw_then.len())?;
+      return match parse_hotkey(&hotkey[..at], warnings)? {
+         Some(leader) => Ok(Some(Hotkey {
+            then: Some(then),
+            ..leader
+         })),
+         None => Err(LineError::NoneInSequence.into()),
+      };
+   }
+   let (tokens, (raw_key, key_span)) = split_hotkey(hotkey);
+   if raw_key == "none" {
+      if !tokens.is_empty() {
+         return Err(LineError::ModifierWithNoKey.into());
+      }
+      return Ok(None);
+   }
+   let key = parse_key(raw_key).map_err(|error| ValueError {
+      error,
+      span: Some(key_span),
+   })?;
+   if let Key::Virtual(virtual_key) = key {
+      if virtual_key.is_modifier() {
+         warnings.push(ConfigWarning::ModifierInKeyPosition(raw_key.to_owned()));
+      }
+   }
+   // The key counts as already seen, so that it can't also be given as a modifier
+   let key_as_modifier = raw_key
+      .parse::<win::Modifiers>()
+      .unwrap_or_else(|_| win::Modifiers::empty());
+   let mut modifiers = win::Modifiers::empty();
+   for (raw_modifier, modifier_span) in tokens {
+      let duplicate = || ValueError {
+         error: LineError::DuplicateModifier(raw_modifier.to_owned()),
+         span: Some(modifier_span.clone()),
+      };
+      if raw_modifier == raw_key {
+         return Err(duplicate());
+      }
+      let modifier: win::Modifiers = raw_modifier.parse().map_err(|e: win::ParseModifierError| ValueError {
+         error: e.into(),
+         span: Some(modifier_span.clone()),
+      })?;
+      if modifiers.intersects(modifier) || key_as_modifier.intersects(modifier) {
+         return Err(duplicate());
+      }
+      modifiers |= modifier;
+   }
+   if modifiers.is_empty() && !matches!(key, Key::Virtual(x) if x.is_spare()) {
+      warnings.push(ConfigWarning::NoModifiers(raw_key.to_owned()));
+   }
+   Ok(Some(Hotkey {
+      key,
+      modifiers,
+      then: None,
+   }))
+}
+
+/// Splits a list of hotkeys on its commas, like `split_with_spans`. A comma straight after a separator is the key of
+/// that hotkey rather than the end of it, as in `Control + ,`.
+fn split_hotkey_list(value: &str) -> Vec<Token<'_>> {
+   let mut ranges = Vec::new();
+   let mut start = 0;
+   let mut empty = true;
+   let mut after_separator = false;
+   for (i, c) in value.char_indices() {
+      if c == ',' && !after_separator {
+         ranges.push(start..i);
+         start = i + 1;
+         empty = true;
+      } else if !c.is_whitespace() {
+         // A separator with nothing to separate is a key, as in `Control + -` or a lone `-`
+         after_separator = HOTKEY_SEPARATORS.contains(&c) && !empty && !after_separator;
+         empty = false;
+      }
+   }
+   ranges.push(start..value.len());
+   ranges
+      .into_iter()
+      .map(|range| {
+         let trimmed = value[range].trim();
+         let offset = trimmed.as_ptr() as usize - value.as_ptr() as usize;
+         (trimmed, offset..offset + trimmed.len())
+      })
+      .collect()
+}
+
+/// Parses a comma separated list of hotkeys. `None` gives an empty list, and is only allowed on its own.
+fn parse_hotkeys(value: &str, warnings: &mut Vec<ConfigWarning>) -> Result<Vec<Hotkey>, ValueError> {
+   let elements = split_hotkey_list(value);
+   if elements.len() == 1 {
+      return Ok(parse_hotkey(value, warnings)?.into_iter().collect());
+   }
+   let mut hotkeys = Vec::new();
+   for (i, (element, element_span)) in elements.into_iter().enumerate() {
+      match parse_hotkey(element, warnings) {
+         Ok(Some(hotkey)) => hotkeys.push(hotkey),
+         Ok(None) => {
+            return Err(ValueError {
+               error: LineError::NoneInHotkeyList,
+               span: Some(element_span),
+            })
+         }
+         Err(e) => {
+            let offset = element_span.start;
+            return Err(ValueError {
+               error: LineError::InHotkeyList {
+                  element: i + 1,
+                  error: Box::new(e.error),
+               },
+               span: Some(e.span.map_or(element_span, |x| x.start + offset..x.end + offset)),
+            });
+         }
+      }
+   }
+   Ok(hotkeys)
+}
+
+/// Parses modifiers joined with `+` and no key, such as `Control + Shift`
+fn parse_modifiers(value: &str) -> Result<Option<win::Modifiers>, ValueError> {
+   if value == "none" {
+      return Ok(None);
+   }
+   let mut modifiers = win::Modifiers::empty();
+   for (raw_modifier, modifier_span) in split_with_spans(value, HOTKEY_SEPARATORS) {
+      let modifier: win::Modifiers = raw_modifier.parse().map_err(|e: win::ParseModifierError| ValueError {
+         error: e.into(),
+         span: Some(modifier_span.clone()),
+      })?;
+      if modifiers.intersects(modifier) {
+         return Err(ValueError {
+            error: LineError::DuplicateModifier(raw_modifier.to_owned()),
+            span: Some(modifier_span),
+         });
+      }
+      modifiers |= modifier;
+   }
+   Ok(Some(modifiers))
+}
+
+/// Quoted values are taken exactly as written (aside from the escapes `\"` and `\\`, plus `\n`, `\t`
+/// and `\r` if `escapes` is set, which makes any other escape an error), while unquoted values are case-folded
+/// like the rest of the configuration.
+fn parse_value(raw: &str, escapes: bool) -> Result<String, LineError> {
+   if !raw.starts_with('"') {
+      return Ok(raw.to_ascii_lowercase());
+   }
+   let mut value = String::new();
+   let mut chars = raw[1..].chars();
+   loop {
+      match chars.next() {
+         None => return Err(LineError::UnterminatedString),
+         Some('"') => break,
+         Some('\\') => match chars.next() {
+            Some(c @ '"') | Some(c @ '\\') => value.push(c),
+            Some('n') if escapes => value.push('\n'),
+            Some('t') if escapes => value.push('\t'),
+            Some('r') if escapes => value.push('\r'),
+            Some(c) if escapes => return Err(LineError::UnknownEscape(c)),
+            Some(c) => {
+               value.push('\\');
+               value.push(c);
+            }
+            None => return Err(LineError::UnterminatedString),
+         },
+         Some(c) => value.push(c),
+      }
+   }
+   if !chars.as_str().trim().is_empty() {
+      return Err(LineError::Malformed);
+   }
+   Ok(value)
+}
+
+fn parse_bool(value: &str) -> Result<bool, ValueError> {
+   match value {
+      "true" => Ok(true),
+      "false" => Ok(false),
+      x => Err(LineError::ExpectedBool(x.to_owned()).into()),
+   }
+}
+
+const BYTE_SUFFIXES: &[(&str, usize)] = &[("gb", 1024 * 1024 * 1024), ("mb", 1024 * 1024), ("kb", 1024), ("b", 1)];
+
+/// Parses a number of bytes, which may be followed by a suffix such as `mb`
+fn parse_byte_size(value: &str) -> Result<usize, LineError> {
+   let (number, multiplier) = BYTE_SUFFIXES
+      .iter()
+      .find_map(|(suffix, multiplier)| Some((value.strip_suffix(suffix)?, *multiplier)))
+      .unwrap_or((value, 1));
+   number
+      .trim_end()
+      .parse::<usize>()
+      .ok()
+      .and_then(|x| x.checked_mul(multiplier))
+      .ok_or_else(|| LineError::ExpectedByteSize(value.to_owned()))
+}
+
+/// Writes a number of bytes with the largest suffix that divides it evenly
+fn byte_size_value(bytes: usize) -> String {
+   match BYTE_SUFFIXES
+      .iter()
+      .find(|(_, multiplier)| bytes != 0 && bytes.is_multiple_of(*multiplier))
+   {
+      Some((suffix, multiplier)) if *multiplier > 1 => format!("{}{}", bytes / multiplier, suffix.to_uppercase()),
+      _ => bytes.to_string(),
+   }
+}
+
+const DURATION_UNITS: &[(&str, u64)] = &[("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+
+/// Parses a length of time, written as a number followed by a unit such as `m` for minutes
+fn parse_duration(value: &str) -> Result<Duration, LineError> {
+   DURATION_UNITS
+      .iter()
+      .find_map(|(unit, seconds)| {
+         let number = value.strip_suffix(unit)?.trim_end().parse::<u64>().ok()?;
+         number.checked_mul(*seconds).map(Duration::from_secs)
+      })
+      .ok_or_else(|| LineError::ExpectedDuration(value.to_owned()))
+}
+
+/// Writes a length of time with the largest unit that divides it evenly
+fn duration_value(duration: Duration) -> String {
+   let seconds = duration.as_secs();
+   let (unit, unit_seconds) = DURATION_UNITS
+      .iter()
+      .find(|(_, unit_seconds)| seconds != 0 && seconds.is_multiple_of(*unit_seconds))
+      .unwrap_or(&("s", 1));
+   format!("{}{}", seconds / unit_seconds, unit)
+}
+
+fn hotkeys_value(hotkeys: &[Hotkey]) -> String {
+   if hotkeys.is_empty() {
+      return String::from("None");
+   }
+   hotkeys.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+type ParseFn = fn(&mut Config, &str, &mut Vec<ConfigWarning>) -> Result<(), ValueError>;
+type WriteFn = fn(&Config) -> String;
+
+/// Everything there is to know about a configuration option. Parsing, writing,
+/// and the documentation in the generated default configuration all come from `OPTIONS`.
+struct ConfigOption {
+   name: &'static str,
+   description: &'static str,
+   accepts: &'static str,
+   parse: ParseFn,
+   /// The value of this option in `config`, as it would be written in the configuration file
+   write: WriteFn,
+}
+
+const HOTKEY_ACCEPTS: &str = "Modifiers and a key joined with +, such as Control + Shift + C, or None. \
+   Follow a hotkey with then and a key for a sequence, such as Control + Shift + R then P. \
+   Separate several hotkeys with commas";
+
+const OPTIONS: &[ConfigOption] = &[
+   ConfigOption {
+      name: "mode",
+      description: "Stack pops the newest copy first, while Queue pops copies in the order they were made",
+      accepts: "Stack or Queue",
+      parse: |config, value, _| {
+         config.mode = match value {
+            "stack" => Mode::Stack,
+            "queue" => Mode::Queue,
+            x => return Err(LineError::ExpectedMode(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.mode {
+            Mode::Stack => "stack",
+            Mode::Queue => "queue",
+         })
+      },
+   },
+   ConfigOption {
+      name: "max_stack_size",
+      description: "The most entries the stack holds; past that, the oldest entry is discarded",
+      accepts: "A number greater than 0, or None (or Unlimited) for no limit",
+      parse: |config, value, _| {
+         config.max_stack_size = if value == "none" || value == "unlimited" {
+            None
+         } else {
+            match value.parse::<usize>() {
+               Ok(0) => return Err(LineError::ZeroStackSize.into()),
+               Ok(value) => Some(value),
+               Err(e) => return Err(LineError::ExpectedInt(e).into()),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.max_stack_size {
+         Some(max_stack_size) => max_stack_size.to_string(),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "stack_full_policy",
+      description: "What to do with a copy when the stack already holds max_stack_size entries. \
+         Reject_New discards the copy instead of the oldest entry",
+      accepts: "Drop_Oldest or Reject_New",
+      parse: |config, value, _| {
+         config.stack_full_policy = match value {
+            "drop_oldest" => StackFullPolicy::DropOldest,
+            "reject_new" => StackFullPolicy::RejectNew,
+            x => return Err(LineError::ExpectedStackFullPolicy(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.stack_full_policy {
+            StackFullPolicy::DropOldest => "drop_oldest",
+            StackFullPolicy::RejectNew => "reject_new",
+         })
+      },
+   },
+   ConfigOption {
+      name: "max_stack_memory",
+      description: "The most memory, in bytes, the entries on the stack can take up altogether; past that, \
+         the oldest entries are discarded",
+      accepts: "A number greater than 0, optionally followed by KB, MB or GB, or None (or Unlimited) for no limit",
+      parse: |config, value, _| {
+         config.max_stack_memory = if value == "none" || value == "unlimited" {
+            None
+         } else {
+            match parse_byte_size(value)? {
+               0 => return Err(LineError::ZeroStackMemory.into()),
+               value => Some(value),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.max_stack_memory {
+         Some(max_stack_memory) => byte_size_value(max_stack_memory),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "max_entry_age",
+      description: "How long after being copied an entry is discarded from the stack",
+      accepts: "A number followed by s, m, h or d (seconds, minutes, hours or days), such as 30m, or None",
+      parse: |config, value, _| {
+         config.max_entry_age = if value == "none" {
+            None
+         } else {
+            match parse_duration(value)? {
+               x if x.is_zero() => return Err(LineError::ZeroEntryAge.into()),
+               x => Some(x),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.max_entry_age {
+         Some(max_entry_age) => duration_value(max_entry_age),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "max_item_size",
+      description: "The largest copy, in bytes, that's pushed whole. Text takes two bytes for most characters",
+      accepts: "A number greater than 0, or None (or Unlimited) for no limit",
+      parse: |config, value, _| {
+         config.max_item_size = if value == "none" || value == "unlimited" {
+            None
+         } else {
+            match value.parse::<usize>() {
+               Ok(0) => return Err(LineError::ZeroItemSize.into()),
+               Ok(value) => Some(value),
+               Err(e) => return Err(LineError::ExpectedInt(e).into()),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.max_item_size {
+         Some(max_item_size) => max_item_size.to_string(),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "oversize_action",
+      description: "What to do with a copy larger than max_item_size. Truncate pushes as much of it as fits",
+      accepts: "Skip or Truncate",
+      parse: |config, value, _| {
+         config.oversize_action = match value {
+            "skip" => OversizeAction::Skip,
+            "truncate" => OversizeAction::Truncate,
+            x => return Err(LineError::ExpectedOversizeAction(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.oversize_action {
+            OversizeAction::Skip => "skip",
+            OversizeAction::Truncate => "truncate",
+         })
+      },
+   },
+   ConfigOption {
+      name: "normalize_line_endings",
+      description: "Line endings to give copied text, so that text copied from different places is the same",
+      accepts: "None, Crlf or Lf",
+      parse: |config, value, _| {
+         config.normalize_line_endings = match value {
+            "none" => LineEndings::Keep,
+            "crlf" => LineEndings::Crlf,
+            "lf" => LineEndings::Lf,
+            x => return Err(LineError::ExpectedLineEndings(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.normalize_line_endings {
+            LineEndings::Keep => "none",
+            LineEndings::Crlf => "crlf",
+            LineEndings::Lf => "lf",
+         })
+      },
+   },
+   ConfigOption {
+      name: "transform",
+      description: "A rewrite of the text of each copy. Repeat the option for more; they're applied in order",
+      accepts: "A quoted regex and replacement as \"/pattern/replacement/\", with \\/ for a slash, or None",
+      parse: |config, value, _| {
+         if value == "none" {
+            config.transform.clear();
+         } else {
+            config.transform.push(parse_transform(value)?);
+         }
+         Ok(())
+      },
+      write: |config| {
+         if config.transform.is_empty() {
+            String::from("None")
+         } else {
+            config
+               .transform
+               .iter()
+               .map(transform_value)
+               .collect::<Vec<_>>()
+               .join("\n")
+         }
+      },
+   },
+   ConfigOption {
+      name: "capture_images",
+      description: "Whether copied images are pushed onto the stack",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.capture_images = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.capture_images.to_string(),
+   },
+   ConfigOption {
+      name: "max_image_pixels",
+      description: "The most pixels, width times height, an image can have to be pushed as it is",
+      accepts: "A number greater than 0, or None (or Unlimited) for no limit",
+      parse: |config, value, _| {
+         config.max_image_pixels = if value == "none" || value == "unlimited" {
+            None
+         } else {
+            match value.parse::<usize>() {
+               Ok(0) => return Err(LineError::ZeroImagePixels.into()),
+               Ok(value) => Some(value),
+               Err(e) => return Err(LineError::ExpectedInt(e).into()),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.max_image_pixels {
+         Some(max_image_pixels) => max_image_pixels.to_string(),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "image_oversize_action",
+      description: "What to do with an image larger than max_image_pixels. Downscale shrinks it to fit",
+      accepts: "Skip or Downscale",
+      parse: |config, value, _| {
+         config.image_oversize_action = match value {
+            "skip" => ImageOversizeAction::Skip,
+            "downscale" => ImageOversizeAction::Downscale,
+            x => return Err(LineError::ExpectedImageOversizeAction(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.image_oversize_action {
+            ImageOversizeAction::Skip => "skip",
+            ImageOversizeAction::Downscale => "downscale",
+         })
+      },
+   },
+   ConfigOption {
+      name: "capture_html",
+      description: "Whether text copied with HTML formatting, such as from a browser or Word, keeps it when popped",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.capture_html = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.capture_html.to_string(),
+   },
+   ConfigOption {
+      name: "capture_rtf",
+      description: "Whether text copied with Rich Text Format, such as from Word or Outlook, keeps it when popped",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.capture_rtf = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.capture_rtf.to_string(),
+   },
+   ConfigOption {
+      name: "capture_formats",
+      description: "Other clipboard formats to keep from each copy, and place back on the clipboard along with it",
+      accepts: "Quoted format names, such as \"XML Spreadsheet\", or format numbers, separated by commas, or None",
+      parse: |config, value, _| {
+         config.capture_formats = parse_formats(value)?;
+         Ok(())
+      },
+      write: |config| {
+         if config.capture_formats.is_empty() {
+            String::from("None")
+         } else {
+            quote(
+               &config
+                  .capture_formats
+                  .iter()
+                  .map(Format::to_string)
+                  .collect::<Vec<_>>()
+                  .join(", "),
+            )
+         }
+      },
+   },
+   ConfigOption {
+      name: "plain_text_only",
+      description: "Whether copies are kept and popped as plain text only, without their formatting or other formats",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.plain_text_only = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.plain_text_only.to_string(),
+   },
+   ConfigOption {
+      name: "delayed_render",
+      description: "Whether popped entries are only placed on the clipboard once a program pastes them",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.delayed_render = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.delayed_render.to_string(),
+   },
+   ConfigOption {
+      name: "respect_exclusion_formats",
+      description:
+         "Whether copies that password managers mark as not to be kept, such as passwords, are left off the stack",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.respect_exclusion_formats = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.respect_exclusion_formats.to_string(),
+   },
+   ConfigOption {
+      name: "ignore_remote_clipboard",
+      description:
+         "Whether copies made in a remote desktop session, and passed on to this computer, are left off the stack",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.ignore_remote_clipboard = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.ignore_remote_clipboard.to_string(),
+   },
+   ConfigOption {
+      name: "ignored_applications",
+      description: "Programs whose copies are left off the stack, such as password managers",
+      accepts:
+         "Program file names, such as keepass.exe, or full paths, which may end in *, separated by commas, or None",
+      parse: |config, value, _| {
+         config.ignored_applications = parse_applications(value)?;
+         Ok(())
+      },
+      write: |config| {
+         if config.ignored_applications.is_empty() {
+            String::from("None")
+         } else {
+            quote(
+               &config
+                  .ignored_applications
+                  .iter()
+                  .map(IgnoredApplication::to_string)
+                  .collect::<Vec<_>>()
+                  .join(", "),
+            )
+         }
+      },
+   },
+   ConfigOption {
+      name: "persist_stack",
+      description: "Whether to keep the stack in a file, so that it's restored when ripclip starts",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.persist_stack = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.persist_stack.to_string(),
+   },
+   ConfigOption {
+      name: "persist_encrypted",
+      description: "Whether the file persist_stack keeps is encrypted, so that only your Windows account can read it",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.persist_encrypted = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.persist_encrypted.to_string(),
+   },
+   ConfigOption {
+      name: "clear_on_lock",
+      description: "Whether to clear the whole stack, pinned entries included, when the workstation is locked",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.clear_on_lock = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.clear_on_lock.to_string(),
+   },
+   ConfigOption {
+      name: "clear_clipboard_on_lock",
+      description: "Whether to empty the clipboard itself when the workstation is locked",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.clear_clipboard_on_lock = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.clear_clipboard_on_lock.to_string(),
+   },
+   ConfigOption {
+      name: "pause_in_fullscreen",
+      description: "Whether copies are left off the stack while a fullscreen program, such as a game, is in front",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.pause_in_fullscreen = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.pause_in_fullscreen.to_string(),
+   },
+   ConfigOption {
+      name: "pause_hotkeys_in_fullscreen",
+      description: "Whether hotkeys are also let go while pause_in_fullscreen is pausing, so programs can use them",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.pause_hotkeys_in_fullscreen = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.pause_hotkeys_in_fullscreen.to_string(),
+   },
+   ConfigOption {
+      name: "show_tray_icon",
+      description: "Whether to show an icon in the notification area",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.show_tray_icon = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.show_tray_icon.to_string(),
+   },
+   ConfigOption {
+      name: "tray_menu_entries",
+      description: "How many entries from the top of the stack the tray menu lists, to pop any of them",
+      accepts: "A number of entries, or 0 to list none",
+      parse: |config, value, _| {
+         config.tray_menu_entries = value.parse::<usize>().map_err(LineError::ExpectedInt)?;
+         Ok(())
+      },
+      write: |config| config.tray_menu_entries.to_string(),
+   },
+   ConfigOption {
+      name: "tray_tooltip_preview",
+      description:
+         "Whether the tray icon's tooltip shows the start of the top entry, besides how many entries there are",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.tray_tooltip_preview = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.tray_tooltip_preview.to_string(),
+   },
+   ConfigOption {
+      name: "tray_badge",
+      description: "Whether the tray icon shows how many entries there are, up to 9",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.tray_badge = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.tray_badge.to_string(),
+   },
+   ConfigOption {
+      name: "pop_keybinding",
+      description: "Removes the top of the stack, putting the entry below it on the clipboard",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.pop_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.pop_keybinding),
+   },
+   ConfigOption {
+      name: "pop_removes_entry",
+      description: "Whether pop_keybinding removes the top of the stack. If not, it steps down the stack instead, \
+         putting each entry on the clipboard in turn and starting again from the top after the bottom",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.pop_removes_entry = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.pop_removes_entry.to_string(),
+   },
+   ConfigOption {
+      name: "paste_after_pop",
+      description: "Whether pop_keybinding also pastes the entry it puts on the clipboard into the active window, \
+         by typing paste_keystroke",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.paste_after_pop = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.paste_after_pop.to_string(),
+   },
+   ConfigOption {
+      name: "paste_keystroke",
+      description: "The keystroke paste_after_pop types to paste, for apps that paste with something other than \
+         Control + V, such as Control + Shift + V in terminals",
+      accepts: "Modifiers and a key joined with +, such as Control + V",
+      parse: |config, value, warnings| {
+         config.paste_keystroke = match parse_hotkey(value, warnings)? {
+            Some(hotkey) if hotkey.then.is_none() && !hotkey.key.is_mouse_button() => hotkey,
+            _ => return Err(LineError::ExpectedKeystroke.into()),
+         };
+         Ok(())
+      },
+      write: |config| config.paste_keystroke.to_string(),
+   },
+   ConfigOption {
+      name: "swap_keybinding",
+      description: "Swaps the top two entries of the stack",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.swap_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.swap_keybinding),
+   },
+   ConfigOption {
+      name: "swap_behavior",
+      description:
+         "What swap_keybinding swaps. Clipboard swaps text on the clipboard that isn't on the stack, such as \
+         a copy made while paused, with the top of the stack",
+      accepts: "Top_Two or Clipboard",
+      parse: |config, value, _| {
+         config.swap_behavior = match value {
+            "top_two" => SwapBehavior::TopTwo,
+            "clipboard" => SwapBehavior::Clipboard,
+            x => return Err(LineError::ExpectedSwapBehavior(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.swap_behavior {
+            SwapBehavior::TopTwo => "top_two",
+            SwapBehavior::Clipboard => "clipboard",
+         })
+      },
+   },
+   ConfigOption {
+      name: "clear_keybinding",
+      description: "Empties the stack and the clipboard, except for pinned entries",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.clear_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.clear_keybinding),
+   },
+   ConfigOption {
+      name: "push_keybinding",
+      description: "Pushes what's on the clipboard onto the stack, as if it had just been copied",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.push_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.push_keybinding),
+   },
+   ConfigOption {
+      name: "peek_keybinding",
+      description: "Shows the start of the top of the stack in a notification, without changing anything",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.peek_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.peek_keybinding),
+   },
+   ConfigOption {
+      name: "pause_keybinding",
+      description: "Stops or resumes pushing what's copied onto the stack",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.pause_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.pause_keybinding),
+   },
+   ConfigOption {
+      name: "rotate_keybinding",
+      description: "Moves the top of the stack to the bottom, putting the entry below it on the clipboard",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.rotate_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.rotate_keybinding),
+   },
+   ConfigOption {
+      name: "drop_keybinding",
+      description: "Removes the top of the stack, leaving the clipboard as it is",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.drop_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.drop_keybinding),
+   },
+   ConfigOption {
+      name: "pop_all_keybinding",
+      description: "Empties the stack, joining every entry onto the clipboard",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.pop_all_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.pop_all_keybinding),
+   },
+   ConfigOption {
+      name: "join_separator",
+      description: "What goes between entries joined by pop_all_keybinding and copy_all_keybinding",
+      accepts: "A quoted string, which may use \\n, \\t and \\r",
+      parse: |config, value, _| {
+         config.join_separator = value.to_owned();
+         Ok(())
+      },
+      write: |config| quote_escaped(&config.join_separator),
+   },
+   ConfigOption {
+      name: "join_order",
+      description: "Which end of the stack comes first when pop_all_keybinding and copy_all_keybinding join it",
+      accepts: "Top_First or Bottom_First",
+      parse: |config, value, _| {
+         config.join_order = match value {
+            "top_first" => JoinOrder::TopFirst,
+            "bottom_first" => JoinOrder::BottomFirst,
+            x => return Err(LineError::ExpectedJoinOrder(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.join_order {
+            JoinOrder::TopFirst => "top_first",
+            JoinOrder::BottomFirst => "bottom_first",
+         })
+      },
+   },
+   ConfigOption {
+      name: "duplicate_top_keybinding",
+      description: "Pushes a copy of the top of the stack, so the next pop leaves the original behind",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.duplicate_top_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.duplicate_top_keybinding),
+   },
+   ConfigOption {
+      name: "undo_pop_keybinding",
+      description: "Puts the most recently popped entry back on the stack and the clipboard",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.undo_pop_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.undo_pop_keybinding),
+   },
+   ConfigOption {
+      name: "show_stack_keybinding",
+      description: "Opens a window listing the stack, top first",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.show_stack_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.show_stack_keybinding),
+   },
+   ConfigOption {
+      name: "reverse_stack_keybinding",
+      description: "Reverses the order of the stack, putting the new top on the clipboard",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.reverse_stack_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.reverse_stack_keybinding),
+   },
+   ConfigOption {
+      name: "move_to_bottom_keybinding",
+      description: "Moves the top of the stack to the bottom, to be used later",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.move_to_bottom_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.move_to_bottom_keybinding),
+   },
+   ConfigOption {
+      name: "copy_all_keybinding",
+      description: "Joins every entry onto the clipboard, leaving the stack as it is",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.copy_all_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.copy_all_keybinding),
+   },
+   ConfigOption {
+      name: "copy_all_max_bytes",
+      description: "The most text copy_all_keybinding will copy; anything past that is cut off",
+      accepts: "A number of bytes",
+      parse: |config, value, _| {
+         config.copy_all_max_bytes = value.parse::<usize>().map_err(LineError::ExpectedInt)?;
+         Ok(())
+      },
+      write: |config| config.copy_all_max_bytes.to_string(),
+   },
+   ConfigOption {
+      name: "save_snapshot_keybinding",
+      description: "Saves the stack to a file, replacing any snapshot saved before",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.save_snapshot_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.save_snapshot_keybinding),
+   },
+   ConfigOption {
+      name: "restore_snapshot_keybinding",
+      description: "Replaces the stack with the saved snapshot",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.restore_snapshot_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.restore_snapshot_keybinding),
+   },
+   ConfigOption {
+      name: "paste_second_keybinding",
+      description: "Places the entry below the top of the stack in the clipboard, leaving the stack alone",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.paste_second_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.paste_second_keybinding),
+   },
+   ConfigOption {
+      name: "export_keybinding",
+      description: "Writes the stack out as JSON to export_path, for reading by people or other programs",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.export_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.export_keybinding),
+   },
+   ConfigOption {
+      name: "export_path",
+      description:
+         "Where export_keybinding writes the stack, relative to this directory. Each export replaces the last.",
+      accepts: "A quoted path, or None for a new stack-export-<timestamp>.json in this directory each time",
+      parse: |config, value, _| {
+         config.export_path = if value == "none" {
+            None
+         } else {
+            Some(PathBuf::from(value))
+         };
+         Ok(())
+      },
+      write: |config| match &config.export_path {
+         Some(export_path) => quote(&export_path.to_string_lossy()),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "import_keybinding",
+      description: "Replaces the stack with one exported by export_keybinding, read from import_path",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.import_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.import_keybinding),
+   },
+   ConfigOption {
+      name: "import_path",
+      description: "Where import_keybinding reads a stack from, relative to this directory",
+      accepts: "A quoted path, or None for export_path, or the newest stack-export-<timestamp>.json in this directory",
+      parse: |config, value, _| {
+         config.import_path = if value == "none" {
+            None
+         } else {
+            Some(PathBuf::from(value))
+         };
+         Ok(())
+      },
+      write: |config| match &config.import_path {
+         Some(import_path) => quote(&import_path.to_string_lossy()),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "import_append",
+      description: "Whether import_keybinding pushes the imported entries onto the stack, rather than replacing it",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.import_append = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.import_append.to_string(),
+   },
+   ConfigOption {
+      name: "history_file",
+      description: "A file to append every popped entry to, one JSON object per line, relative to this directory",
+      accepts: "A quoted path, or None to not keep a history",
+      parse: |config, value, _| {
+         config.history_file = if value == "none" {
+            None
+         } else {
+            Some(PathBuf::from(value))
+         };
+         Ok(())
+      },
+      write: |config| match &config.history_file {
+         Some(history_file) => quote(&history_file.to_string_lossy()),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "history_max_bytes",
+      description: "How large history_file can grow before it's renamed with .1 on the end, replacing the last one, \
+         and a new one started",
+      accepts: "A number greater than 0, optionally followed by KB, MB or GB, or None (or Unlimited) for no limit",
+      parse: |config, value, _| {
+         config.history_max_bytes = if value == "none" || value == "unlimited" {
+            None
+         } else {
+            match parse_byte_size(value)? {
+               0 => return Err(LineError::ZeroHistorySize.into()),
+               value => Some(value),
+            }
+         };
+         Ok(())
+      },
+      write: |config| match config.history_max_bytes {
+         Some(history_max_bytes) => byte_size_value(history_max_bytes),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "pin_top_keybinding",
+      description: "Pins the top of the stack, or unpins it if it's pinned. Pinned entries survive clearing and are \
+         never evicted or expired.",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.pin_top_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.pin_top_keybinding),
+   },
+   ConfigOption {
+      name: "undo_clear_keybinding",
+      description: "Puts back the entries removed by the last clear, beneath anything pushed since",
+      accepts: HOTKEY_ACCEPTS,
+      parse: |config, value, warnings| {
+         config.undo_clear_keybinding = parse_hotkeys(value, warnings)?;
+         Ok(())
+      },
+      write: |config| hotkeys_value(&config.undo_clear_keybinding),
+   },
+   ConfigOption {
+      name: "undo_clear_timeout",
+      description: "How long after a clear it can be undone, after which the entries removed are discarded",
+      accepts: "A number followed by s, m, h or d (seconds, minutes, hours or days), such as 5m, 0s so that clears \
+         can't be undone, or None to keep them until the next clear",
+      parse: |config, value, _| {
+         config.undo_clear_timeout = if value == "none" {
+            None
+         } else {
+            Some(parse_duration(value)?)
+         };
+         Ok(())
+      },
+      write: |config| match config.undo_clear_timeout {
+         Some(undo_clear_timeout) => duration_value(undo_clear_timeout),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "indexed_pop_modifiers",
+      description: "Held with a number from 1 to 9, pops the entry that far down the stack",
+      accepts: "Modifiers joined with +, such as Control + Shift, or None",
+      parse: |config, value, _| {
+         config.indexed_pop_modifiers = parse_modifiers(value)?;
+         Ok(())
+      },
+      write: |config| match config.indexed_pop_modifiers {
+         Some(modifiers) => modifiers.to_string(),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "hotkey_no_repeat",
+      description: "Whether holding down a hotkey is kept from repeating its action",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.hotkey_no_repeat = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.hotkey_no_repeat.to_string(),
+   },
+   ConfigOption {
+      name: "use_keyboard_hook",
+      description: "Whether to watch for hotkeys with a keyboard hook instead of registering them with Windows",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.use_keyboard_hook = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.use_keyboard_hook.to_string(),
+   },
+   ConfigOption {
+      name: "dedupe_consecutive",
+      description: "Ignores a copy that's the same as the top of the stack",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.prevent_duplicate_push = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.prevent_duplicate_push.to_string(),
+   },
+   ConfigOption {
+      name: "dedupe_scope",
+      description: "Which items a copy is checked against for duplicates. Stack checks every item, \
+         even without dedupe_consecutive",
+      accepts: "Consecutive or Stack",
+      parse: |config, value, _| {
+         config.dedupe_scope = match value {
+            "consecutive" => DedupeScope::Consecutive,
+            "stack" => DedupeScope::Stack,
+            x => return Err(LineError::ExpectedDedupeScope(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.dedupe_scope {
+            DedupeScope::Consecutive => "consecutive",
+            DedupeScope::Stack => "stack",
+         })
+      },
+   },
+   ConfigOption {
+      name: "dedupe_action",
+      description: "What to do with a copy that's already on the stack. Promote moves that item to the top",
+      accepts: "Skip or Promote",
+      parse: |config, value, _| {
+         config.dedupe_action = match value {
+            "skip" => DedupeAction::Skip,
+            "promote" => DedupeAction::Promote,
+            x => return Err(LineError::ExpectedDedupeAction(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| {
+         String::from(match config.dedupe_action {
+            DedupeAction::Skip => "skip",
+            DedupeAction::Promote => "promote",
+         })
+      },
+   },
+   ConfigOption {
+      name: "dedupe_trim_whitespace",
+      description: "Whether whitespace at the start and end of a copy is ignored when checking for duplicates",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.dedupe_trim_whitespace = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.dedupe_trim_whitespace.to_string(),
+   },
+   ConfigOption {
+      name: "dedupe_ignore_case",
+      description: "Whether case is ignored when checking for duplicates",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.dedupe_ignore_case = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.dedupe_ignore_case.to_string(),
+   },
+   ConfigOption {
+      name: "dedupe_formatting",
+      description: "Whether the same text with different formatting counts as a different copy when checking for \
+         duplicates",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.dedupe_formatting = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.dedupe_formatting.to_string(),
+   },
+   ConfigOption {
+      name: "duplicate_window_ms",
+      description:
+         "Ignores a copy that's the same as the last one, if it comes within this long of it. 0 turns this off",
+      accepts: "A number of milliseconds",
+      parse: |config, value, _| {
+         config.duplicate_window_ms = value.parse::<u64>().map_err(LineError::ExpectedInt)?;
+         Ok(())
+      },
+      write: |config| config.duplicate_window_ms.to_string(),
+   },
+   ConfigOption {
+      name: "capture_debounce_ms",
+      description: "Waits this long after the clipboard changes, until it stops changing, before reading it. 0 turns \
+         this off",
+      accepts: "A number of milliseconds",
+      parse: |config, value, _| {
+         config.capture_debounce_ms = value.parse::<u32>().map_err(LineError::ExpectedInt)?;
+         Ok(())
+      },
+      write: |config| config.capture_debounce_ms.to_string(),
+   },
+   ConfigOption {
+      name: "clipboard_retry_ms",
+      description: "How long to keep trying to open the clipboard while another program has it open",
+      accepts: "A number of milliseconds",
+      parse: |config, value, _| {
+         config.clipboard_retry_ms = value.parse::<u32>().map_err(LineError::ExpectedInt)?;
+         Ok(())
+      },
+      write: |config| config.clipboard_retry_ms.to_string(),
+   },
+   ConfigOption {
+      name: "log_level",
+      description: "How much detail to log",
+      accepts: "Error, Warn, Info, Debug or Trace",
+      parse: |config, value, _| {
+         config.log_level = match value {
+            "error" => LevelFilter::Error,
+            "warn" => LevelFilter::Warn,
+            "info" => LevelFilter::Info,
+            "debug" => LevelFilter::Debug,
+            "trace" => LevelFilter::Trace,
+            x => return Err(LineError::ExpectedLogLevel(x.to_owned()).into()),
+         };
+         Ok(())
+      },
+      write: |config| config.log_level.to_string().to_ascii_lowercase(),
+   },
+   ConfigOption {
+      name: "log_file",
+      description: "A file to append log output to, relative to this directory",
+      accepts: "A quoted path, or None to not log to a file",
+      parse: |config, value, _| {
+         config.log_file = if value == "none" {
+            None
+         } else {
+            Some(PathBuf::from(value))
+         };
+         Ok(())
+      },
+      write: |config| match &config.log_file {
+         Some(log_file) => quote(&log_file.to_string_lossy()),
+         None => String::from("None"),
+      },
+   },
+   ConfigOption {
+      name: "log_skipped_updates",
+      description: "Whether to log why each copy that isn't pushed was skipped, at the info level rather than debug",
+      accepts: "True or False",
+      parse: |config, value, _| {
+         config.log_skipped_updates = parse_bool(value)?;
+         Ok(())
+      },
+      write: |config| config.log_skipped_updates.to_string(),
+   },
+];
+
+/// Old option names that are still accepted, along with the name that replaced them
+const ALIASES: &[(&str, &str)] = &[
+   ("prevent_duplicate_push", "dedupe_consecutive"),
+   ("pop_all_separator", "join_separator"),
+];
+
+/// Options whose quoted values understand the escapes `\n`, `\t` and `\r`
+const ESCAPING_OPTIONS: &[&str] = &["join_separator"];
+
+/// Options that can be given more than once, each time adding to the values before rather than replacing them
+const REPEATABLE_OPTIONS: &[&str] = &["transform"];
+
+fn parse_option(
+   config: &mut Config,
+   option: &str,
+   value: &str,
+   warnings: &mut Vec<ConfigWarning>,
+) -> Result<(), ValueError> {
+   match OPTIONS.iter().find(|x| x.name == option) {
+      Some(config_option) => (config_option.parse)(config, value, warnings),
+      None => Err(LineError::UnknownOption(option.to_owned()).into()),
+   }
+}
+
+/// A successfully parsed configuration, along with anything suspicious noticed while parsing it
+#[derive(Debug)]
+pub struct ParsedConfig {
+   pub config: Config,
+   pub warnings: Vec<(ConfigWarning, Location)>,
+   /// Every file read, the configuration file first and then each included file as it was reached
+   pub files: Vec<PathBuf>,
+}
+
+struct ParseState {
+   config: Config,
+   warnings: Vec<(ConfigWarning, Location)>,
+   errors: Vec<BadLine>,
+   /// Canonical paths of the files currently being parsed, outermost first
+   include_stack: Vec<PathBuf>,
+   files: Vec<PathBuf>,
+}
+
+impl ParseState {
+   fn new() -> ParseState {
+      ParseState {
+         config: Config::default(),
+         warnings: Vec::new(),
+         errors: Vec::new(),
+         include_stack: Vec::new(),
+         files: Vec::new(),
+      }
+   }
+
+   fn parse<R>(&mut self, input: R, file: Option<&Path>) -> Result<(), ParseError>
+   where
+      R: BufRead,
+   {
+      // Options are only checked for duplicates within one file, since overriding
+      // options is the whole point of including another file
+      let mut seen_options: HashMap<String, Location> = HashMap::new();
+      for (i, line) in input.lines().enumerate() {
+         let line = line?;
+         let line = line.trim();
+         if line.is_empty() || line.starts_with('#') {
+            continue;
+         }
+         let location = Location {
+            file: file.map(Path::to_path_buf),
+            line: i,
+         };
+         let bad_line = |error, span| BadLine {
+            error,
+            location: location.clone(),
+            text: line.to_owned(),
+            span,
+         };
+         let mut pieces = line.splitn(2, '=');
+         let (raw_option, raw_value) = match (pieces.next(), pieces.next()) {
+            (Some(option), Some(value)) => (option.trim_end(), value.trim()),
+            _ => {
+               self.errors.push(bad_line(LineError::Malformed, 0..line.len()));
+               continue;
+            }
+         };
+         let mut line_warnings = Vec::new();
+         let mut option = raw_option.to_ascii_lowercase();
+         if let Some((_, replacement)) = ALIASES.iter().find(|(alias, _)| *alias == option) {
+            line_warnings.push(ConfigWarning::DeprecatedOption { option, replacement });
+            option = String::from(*replacement);
+         }
+         let value_start = raw_value.as_ptr() as usize - line.as_ptr() as usize;
+         let value_span = value_start..value_start + raw_value.len();
+         let escapes = ESCAPING_OPTIONS.contains(&option.as_str());
+         let value = match parse_value(raw_value, escapes) {
+            Ok(value) => value,
+            Err(e) => {
+               self.errors.push(bad_line(e, value_span));
+               continue;
+            }
+         };
+         if option == "include" {
+            match read_include(&value, &location) {
+               Ok((path, canonical_path, contents)) => self.include(path, canonical_path, contents, &location)?,
+               Err(e) => self.errors.push(bad_line(e, value_span)),
+            }
+            continue;
+         }
+         if let Err(e) = parse_option(&mut self.config, &option, &value, &mut line_warnings) {
+            let span = match (&e.error, e.span) {
+               (LineError::UnknownOption(_), _) => 0..raw_option.len(),
+               // Spans within quoted values don't line up with what was written, because of escapes
+               (_, Some(span)) if !raw_value.starts_with('"') => value_start + span.start..value_start + span.end,
+               _ => value_span,
+            };
+            self.errors.push(bad_line(e.error, span));
+            continue;
+         }
+         for warning in line_warnings {
+            self.warnings.push((warning, location.clone()));
+         }
+         if REPEATABLE_OPTIONS.contains(&option.as_str()) {
+            continue;
+         }
+         if let Some(first) = seen_options.insert(option.clone(), location.clone()) {
+            let warning = ConfigWarning::DuplicateOption { option, first };
+            self.warnings.push((warning, location));
+         }
+      }
+      Ok(())
+   }
+
+   /// Applies an included file on top of the configuration parsed so far
+   fn include(
+      &mut self,
+      path: PathBuf,
+      canonical_path: PathBuf,
+      contents: String,
+      location: &Location,
+   ) -> Result<(), ParseError> {
+      if self.include_stack.contains(&canonical_path) {
+         return Err(ParseError::IncludeCycle {
+            including: location.file.clone().unwrap_or_default(),
+            included: path,
+         });
+      }
+      self.include_stack.push(canonical_path);
+      self.files.push(path.clone());
+      let result = self.parse(contents.as_bytes(), Some(&path));
+      self.include_stack.pop();
+      result
+   }
+
+   fn finish(self) -> (ParsedConfig, Vec<BadLine>) {
+      (
+         ParsedConfig {
+            config: self.config,
+            warnings: self.warnings,
+            files: self.files,
+         },
+         self.errors,
+      )
+   }
+}
+
+/// Reads the file named by an include line, relative to the including file.
+/// Returns the path, its canonical form, and its contents.
+fn read_include(value: &str, location: &Location) -> Result<(PathBuf, PathBuf, String), LineError> {
+   let path = match location.file.as_deref().and_then(Path::parent) {
+      Some(dir) => dir.join(value),
+      None => PathBuf::from(value),
+   };
+   let read = fs::canonicalize(&path).and_then(|canonical_path| {
+      let contents = fs::read_to_string(&canonical_path)?;
+      Ok((canonical_path, contents))
+   });
+   match read {
+      Ok((canonical_path, contents)) => Ok((path, canonical_path, contents)),
+      Err(e) => Err(LineError::IncludeFailed(path, e)),
+   }
+}
+
+/// Parses the whole configuration, failing if any line is bad.
+/// `file` is where the input came from (if anywhere), which relative includes are resolved against.
+pub fn parse_config<R>(input: R, file: Option<&Path>) -> Result<ParsedConfig, ParseError>
+where
+   R: BufRead,
+{
+   let (parsed, errors) = parse_config_all_errors(input, file)?;
+   if errors.is_empty() {
+      Ok(parsed)
+   } else {
+      Err(ParseError::Lines(errors))
+   }
+}
+
+/// Parses the whole configuration, collecting every bad line instead of stopping at the first.
+/// Lines that parse successfully are still applied to the returned configuration.
+pub fn parse_config_all_errors<R>(input: R, file: Option<&Path>) -> Result<(ParsedConfig, Vec<BadLine>), ParseError>
+where
+   R: BufRead,
+{
+   let mut state = ParseState::new();
+   if let Some(file) = file {
+      state.include_stack.push(fs::canonicalize(file)?);
+      state.files.push(file.to_path_buf());
+   }
+   state.parse(input, file)?;
+   Ok(state.finish())
+}
+
+/// Quotes a value so that it's read back exactly as given
+fn quote(value: &str) -> String {
+   format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Like `quote`, but also escapes line breaks and tabs for options that understand those escapes
+fn quote_escaped(value: &str) -> String {
+   quote(value)
+      .replace('\n', "\\n")
+      .replace('\t', "\\t")
+      .replace('\r', "\\r")
+}
+
+/// Writes every option in `config`, in a form that `parse_config` reads back into the same configuration
+pub fn write_config<W>(config: &Config, output: &mut W) -> io::Result<()>
+where
+   W: Write,
+{
+   for option in OPTIONS {
+      write_option(output, option, config)?;
+   }
+   Ok(())
+}
+
+/// Writes an option's line, or a line for each of its values if it's in `REPEATABLE_OPTIONS`, whose values are
+/// written one per line
+fn write_option<W>(output: &mut W, option: &ConfigOption, config: &Config) -> io::Result<()>
+where
+   W: Write,
+{
+   let value = (option.write)(config);
+   if REPEATABLE_OPTIONS.contains(&option.name) {
+      for value in value.lines() {
+         writeln!(output, "{} = {}", option.name, value)?;
+      }
+      Ok(())
+   } else {
+      writeln!(output, "{} = {}", option.name, value)
+   }
+}
+
+/// Like `write_config`, but with a comment describing each option
+pub fn write_documented_config<W>(config: &Config, output: &mut W) -> io::Result<()>
+where
+   W: Write,
+{
+   writeln!(output, "# ripclip configuration")?;
+   writeln!(
+      output,
+      "# Lines starting with # are ignored. Option names and unquoted values are case insensitive."
+   )?;
+   for option in OPTIONS {
+      writeln!(output)?;
+      writeln!(output, "# {}", option.description)?;
+      writeln!(output, "# Accepts: {}", option.accepts)?;
+      write_option(output, option, config)?;
+   }
+   Ok(())
+}
+
+/// The directory next to the executable, if ripclip is running in portable mode.
+/// That's signalled by a `ripclip.conf` or `portable` file being there.
+pub fn portable_dir() -> Option<PathBuf> {
+   let exe = std::env::current_exe().ok()?;
+   let dir = exe.parent()?;
+   if dir.join("ripclip.conf").is_file() || dir.join("portable").is_file() {
+      Some(dir.to_path_buf())
+   } else {
+      None
+   }
+}
+
+/// The directory where ripclip keeps its files: next to the executable in portable mode,
+/// otherwise `ripclip` in the user's configuration directory
+pub fn data_dir() -> Option<PathBuf> {
+   portable_dir().or_else(|| dirs::config_dir().map(|x| x.join("ripclip")))
+}
+
+/// Where the configuration lives: `ripclip.conf` in the data directory
+pub fn config_path() -> Option<PathBuf> {
+   data_dir().map(|x| x.join("ripclip.conf"))
+}
+
+/// Reads the configuration at `path`, which must exist, returning it along with every file it was read from, for
+/// `ConfigWatcher`
+pub fn load_config_from(path: &Path) -> Result<(Config, Vec<PathBuf>), ParseError> {
+   let file = File::open(path)?;
+   let (mut config, files) = match parse_config(BufReader::new(file), Some(path)) {
+      Ok(parsed) => {
+         for (warning, location) in parsed.warnings.iter() {
+            warn!("Warning at {}: {}", location, warning);
+         }
+         (parsed.config, parsed.files)
+      }
+      Err(e) => {
+         warn!("Failed to parse configuration at {:#?}:\n{}", path, e);
+         return Err(e);
+      }
+   };
+   if let Err(e) = config.validate() {
+      warn!("Invalid configuration at {:#?}: {}", path, e);
+      return Err(ParseError::Invalid(e));
+   }
+   info!("Read configuration from {:#?}", path);
+   // Relative paths in the configuration are relative to the configuration directory
+   let paths = config.log_file.iter_mut().chain(config.export_path.iter_mut());
+   let paths = paths
+      .chain(config.import_path.iter_mut())
+      .chain(config.history_file.iter_mut());
+   for relative in paths {
+      if relative.is_relative() {
+         *relative = path.with_file_name(&relative);
+      }
+   }
+   Ok((config, files))
+}
+
+/// Reads the configuration at the default location like `load_config_from`, writing out the default configuration
+/// if there isn't one yet
+pub fn load_config() -> Result<(Config, Vec<PathBuf>), ParseError> {
+   if let Some(dir) = portable_dir() {
+      info!("Portable mode is active; using {:#?}", dir);
+   }
+   if let Some(path) = config_path() {
+      // Maybe it already exists, maybe not.
+      // We ignore errors because it will be handled when we try to
+      // write/read the configuration
+      if let Some(dir) = path.parent() {
+         let _ = fs::create_dir(dir);
+      }
+      if path.exists() {
+         load_config_from(&path)
+      } else {
+         match File::create(&path) {
+            Ok(mut file) => {
+               if let Err(e) = write_documented_config(&Config::default(), &mut file) {
+                  warn!("Unable to write default configuration to {:#?}.\n Error: {}", path, e);
+               } else {
+                  info!("Wrote default configuration to {:#?}", path);
+               }
+            }
+            Err(e) => {
+               warn!("Unable to write default configuration to {:#?}.\n Error: {}", path, e);
+            }
+         }
+         Ok((Config::default(), vec![path]))
+      }
+   } else {
+      warn!("Unable to determine configuration directory; Falling back to default");
+      Ok((Config::default(), Vec::new()))
+   }
+}
+
+/// Notices when the configuration file, or a file it includes, has been modified. A change is only reported once
+/// the files have stopped changing between two polls, so that an editor saving several times in quick succession
+/// causes a single reload.
+pub struct ConfigWatcher {
+   files: Vec<PathBuf>,
+   /// When each of `files` was last modified, as of when they were loaded
+   loaded: Vec<Option<SystemTime>>,
+   pending: Option<Vec<Option<SystemTime>>>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+   fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+impl ConfigWatcher {
+   /// Watches `files`, as given by `load_config_from`
+   pub fn new(files: Vec<PathBuf>) -> ConfigWatcher {
+      let mut watcher = ConfigWatcher {
+         files,
+         loaded: Vec::new(),
+         pending: None,
+      };
+      watcher.mark_loaded();
+      watcher
+   }
+
+   /// Watches `files` instead, as they are now, after the configuration was loaded from them again
+   pub fn watch(&mut self, files: Vec<PathBuf>) {
+      self.files = files;
+      self.mark_loaded();
+   }
+
+   /// Records the files as they are now as loaded, e.g. after reloading them by some other means
+   pub fn mark_loaded(&mut self) {
+      self.loaded = self.modified_times();
+      self.pending = None;
+   }
+
+   fn modified_times(&self) -> Vec<Option<SystemTime>> {
+      self.files.iter().map(|x| modified_time(x)).collect()
+   }
+
+   /// Returns true if the configuration should be reloaded
+   pub fn poll(&mut self) -> bool {
+      let modified = self.modified_times();
+      // The configuration file going missing is left alone, though an included one doing so is a change
+      if modified.first().is_some_and(Option::is_none) || modified == self.loaded {
+         self.pending = None;
+         return false;
+      }
+      if self.pending.as_ref() == Some(&modified) {
+         self.loaded = modified;
+         self.pending = None;
+         true
+      } else {
+         self.pending = Some(modified);
+         false
+      }
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use crate::test_files::write_test_files;
+
+   #[test]
+   fn ignores_case() {
+      let config: &[u8] = b"
+         max_STACK_size = nonE
+         clear_keybinding = CTRL + shift + c
+      ";
+      let parsed_cfg = parse_config(config, None);
+      assert!(parsed_cfg.is_ok());
+      let parsed_cfg = parsed_cfg.unwrap().config;
+      assert!(parsed_cfg.max_stack_size.is_none());
+      assert_eq!(
+         parsed_cfg.clear_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+            key: Key::Virtual(win::VirtualKey::C),
+            then: None,
+         }]
+      );
+   }
 
+   fn written(config: &Config) -> Vec<u8> {
+      let mut output = Vec::new();
+      write_config(config, &mut output).unwrap();
+      output
+   }
 
+   #[test]
+   fn parses_default_config() {
+      let mut output = Vec::new();
+      write_documented_config(&Config::default(), &mut output).unwrap();
+      let parsed = parse_config(&output[..], None).unwrap();
+      assert_eq!(parsed.config, Config::default());
+      assert!(parsed.warnings.is_empty());
+   }
 
+   #[test]
+   fn documents_every_option() {
+      let mut output = Vec::new();
+      write_documented_config(&Config::default(), &mut output).unwrap();
+      let output = String::from_utf8(output).unwrap();
+      for option in OPTIONS {
+         assert!(output.contains(&format!("# {}\n", option.description)));
+         assert!(output.contains(&format!("\n{} = ", option.name)));
+      }
+   }
 
+   #[test]
+   fn ignores_comments() {
+      let config: &[u8] = b"
+         # max_stack_size = 5
+         max_stack_size = 10
+            #show_tray_icon = false = really
       ";
-      assert!(parse_config(config_blank_lines).is_ok());
+      let parsed_cfg = parse_config(config, None).unwrap().config;
+      assert_eq!(parsed_cfg.max_stack_size, Some(10));
+      assert!(parsed_cfg.show_tray_icon);
+   }
+
+   #[test]
+   fn ignores_blank_lines() {
+      let config_blank_lines: &[u8] = b"
+
+
+
+      max_stack_size = 100
+
+
+
+
+      ";
+      assert!(parse_config(config_blank_lines, None).is_ok());
+   }
+
+   #[test]
+   fn splits_on_first_equals() {
+      // The second `=` is the key, which is looked up in the keyboard layout
+      let config: &[u8] = b"pop_keybinding = control + =";
+      let parsed = parse_config(config, None).unwrap();
+      assert_eq!(parsed.config.pop_keybinding[0].key, Key::Character('='));
+   }
+
+   #[test]
+   fn rejects_line_without_equals() {
+      let config: &[u8] = b"show_tray_icon true";
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::Malformed,
+               location: Location { line: 0, .. },
+               ..
+            }] => (),
+            x => panic!("expected malformed line, got {:?}", x),
+         },
+         x => panic!("expected malformed line, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn rejects_empty_value() {
+      let config: &[u8] = b"show_tray_icon =";
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::ExpectedBool(got),
+               location: Location { line: 0, .. },
+               ..
+            }] => assert_eq!(got, ""),
+            x => panic!("expected bool error, got {:?}", x),
+         },
+         x => panic!("expected bool error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn reports_every_bad_line() {
+      let config: &[u8] = b"
+         pop_keybinding = control + shfit + c
+         max_stack_size = 5
+         show_tray_icon = maybe
+      ";
+      let (parsed_cfg, errors) = parse_config_all_errors(config, None).unwrap();
+      let parsed_cfg = parsed_cfg.config;
+      assert_eq!(parsed_cfg.max_stack_size, Some(5));
+      assert_eq!(parsed_cfg.pop_keybinding, Config::default().pop_keybinding);
+      match &errors[..] {
+         [first, second] => {
+            match (&first.error, &second.error) {
+               (LineError::UnknownModifier(modifier), LineError::ExpectedBool(value)) => {
+                  assert_eq!(modifier, "shfit");
+                  assert_eq!(value, "maybe");
+               }
+               x => panic!("expected modifier and bool errors, got {:?}", x),
+            }
+            assert_eq!(first.location.line, 1);
+            assert_eq!(second.location.line, 3);
+         }
+         x => panic!("expected two errors, got {:?}", x),
+      }
+      let rendered = ParseError::Lines(errors).to_string();
+      assert_eq!(
+         rendered,
+         "Error at line 2: Unknown modifier `shfit`
+  pop_keybinding = control + shfit + c
+                             ^^^^^
+Error at line 4: Expected value to be one of `true` or `false`, got maybe
+  show_tray_icon = maybe
+                   ^^^^^"
+      );
+   }
+
+   #[test]
+   fn parses_log_level() {
+      let config: &[u8] = b"log_level = TRACE";
+      assert_eq!(parse_config(config, None).unwrap().config.log_level, LevelFilter::Trace);
+
+      let config: &[u8] = b"log_level = loud";
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::ExpectedLogLevel(got),
+               location: Location { line: 0, .. },
+               ..
+            }] => assert_eq!(got, "loud"),
+            x => panic!("expected log level error, got {:?}", x),
+         },
+         x => panic!("expected log level error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_log_file() {
+      let config: &[u8] = b"log_file = logs/ripclip.log";
+      assert_eq!(
+         parse_config(config, None).unwrap().config.log_file,
+         Some(PathBuf::from("logs/ripclip.log"))
+      );
+
+      let config: &[u8] = b"log_file = none";
+      assert_eq!(parse_config(config, None).unwrap().config.log_file, None);
+   }
+
+   #[test]
+   fn parses_export_path() {
+      assert_eq!(Config::default().export_path, None);
+      let config: &[u8] = b"export_path = \"exports/stack.json\"";
+      assert_eq!(
+         parse_config(config, None).unwrap().config.export_path,
+         Some(PathBuf::from("exports/stack.json"))
+      );
+   }
+
+   #[test]
+   fn parses_history_options() {
+      let config = Config::default();
+      assert_eq!(config.history_file, None);
+      assert_eq!(config.history_max_bytes, Some(10 * 1024 * 1024));
+      let config: &[u8] = b"history_file = history.jsonl\nhistory_max_bytes = 1MB";
+      let config = parse_config(config, None).unwrap().config;
+      assert_eq!(config.history_file, Some(PathBuf::from("history.jsonl")));
+      assert_eq!(config.history_max_bytes, Some(1024 * 1024));
+      let config = parse_config(&b"history_max_bytes = unlimited"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.history_max_bytes, None);
+      match parse_config(&b"history_max_bytes = 0"[..], None) {
+         Err(ParseError::Lines(errors)) => assert!(matches!(errors[0].error, LineError::ZeroHistorySize)),
+         x => panic!("expected a zero size error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_import_options() {
+      assert_eq!(Config::default().import_path, None);
+      assert!(!Config::default().import_append);
+      let config: &[u8] = b"import_path = exports/stack.json\nimport_append = true";
+      let config = parse_config(config, None).unwrap().config;
+      assert_eq!(config.import_path, Some(PathBuf::from("exports/stack.json")));
+      assert!(config.import_append);
+   }
+
+   #[test]
+   fn warns_on_duplicate_keybinding() {
+      let config: &[u8] = b"
+         pop_keybinding = control + shift + c
+         pop_keybinding = control + shift + v
+      ";
+      let parsed = parse_config(config, None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+            key: Key::Virtual(win::VirtualKey::V),
+            then: None,
+         }]
+      );
+      assert_eq!(
+         parsed.warnings,
+         vec![(
+            ConfigWarning::DuplicateOption {
+               option: "pop_keybinding".into(),
+               first: Location { file: None, line: 1 }
+            },
+            Location { file: None, line: 2 }
+         )]
+      );
+   }
+
+   #[test]
+   fn applies_aliased_option() {
+      let config: &[u8] = b"show_tray_icon = false\nPrevent_Duplicate_Push = true";
+      let parsed = parse_config(config, None).unwrap();
+      assert!(parsed.config.prevent_duplicate_push);
+      assert_eq!(
+         parsed.warnings,
+         vec![(
+            ConfigWarning::DeprecatedOption {
+               option: "prevent_duplicate_push".into(),
+               replacement: "dedupe_consecutive"
+            },
+            Location { file: None, line: 1 }
+         )]
+      );
+      assert_eq!(
+         parsed.warnings[0].0.to_string(),
+         "`prevent_duplicate_push` has been renamed to `dedupe_consecutive`"
+      );
+   }
+
+   #[test]
+   fn warns_on_alias_and_canonical_name() {
+      let config: &[u8] = b"dedupe_consecutive = false\nprevent_duplicate_push = true";
+      let parsed = parse_config(config, None).unwrap();
+      assert!(parsed.config.prevent_duplicate_push);
+      assert_eq!(parsed.warnings.len(), 2);
+      assert_eq!(
+         parsed.warnings[1],
+         (
+            ConfigWarning::DuplicateOption {
+               option: "dedupe_consecutive".into(),
+               first: Location { file: None, line: 0 }
+            },
+            Location { file: None, line: 1 }
+         )
+      );
+   }
+
+   #[test]
+   fn warns_on_duplicate_bool() {
+      let config: &[u8] = b"show_tray_icon = false\nmax_stack_size = 3\nshow_tray_icon = true";
+      let parsed = parse_config(config, None).unwrap();
+      assert!(parsed.config.show_tray_icon);
+      assert_eq!(
+         parsed.warnings,
+         vec![(
+            ConfigWarning::DuplicateOption {
+               option: "show_tray_icon".into(),
+               first: Location { file: None, line: 0 }
+            },
+            Location { file: None, line: 2 }
+         )]
+      );
+      assert_eq!(
+         parsed.warnings[0].0.to_string(),
+         "`show_tray_icon` was already set at line 1; the value on this line takes precedence"
+      );
+   }
+
+   #[test]
+   fn preserves_quoted_values() {
+      let config: &[u8] = br#"log_file = "C:\Logs\Rip=Clip \"Quoted\".log""#;
+      assert_eq!(
+         parse_config(config, None).unwrap().config.log_file,
+         Some(PathBuf::from(r#"C:\Logs\Rip=Clip "Quoted".log"#))
+      );
+
+      let config: &[u8] = br#"log_file = C:\Logs\RipClip.log"#;
+      assert_eq!(
+         parse_config(config, None).unwrap().config.log_file,
+         Some(PathBuf::from(r#"c:\logs\ripclip.log"#))
+      );
+   }
+
+   #[test]
+   fn unescapes_separator() {
+      let config: &[u8] = br#"
+         join_separator = "\r\n--\t\\n\"\\"
+         log_file = "C:\temp\new.log"
+      "#;
+      let parsed_cfg = parse_config(config, None).unwrap().config;
+      assert_eq!(parsed_cfg.join_separator, "\r\n--\t\\n\"\\");
+      assert_eq!(parsed_cfg.log_file, Some(PathBuf::from("C:\\temp\\new.log")));
+   }
+
+   #[test]
+   fn rejects_unknown_escape() {
+      match parse_config(&br#"join_separator = ",\x""#[..], None) {
+         Err(ParseError::Lines(errors)) => assert!(matches!(errors[0].error, LineError::UnknownEscape('x'))),
+         x => panic!("Expected an error, got {:?}", x),
+      }
+      // Values without the newline escapes keep other backslashes as they are
+      let config = parse_config(&br#"log_file = "C:\xyz\ripclip.log""#[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.log_file, Some(PathBuf::from("C:\\xyz\\ripclip.log")));
+   }
+
+   #[test]
+   fn parses_join_order() {
+      assert_eq!(
+         parse_config(&b""[..], None).unwrap().config.join_order,
+         JoinOrder::BottomFirst
+      );
+      let config = parse_config(&b"join_order = Top_First"[..], None).unwrap().config;
+      assert_eq!(config.join_order, JoinOrder::TopFirst);
+      match parse_config(&b"join_order = newest_first"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::ExpectedJoinOrder(got) => assert_eq!(got, "newest_first"),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+      // The old name of join_separator still works
+      let parsed = parse_config(&br#"pop_all_separator = ", ""#[..], None).unwrap();
+      assert_eq!(parsed.config.join_separator, ", ");
+      assert_eq!(parsed.warnings.len(), 1);
+   }
+
+   #[test]
+   fn parses_capture_formats() {
+      let config = parse_config(&b""[..], None).unwrap().config;
+      assert_eq!(config.captured_formats(), vec![Format::html()]);
+      let config = parse_config(
+         &br#"capture_rtf = true
+capture_formats = "XML Spreadsheet, 49161, Rich Text Format, xml spreadsheet""#[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(
+         config.capture_formats,
+         vec![
+            Format::Name(String::from("XML Spreadsheet")),
+            Format::Id(49161),
+            Format::rtf()
+         ]
+      );
+      assert_eq!(
+         config.captured_formats(),
+         vec![
+            Format::html(),
+            Format::rtf(),
+            Format::Name(String::from("XML Spreadsheet")),
+            Format::Id(49161)
+         ]
+      );
+      let config = Config {
+         plain_text_only: true,
+         ..config
+      };
+      assert_eq!(config.captured_formats(), Vec::new());
+      for (value, error) in [
+         ("html format,,49161", "ExpectedFormat(\"\")"),
+         ("14", "HandleFormat(14)"),
+      ]
+      .iter()
+      {
+         match parse_config(format!("capture_formats = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => assert_eq!(format!("{:?}", errors[0].error), *error),
+            x => panic!("Expected an error, got {:?}", x),
+         }
+      }
+   }
+
+   #[test]
+   fn parses_ignored_applications() {
+      let config = parse_config(
+         &br#"ignored_applications = "KeePass.exe, C:/Program Files/Bank/*,mstsc*, keepass.exe""#[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(
+         config.ignored_applications,
+         vec![
+            IgnoredApplication::new("keepass.exe"),
+            IgnoredApplication::new(r"c:\program files\bank\*"),
+            IgnoredApplication::new("mstsc*"),
+         ]
+      );
+      let ignored = |path| config.ignored_applications.iter().any(|x| x.matches(path));
+      assert!(ignored(r"C:\Program Files\KeePass Password Safe 2\KEEPASS.EXE"));
+      assert!(ignored(r"C:\Program Files\Bank\app\bank.exe"));
+      assert!(ignored(r"C:\Windows\System32\mstsc.exe"));
+      assert!(!ignored(r"C:\Program Files\Banking\bank.exe"));
+      assert!(!ignored(r"C:\Tools\keepass.exe.old"));
+      // A name without a path only matches the file name, not the directories it's in
+      assert!(!ignored(r"C:\mstsc\notepad.exe"));
+      let config = parse_config(&b"ignored_applications = none"[..], None).unwrap().config;
+      assert!(config.ignored_applications.is_empty());
+      for (value, error) in [
+         ("keepass.exe,,notepad.exe", "ExpectedApplication(\"\")"),
+         ("keepass.exe,", "ExpectedApplication(\"\")"),
+         ("*pass.exe", "ExpectedApplication(\"*pass.exe\")"),
+         ("*", "ExpectedApplication(\"*\")"),
+      ]
+      .iter()
+      {
+         match parse_config(format!("ignored_applications = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => assert_eq!(format!("{:?}", errors[0].error), *error),
+            x => panic!("Expected an error, got {:?}", x),
+         }
+      }
+   }
+
+   #[test]
+   fn parses_transforms() {
+      let parsed = parse_config(
+         &br#"transform = "/^mailto://"
+transform = "/https?:\/\/(\w+)\.com\//$1 \/ ${1}/"
+"#[..],
+         None,
+      )
+      .unwrap();
+      // Repeating the option adds another transform rather than replacing the first
+      assert!(parsed.warnings.is_empty());
+      let transforms = parsed.config.transform;
+      assert_eq!(transforms.len(), 2);
+      assert_eq!(transforms[0].regex.as_str(), "^mailto:");
+      assert_eq!(transforms[0].replacement, "");
+      assert_eq!(transforms[1].regex.as_str(), r"https?://(\w+)\.com/");
+      assert_eq!(transforms[1].replacement, "$1 / ${1}");
+      let config = parse_config(&b"transform = \"/a/b/\"\ntransform = None"[..], None)
+         .unwrap()
+         .config;
+      assert!(config.transform.is_empty());
+   }
+
+   #[test]
+   fn rejects_bad_transforms() {
+      for value in [r#""a/b/""#, r#""/a/b""#, r#""/a""#, r#""/a/b/c""#, r#""""#] {
+         match parse_config(format!("transform = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::BadTransform(message) => assert_eq!(message, "expected /pattern/replacement/"),
+               e => panic!("Unexpected error {:?} for {}", e, value),
+            },
+            x => panic!("Expected an error for {}, got {:?}", value, x),
+         }
+      }
+      // The regex's own error is given
+      match parse_config(&b"transform = \"/(unclosed//\""[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::BadTransform(message) => assert!(message.contains("unclosed group"), "{}", message),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_hotkey_list() {
+      let config: &[u8] = b"pop_keybinding = Control + Shift + C, F13";
+      let parsed = parse_config(config, None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![
+            Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::C),
+               then: None,
+            },
+            Hotkey {
+               modifiers: win::Modifiers::empty(),
+               key: Key::Virtual(win::VirtualKey::F13),
+               then: None,
+            },
+         ]
+      );
+   }
+
+   #[test]
+   fn rejects_none_in_hotkey_list() {
+      let config: &[u8] = b"pop_keybinding = Control + Shift + C, None";
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::NoneInHotkeyList,
+               span,
+               ..
+            }] => assert_eq!(*span, 38..42),
+            x => panic!("expected none in list error, got {:?}", x),
+         },
+         x => panic!("expected none in list error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn reports_bad_element_of_hotkey_list() {
+      let config: &[u8] = b"pop_keybinding = Control + C, Shfit + V";
+      let rendered = parse_config(config, None).unwrap_err().to_string();
+      assert_eq!(
+         rendered,
+         "Error at line 1: In hotkey 2 of the list: Unknown modifier `shfit`
+  pop_keybinding = Control + C, Shfit + V
+                                ^^^^^"
+      );
+   }
+
+   #[test]
+   fn parses_comma_key_in_hotkey_list() {
+      let hotkey = |modifiers, key| Hotkey {
+         modifiers,
+         key,
+         then: None,
+      };
+      let parsed = parse_config(&b"pop_keybinding = Control + ,"[..], None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![hotkey(win::Modifiers::CONTROL, Key::Character(','))]
+      );
+      let parsed = parse_config(&b"pop_keybinding = Control + ,, Alt-,,Control + -, -"[..], None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![
+            hotkey(win::Modifiers::CONTROL, Key::Character(',')),
+            hotkey(win::Modifiers::ALT, Key::Character(',')),
+            hotkey(win::Modifiers::CONTROL, Key::Virtual(win::VirtualKey::Minus)),
+            hotkey(win::Modifiers::empty(), Key::Virtual(win::VirtualKey::Minus)),
+         ]
+      );
+      // A comma after a whole hotkey still ends it
+      assert!(parse_config(&b"pop_keybinding = Control + C,, Alt + V"[..], None).is_err());
+   }
+
+   #[test]
+   fn parses_indexed_pop_modifiers() {
+      let config: &[u8] = b"indexed_pop_modifiers = Control + Shift";
+      let parsed = parse_config(config, None).unwrap();
+      assert_eq!(
+         parsed.config.indexed_pop_modifiers,
+         Some(win::Modifiers::CONTROL | win::Modifiers::SHIFT)
+      );
+      assert!(parsed.warnings.is_empty());
+   }
+
+   #[test]
+   fn rejects_key_in_indexed_pop_modifiers() {
+      let config: &[u8] = b"indexed_pop_modifiers = Control + 1";
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::UnknownModifier(got),
+               ..
+            }] => assert_eq!(got, "1"),
+            x => panic!("expected unknown modifier, got {:?}", x),
+         },
+         x => panic!("expected unknown modifier, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn rejects_unterminated_string() {
+      let config: &[u8] = br#"log_file = "C:\ripclip.log\""#;
+      match parse_config(config, None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::UnterminatedString,
+               location: Location { line: 0, .. },
+               ..
+            }] => (),
+            x => panic!("expected unterminated string, got {:?}", x),
+         },
+         x => panic!("expected unterminated string, got {:?}", x),
+      }
+   }
+
+   fn parse_test_file(path: &Path) -> Result<ParsedConfig, ParseError> {
+      parse_config(BufReader::new(File::open(path).unwrap()), Some(path))
+   }
+
+   #[test]
+   fn applies_included_file() {
+      let dir = write_test_files(
+         "include",
+         &[
+            (
+               "ripclip.conf",
+               "max_stack_size = 5\ninclude = extra.conf\nshow_tray_icon = false",
+            ),
+            ("extra.conf", "max_stack_size = 7\npop_keybinding = f13"),
+         ],
+      );
+      let config = parse_test_file(&dir.join("ripclip.conf")).unwrap().config;
+      assert_eq!(config.max_stack_size, Some(7));
+      assert!(!config.show_tray_icon);
+      assert_eq!(
+         config.pop_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::empty(),
+            key: Key::Virtual(win::VirtualKey::F13),
+            then: None,
+         }]
+      );
+      fs::remove_dir_all(dir).unwrap();
+   }
+
+   #[test]
+   fn watches_included_files() {
+      let dir = write_test_files(
+         "include-watch",
+         &[
+            ("ripclip.conf", "include = extra.conf"),
+            ("extra.conf", "max_stack_size = 7"),
+         ],
+      );
+      let (_, files) = load_config_from(&dir.join("ripclip.conf")).unwrap();
+      assert_eq!(files, [dir.join("ripclip.conf"), dir.join("extra.conf")]);
+      let mut watcher = ConfigWatcher::new(files);
+      assert!(!watcher.poll());
+      let extra = File::options().write(true).open(dir.join("extra.conf")).unwrap();
+      extra.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+      // Reported once it's stopped changing
+      assert!(!watcher.poll());
+      assert!(watcher.poll());
+      assert!(!watcher.poll());
+      fs::remove_dir_all(dir).unwrap();
+   }
+
+   #[test]
+   fn reports_errors_in_included_file() {
+      let dir = write_test_files(
+         "include-error",
+         &[
+            ("ripclip.conf", "\ninclude = extra.conf"),
+            ("extra.conf", "max_stack_size = 7\nshow_tray_icon = yes"),
+         ],
+      );
+      match parse_test_file(&dir.join("ripclip.conf")) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::ExpectedBool(_),
+               location,
+               ..
+            }] => {
+               assert_eq!(location.file, Some(dir.join("extra.conf")));
+               assert_eq!(location.line, 1);
+            }
+            x => panic!("expected bool error, got {:?}", x),
+         },
+         x => panic!("expected bool error, got {:?}", x),
+      }
+      fs::remove_dir_all(dir).unwrap();
+   }
+
+   #[test]
+   fn detects_include_cycle() {
+      let dir = write_test_files(
+         "include-cycle",
+         &[
+            ("ripclip.conf", "include = extra.conf"),
+            ("extra.conf", "include = ripclip.conf"),
+         ],
+      );
+      match parse_test_file(&dir.join("ripclip.conf")) {
+         Err(ParseError::IncludeCycle { including, included }) => {
+            assert_eq!(including, dir.join("extra.conf"));
+            assert_eq!(included, dir.join("ripclip.conf"));
+         }
+         x => panic!("expected include cycle, got {:?}", x),
+      }
+      fs::remove_dir_all(dir).unwrap();
+   }
+
+   fn render_errors(config: &[u8]) -> String {
+      parse_config(config, None).unwrap_err().to_string()
+   }
+
+   #[test]
+   fn points_at_bad_modifier() {
+      assert_eq!(
+         render_errors(b"pop_keybinding = Control + Shfit + C"),
+         "Error at line 1: Unknown modifier `shfit`
+  pop_keybinding = Control + Shfit + C
+                             ^^^^^"
+      );
+   }
+
+   #[test]
+   fn points_at_bad_key() {
+      assert_eq!(
+         render_errors(b"  swap_keybinding=alt+ctrl+ Kay "),
+         "Error at line 1: Unknown key `kay`
+  swap_keybinding=alt+ctrl+ Kay
+                            ^^^"
+      );
+   }
+
+   #[test]
+   fn points_at_malformed_line() {
+      assert_eq!(
+         render_errors(b"max_stack_size 100"),
+         "Error at line 1: Line must be an option, followed by an equals sign, followed by a value.
+  max_stack_size 100
+  ^^^^^^^^^^^^^^^^^^"
+      );
+   }
+
+   #[test]
+   fn points_at_unknown_option() {
+      assert_eq!(
+         render_errors(b"max_stak_size = 100"),
+         "Error at line 1: Unknown option `max_stak_size`
+  max_stak_size = 100
+  ^^^^^^^^^^^^^"
+      );
+   }
+
+   fn warnings(config: &[u8]) -> Vec<ConfigWarning> {
+      parse_config(config, None)
+         .unwrap()
+         .warnings
+         .into_iter()
+         .map(|(warning, _)| warning)
+         .collect()
+   }
+
+   #[test]
+   fn default_config_has_no_warnings() {
+      assert_eq!(warnings(&written(&Config::default())), vec![]);
+   }
+
+   #[test]
+   fn warns_on_modifier_in_key_position() {
+      assert_eq!(
+         warnings(b"pop_keybinding = control + shift"),
+         vec![ConfigWarning::ModifierInKeyPosition("shift".into())]
+      );
+   }
+
+   #[test]
+   fn warns_on_hotkey_without_modifiers() {
+      assert_eq!(
+         warnings(b"swap_keybinding = Q"),
+         vec![ConfigWarning::NoModifiers("q".into())]
+      );
+      assert_eq!(
+         warnings(b"swap_keybinding = Shift"),
+         vec![
+            ConfigWarning::ModifierInKeyPosition("shift".into()),
+            ConfigWarning::NoModifiers("shift".into())
+         ]
+      );
+   }
+
+   #[test]
+   fn parses_extended_function_keys_without_warning() {
+      let parsed = parse_config(&b"pop_keybinding = f13\nswap_keybinding = F24"[..], None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::empty(),
+            key: Key::Virtual(win::VirtualKey::F13),
+            then: None,
+         }]
+      );
+      assert_eq!(
+         parsed.config.swap_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::empty(),
+            key: Key::Virtual(win::VirtualKey::F24),
+            then: None,
+         }]
+      );
+      assert!(parsed.warnings.is_empty());
+   }
+
+   #[test]
+   fn parses_numpad_keys() {
+      let keys = [
+         ("numpad0", win::VirtualKey::NumpadZero),
+         ("Numpad 7", win::VirtualKey::NumpadSeven),
+         ("numpad_plus", win::VirtualKey::NumpadAdd),
+         ("NumpadAdd", win::VirtualKey::NumpadAdd),
+         ("numpad_minus", win::VirtualKey::NumpadSubtract),
+         ("numpad *", win::VirtualKey::NumpadMultiply),
+         ("numpad/", win::VirtualKey::NumpadDivide),
+         ("numpad_decimal", win::VirtualKey::NumpadDecimal),
+      ];
+      for (raw_key, key) in keys.iter() {
+         let config = format!("pop_keybinding = Control + {}", raw_key);
+         let parsed = parse_config(config.as_bytes(), None).unwrap();
+         assert_eq!(
+            parsed.config.pop_keybinding,
+            vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL,
+               key: Key::Virtual(*key),
+               then: None,
+            }]
+         );
+         assert!(parsed.warnings.is_empty());
+         assert!(!key.is_modifier());
+         // What's written must read back as the same key
+         assert_eq!(
+            key.to_string().to_ascii_lowercase().parse::<win::VirtualKey>().ok(),
+            Some(*key)
+         );
+      }
+      assert_ne!(win::VirtualKey::NumpadZero as u8, win::VirtualKey::Zero as u8);
+   }
+
+   #[test]
+   fn parses_media_keys_without_warning() {
+      let parsed = parse_config(
+         &b"pop_keybinding = launch_app2\nswap_keybinding = Media Play Pause"[..],
+         None,
+      )
+      .unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::empty(),
+            key: Key::Virtual(win::VirtualKey::LaunchApp2),
+            then: None,
+         }]
+      );
+      assert_eq!(
+         parsed.config.swap_keybinding,
+         vec![Hotkey {
+            modifiers: win::Modifiers::empty(),
+            key: Key::Virtual(win::VirtualKey::MediaPlayPause),
+            then: None,
+         }]
+      );
+      assert!(parsed.warnings.is_empty());
+      assert_eq!(win::VirtualKey::BrowserBack as u8, 0xA6);
+      assert_eq!(win::VirtualKey::LaunchApp2 as u8, 0xB7);
+   }
+
+   #[test]
+   fn parses_mouse_buttons() {
+      let parsed = parse_config(&b"pop_keybinding = Control + mouse_x1, Mouse Middle"[..], None).unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![
+            Hotkey {
+               modifiers: win::Modifiers::CONTROL,
+               key: Key::Virtual(win::VirtualKey::XButton1),
+               then: None,
+            },
+            Hotkey {
+               modifiers: win::Modifiers::empty(),
+               key: Key::Virtual(win::VirtualKey::MiddleClick),
+               then: None,
+            },
+         ]
+      );
+      assert_eq!(win::VirtualKey::XButton2 as u8, 0x06);
+   }
+
+   #[test]
+   fn rejects_duplicate_modifier() {
+      let rendered = parse_config(&b"pop_keybinding = shift + shift + a"[..], None)
+         .unwrap_err()
+         .to_string();
+      assert_eq!(
+         rendered,
+         "Error at line 1: `shift` is already part of this hotkey
+  pop_keybinding = shift + shift + a
+                           ^^^^^"
+      );
+      match parse_config(&b"pop_keybinding = ctrl + Control + a"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::DuplicateModifier(got),
+               ..
+            }] => assert_eq!(got, "control"),
+            x => panic!("expected duplicate modifier, got {:?}", x),
+         },
+         x => panic!("expected duplicate modifier, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn rejects_key_repeated_as_modifier() {
+      for config in [
+         &b"pop_keybinding = control + c + c"[..],
+         &b"pop_keybinding = shift + control + shift"[..],
+      ]
+      .iter()
+      {
+         match parse_config(*config, None) {
+            Err(ParseError::Lines(errors)) => match &errors[..] {
+               [BadLine {
+                  error: LineError::DuplicateModifier(_),
+                  ..
+               }] => (),
+               x => panic!("expected duplicate modifier, got {:?}", x),
+            },
+            x => panic!("expected duplicate modifier, got {:?}", x),
+         }
+      }
+   }
+
+   #[test]
+   fn accepts_dash_as_separator() {
+      let control_shift_c = vec![Hotkey {
+         modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+         key: Key::Virtual(win::VirtualKey::C),
+         then: None,
+      }];
+      for config in [
+         &b"pop_keybinding = ctrl-shift-c"[..],
+         &b"pop_keybinding = Control - Shift + C"[..],
+         &b"pop_keybinding = ctrl+shift-c"[..],
+      ]
+      .iter()
+      {
+         assert_eq!(
+            parse_config(*config, None).unwrap().config.pop_keybinding,
+            control_shift_c
+         );
+      }
+   }
+
+   #[test]
+   fn parses_separator_as_key() {
+      let control_minus = vec![Hotkey {
+         modifiers: win::Modifiers::CONTROL,
+         key: Key::Virtual(win::VirtualKey::Minus),
+         then: None,
+      }];
+      for config in [
+         &b"pop_keybinding = ctrl+-"[..],
+         &b"pop_keybinding = Control - -"[..],
+         &b"pop_keybinding = Control + Minus"[..],
+      ]
+      .iter()
+      {
+         assert_eq!(
+            parse_config(*config, None).unwrap().config.pop_keybinding,
+            control_minus
+         );
+      }
+      assert_eq!(
+         warnings(b"pop_keybinding = -"),
+         vec![ConfigWarning::NoModifiers("-".into())]
+      );
+   }
+
+   #[test]
+   fn no_repeat_is_not_a_modifier() {
+      assert!(Config::default().hotkey_no_repeat);
+      assert!(parse_config(&b"pop_keybinding = norepeat + c"[..], None).is_err());
+      let hotkey = Hotkey {
+         modifiers: win::Modifiers::CONTROL | win::Modifiers::NO_REPEAT,
+         key: Key::Virtual(win::VirtualKey::C),
+         then: None,
+      };
+      assert_eq!(hotkey.to_string(), "Control + C");
+   }
+
+   #[test]
+   fn parses_dedupe_options() {
+      let config = parse_config(&b"dedupe_scope = Stack\ndedupe_action = promote"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.dedupe_scope, DedupeScope::Stack);
+      assert_eq!(config.dedupe_action, DedupeAction::Promote);
+      assert!(!config.prevent_duplicate_push);
+      match parse_config(&b"dedupe_scope = everything"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::ExpectedDedupeScope(got) => assert_eq!(got, "everything"),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_stack_full_policy() {
+      assert_eq!(
+         parse_config(&b""[..], None).unwrap().config.stack_full_policy,
+         StackFullPolicy::DropOldest
+      );
+      let config = parse_config(&b"stack_full_policy = Reject_New"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.stack_full_policy, StackFullPolicy::RejectNew);
+      match parse_config(&b"stack_full_policy = drop_newest"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::ExpectedStackFullPolicy(got) => assert_eq!(got, "drop_newest"),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_mode() {
+      assert_eq!(parse_config(&b""[..], None).unwrap().config.mode, Mode::Stack);
+      let config = parse_config(&b"mode = Queue"[..], None).unwrap().config;
+      assert_eq!(config.mode, Mode::Queue);
+      match parse_config(&b"mode = deque"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::ExpectedMode(got) => assert_eq!(got, "deque"),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_swap_behavior() {
+      assert_eq!(
+         parse_config(&b""[..], None).unwrap().config.swap_behavior,
+         SwapBehavior::TopTwo
+      );
+      let config = parse_config(&b"swap_behavior = Clipboard"[..], None).unwrap().config;
+      assert_eq!(config.swap_behavior, SwapBehavior::Clipboard);
+      match parse_config(&b"swap_behavior = top_three"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[0].error {
+            LineError::ExpectedSwapBehavior(got) => assert_eq!(got, "top_three"),
+            e => panic!("Unexpected error {:?}", e),
+         },
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_paste_keystroke() {
+      let config = parse_config(&b"paste_keystroke = Shift + Insert"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(
+         config.paste_keystroke,
+         Hotkey {
+            key: Key::Virtual(win::VirtualKey::Insert),
+            modifiers: win::Modifiers::SHIFT,
+            then: None,
+         }
+      );
+      for value in &["None", "Control + K then V"] {
+         match parse_config(format!("paste_keystroke = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => assert!(matches!(errors[0].error, LineError::ExpectedKeystroke)),
+            x => panic!("Expected an error, got {:?}", x),
+         }
+      }
+   }
+
+   #[test]
+   fn parses_max_stack_memory() {
+      let memory = |value: &str| {
+         parse_config(format!("max_stack_memory = {}", value).as_bytes(), None)
+            .unwrap()
+            .config
+            .max_stack_memory
+      };
+      assert_eq!(parse_config(&b""[..], None).unwrap().config.max_stack_memory, None);
+      assert_eq!(memory("1000"), Some(1000));
+      assert_eq!(memory("1000b"), Some(1000));
+      assert_eq!(memory("64MB"), Some(64 * 1024 * 1024));
+      assert_eq!(memory("64 mb"), Some(64 * 1024 * 1024));
+      assert_eq!(memory("512kb"), Some(512 * 1024));
+      assert_eq!(memory("2gb"), Some(2 * 1024 * 1024 * 1024));
+      assert_eq!(memory("unlimited"), None);
+      for (bad, error) in [("64tb", "64tb"), ("mb", "mb"), ("-1kb", "-1kb")] {
+         match parse_config(format!("max_stack_memory = {}", bad).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::ExpectedByteSize(got) => assert_eq!(got, error),
+               e => panic!("Unexpected error {:?}", e),
+            },
+            x => panic!("Expected an error, got {:?}", x),
+         }
+      }
+      assert!(matches!(
+         parse_config(&b"max_stack_memory = 0kb"[..], None),
+         Err(ParseError::Lines(errors)) if matches!(errors[0].error, LineError::ZeroStackMemory)
+      ));
+      assert_eq!(byte_size_value(64 * 1024 * 1024), "64MB");
+      assert_eq!(byte_size_value(1536), "1536");
+   }
+
+   #[test]
+   fn parses_undo_clear_timeout() {
+      let timeout = |value: &str| {
+         parse_config(format!("undo_clear_timeout = {}", value).as_bytes(), None)
+            .unwrap()
+            .config
+            .undo_clear_timeout
+      };
+      assert_eq!(
+         parse_config(&b""[..], None).unwrap().config.undo_clear_timeout,
+         Some(Duration::from_secs(5 * 60))
+      );
+      assert_eq!(timeout("90s"), Some(Duration::from_secs(90)));
+      assert_eq!(timeout("0m"), Some(Duration::ZERO));
+      assert_eq!(timeout("none"), None);
+      assert!(parse_config(&b"undo_clear_timeout = 5"[..], None).is_err());
+   }
+
+   #[test]
+   fn parses_max_entry_age() {
+      let age = |value: &str| {
+         parse_config(format!("max_entry_age = {}", value).as_bytes(), None)
+            .unwrap()
+            .config
+            .max_entry_age
+      };
+      assert_eq!(parse_config(&b""[..], None).unwrap().config.max_entry_age, None);
+      assert_eq!(age("45s"), Some(Duration::from_secs(45)));
+      assert_eq!(age("30m"), Some(Duration::from_secs(30 * 60)));
+      assert_eq!(age("8H"), Some(Duration::from_secs(8 * 60 * 60)));
+      assert_eq!(age("2 d"), Some(Duration::from_secs(2 * 24 * 60 * 60)));
+      assert_eq!(age("None"), None);
+      for bad in ["30", "m", "1.5h", "-5m", "10w", "99999999999999999999d"] {
+         match parse_config(format!("max_entry_age = {}", bad).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::ExpectedDuration(got) => assert_eq!(got, bad),
+               e => panic!("Unexpected error {:?}", e),
+            },
+            x => panic!("Expected an error, got {:?}", x),
+         }
+      }
+      assert!(matches!(
+         parse_config(&b"max_entry_age = 0h"[..], None),
+         Err(ParseError::Lines(errors)) if matches!(errors[0].error, LineError::ZeroEntryAge)
+      ));
+      assert_eq!(duration_value(Duration::from_secs(90 * 60)), "90m");
+      assert_eq!(duration_value(Duration::from_secs(48 * 60 * 60)), "2d");
+      assert_eq!(duration_value(Duration::from_secs(61)), "61s");
+   }
+
+   #[test]
+   fn parses_image_size_options() {
+      let config = parse_config(&b""[..], None).unwrap().config;
+      assert_eq!(config.max_image_pixels, None);
+      assert_eq!(config.image_oversize_action, ImageOversizeAction::Skip);
+      let config = parse_config(
+         &b"max_image_pixels = 2073600\nimage_oversize_action = Downscale"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(config.max_image_pixels, Some(2073600));
+      assert_eq!(config.image_oversize_action, ImageOversizeAction::Downscale);
+      match parse_config(&b"max_image_pixels = 0\nimage_oversize_action = truncate"[..], None) {
+         Err(ParseError::Lines(errors)) => {
+            assert!(matches!(errors[0].error, LineError::ZeroImagePixels));
+            match &errors[1].error {
+               LineError::ExpectedImageOversizeAction(got) => assert_eq!(got, "truncate"),
+               e => panic!("Unexpected error {:?}", e),
+            }
+         }
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn parses_item_size_options() {
+      let config = parse_config(&b""[..], None).unwrap().config;
+      assert_eq!(config.max_item_size, None);
+      assert_eq!(config.oversize_action, OversizeAction::Skip);
+      let config = parse_config(&b"max_item_size = 1048576\noversize_action = Truncate"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.max_item_size, Some(1048576));
+      assert_eq!(config.oversize_action, OversizeAction::Truncate);
+      let config = parse_config(&b"max_item_size = 5\nmax_item_size = unlimited"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(config.max_item_size, None);
+      match parse_config(&b"max_item_size = 0\noversize_action = shrink"[..], None) {
+         Err(ParseError::Lines(errors)) => {
+            assert!(matches!(errors[0].error, LineError::ZeroItemSize));
+            match &errors[1].error {
+               LineError::ExpectedOversizeAction(got) => assert_eq!(got, "shrink"),
+               e => panic!("Unexpected error {:?}", e),
+            }
+         }
+         x => panic!("Expected an error, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn persist_stack() {
+      assert!(!Config::default().persist_stack);
+      assert!(Config::default().persist_encrypted);
+      let config = parse_config(&b"persist_stack = true\npersist_encrypted = false"[..], None)
+         .unwrap()
+         .config;
+      assert!(config.persist_stack);
+      assert!(!config.persist_encrypted);
+   }
+
+   #[test]
+   fn use_keyboard_hook() {
+      assert!(!Config::default().use_keyboard_hook);
+      let config = parse_config(&b"use_keyboard_hook = True"[..], None).unwrap().config;
+      assert!(config.use_keyboard_hook);
+   }
+
+   #[test]
+   fn rejects_conflicting_hotkeys() {
+      let config = parse_config(
+         &b"pop_keybinding = control + shift + c\nclear_keybinding = shift + ctrl + c"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      let error = config.validate().unwrap_err();
+      assert_eq!(
+         error,
+         ValidationError::ConflictingHotkeys {
+            hotkey: Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::C),
+               then: None,
+            },
+            first: "pop_keybinding",
+            second: "clear_keybinding",
+         }
+      );
+      assert_eq!(
+         error.to_string(),
+         "`pop_keybinding` and `clear_keybinding` both have Control + Shift + C; each hotkey can only do one thing"
+      );
+   }
+
+   #[test]
+   fn parses_sequences() {
+      let parsed = parse_config(
+         &b"pop_keybinding = Control + Shift + R then P, F13\nswap_keybinding = ctrl-shift-r then s"[..],
+         None,
+      )
+      .unwrap();
+      let leader = Hotkey {
+         modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+         key: Key::Virtual(win::VirtualKey::R),
+         then: None,
+      };
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![
+            Hotkey {
+               then: Some(win::VirtualKey::P),
+               ..leader
+            },
+            Hotkey {
+               modifiers: win::Modifiers::empty(),
+               key: Key::Virtual(win::VirtualKey::F13),
+               then: None,
+            },
+         ]
+      );
+      assert_eq!(parsed.config.pop_keybinding[0].leader(), leader);
+      assert_eq!(
+         parsed.config.pop_keybinding[0].to_string(),
+         "Control + Shift + R then P"
+      );
+      // Sequences can share a first step, so long as the second steps differ
+      assert_eq!(parsed.config.validate(), Ok(()));
+      // The second step is meant to be pressed on its own
+      assert!(parsed.warnings.is_empty());
+   }
+
+   #[test]
+   fn rejects_conflicting_sequences() {
+      let config = parse_config(
+         &b"pop_keybinding = control + shift + r then p\nswap_keybinding = control + shift + r then p"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(
+         config.validate().unwrap_err().to_string(),
+         "`pop_keybinding` and `swap_keybinding` both have Control + Shift + R then P; each hotkey can only do one thing"
+      );
+      let config = parse_config(
+         &b"pop_keybinding = control + shift + r\nswap_keybinding = control + shift + r then s"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(
+         config.validate().unwrap_err().to_string(),
+         "`pop_keybinding` and `swap_keybinding` both have Control + Shift + R then S; each hotkey can only do one thing"
+      );
+   }
+
+   #[test]
+   fn rejects_bad_sequence_keys() {
+      for value in &[
+         "control + r then shift + p",
+         "control + r then escape",
+         "control + r then shift",
+      ] {
+         match parse_config(format!("pop_keybinding = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::BadSequenceKey(_) => (),
+               e => panic!("Unexpected error {:?} for {}", e, value),
+            },
+            _ => panic!("Expected {} to be rejected", value),
+         }
+      }
+      for value in &["none then p", "control + r then none"] {
+         match parse_config(format!("pop_keybinding = {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::NoneInSequence => (),
+               e => panic!("Unexpected error {:?} for {}", e, value),
+            },
+            _ => panic!("Expected {} to be rejected", value),
+         }
+      }
+   }
+
+   #[test]
+   fn parses_scancodes() {
+      let parsed = parse_config(
+         &b"pop_keybinding = Control + Shift + scancode:0x2E, Control + scancode:c"[..],
+         None,
+      )
+      .unwrap();
+      assert_eq!(
+         parsed.config.pop_keybinding,
+         vec![
+            Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Scancode(0x2E),
+               then: None,
+            },
+            Hotkey {
+               modifiers: win::Modifiers::CONTROL,
+               key: Key::Scancode(0x2E),
+               then: None,
+            },
+         ]
+      );
+      assert_eq!(
+         parsed.config.pop_keybinding[0].to_string(),
+         "Control + Shift + Scancode:0x2E"
+      );
+      assert_eq!(
+         Config {
+            pop_keybinding: vec![parsed.config.pop_keybinding[0]],
+            ..Config::default()
+         },
+         parse_config(&b"pop_keybinding = Control + Shift + Scancode:0x2E"[..], None)
+            .unwrap()
+            .config
+      );
+      for value in &[
+         "scancode:",
+         "scancode:0x0",
+         "scancode:0xfffff",
+         "scancode:46",
+         "scancode:c1",
+      ] {
+         match parse_config(format!("pop_keybinding = Control + {}", value).as_bytes(), None) {
+            Err(ParseError::Lines(errors)) => match &errors[0].error {
+               LineError::BadScancode(_) => (),
+               e => panic!("Unexpected error {:?} for {}", e, value),
+            },
+            _ => panic!("Expected {} to be rejected", value),
+         }
+      }
+   }
+
+   /// A keyboard layout with only the keys it's given
+   struct TestLayout {
+      scancodes: Vec<(u16, win::VirtualKey)>,
+      characters: Vec<(char, win::VirtualKey, win::Modifiers)>,
+   }
+
+   impl KeyboardLayout for TestLayout {
+      fn scancode_key(&self, scancode: u16) -> Option<u32> {
+         self.scancodes.iter().find(|x| x.0 == scancode).map(|x| x.1 as u32)
+      }
+
+      fn character_key(&self, c: char) -> Option<(u32, win::Modifiers)> {
+         self.characters.iter().find(|x| x.0 == c).map(|x| (x.1 as u32, x.2))
+      }
+   }
+
+   #[test]
+   fn layout_mapping_follows_layout() {
+      let config = parse_config(
+         &b"pop_keybinding = Control + scancode:c\nswap_keybinding = Control + X, Control + scancode:0x10"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      let qwerty = TestLayout {
+         scancodes: vec![(0x2E, win::VirtualKey::C), (0x10, win::VirtualKey::Q)],
+         characters: Vec::new(),
+      };
+      let azerty = TestLayout {
+         scancodes: vec![(0x2E, win::VirtualKey::C), (0x10, win::VirtualKey::A)],
+         characters: Vec::new(),
+      };
+      let mapping = config.layout_mapping(&qwerty);
+      assert_eq!(
+         mapping,
+         vec![
+            Ok((win::Modifiers::CONTROL, win::VirtualKey::C as u32)),
+            Ok((win::Modifiers::CONTROL, win::VirtualKey::Q as u32))
+         ]
+      );
+      // Switching layouts moves Q, so the hotkeys need registering again
+      assert_ne!(config.layout_mapping(&azerty), mapping);
+      assert_eq!(config.layout_mapping(&qwerty), mapping);
+      // Without keys that depend on the layout, the layout doesn't matter
+      assert!(Config::default().layout_mapping(&azerty).is_empty());
+      assert_eq!(Key::Scancode(0x39).resolve(&qwerty), Err(ResolveError::NotOnLayout));
+      assert_eq!(
+         Key::Virtual(win::VirtualKey::X).resolve(&qwerty),
+         Ok((win::VirtualKey::X as u32, win::Modifiers::empty()))
+      );
+   }
+
+   #[test]
+   fn parses_characters_through_layout() {
+      let parsed = parse_config(
+         "pop_keybinding = Control + é\nswap_keybinding = Control + ! ".as_bytes(),
+         None,
+      )
+      .unwrap();
+      assert_eq!(parsed.config.pop_keybinding[0].key, Key::Character('é'));
+      assert_eq!(parsed.config.pop_keybinding[0].to_string(), "Control + é");
+      assert_eq!(parsed.config.swap_keybinding[0].key, Key::Character('!'));
+      // Letters and numbers keep meaning the same virtual key whatever the layout
+      let parsed = parse_config(&b"pop_keybinding = Control + Q"[..], None).unwrap();
+      assert_eq!(parsed.config.pop_keybinding[0].key, Key::Virtual(win::VirtualKey::Q));
+      assert!(parse_config(&b"pop_keybinding = Control + ab"[..], None).is_err());
+
+      let azerty = TestLayout {
+         scancodes: Vec::new(),
+         characters: vec![
+            ('é', win::VirtualKey::Two, win::Modifiers::empty()),
+            ('!', win::VirtualKey::Eight, win::Modifiers::empty()),
+            ('~', win::VirtualKey::Two, win::Modifiers::CONTROL | win::Modifiers::ALT),
+         ],
+      };
+      let us = TestLayout {
+         scancodes: Vec::new(),
+         characters: vec![('!', win::VirtualKey::One, win::Modifiers::SHIFT)],
+      };
+      let hotkey = |key| Hotkey {
+         modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+         key: Key::Character(key),
+         then: None,
+      };
+      assert_eq!(
+         hotkey('!').resolve(&azerty),
+         Ok((
+            win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+            win::VirtualKey::Eight as u32
+         ))
+      );
+      // The shift needed for the character merges with the shift already given
+      assert_eq!(
+         hotkey('!').resolve(&us),
+         Ok((
+            win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+            win::VirtualKey::One as u32
+         ))
+      );
+      assert_eq!(hotkey('é').resolve(&us), Err(ResolveError::NotOnLayout));
+      assert_eq!(hotkey('~').resolve(&azerty), Err(ResolveError::NeedsAltGr));
+   }
+
+   #[test]
+   fn rejects_hotkey_repeated_in_list() {
+      let config = parse_config(&b"swap_keybinding = F13, Control + F14, F13"[..], None)
+         .unwrap()
+         .config;
+      assert_eq!(
+         config.validate().unwrap_err().to_string(),
+         "`swap_keybinding` has F13 more than once"
+      );
+   }
+
+   #[test]
+   fn rejects_hotkey_conflicting_with_indexed_pop() {
+      let config = parse_config(
+         &b"indexed_pop_modifiers = control\nswap_keybinding = control + 3"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      match config.validate() {
+         Err(ValidationError::ConflictingHotkeys { first, second, .. }) => {
+            assert_eq!((first, second), ("swap_keybinding", "indexed_pop_modifiers"))
+         }
+         x => panic!("expected conflict, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn accepts_distinct_hotkeys() {
+      let config = parse_config(
+         &b"pop_keybinding = control + shift + c\nclear_keybinding = control + alt + c\nswap_keybinding = control + shift + v"[..],
+         None,
+      )
+      .unwrap()
+      .config;
+      assert_eq!(config.validate(), Ok(()));
+      assert_eq!(Config::default().validate(), Ok(()));
+   }
+
+   #[test]
+   fn rejects_f25() {
+      match parse_config(&b"pop_keybinding = f25"[..], None) {
+         Err(ParseError::Lines(errors)) => match &errors[..] {
+            [BadLine {
+               error: LineError::UnknownKey(got),
+               ..
+            }] => assert_eq!(got, "f25"),
+            x => panic!("expected unknown key, got {:?}", x),
+         },
+         x => panic!("expected unknown key, got {:?}", x),
+      }
+   }
+
+   #[test]
+   fn rejects_zero_stack_size() {
+      assert_eq!(
+         render_errors(b"max_stack_size = 0"),
+         "Error at line 1: A max_stack_size of 0 would keep nothing on the stack. Use `none` or `unlimited` for no limit
+  max_stack_size = 0
+                   ^"
+      );
+   }
+
+   #[test]
+   fn parses_unlimited_stack_size() {
+      let parsed_cfg = parse_config(&b"max_stack_size = Unlimited"[..], None).unwrap().config;
+      assert_eq!(parsed_cfg.max_stack_size, None);
+   }
+
+   #[test]
+   fn displays_hotkeys() {
+      let hotkey = Hotkey {
+         modifiers: win::Modifiers::SHIFT | win::Modifiers::CONTROL,
+         key: Key::Virtual(win::VirtualKey::C),
+         then: None,
+      };
+      assert_eq!(hotkey.to_string(), "Control + Shift + C");
+      let hotkey = Hotkey {
+         modifiers: win::Modifiers::empty(),
+         key: Key::Virtual(win::VirtualKey::NumpadSeven),
+         then: None,
+      };
+      assert_eq!(hotkey.to_string(), "Numpad 7");
+   }
+
+   #[test]
+   fn round_trips_configs() {
+      let configs = vec![
+         Config::default(),
+         Config {
+            mode: Mode::Queue,
+            max_stack_size: None,
+            stack_full_policy: StackFullPolicy::RejectNew,
+            max_stack_memory: Some(64 * 1024 * 1024),
+            max_entry_age: Some(Duration::from_secs(8 * 60 * 60)),
+            max_item_size: Some(1024),
+            oversize_action: OversizeAction::Truncate,
+            normalize_line_endings: LineEndings::Crlf,
+            transform: vec![
+               parse_transform(r"/^mailto:(\S+)/$1/").unwrap(),
+               parse_transform(r"/a\/b\\/\\\//").unwrap(),
+            ],
+            capture_images: false,
+            max_image_pixels: Some(1920 * 1080),
+            image_oversize_action: ImageOversizeAction::Downscale,
+            capture_html: false,
+            capture_rtf: true,
+            capture_formats: vec![Format::Id(49161), Format::Name(String::from("XML Spreadsheet"))],
+            plain_text_only: true,
+            delayed_render: true,
+            respect_exclusion_formats: false,
+            ignore_remote_clipboard: true,
+            ignored_applications: vec![
+               IgnoredApplication::new("keepass.exe"),
+               IgnoredApplication::new(r"c:\program files\bank\*"),
+            ],
+            persist_stack: true,
+            persist_encrypted: false,
+            clear_on_lock: true,
+            clear_clipboard_on_lock: true,
+            pause_in_fullscreen: true,
+            pause_hotkeys_in_fullscreen: true,
+            show_tray_icon: false,
+            tray_menu_entries: 3,
+            tray_tooltip_preview: false,
+            tray_badge: false,
+            pop_keybinding: Vec::new(),
+            clear_keybinding: vec![
+               Hotkey {
+                  modifiers: win::Modifiers::WIN | win::Modifiers::ALT,
+                  key: Key::Virtual(win::VirtualKey::PageDown),
+                  then: None,
+               },
+               Hotkey {
+                  modifiers: win::Modifiers::CONTROL,
+                  key: Key::Character(','),
+                  then: None,
+               },
+               Hotkey {
+                  modifiers: win::Modifiers::CONTROL,
+                  key: Key::Virtual(win::VirtualKey::PageUp),
+                  then: None,
+               },
+            ],
+            pop_removes_entry: false,
+            paste_after_pop: true,
+            paste_keystroke: Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::V),
+               then: None,
+            },
+            swap_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::empty(),
+               key: Key::Virtual(win::VirtualKey::F13),
+               then: None,
+            }],
+            swap_behavior: SwapBehavior::Clipboard,
+            push_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::V),
+               then: None,
+            }],
+            peek_keybinding: Vec::new(),
+            pause_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::Pause),
+               then: None,
+            }],
+            rotate_keybinding: Vec::new(),
+            drop_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::Delete),
+               then: None,
+            }],
+            pop_all_keybinding: Vec::new(),
+            join_separator: String::from("\t\"\\n\\"),
+            join_order: JoinOrder::TopFirst,
+            duplicate_top_keybinding: Vec::new(),
+            undo_pop_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::SHIFT,
+               key: Key::Virtual(win::VirtualKey::Z),
+               then: None,
+            }],
+            show_stack_keybinding: Vec::new(),
+            reverse_stack_keybinding: Vec::new(),
+            move_to_bottom_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::End),
+               then: None,
+            }],
+            copy_all_keybinding: Vec::new(),
+            copy_all_max_bytes: 1,
+            save_snapshot_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::S),
+               then: None,
+            }],
+            restore_snapshot_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::R),
+               then: None,
+            }],
+            indexed_pop_modifiers: Some(win::Modifiers::CONTROL | win::Modifiers::WIN),
+            paste_second_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::V),
+               then: None,
+            }],
+            export_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::E),
+               then: None,
+            }],
+            export_path: Some(PathBuf::from(r#"C:\Users\Me\Exports\stack.json"#)),
+            import_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::I),
+               then: None,
+            }],
+            import_path: Some(PathBuf::from("stack.json")),
+            import_append: true,
+            history_file: Some(PathBuf::from(r#"D:\History\ripclip.jsonl"#)),
+            history_max_bytes: None,
+            pin_top_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::P),
+               then: None,
+            }],
+            undo_clear_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT,
+               key: Key::Virtual(win::VirtualKey::U),
+               then: None,
+            }],
+            undo_clear_timeout: None,
+            hotkey_no_repeat: false,
+            use_keyboard_hook: true,
+            prevent_duplicate_push: true,
+            dedupe_scope: DedupeScope::Stack,
+            dedupe_action: DedupeAction::Promote,
+            dedupe_trim_whitespace: true,
+            dedupe_ignore_case: true,
+            dedupe_formatting: true,
+            duplicate_window_ms: 250,
+            capture_debounce_ms: 0,
+            clipboard_retry_ms: 1000,
+            log_level: LevelFilter::Trace,
+            log_file: Some(PathBuf::from(r#"C:\Users\Me\"Logs"\ripclip.log"#)),
+            log_skipped_updates: true,
+         },
+         Config {
+            max_stack_size: Some(1),
+            pop_keybinding: vec![Hotkey {
+               modifiers: win::Modifiers::CONTROL | win::Modifiers::ALT | win::Modifiers::SHIFT | win::Modifiers::WIN,
+               key: Key::Virtual(win::VirtualKey::Zero),
+               then: None,
+            }],
+            log_level: LevelFilter::Error,
+            log_file: Some(PathBuf::from("ripclip.log")),
+            ..Config::default()
+         },
+      ];
+      for config in configs {
+         assert_eq!(parse_config(&written(&config)[..], None).unwrap().config, config);
+      }
    }
 }
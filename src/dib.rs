@@ -0,0 +1,497 @@
+//! Reading the device-independent bitmaps (DIBs) that images are copied as: a bitmap header, followed by the color
+//! table and pixels. Images are saved and exported as PNG, and turned back into DIBs when they're read back.
+
+use std::convert::{TryFrom, TryInto};
+
+/// The size of a BITMAPCOREHEADER, which keeps its dimensions in 16 bits
+const CORE_HEADER_SIZE: u32 = 12;
+/// The size of a BITMAPINFOHEADER. Later headers start the same way, with more fields after.
+const INFO_HEADER_SIZE: u32 = 40;
+/// Compression meaning the pixels aren't compressed
+const BI_RGB: u32 = 0;
+/// Compression meaning the pixels aren't compressed, with masks saying where each color is in a pixel. A
+/// BITMAPINFOHEADER is followed by the masks, while later headers hold them.
+const BI_BITFIELDS: u32 = 3;
+/// The masks of 32 bit BI_BITFIELDS pixels laid out like BI_RGB ones: red, green, then blue
+const RGB_MASKS: (u32, u32, u32) = (0xFF_0000, 0xFF00, 0xFF);
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn read_u16(dib: &[u8], at: usize) -> Option<u16> {
+   Some(u16::from_le_bytes(dib.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(dib: &[u8], at: usize) -> Option<u32> {
+   Some(u32::from_le_bytes(dib.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The width and height of the image in `dib`, or None if it doesn't start with a bitmap header
+pub fn dimensions(dib: &[u8]) -> Option<(u32, u32)> {
+   let header_size = read_u32(dib, 0)?;
+   if header_size as usize > dib.len() {
+      return None;
+   }
+   match header_size {
+      CORE_HEADER_SIZE => Some((u32::from(read_u16(dib, 4)?), u32::from(read_u16(dib, 6)?))),
+      size if size >= INFO_HEADER_SIZE => {
+         let width = read_u32(dib, 4)? as i32;
+         // Images stored top row first have a negative height
+         let height = read_u32(dib, 8)? as i32;
+         if width < 0 {
+            return None;
+         }
+         Some((width as u32, height.unsigned_abs()))
+      }
+      _ => None,
+   }
+}
+
+/// Where the pixels of an uncompressed 24 or 32 bit image are, which are the kinds `downscale` can read
+struct Pixels {
+   width: usize,
+   height: usize,
+   bytes_per_pixel: usize,
+   /// Where the pixels start in the DIB
+   offset: usize,
+}
+
+impl Pixels {
+   /// How many bytes each row of pixels takes, as rows are padded to a multiple of 4 bytes
+   fn stride(&self) -> usize {
+      (self.width * self.bytes_per_pixel).div_ceil(4) * 4
+   }
+
+   /// Where the first byte of a pixel is in the DIB, counting rows in the order they're stored
+   fn at(&self, x: usize, row: usize) -> usize {
+      self.offset + row * self.stride() + x * self.bytes_per_pixel
+   }
+}
+
+fn pixels(dib: &[u8]) -> Option<Pixels> {
+   let header_size = read_u32(dib, 0)?;
+   if header_size < INFO_HEADER_SIZE {
+      return None;
+   }
+   let (width, height) = dimensions(dib)?;
+   let bit_count = read_u16(dib, 14)?;
+   let masks = match (read_u32(dib, 16)?, bit_count) {
+      (BI_RGB, 24) | (BI_RGB, 32) => 0,
+      (BI_BITFIELDS, 32) if header_size == INFO_HEADER_SIZE => 12,
+      (BI_BITFIELDS, 32) => 0,
+      _ => return None,
+   };
+   let colors_used = read_u32(dib, 32)? as usize;
+   let pixels = Pixels {
+      width: width as usize,
+      height: height as usize,
+      bytes_per_pixel: usize::from(bit_count / 8),
+      offset: header_size as usize + masks + colors_used.checked_mul(4)?,
+   };
+   let end = pixels.stride().checked_mul(pixels.height)?.checked_add(pixels.offset)?;
+   if end > dib.len() {
+      return None;
+   }
+   Some(pixels)
+}
+
+/// Shrinks the image in `dib` to at most `max_pixels` pixels, keeping its aspect ratio. Each pixel of the smaller
+/// image is the average of the block of pixels it replaces. Returns None if the image already fits, or isn't an
+/// uncompressed 24 or 32 bit image, which is how copied images almost always come.
+pub fn downscale(dib: &[u8], max_pixels: usize) -> Option<Vec<u8>> {
+   let from = pixels(dib)?;
+   let pixel_count = from.width.checked_mul(from.height)?;
+   if pixel_count <= max_pixels {
+      return None;
+   }
+   let scale = (max_pixels as f64 / pixel_count as f64).sqrt();
+   let mut width = ((from.width as f64 * scale) as usize).max(1);
+   let mut height = ((from.height as f64 * scale) as usize).max(1);
+   // Rounding can leave it a pixel or so too large
+   while width * height > max_pixels && width * height > 1 {
+      if width >= height {
+         width -= 1;
+      } else {
+         height -= 1;
+      }
+   }
+   let to = Pixels {
+      width,
+      height,
+      bytes_per_pixel: from.bytes_per_pixel,
+      offset: from.offset,
+   };
+
+   // The header, masks and color table stay as they were, but for the size. Images stored top row first keep their
+   // negative height.
+   let mut scaled = dib[..from.offset].to_vec();
+   let top_down = (read_u32(dib, 8)? as i32) < 0;
+   let stored_height = if top_down { -(height as i32) } else { height as i32 };
+   scaled[4..8].copy_from_slice(&(width as i32).to_le_bytes());
+   scaled[8..12].copy_from_slice(&stored_height.to_le_bytes());
+   scaled[20..24].copy_from_slice(&((to.stride() * height) as u32).to_le_bytes());
+   scaled.resize(from.offset + to.stride() * height, 0);
+
+   for row in 0..height {
+      let rows = row * from.height / height..(row + 1) * from.height / height;
+      for x in 0..width {
+         let columns = x * from.width / width..(x + 1) * from.width / width;
+         let count = rows.len() * columns.len();
+         for channel in 0..from.bytes_per_pixel {
+            let sum: usize = rows
+               .clone()
+               .flat_map(|y| columns.clone().map(move |x| (x, y)))
+               .map(|(x, y)| usize::from(dib[from.at(x, y) + channel]))
+               .sum();
+            scaled[to.at(x, row) + channel] = ((sum + count / 2) / count) as u8;
+         }
+      }
+   }
+   Some(scaled)
+}
+
+/// The pixels of an image as 8 bit RGBA, top row first, which is how PNG holds them
+#[derive(Debug, PartialEq)]
+pub struct Rgba {
+   pub width: usize,
+   pub height: usize,
+   pub pixels: Vec<u8>,
+   /// Whether any pixel is less than fully opaque
+   pub has_alpha: bool,
+}
+
+/// The pixels of the image in `dib`, or None if it isn't one `downscale` can read or its colors aren't laid out as
+/// blue, green and red bytes. The fourth byte of 32 bit pixels is taken to be alpha, unless it's 0 throughout, which
+/// is how apps that don't use alpha leave it.
+pub fn to_rgba(dib: &[u8]) -> Option<Rgba> {
+   let from = pixels(dib)?;
+   if read_u32(dib, 16)? == BI_BITFIELDS && (read_u32(dib, 40)?, read_u32(dib, 44)?, read_u32(dib, 48)?) != RGB_MASKS {
+      return None;
+   }
+   let top_down = (read_u32(dib, 8)? as i32) < 0;
+   let mut pixels = Vec::with_capacity(from.width * from.height * 4);
+   for y in 0..from.height {
+      let row = if top_down { y } else { from.height - 1 - y };
+      for x in 0..from.width {
+         let pixel = &dib[from.at(x, row)..from.at(x, row) + from.bytes_per_pixel];
+         pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], *pixel.get(3).unwrap_or(&0xFF)]);
+      }
+   }
+   if from.bytes_per_pixel == 4 && pixels.iter().skip(3).step_by(4).all(|x| *x == 0) {
+      pixels.iter_mut().skip(3).step_by(4).for_each(|x| *x = 0xFF);
+   }
+   let has_alpha = pixels.iter().skip(3).step_by(4).any(|x| *x != 0xFF);
+   Some(Rgba {
+      width: from.width,
+      height: from.height,
+      pixels,
+      has_alpha,
+   })
+}
+
+/// Writes `image` as a DIB, bottom row first: 32 bit if it has alpha, otherwise 24 bit
+pub fn from_rgba(image: &Rgba) -> Vec<u8> {
+   let bit_count: u16 = if image.has_alpha { 32 } else { 24 };
+   let to = Pixels {
+      width: image.width,
+      height: image.height,
+      bytes_per_pixel: usize::from(bit_count / 8),
+      offset: INFO_HEADER_SIZE as usize,
+   };
+   let mut dib = Vec::with_capacity(to.offset + to.stride() * to.height);
+   dib.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+   dib.extend_from_slice(&(image.width as i32).to_le_bytes());
+   dib.extend_from_slice(&(image.height as i32).to_le_bytes());
+   dib.extend_from_slice(&1u16.to_le_bytes());
+   dib.extend_from_slice(&bit_count.to_le_bytes());
+   dib.extend_from_slice(&BI_RGB.to_le_bytes());
+   dib.extend_from_slice(&((to.stride() * to.height) as u32).to_le_bytes());
+   dib.resize(to.offset, 0);
+   for y in (0..image.height).rev() {
+      let start = dib.len();
+      for pixel in image.pixels[y * image.width * 4..(y + 1) * image.width * 4].chunks_exact(4) {
+         dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]][..to.bytes_per_pixel]);
+      }
+      dib.resize(start + to.stride(), 0);
+   }
+   dib
+}
+
+/// Whether `data` is a PNG rather than a DIB, going by its first bytes
+pub fn is_png(data: &[u8]) -> bool {
+   data.starts_with(PNG_SIGNATURE)
+}
+
+/// Encodes the image in `dib` as PNG, so that images in saved stacks and exports take a fraction of the space of the
+/// bitmaps they're copied as. Gives None if it's empty or `to_rgba` can't read it.
+pub fn to_png(dib: &[u8]) -> Option<Vec<u8>> {
+   let image = to_rgba(dib)?;
+   if image.width == 0 || image.height == 0 {
+      return None;
+   }
+   let (color, pixels) = if image.has_alpha {
+      (png::ColorType::Rgba, image.pixels)
+   } else {
+      let rgb = image.pixels.chunks_exact(4).flat_map(|x| &x[..3]).copied().collect();
+      (png::ColorType::Rgb, rgb)
+   };
+   let mut encoded = Vec::new();
+   let mut encoder = png::Encoder::new(
+      &mut encoded,
+      u32::try_from(image.width).ok()?,
+      u32::try_from(image.height).ok()?,
+   );
+   encoder.set_color(color);
+   encoder.set_depth(png::BitDepth::Eight);
+   // Picks the filter for each row, which suits screenshots and photos alike
+   encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+   let mut writer = encoder.write_header().ok()?;
+   writer.write_image_data(&pixels).ok()?;
+   writer.finish().ok()?;
+   Some(encoded)
+}
+
+/// Decodes a PNG back to a DIB, as `from_rgba` writes it. Returns None if `data` is damaged.
+pub fn from_png(data: &[u8]) -> Option<Vec<u8>> {
+   // Images are decoded whole, however large they were when copied
+   let mut decoder = png::Decoder::new_with_limits(data, png::Limits { bytes: usize::MAX });
+   decoder.set_transformations(png::Transformations::normalize_to_color8());
+   let mut reader = decoder.read_info().ok()?;
+   let mut decoded = vec![0; reader.output_buffer_size()];
+   let info = reader.next_frame(&mut decoded).ok()?;
+   decoded.truncate(info.buffer_size());
+   let expand = |channels: usize, pixel: fn(&[u8]) -> [u8; 4]| {
+      let mut pixels = Vec::with_capacity(decoded.len() / channels * 4);
+      decoded
+         .chunks_exact(channels)
+         .for_each(|x| pixels.extend_from_slice(&pixel(x)));
+      pixels
+   };
+   let pixels = match info.color_type {
+      png::ColorType::Rgba => decoded,
+      png::ColorType::Rgb => expand(3, |x| [x[0], x[1], x[2], 0xFF]),
+      png::ColorType::GrayscaleAlpha => expand(2, |x| [x[0], x[0], x[0], x[1]]),
+      png::ColorType::Grayscale => expand(1, |x| [x[0], x[0], x[0], 0xFF]),
+      // Expanded to RGB by `normalize_to_color8`
+      png::ColorType::Indexed => return None,
+   };
+   let has_alpha = pixels.iter().skip(3).step_by(4).any(|x| *x != 0xFF);
+   Some(from_rgba(&Rgba {
+      width: info.width as usize,
+      height: info.height as usize,
+      pixels,
+      has_alpha,
+   }))
+}
+
+/// A description of the image in `dib` for previews, like "1920×1080 image"
+pub fn describe(dib: &[u8]) -> String {
+   match dimensions(dib) {
+      Some((width, height)) => format!("{}×{} image", width, height),
+      None => String::from("Image"),
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   fn info_header(width: i32, height: i32) -> Vec<u8> {
+      let mut dib = Vec::new();
+      dib.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+      dib.extend_from_slice(&width.to_le_bytes());
+      dib.extend_from_slice(&height.to_le_bytes());
+      dib.resize(INFO_HEADER_SIZE as usize, 0);
+      dib
+   }
+
+   #[test]
+   fn reads_dimensions() {
+      assert_eq!(dimensions(&info_header(1920, 1080)), Some((1920, 1080)));
+      assert_eq!(describe(&info_header(1920, 1080)), "1920×1080 image");
+      // Top row first
+      assert_eq!(dimensions(&info_header(640, -480)), Some((640, 480)));
+      let mut core = CORE_HEADER_SIZE.to_le_bytes().to_vec();
+      core.extend_from_slice(&[32, 0, 16, 0, 1, 0, 24, 0]);
+      assert_eq!(dimensions(&core), Some((32, 16)));
+   }
+
+   /// An uncompressed image with the given pixels, each row padded with 0xEE bytes, which `downscale` must ignore
+   fn image(bit_count: u16, compression: u32, masks: &[u8], height: i32, rows: &[&[&[u8]]]) -> Vec<u8> {
+      let mut dib = info_header(rows[0].len() as i32, height);
+      dib[14..16].copy_from_slice(&bit_count.to_le_bytes());
+      dib[16..20].copy_from_slice(&compression.to_le_bytes());
+      dib.extend_from_slice(masks);
+      for row in rows {
+         let start = dib.len();
+         for pixel in row.iter() {
+            dib.extend_from_slice(pixel);
+         }
+         let len = dib.len() - start;
+         dib.resize(start + len.div_ceil(4) * 4, 0xEE);
+      }
+      dib
+   }
+
+   #[test]
+   fn downscales_24_bit_images() {
+      let (a, b, c, d) = (
+         &[0, 0, 0][..],
+         &[8, 16, 24][..],
+         &[100, 100, 100][..],
+         &[200, 0, 50][..],
+      );
+      let dib = image(24, BI_RGB, &[], 2, &[&[a, b, c, c], &[b, a, d, d]]);
+      let scaled = downscale(&dib, 2).unwrap();
+      assert_eq!(dimensions(&scaled), Some((2, 1)));
+      // Each pixel is the average of a 2×2 block, rounded, and the row is padded to 8 bytes
+      assert_eq!(&scaled[40..], &[4, 8, 12, 150, 50, 75, 0, 0]);
+      assert_eq!(read_u32(&scaled, 20), Some(8));
+   }
+
+   #[test]
+   fn downscales_odd_widths() {
+      // Rows of 5 pixels take 15 bytes, padded to 16
+      let (a, b) = (&[10, 20, 30][..], &[200, 210, 220][..]);
+      let row: &[&[u8]] = &[a, a, b, b, b];
+      let dib = image(24, BI_RGB, &[], 3, &[row, row, row]);
+      assert_eq!(dib.len(), 40 + 3 * 16);
+      let scaled = downscale(&dib, 4).unwrap();
+      assert_eq!(dimensions(&scaled), Some((2, 1)));
+      assert_eq!(&scaled[40..], &[10, 20, 30, 200, 210, 220, 0, 0]);
+      // An odd width after scaling is padded too
+      let row: &[&[u8]] = &[a; 6];
+      let scaled = downscale(&image(24, BI_RGB, &[], 2, &[row, row]), 3).unwrap();
+      assert_eq!(dimensions(&scaled), Some((3, 1)));
+      assert_eq!(&scaled[40..], &[10, 20, 30, 10, 20, 30, 10, 20, 30, 0, 0, 0]);
+   }
+
+   #[test]
+   fn downscales_32_bit_images() {
+      // Top row first, with alpha
+      let (a, b) = (&[0, 0, 255, 255][..], &[255, 0, 0, 0][..]);
+      let dib = image(32, BI_RGB, &[], -2, &[&[a, b], &[b, a]]);
+      let scaled = downscale(&dib, 1).unwrap();
+      assert_eq!(read_u32(&scaled, 8), Some(-1i32 as u32));
+      assert_eq!(dimensions(&scaled), Some((1, 1)));
+      assert_eq!(&scaled[40..], &[128, 0, 128, 128]);
+      // The masks after the header are kept
+      let masks = [0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0];
+      let dib = image(32, BI_BITFIELDS, &masks, 1, &[&[a, a, b, b]]);
+      let scaled = downscale(&dib, 2).unwrap();
+      assert_eq!(dimensions(&scaled), Some((2, 1)));
+      assert_eq!(&scaled[40..52], &masks);
+      assert_eq!(&scaled[52..], &[0, 0, 255, 255, 255, 0, 0, 0]);
+   }
+
+   #[test]
+   fn downscales_only_what_it_can() {
+      let pixel = &[1, 2, 3][..];
+      let dib = image(24, BI_RGB, &[], 2, &[&[pixel, pixel], &[pixel, pixel]]);
+      // Already small enough
+      assert_eq!(downscale(&dib, 4), None);
+      // Pixels cut short
+      assert_eq!(downscale(&dib[..dib.len() - 1], 1), None);
+      // Palette images and compressed images aren't read
+      assert_eq!(downscale(&image(8, BI_RGB, &[], 1, &[&[&[0], &[1]]]), 1), None);
+      assert_eq!(
+         downscale(&image(24, 1, &[], 2, &[&[pixel, pixel], &[pixel, pixel]]), 1),
+         None
+      );
+      let mut core = CORE_HEADER_SIZE.to_le_bytes().to_vec();
+      core.extend_from_slice(&[2, 0, 1, 0, 1, 0, 24, 0, 1, 2, 3, 1, 2, 3, 0, 0]);
+      assert_eq!(downscale(&core, 1), None);
+   }
+
+   #[test]
+   fn converts_to_rgba() {
+      // Bottom row first, in blue, green, red order
+      let (a, b) = (&[1, 2, 3][..], &[4, 5, 6][..]);
+      let rgba = to_rgba(&image(24, BI_RGB, &[], 2, &[&[a, a], &[b, b]])).unwrap();
+      assert_eq!((rgba.width, rgba.height, rgba.has_alpha), (2, 2, false));
+      assert_eq!(rgba.pixels, [6, 5, 4, 255, 6, 5, 4, 255, 3, 2, 1, 255, 3, 2, 1, 255]);
+      let dib = from_rgba(&rgba);
+      assert_eq!(to_rgba(&dib), Some(rgba));
+      assert_eq!(read_u16(&dib, 14), Some(24));
+      // 32 bit pixels whose alpha is all 0 don't use it
+      let (a, b) = (&[1, 2, 3, 0][..], &[4, 5, 6, 0][..]);
+      let rgba = to_rgba(&image(32, BI_RGB, &[], -1, &[&[a, b]])).unwrap();
+      assert_eq!(rgba.pixels, [3, 2, 1, 255, 6, 5, 4, 255]);
+      assert!(!rgba.has_alpha);
+      let (a, b) = (&[1, 2, 3, 0][..], &[4, 5, 6, 128][..]);
+      let rgba = to_rgba(&image(32, BI_RGB, &[], -1, &[&[a, b]])).unwrap();
+      assert_eq!(rgba.pixels, [3, 2, 1, 0, 6, 5, 4, 128]);
+      assert!(rgba.has_alpha);
+      let dib = from_rgba(&rgba);
+      assert_eq!(read_u16(&dib, 14), Some(32));
+      assert_eq!(to_rgba(&dib), Some(rgba));
+      // Masks that put the colors anywhere else aren't read
+      let masks = [0xFF, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0];
+      assert_eq!(to_rgba(&image(32, BI_BITFIELDS, &masks, 1, &[&[a]])), None);
+      let masks = [0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0xFF, 0, 0, 0];
+      assert!(to_rgba(&image(32, BI_BITFIELDS, &masks, 1, &[&[a]])).is_some());
+   }
+
+   /// An image with smooth gradients, some noise and, if `alpha` is set, some transparency
+   fn gradient(width: usize, height: usize, alpha: bool) -> Rgba {
+      let mut pixels = Vec::new();
+      for y in 0..height {
+         for x in 0..width {
+            let noise = ((x * 7919 + y * 104_729) % 251) as u8;
+            let a = if alpha { (x * 255 / width) as u8 } else { 0xFF };
+            pixels.extend_from_slice(&[(x * 4) as u8, (y * 3) as u8, noise, a]);
+         }
+      }
+      Rgba {
+         width,
+         height,
+         pixels,
+         has_alpha: alpha,
+      }
+   }
+
+   #[test]
+   fn round_trips_png() {
+      for (width, height, alpha) in [(1, 1, false), (5, 3, false), (333, 17, false), (40, 30, true)].iter() {
+         let dib = from_rgba(&gradient(*width, *height, *alpha));
+         let png = to_png(&dib).unwrap();
+         assert!(is_png(&png));
+         assert!(!is_png(&dib));
+         assert_eq!(from_png(&png).unwrap(), dib);
+      }
+      // A bottom-up 24 bit image, with padding at the end of each row
+      let (a, b) = (&[1, 2, 3][..], &[4, 5, 6][..]);
+      let dib = image(24, BI_RGB, &[], 2, &[&[a, b, a], &[b, a, b]]);
+      assert_eq!(to_rgba(&from_png(&to_png(&dib).unwrap()).unwrap()), to_rgba(&dib));
+      // A flat screenshot takes far less space
+      let flat = from_rgba(&Rgba {
+         width: 800,
+         height: 600,
+         pixels: [0x20, 0x40, 0x60, 0xFF].repeat(800 * 600),
+         has_alpha: false,
+      });
+      let png = to_png(&flat).unwrap();
+      assert!(png.len() < flat.len() / 100);
+      assert_eq!(from_png(&png).unwrap(), flat);
+      // Images that can't be read are left as they are
+      assert_eq!(to_png(b"not an image"), None);
+      assert_eq!(to_png(&from_rgba(&gradient(0, 0, false))), None);
+   }
+
+   #[test]
+   fn rejects_damaged_pngs() {
+      let png = to_png(&from_rgba(&gradient(20, 10, false))).unwrap();
+      assert_eq!(from_png(&png[..png.len() / 2]), None);
+      assert_eq!(from_png(&png[1..]), None);
+      assert_eq!(from_png(PNG_SIGNATURE), None);
+      assert_eq!(from_png(b"GIF89a"), None);
+   }
+
+   #[test]
+   fn rejects_other_data() {
+      assert_eq!(dimensions(&[]), None);
+      assert_eq!(dimensions(b"not a bitmap"), None);
+      // The header is cut short
+      assert_eq!(dimensions(&info_header(10, 10)[..20]), None);
+      assert_eq!(dimensions(&info_header(-10, 10)), None);
+      assert_eq!(describe(b"xx"), "Image");
+   }
+}
@@ -0,0 +1,512 @@
+//! What the stack holds for each copy: the text, files or image that was copied, along with any other clipboard
+//! formats kept from the same copy, such as the HTML a browser copies alongside its text
+
+use crate::config::Config;
+use crate::stack::{Augment, Formats, Kind, Size, Text};
+use crate::win::{ClipboardEntry, ClipboardFormat};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The name browsers and Office register CF_HTML under
+pub const HTML_FORMAT: &str = "HTML Format";
+/// The name Word, Outlook and WordPad register Rich Text Format under
+pub const RTF_FORMAT: &str = "Rich Text Format";
+
+/// Formats that password managers and Windows place alongside a copy to say it isn't to be kept, such as when copying
+/// a password. Clipboard monitors are to ignore a copy with the first whatever it holds; the others can also say
+/// that keeping the copy is fine, and mark it as sensitive only when they hold 0.
+pub const PRIVACY_FORMATS: [&str; 3] = [
+   "ExcludeClipboardContentFromMonitorProcessing",
+   "CanIncludeInClipboardHistory",
+   "CanUploadToCloudClipboard",
+];
+
+/// The format ripclip places alongside everything it writes to the clipboard, so that the updates its own writes cause
+/// aren't taken for copies
+pub const MARKER_FORMAT: &str = "RipclipMarker";
+
+/// A clipboard format, either one of the standard formats by its id, or a registered format by its name. Registered
+/// formats get a different id each time Windows starts, so they're kept by name. Windows ignores case in names, and
+/// so do comparisons between formats.
+#[derive(Clone, Debug)]
+pub enum Format {
+   Id(u32),
+   Name(String),
+}
+
+impl PartialEq for Format {
+   fn eq(&self, other: &Format) -> bool {
+      match (self, other) {
+         (Format::Id(a), Format::Id(b)) => a == b,
+         (Format::Name(a), Format::Name(b)) => a.eq_ignore_ascii_case(b),
+         _ => false,
+      }
+   }
+}
+
+impl Eq for Format {}
+
+impl Hash for Format {
+   fn hash<H: Hasher>(&self, state: &mut H) {
+      match self {
+         Format::Id(id) => id.hash(state),
+         Format::Name(name) => name.to_ascii_lowercase().hash(state),
+      }
+   }
+}
+
+impl Format {
+   pub fn html() -> Format {
+      Format::Name(String::from(HTML_FORMAT))
+   }
+
+   pub fn rtf() -> Format {
+      Format::Name(String::from(RTF_FORMAT))
+   }
+
+   pub fn marker() -> Format {
+      Format::Name(String::from(MARKER_FORMAT))
+   }
+
+   pub fn is_html(&self) -> bool {
+      matches!(self, Format::Name(x) if x.eq_ignore_ascii_case(HTML_FORMAT))
+   }
+}
+
+impl FromStr for Format {
+   type Err = ();
+
+   /// Reads a format id, or otherwise a format name, which can't be empty
+   fn from_str(s: &str) -> Result<Format, ()> {
+      let s = s.trim();
+      if s.is_empty() {
+         return Err(());
+      }
+      Ok(s.parse().map_or_else(|_| Format::Name(String::from(s)), Format::Id))
+   }
+}
+
+impl fmt::Display for Format {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         Format::Id(id) => write!(f, "{}", id),
+         Format::Name(name) => write!(f, "{}", name),
+      }
+   }
+}
+
+/// A format kept from a copy besides its main contents, as it was on the clipboard
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extra {
+   pub format: Format,
+   pub data: Vec<u8>,
+}
+
+impl Extra {
+   /// The data as it's to be placed back on the clipboard. HTML has its header written anew, so that its offsets are
+   /// right however the data was read.
+   pub fn data_to_set(&self) -> Cow<'_, [u8]> {
+      if self.format.is_html() {
+         if let Some(html) = crate::html::Html::parse(&self.data) {
+            return Cow::Owned(html.to_cf_html());
+         }
+      }
+      Cow::Borrowed(&self.data)
+   }
+}
+
+/// What a copy is read as, going by the formats on the clipboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capture {
+   Files,
+   Text,
+   /// Text in the code page of the clipboard's locale, with no Unicode text alongside
+   AnsiText,
+   /// Text taken from Rich Text Format, with no plain text alongside
+   Rtf,
+   Image,
+}
+
+/// What to read a copy as, given the formats on the clipboard, or None if there's nothing to read, as when another
+/// app has emptied the clipboard. Some apps put the paths on the clipboard as text alongside files, but it's the files
+/// that paste. Likewise, text copied along with an image, such as from a spreadsheet, is what's usually wanted.
+pub fn capture(available: &[Format], config: &Config) -> Option<Capture> {
+   let has = |format: ClipboardFormat| available.contains(&Format::Id(format as u32));
+   if has(ClipboardFormat::Files) {
+      Some(Capture::Files)
+   } else if has(ClipboardFormat::UnicodeText) {
+      Some(Capture::Text)
+   } else if has(ClipboardFormat::Text) {
+      Some(Capture::AnsiText)
+   } else if config.capture_rtf && available.contains(&Format::rtf()) {
+      Some(Capture::Rtf)
+   } else if config.capture_images && (has(ClipboardFormat::Dib) || has(ClipboardFormat::Bitmap)) {
+      Some(Capture::Image)
+   } else {
+      None
+   }
+}
+
+/// Whether a copy is marked as sensitive, given those of `PRIVACY_FORMATS` that are on the clipboard with it
+pub fn is_sensitive(marks: &[Extra]) -> bool {
+   let exclude = Format::Name(String::from(PRIVACY_FORMATS[0]));
+   marks.iter().any(|mark| {
+      mark.format == exclude
+         || (PRIVACY_FORMATS[1..]
+            .iter()
+            .any(|x| mark.format == Format::Name(String::from(*x)))
+            && mark.data.iter().all(|x| *x == 0))
+   })
+}
+
+/// Everything kept from a single copy. The entry is always placed back on the clipboard whole, with all its formats,
+/// so moving it around the stack never mixes the formats of different copies.
+#[derive(Clone)]
+pub struct Entry {
+   contents: ClipboardEntry,
+   extras: Vec<Extra>,
+   /// A hash of the extras, so that entries can be told apart by them without comparing them
+   extras_hash: u64,
+}
+
+impl Entry {
+   pub fn new(contents: ClipboardEntry) -> Entry {
+      Entry::with_extras(contents, Vec::new())
+   }
+
+   pub fn with_extras(contents: ClipboardEntry, extras: Vec<Extra>) -> Entry {
+      Entry {
+         contents,
+         extras_hash: hash_extras(&extras),
+         extras,
+      }
+   }
+
+   /// The text, files or image that was copied
+   pub fn contents(&self) -> &ClipboardEntry {
+      &self.contents
+   }
+
+   /// The other formats kept from the copy, in the order they were read
+   pub fn extras(&self) -> &[Extra] {
+      &self.extras
+   }
+
+   /// A single line summary of the entry, as `ClipboardEntry::preview` gives
+   pub fn preview(&self, max_chars: usize) -> String {
+      self.contents.preview(max_chars)
+   }
+
+   /// A label for the entry in a menu: a single line preview of its text, of at most `max_chars` characters and an
+   /// ellipsis, or for files and images, what they are
+   pub fn menu_label(&self, max_chars: usize) -> String {
+      match &self.contents {
+         ClipboardEntry::Text(text) => crate::stack::menu_text(&crate::stack::preview(text.chars(), max_chars)),
+         ClipboardEntry::Files(files) => match files.paths().len() {
+            1 => String::from("[1 file]"),
+            count => format!("[{} files]", count),
+         },
+         ClipboardEntry::Image(image) => match crate::dib::dimensions(image.as_ref()) {
+            Some((width, height)) => format!("[image {}×{}]", width, height),
+            None => String::from("[image]"),
+         },
+      }
+   }
+
+   /// The entry's text, which for a file list is its paths, one per line
+   pub fn chars(&self) -> Box<dyn Iterator<Item = char> + '_> {
+      self.contents.chars()
+   }
+}
+
+fn hash_extras(extras: &[Extra]) -> u64 {
+   let mut hasher = DefaultHasher::new();
+   for extra in extras.iter() {
+      extra.format.hash(&mut hasher);
+      extra.data.hash(&mut hasher);
+   }
+   hasher.finish()
+}
+
+impl Augment for Entry {
+   /// The contents must be the same, as must every format the two have in common, and one must have all of the
+   /// other's formats. The same copy placed again with the very same formats isn't compared, as that's for
+   /// `duplicate_window_ms` to decide.
+   fn compare_formats(&self, other: &Entry) -> Option<Formats> {
+      if self.contents != other.contents {
+         return None;
+      }
+      let mut shared = 0;
+      let mut added_bytes = 0;
+      for extra in other.extras.iter() {
+         match self.extras.iter().find(|x| x.format == extra.format) {
+            Some(x) if x.data != extra.data => return None,
+            Some(_) => shared += 1,
+            None => added_bytes += extra.data.len(),
+         }
+      }
+      let has_all_of_self = shared == self.extras.len();
+      let has_more = shared < other.extras.len();
+      match (has_all_of_self, has_more) {
+         (true, true) => Some(Formats::More { added_bytes }),
+         (false, false) => Some(Formats::Fewer),
+         // Either the formats are the same, or each has some the other doesn't
+         _ => None,
+      }
+   }
+
+   fn augment(&mut self, other: &Entry) {
+      let missing: Vec<Extra> = other
+         .extras
+         .iter()
+         .filter(|extra| !self.extras.iter().any(|x| x.format == extra.format))
+         .cloned()
+         .collect();
+      self.extras.extend(missing);
+      self.extras_hash = hash_extras(&self.extras);
+   }
+}
+
+impl PartialEq for Entry {
+   /// Entries are the same if their contents are, whatever other formats they have. `dedupe_formatting` compares
+   /// those too, with `formatting_hash`.
+   fn eq(&self, other: &Entry) -> bool {
+      self.contents == other.contents
+   }
+}
+
+impl Size for Entry {
+   /// The size of the contents along with every other format
+   fn size(&self) -> usize {
+      self.contents.size() + self.extras.iter().map(|x| x.data.len()).sum::<usize>()
+   }
+}
+
+impl Text for Entry {
+   fn to_text(&self) -> String {
+      self.contents.to_text()
+   }
+
+   fn kind(&self) -> Kind {
+      self.contents.kind()
+   }
+
+   fn formatting_hash(&self) -> u64 {
+      self.extras_hash
+   }
+}
+
+impl AsRef<[u8]> for Entry {
+   fn as_ref(&self) -> &[u8] {
+      self.contents.as_ref()
+   }
+}
+
+impl crate::export::Saved for Entry {
+   /// What the contents are saved as, as `ClipboardEntry::saved_bytes` gives
+   fn saved_bytes(&self) -> &[u8] {
+      self.contents.saved_bytes()
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use crate::config;
+   use crate::stack::{self, augment_newest, Push, Stack};
+   use std::time::{Duration, SystemTime};
+
+   fn extra(format: &str, data: &[u8]) -> Extra {
+      Extra {
+         format: format.parse().unwrap(),
+         data: data.to_vec(),
+      }
+   }
+
+   #[test]
+   fn parses_formats() {
+      assert_eq!("49161".parse(), Ok(Format::Id(49161)));
+      assert_eq!(" HTML Format ".parse(), Ok(Format::html()));
+      assert_eq!("".parse::<Format>(), Err(()));
+      assert_eq!(Format::Name(String::from("html format")), Format::html());
+      assert!(Format::Name(String::from("html format")).is_html());
+      assert!(!Format::Id(13).is_html());
+      assert_ne!(Format::Id(13), Format::Name(String::from("13")));
+      assert_eq!(Format::rtf().to_string(), "Rich Text Format");
+   }
+
+   #[test]
+   fn counts_every_format() {
+      let entry = Entry::with_extras(
+         ClipboardEntry::from("hi"),
+         vec![extra("Rich Text Format", b"{\\rtf1 hi}"), extra("49161", b"xyz")],
+      );
+      assert_eq!(entry.size(), 4 + 10 + 3);
+      assert_eq!(entry.to_text(), "hi");
+      assert_eq!(entry.preview(10), "hi");
+      assert_eq!(entry.kind(), Kind::Text);
+   }
+
+   #[test]
+   fn tells_formatting_apart_by_hash() {
+      let plain = Entry::new(ClipboardEntry::from("hi"));
+      let bold = Entry::with_extras(ClipboardEntry::from("hi"), vec![extra("HTML Format", b"<b>hi</b>")]);
+      let italic = Entry::with_extras(ClipboardEntry::from("hi"), vec![extra("HTML Format", b"<i>hi</i>")]);
+      assert!(plain == bold && bold == italic);
+      assert_ne!(plain.formatting_hash(), bold.formatting_hash());
+      assert_ne!(bold.formatting_hash(), italic.formatting_hash());
+      assert_eq!(bold.formatting_hash(), bold.clone().formatting_hash());
+      assert!(Entry::new(ClipboardEntry::from("bye")) != plain);
+   }
+
+   #[test]
+   fn recognizes_sensitive_copies() {
+      assert!(!is_sensitive(&[]));
+      assert!(is_sensitive(&[extra(
+         "ExcludeClipboardContentFromMonitorProcessing",
+         b""
+      )]));
+      assert!(is_sensitive(&[
+         extra("HTML Format", b"<b>hi</b>"),
+         extra("excludeclipboardcontentfrommonitorprocessing", &1u32.to_le_bytes()),
+      ]));
+      // The others mark a copy only when they hold 0
+      assert!(is_sensitive(&[extra(
+         "CanIncludeInClipboardHistory",
+         &0u32.to_le_bytes()
+      )]));
+      assert!(is_sensitive(&[extra("CanUploadToCloudClipboard", &0u32.to_le_bytes())]));
+      assert!(!is_sensitive(&[extra(
+         "CanIncludeInClipboardHistory",
+         &1u32.to_le_bytes()
+      )]));
+      assert!(is_sensitive(&[
+         extra("CanIncludeInClipboardHistory", &1u32.to_le_bytes()),
+         extra("CanUploadToCloudClipboard", &0u32.to_le_bytes()),
+      ]));
+      assert!(!is_sensitive(&[extra("Rich Text Format", &0u32.to_le_bytes())]));
+   }
+
+   #[test]
+   fn decides_what_to_capture() {
+      let ids = |ids: &[u32]| ids.iter().map(|x| Format::Id(*x)).collect::<Vec<Format>>();
+      let mut config = Config::default();
+      // An emptied clipboard, or one with only formats that aren't read, has nothing to capture
+      assert_eq!(capture(&[], &config), None);
+      assert_eq!(capture(&[Format::html(), extra("49161", b"").format], &config), None);
+      assert_eq!(capture(&ids(&[13, 16, 1, 7]), &config), Some(Capture::Text));
+      assert_eq!(capture(&ids(&[1, 16]), &config), Some(Capture::AnsiText));
+      assert_eq!(capture(&ids(&[13, 15]), &config), Some(Capture::Files));
+      assert_eq!(capture(&ids(&[8, 13]), &config), Some(Capture::Text));
+      assert_eq!(capture(&ids(&[2, 8, 17]), &config), Some(Capture::Image));
+      assert_eq!(capture(&ids(&[2]), &config), Some(Capture::Image));
+      assert_eq!(capture(&[Format::rtf()], &config), None);
+      config.capture_rtf = true;
+      config.capture_images = false;
+      assert_eq!(capture(&[Format::rtf()], &config), Some(Capture::Rtf));
+      assert_eq!(capture(&ids(&[8]), &config), None);
+   }
+
+   #[test]
+   fn labels_entries_for_menus() {
+      let text = Entry::new(ClipboardEntry::from("Fish & chips\r\n\tto go, please"));
+      assert_eq!(text.menu_label(40), "Fish && chips  to go, please");
+      assert_eq!(text.menu_label(6), "Fish &&…");
+      assert_eq!(
+         Entry::new(ClipboardEntry::from("日本語のテキスト")).menu_label(3),
+         "日本語…"
+      );
+      let files = |paths: &str| {
+         Entry::new(ClipboardEntry::Files(
+            crate::win::FileList::from_paths(paths.lines()).unwrap(),
+         ))
+      };
+      assert_eq!(files("C:\\a.txt").menu_label(40), "[1 file]");
+      assert_eq!(files("C:\\a.txt\nC:\\b.txt\nC:\\c").menu_label(40), "[3 files]");
+      let mut dib = 40u32.to_le_bytes().to_vec();
+      dib.extend_from_slice(&800i32.to_le_bytes());
+      dib.extend_from_slice(&(-600i32).to_le_bytes());
+      dib.resize(40, 0);
+      let image = Entry::new(ClipboardEntry::Image(crate::win::Image::from_bytes(dib).unwrap()));
+      assert_eq!(image.menu_label(40), "[image 800×600]");
+   }
+
+   #[test]
+   fn augments_office_copies() {
+      let config = Config::default();
+      let text = || ClipboardEntry::from("quarterly results");
+      let html = extra("HTML Format", b"<b>quarterly results</b>");
+      let rtf = extra("Rich Text Format", b"{\\rtf1 quarterly results}");
+      let mut stack = Stack::default();
+      let start = SystemTime::now();
+      let at = |ms| start + Duration::from_millis(ms);
+      assert_eq!(augment_newest(&mut stack, &Entry::new(text()), at(0), &config), None);
+      stack::push_at(&mut stack, Entry::new(text()), at(0), &config);
+      // Office first places only the plain text, then clears the clipboard and places everything
+      let rich = Entry::with_extras(text(), vec![html.clone(), rtf.clone()]);
+      assert_eq!(
+         augment_newest(&mut stack, &rich, at(200), &config),
+         Some(Push::Augmented { on_top: true })
+      );
+      assert_eq!(stack.len(), 1);
+      assert_eq!(stack[0].extras(), [html.clone(), rtf.clone()]);
+      assert_eq!(stack[0].formatting_hash(), rich.formatting_hash());
+      assert_eq!(stack.bytes(), rich.size());
+      // A pass with fewer formats leaves the fuller entry as it is, which already holds it
+      let html_only = Entry::with_extras(text(), vec![html.clone()]);
+      let changes = stack.changes();
+      assert_eq!(
+         augment_newest(&mut stack, &html_only, at(400), &config),
+         Some(Push::DuplicateOnTop)
+      );
+      assert_eq!(stack[0].extras().len(), 2);
+      assert_eq!(stack.changes(), changes);
+      // The very same formats, different data in a format both have, or different text are copies of their own
+      assert_eq!(augment_newest(&mut stack, &rich, at(500), &config), None);
+      let other_html = Entry::with_extras(text(), vec![extra("HTML Format", b"<i>quarterly results</i>")]);
+      assert_eq!(augment_newest(&mut stack, &other_html, at(500), &config), None);
+      let other_text = Entry::with_extras(ClipboardEntry::from("annual results"), vec![html.clone(), rtf.clone()]);
+      assert_eq!(augment_newest(&mut stack, &other_text, at(500), &config), None);
+      // As is the same copy placed again much later
+      stack::push_at(&mut stack, Entry::new(ClipboardEntry::from("b")), at(2000), &config);
+      let b_rich = Entry::with_extras(ClipboardEntry::from("b"), vec![html]);
+      assert_eq!(augment_newest(&mut stack, &b_rich, at(3000), &config), None);
+   }
+
+   #[test]
+   fn augments_in_queue_mode() {
+      let config = Config::default();
+      let mut stack = Stack::default();
+      stack.set_mode(config::Mode::Queue);
+      let start = SystemTime::now();
+      stack::push_at(&mut stack, Entry::new(ClipboardEntry::from("a")), start, &config);
+      stack::push_at(&mut stack, Entry::new(ClipboardEntry::from("b")), start, &config);
+      let b_rich = Entry::with_extras(ClipboardEntry::from("b"), vec![extra("HTML Format", b"<b>b</b>")]);
+      assert_eq!(
+         augment_newest(&mut stack, &b_rich, start, &config),
+         Some(Push::Augmented { on_top: false })
+      );
+      // The newest copy is at the bottom of a queue
+      assert_eq!(stack[0].extras().len(), 1);
+      assert!(stack[1].extras().is_empty());
+   }
+
+   #[test]
+   fn rewrites_html_header() {
+      // Written by an app that doesn't give a version, which a header of ripclip's own replaces
+      let html = extra(
+         "HTML Format",
+         b"StartHTML:62\r\nEndHTML:73\r\nStartFragment:62\r\nEndFragment:71\r\n\r\n<b>hi</b>\r\n",
+      );
+      let set = html.data_to_set();
+      assert!(set.starts_with(b"Version:0.9\r\n"));
+      assert!(set.ends_with(b"\r\n<b>hi</b>\r\n\0"));
+      assert!(crate::html::Html::parse(&set) == crate::html::Html::parse(&html.data));
+      // Anything else is placed back as it was
+      let other = extra("49161", b"\0\x01");
+      assert_eq!(other.data_to_set(), Cow::Borrowed(&b"\0\x01"[..]));
+   }
+}
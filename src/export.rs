@@ -0,0 +1,669 @@
+//! Writing the stack out as JSON, for people and other programs to read, and reading it back in. Entries are written bottom of the stack
+//! first, each with its format and when it was copied. Text entries keep their text in `text`, and file lists their
+//! paths in `paths`; images keep their contents base64 encoded in `data`, as PNG unless they couldn't be encoded, in
+//! which case they're kept as the bitmaps they were copied as.
+
+use crate::dib;
+use crate::stack::{Kind, Text};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tells our exports apart from any other JSON
+pub const FORMAT: &str = "ripclip stack export";
+/// Bumped whenever the layout changes in a way readers of older exports wouldn't understand
+pub const VERSION: u32 = 1;
+/// What separates the paths in the text of a file list
+const PATH_SEPARATOR: &str = "\r\n";
+
+/// What an entry read from an export holds
+#[derive(Debug, PartialEq)]
+pub enum Contents {
+   Text(String),
+   /// The paths of a file list, one per line
+   Files(String),
+   /// An image as a device-independent bitmap, the way `CF_DIB` holds it
+   Image(Vec<u8>),
+}
+
+/// Entries as they're exported, which is in the form given by `as_ref`, except for images, which are exported as
+/// they're saved, as PNG unless they couldn't be encoded
+pub trait Saved: AsRef<[u8]> {
+   fn saved_bytes(&self) -> &[u8] {
+      self.as_ref()
+   }
+}
+
+/// A moment broken down into the calendar date and time of day, in UTC
+struct Civil {
+   year: u64,
+   month: u64,
+   day: u64,
+   hour: u64,
+   minute: u64,
+   second: u64,
+   millisecond: u32,
+}
+
+fn civil(time: SystemTime) -> Civil {
+   // Nothing on the stack was copied before 1970, so there's no need to handle it well
+   let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+   let seconds = since_epoch.as_secs();
+   // Counting from March makes the leap day the last day of the year, which keeps the arithmetic simple.
+   // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+   let days = seconds / 86_400 + 719_468;
+   let era = days / 146_097;
+   let day_of_era = days % 146_097;
+   let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+   let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+   let month_from_march = (5 * day_of_year + 2) / 153;
+   let month = if month_from_march < 10 {
+      month_from_march + 3
+   } else {
+      month_from_march - 9
+   };
+   Civil {
+      year: era * 400 + year_of_era + u64::from(month <= 2),
+      month,
+      day: day_of_year - (153 * month_from_march + 2) / 5 + 1,
+      hour: seconds % 86_400 / 3600,
+      minute: seconds % 3600 / 60,
+      second: seconds % 60,
+      millisecond: since_epoch.subsec_millis(),
+   }
+}
+
+/// Formats `time` like 2024-02-29T13:05:09.250Z
+pub fn rfc3339(time: SystemTime) -> String {
+   let x = civil(time);
+   format!(
+      "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+      x.year, x.month, x.day, x.hour, x.minute, x.second, x.millisecond
+   )
+}
+
+/// Reads a time written like 2024-02-29T13:05:09.250Z, or with an offset from UTC such as +01:00 in place of the Z.
+/// Returns None if it's written any other way, or is before 1970.
+pub fn parse_rfc3339(text: &str) -> Option<SystemTime> {
+   let number = |range: std::ops::Range<usize>| -> Option<u64> {
+      let digits = text.get(range)?;
+      if digits.bytes().all(|x| x.is_ascii_digit()) {
+         digits.parse().ok()
+      } else {
+         None
+      }
+   };
+   let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+   if text.len() < 20 || separators.iter().any(|(i, x)| text.as_bytes()[*i] != *x) {
+      return None;
+   }
+   if !matches!(text.as_bytes()[10], b'T' | b't') {
+      return None;
+   }
+   let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+   let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+   let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+   let days_in_month = match month {
+      2 if leap_year => 29,
+      2 => 28,
+      4 | 6 | 9 | 11 => 30,
+      1..=12 => 31,
+      _ => return None,
+   };
+   if day == 0 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+      return None;
+   }
+   let mut rest = &text[19..];
+   let mut nanoseconds = 0;
+   if let Some(fraction) = rest.strip_prefix('.') {
+      let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+      if digits == 0 {
+         return None;
+      }
+      // Anything finer than a nanosecond is dropped
+      for (i, digit) in fraction.bytes().take(digits.min(9)).enumerate() {
+         nanoseconds += u32::from(digit - b'0') * 10u32.pow(8 - i as u32);
+      }
+      rest = &fraction[digits..];
+   }
+   let offset = match rest.as_bytes() {
+      [b'Z' | b'z'] => 0,
+      [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+         let (hours, minutes) = (
+            number(text.len() - 5..text.len() - 3)?,
+            number(text.len() - 2..text.len())?,
+         );
+         if hours > 23 || minutes > 59 {
+            return None;
+         }
+         let offset = (hours * 3600 + minutes * 60) as i64;
+         if *sign == b'+' {
+            offset
+         } else {
+            -offset
+         }
+      }
+      _ => return None,
+   };
+   // The inverse of the arithmetic in `civil`.
+   // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+   let year_from_march = if month <= 2 { year - 1 } else { year };
+   let (era, year_of_era) = (year_from_march / 400, year_from_march % 400);
+   let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+   let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+   let days = (era * 146_097 + day_of_era) as i64 - 719_468;
+   let seconds = days * 86_400 + (hour * 3600 + minute * 60 + second) as i64 - offset;
+   let seconds = u64::try_from(seconds).ok()?;
+   Some(UNIX_EPOCH + Duration::new(seconds, nanoseconds))
+}
+
+/// The name an export made at `time` gets when `export_path` isn't set, like stack-export-20240229-130509.json.
+/// Windows doesn't allow colons in file names, so this can't use `rfc3339`.
+pub fn default_file_name(time: SystemTime) -> String {
+   let x = civil(time);
+   format!(
+      "stack-export-{:04}{:02}{:02}-{:02}{:02}{:02}.json",
+      x.year, x.month, x.day, x.hour, x.minute, x.second
+   )
+}
+
+/// Builds the export of the entries given, from the bottom of the stack to the top, along with when each was copied
+pub fn export_document<'a, I, E>(entries: I, exported_at: SystemTime) -> Value
+where
+   I: Iterator<Item = (&'a E, SystemTime)>,
+   E: Text + Saved + 'a,
+{
+   let entries: Vec<Value> = entries
+      .map(|(entry, copied_at)| {
+         let text = entry.to_text();
+         let (format, name, contents) = match entry.kind() {
+            Kind::Text => ("text", "text", json!(text)),
+            Kind::Files => ("files", "paths", json!(text.split(PATH_SEPARATOR).collect::<Vec<_>>())),
+            Kind::Image => ("image", "data", json!(BASE64.encode(entry.saved_bytes()))),
+         };
+         json!({
+            "format": format,
+            "copied_at": rfc3339(copied_at),
+            name: contents,
+         })
+      })
+      .collect();
+   json!({
+      "format": FORMAT,
+      "version": VERSION,
+      "exported_at": rfc3339(exported_at),
+      "entries": entries,
+   })
+}
+
+/// Why an entry in an export couldn't be read
+#[derive(Debug, PartialEq)]
+pub enum EntryError {
+   NotAnObject,
+   MissingFormat,
+   UnsupportedFormat(String),
+   MissingText,
+   MissingPaths,
+   MissingData,
+   InvalidCopiedAt,
+}
+
+impl fmt::Display for EntryError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         EntryError::NotAnObject => write!(f, "isn't an object"),
+         EntryError::MissingFormat => write!(f, "has no `format`, or it isn't a string"),
+         EntryError::UnsupportedFormat(got) => {
+            write!(
+               f,
+               "has format `{}`, but only `text`, `files` and `image` can be imported",
+               got
+            )
+         }
+         EntryError::MissingText => write!(f, "has no `text`, or it isn't a string"),
+         EntryError::MissingPaths => write!(f, "has no `paths`, or it isn't an array of paths"),
+         EntryError::MissingData => write!(f, "has no `data`, or it isn't base64"),
+         EntryError::InvalidCopiedAt => write!(
+            f,
+            "has a `copied_at` that isn't a time written like 2024-02-29T13:05:09.250Z"
+         ),
+      }
+   }
+}
+
+/// Why a file couldn't be read as an export
+#[derive(Debug)]
+pub enum ImportError {
+   Json(serde_json::Error),
+   NotAnExport,
+   UnsupportedVersion,
+   MissingEntries,
+   /// The entry at the index given, counting from 0 at the bottom of the stack, is invalid
+   Entry(usize, EntryError),
+}
+
+impl fmt::Display for ImportError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         ImportError::Json(e) => write!(f, "Not valid JSON: {}", e),
+         ImportError::NotAnExport => write!(f, "Not a ripclip stack export; `format` must be `{}`", FORMAT),
+         ImportError::UnsupportedVersion => write!(f, "Only version {} exports can be imported", VERSION),
+         ImportError::MissingEntries => write!(f, "Has no `entries`, or it isn't an array"),
+         // People count from 1
+         ImportError::Entry(index, e) => write!(f, "Entry {} {}", index + 1, e),
+      }
+   }
+}
+
+/// The text of the file list in `paths`, if it's an array of paths
+fn read_paths(paths: &Value) -> Option<String> {
+   let paths = paths
+      .as_array()?
+      .iter()
+      .map(Value::as_str)
+      .collect::<Option<Vec<&str>>>()?;
+   if paths.is_empty() || paths.iter().any(|x| x.is_empty() || x.contains(PATH_SEPARATOR)) {
+      return None;
+   }
+   Some(paths.join(PATH_SEPARATOR))
+}
+
+/// Reads an entry of an export, giving None for an image that's PNG but can't be decoded
+fn read_entry(entry: &Value, now: SystemTime) -> Result<Option<(Contents, SystemTime)>, EntryError> {
+   if !entry.is_object() {
+      return Err(EntryError::NotAnObject);
+   }
+   let contents = match entry.get("format").and_then(Value::as_str) {
+      Some("text") => {
+         let text = entry
+            .get("text")
+            .and_then(Value::as_str)
+            .ok_or(EntryError::MissingText)?;
+         Contents::Text(String::from(text))
+      }
+      Some("files") => {
+         let paths = entry
+            .get("paths")
+            .and_then(read_paths)
+            .ok_or(EntryError::MissingPaths)?;
+         Contents::Files(paths)
+      }
+      Some("image") => {
+         let data = entry
+            .get("data")
+            .and_then(Value::as_str)
+            .and_then(|x| BASE64.decode(x).ok())
+            .ok_or(EntryError::MissingData)?;
+         // Exports made by earlier versions keep images as bitmaps
+         if !dib::is_png(&data) {
+            Contents::Image(data)
+         } else {
+            match dib::from_png(&data) {
+               Some(dib) => Contents::Image(dib),
+               None => return Ok(None),
+            }
+         }
+      }
+      Some(format) => return Err(EntryError::UnsupportedFormat(String::from(format))),
+      None => return Err(EntryError::MissingFormat),
+   };
+   // Scripts writing an export by hand needn't say when each entry was copied
+   let copied_at = match entry.get("copied_at") {
+      Some(copied_at) => copied_at
+         .as_str()
+         .and_then(parse_rfc3339)
+         .ok_or(EntryError::InvalidCopiedAt)?,
+      None => now,
+   };
+   Ok(Some((contents, copied_at)))
+}
+
+/// Reads an export written by `write_export`, or by hand in the same form, giving its entries from the bottom of the
+/// stack to the top along with when each was copied. Entries that don't say when they were copied are taken to have
+/// been copied at `now`. Nothing is given unless the whole export is valid, except that images that are damaged are
+/// skipped with a warning.
+pub fn read_export(text: &str, now: SystemTime) -> Result<Vec<(Contents, SystemTime)>, ImportError> {
+   let document: Value = serde_json::from_str(text).map_err(ImportError::Json)?;
+   if document.get("format").and_then(Value::as_str) != Some(FORMAT) {
+      return Err(ImportError::NotAnExport);
+   }
+   if document.get("version").and_then(Value::as_f64) != Some(f64::from(VERSION)) {
+      return Err(ImportError::UnsupportedVersion);
+   }
+   let entries = document
+      .get("entries")
+      .and_then(Value::as_array)
+      .ok_or(ImportError::MissingEntries)?;
+   entries
+      .iter()
+      .enumerate()
+      .filter_map(|(i, entry)| match read_entry(entry, now) {
+         Ok(Some(entry)) => Some(Ok(entry)),
+         Ok(None) => {
+            warn!("Skipping entry {} of the import, an image that's damaged", i + 1);
+            None
+         }
+         Err(e) => Some(Err(ImportError::Entry(i, e))),
+      })
+      .collect()
+}
+
+/// Writes the export of the entries given, from the bottom of the stack to the top, as pretty-printed JSON
+pub fn write_export<'a, W, I, E>(output: &mut W, entries: I, exported_at: SystemTime) -> io::Result<()>
+where
+   W: Write,
+   I: Iterator<Item = (&'a E, SystemTime)>,
+   E: Text + Saved + 'a,
+{
+   serde_json::to_writer_pretty(&mut *output, &export_document(entries, exported_at))?;
+   output.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use std::time::Duration;
+
+   fn at(millis: u64) -> SystemTime {
+      UNIX_EPOCH + Duration::from_millis(millis)
+   }
+
+   impl Saved for &str {}
+
+   #[test]
+   fn formats_times_in_utc() {
+      assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+      assert_eq!(rfc3339(at(951_782_400_000)), "2000-02-29T00:00:00.000Z");
+      assert_eq!(rfc3339(at(1_700_000_000_123)), "2023-11-14T22:13:20.123Z");
+      assert_eq!(rfc3339(at(4_102_444_799_999)), "2099-12-31T23:59:59.999Z");
+      assert_eq!(
+         default_file_name(at(1_709_211_909_250)),
+         "stack-export-20240229-130509.json"
+      );
+   }
+
+   #[test]
+   fn exports_entries_bottom_first() {
+      let entries = [("bottom", at(1000)), ("top \"quoted\"", at(2000))];
+      let mut output = Vec::new();
+      write_export(&mut output, entries.iter().map(|(x, t)| (x, *t)), at(3000)).unwrap();
+      assert_eq!(
+         String::from_utf8(output).unwrap(),
+         r#"{
+  "format": "ripclip stack export",
+  "version": 1,
+  "exported_at": "1970-01-01T00:00:03.000Z",
+  "entries": [
+    {
+      "format": "text",
+      "copied_at": "1970-01-01T00:00:01.000Z",
+      "text": "bottom"
+    },
+    {
+      "format": "text",
+      "copied_at": "1970-01-01T00:00:02.000Z",
+      "text": "top \"quoted\""
+    }
+  ]
+}
+"#
+      );
+   }
+
+   #[test]
+   fn parses_times() {
+      assert_eq!(parse_rfc3339("2023-11-14T22:13:20.123Z"), Some(at(1_700_000_000_123)));
+      assert_eq!(parse_rfc3339("2023-11-14t22:13:20z"), Some(at(1_700_000_000_000)));
+      assert_eq!(
+         parse_rfc3339("2023-11-15T00:43:20.123+02:30"),
+         Some(at(1_700_000_000_123))
+      );
+      assert_eq!(
+         parse_rfc3339("2023-11-14T21:13:20.123-01:00"),
+         Some(at(1_700_000_000_123))
+      );
+      assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(at(951_782_400_000)));
+      assert_eq!(
+         parse_rfc3339("1970-01-01T00:00:00.000000001Z"),
+         Some(UNIX_EPOCH + Duration::from_nanos(1))
+      );
+      for time in [at(0), at(1_709_211_909_250), at(4_102_444_799_999)].iter() {
+         assert_eq!(parse_rfc3339(&rfc3339(*time)), Some(*time));
+      }
+      for invalid in [
+         "2023-11-14 22:13:20Z",
+         "2023-11-14T22:13:20",
+         "2023-11-14T22:13:20.Z",
+         "2023-11-14T24:00:00Z",
+         "2023-02-29T00:00:00Z",
+         "1900-02-29T00:00:00Z",
+         "2023-13-01T00:00:00Z",
+         "2023-11-14T22:13:20+2:00",
+         "1970-01-01T00:00:00+00:01",
+         "+023-11-14T22:13:20Z",
+      ]
+      .iter()
+      {
+         assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+      }
+   }
+
+   #[test]
+   fn reads_back_what_it_writes() {
+      let entries = [("bottom", at(1000)), ("top\n\"quoted\" 🦀", at(2000))];
+      let mut output = Vec::new();
+      write_export(&mut output, entries.iter().map(|(x, t)| (x, *t)), at(3000)).unwrap();
+      let read = read_export(&String::from_utf8(output).unwrap(), at(4000)).unwrap();
+      assert_eq!(
+         read,
+         vec![
+            (Contents::Text(String::from("bottom")), at(1000)),
+            (Contents::Text(String::from("top\n\"quoted\" 🦀")), at(2000))
+         ]
+      );
+   }
+
+   /// A file list, whose text is its paths one per line
+   struct Files(&'static str);
+
+   impl AsRef<[u8]> for Files {
+      fn as_ref(&self) -> &[u8] {
+         self.0.as_bytes()
+      }
+   }
+
+   impl Text for Files {
+      fn to_text(&self) -> String {
+         String::from(self.0)
+      }
+
+      fn kind(&self) -> Kind {
+         Kind::Files
+      }
+   }
+
+   impl Saved for Files {}
+
+   #[test]
+   fn exports_file_lists_as_paths() {
+      let entries = [(Files("C:\\a.txt\r\nC:\\photos"), at(1000))];
+      let document = export_document(entries.iter().map(|(x, t)| (x, *t)), at(2000));
+      let entry = &document.get("entries").and_then(Value::as_array).unwrap()[0];
+      assert_eq!(entry.get("format"), Some(&Value::from("files")));
+      assert_eq!(
+         entry.get("paths"),
+         Some(&Value::Array(vec![Value::from("C:\\a.txt"), Value::from("C:\\photos")]))
+      );
+      let mut output = Vec::new();
+      serde_json::to_writer_pretty(&mut output, &document).unwrap();
+      assert_eq!(
+         read_export(&String::from_utf8(output).unwrap(), at(3000)).unwrap(),
+         vec![(Contents::Files(String::from("C:\\a.txt\r\nC:\\photos")), at(1000))]
+      );
+   }
+
+   /// An image, which has no text, and what it's saved as if that isn't the image itself
+   struct Image(Vec<u8>, Option<Vec<u8>>);
+
+   impl Text for Image {
+      fn to_text(&self) -> String {
+         String::new()
+      }
+
+      fn kind(&self) -> Kind {
+         Kind::Image
+      }
+   }
+
+   impl AsRef<[u8]> for Image {
+      fn as_ref(&self) -> &[u8] {
+         &self.0
+      }
+   }
+
+   impl Saved for Image {
+      fn saved_bytes(&self) -> &[u8] {
+         self.1.as_deref().unwrap_or(&self.0)
+      }
+   }
+
+   #[test]
+   fn exports_images_as_base64() {
+      let entries = [(Image(b"\x28\0\0\0pixels".to_vec(), None), at(1000))];
+      let document = export_document(entries.iter().map(|(x, t)| (x, *t)), at(2000));
+      let entry = &document.get("entries").and_then(Value::as_array).unwrap()[0];
+      assert_eq!(entry.get("format"), Some(&Value::from("image")));
+      assert_eq!(entry.get("data"), Some(&Value::from("KAAAAHBpeGVscw==")));
+      let mut output = Vec::new();
+      serde_json::to_writer_pretty(&mut output, &document).unwrap();
+      assert_eq!(
+         read_export(&String::from_utf8(output).unwrap(), at(3000)).unwrap(),
+         vec![(Contents::Image(b"\x28\0\0\0pixels".to_vec()), at(1000))]
+      );
+   }
+
+   #[test]
+   fn exports_images_as_png() {
+      let image = dib::from_rgba(&dib::Rgba {
+         width: 3,
+         height: 2,
+         pixels: (0..24).collect(),
+         has_alpha: true,
+      });
+      let entries = [(Image(image.clone(), dib::to_png(&image)), at(1000))];
+      let document = export_document(entries.iter().map(|(x, t)| (x, *t)), at(2000));
+      let entry = &document.get("entries").and_then(Value::as_array).unwrap()[0];
+      let data = entry
+         .get("data")
+         .and_then(Value::as_str)
+         .and_then(|x| BASE64.decode(x).ok())
+         .unwrap();
+      assert!(dib::is_png(&data));
+      let mut output = Vec::new();
+      serde_json::to_writer_pretty(&mut output, &document).unwrap();
+      assert_eq!(
+         read_export(&String::from_utf8(output).unwrap(), at(3000)).unwrap(),
+         vec![(Contents::Image(image), at(1000))]
+      );
+   }
+
+   #[test]
+   fn skips_damaged_images() {
+      let mut damaged = dib::to_png(&dib::from_rgba(&dib::Rgba {
+         width: 1,
+         height: 1,
+         pixels: vec![1, 2, 3, 0xFF],
+         has_alpha: false,
+      }))
+      .unwrap();
+      damaged[45] ^= 1;
+      let export = format!(
+         r#"{{"format": "ripclip stack export", "version": 1, "entries": [{{"format": "image", "data": "{}"}}, {{"format": "text", "text": "fine"}}]}}"#,
+         BASE64.encode(&damaged)
+      );
+      assert_eq!(
+         read_export(&export, at(1000)).unwrap(),
+         vec![(Contents::Text(String::from("fine")), at(1000))]
+      );
+   }
+
+   #[test]
+   fn reads_handwritten_export() {
+      let text = r#"{"format": "ripclip stack export", "version": 1, "entries": [{"format": "text", "text": "hi"}]}"#;
+      assert_eq!(
+         read_export(text, at(4000)).unwrap(),
+         vec![(Contents::Text(String::from("hi")), at(4000))]
+      );
+   }
+
+   #[test]
+   fn rejects_invalid_exports() {
+      let read = |text: &str| read_export(text, UNIX_EPOCH).unwrap_err();
+      assert!(matches!(read("{"), ImportError::Json(_)));
+      assert!(matches!(
+         read(r#"{"format": "something else", "version": 1}"#),
+         ImportError::NotAnExport
+      ));
+      assert!(matches!(read("[]"), ImportError::NotAnExport));
+      assert!(matches!(
+         read(r#"{"format": "ripclip stack export", "version": 2, "entries": []}"#),
+         ImportError::UnsupportedVersion
+      ));
+      assert!(matches!(
+         read(r#"{"format": "ripclip stack export", "version": 1, "entries": {}}"#),
+         ImportError::MissingEntries
+      ));
+      let entries = |entries: &str| {
+         read(&format!(
+            r#"{{"format": "ripclip stack export", "version": 1, "entries": [{{"format": "text", "text": "fine"}}, {}]}}"#,
+            entries
+         ))
+      };
+      assert!(matches!(
+         entries(r#""text""#),
+         ImportError::Entry(1, EntryError::NotAnObject)
+      ));
+      assert!(matches!(
+         entries(r#"{"text": "hi"}"#),
+         ImportError::Entry(1, EntryError::MissingFormat)
+      ));
+      match entries(r#"{"format": "audio", "data": ""}"#) {
+         ImportError::Entry(1, EntryError::UnsupportedFormat(format)) => assert_eq!(format, "audio"),
+         x => panic!("expected an unsupported format, got {:?}", x),
+      }
+      for data in ["5", r#""QUJD=""#, r#""QU=D""#, r#""QUJ*""#].iter() {
+         assert!(matches!(
+            entries(&format!(r#"{{"format": "image", "data": {}}}"#, data)),
+            ImportError::Entry(1, EntryError::MissingData)
+         ));
+      }
+      assert!(matches!(
+         entries(r#"{"format": "text", "text": 5}"#),
+         ImportError::Entry(1, EntryError::MissingText)
+      ));
+      for paths in [r#""C:\\a.txt""#, "[]", r#"["C:\\a.txt", 5]"#, r#"["C:\\a.txt", ""]"#].iter() {
+         assert!(matches!(
+            entries(&format!(r#"{{"format": "files", "paths": {}}}"#, paths)),
+            ImportError::Entry(1, EntryError::MissingPaths)
+         ));
+      }
+      assert!(matches!(
+         entries(r#"{"format": "text", "text": "hi", "copied_at": "yesterday"}, {"text": 1}"#),
+         ImportError::Entry(1, EntryError::InvalidCopiedAt)
+      ));
+      assert_eq!(
+         entries(r#"{"text": 1}"#).to_string(),
+         "Entry 2 has no `format`, or it isn't a string"
+      );
+   }
+
+   #[test]
+   fn exports_empty_stack() {
+      let document = export_document(std::iter::empty::<(&&str, SystemTime)>(), UNIX_EPOCH);
+      assert_eq!(document["entries"], json!([]));
+   }
+}
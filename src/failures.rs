@@ -0,0 +1,159 @@
+//! Noticing when a clipboard operation keeps failing, so that the user is told, rather than finding that their
+//! hotkeys do nothing
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many times in a row an operation has to fail before the user is told
+pub const FAILURE_THRESHOLD: u32 = 3;
+/// How close together those failures have to be. A failure this long after the first in a row starts the count again.
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// How long after the user is told about an operation failing before they can be told about it again
+pub const NOTICE_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// A clipboard operation that can fail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+   Open,
+   Write,
+}
+
+impl Operation {
+   fn index(self) -> usize {
+      match self {
+         Operation::Open => 0,
+         Operation::Write => 1,
+      }
+   }
+}
+
+impl fmt::Display for Operation {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         Operation::Open => write!(f, "open the clipboard"),
+         Operation::Write => write!(f, "place an entry on the clipboard"),
+      }
+   }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Streak {
+   /// How many times in a row the operation has failed
+   failures: u32,
+   /// When the first of those failures was
+   since: Option<Instant>,
+   /// When the user was last told about the operation failing
+   noticed: Option<Instant>,
+}
+
+/// Counts the failures of each operation in a row
+#[derive(Debug, Default)]
+pub struct Failures {
+   streaks: [Streak; 2],
+}
+
+impl Failures {
+   /// Records that `operation` failed at `now`, which should come from a monotonic clock. Returns whether it's failed
+   /// often enough that the user should be told.
+   pub fn failed(&mut self, operation: Operation, now: Instant) -> bool {
+      let streak = &mut self.streaks[operation.index()];
+      match streak.since {
+         Some(since) if now.saturating_duration_since(since) < FAILURE_WINDOW => streak.failures += 1,
+         _ => {
+            streak.failures = 1;
+            streak.since = Some(now);
+         }
+      }
+      if streak.failures < FAILURE_THRESHOLD
+         || streak
+            .noticed
+            .is_some_and(|x| now.saturating_duration_since(x) < NOTICE_COOLDOWN)
+      {
+         return false;
+      }
+      streak.noticed = Some(now);
+      true
+   }
+
+   /// Records that `operation` succeeded, which starts the count of its failures again
+   pub fn succeeded(&mut self, operation: Operation) {
+      let streak = &mut self.streaks[operation.index()];
+      streak.failures = 0;
+      streak.since = None;
+   }
+
+   /// How many times in a row `operation` has failed
+   pub fn count(&self, operation: Operation) -> u32 {
+      self.streaks[operation.index()].failures
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   #[test]
+   fn notices_repeated_failures() {
+      let mut failures = Failures::default();
+      let start = Instant::now();
+      for i in 1..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Open, start + Duration::from_secs(u64::from(i))));
+      }
+      assert!(failures.failed(Operation::Open, start + Duration::from_secs(10)));
+      assert_eq!(failures.count(Operation::Open), FAILURE_THRESHOLD);
+      // Other operations are counted on their own
+      assert_eq!(failures.count(Operation::Write), 0);
+      assert!(!failures.failed(Operation::Write, start + Duration::from_secs(10)));
+   }
+
+   #[test]
+   fn success_starts_again() {
+      let mut failures = Failures::default();
+      let start = Instant::now();
+      for _ in 1..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Write, start));
+      }
+      failures.succeeded(Operation::Write);
+      assert_eq!(failures.count(Operation::Write), 0);
+      for _ in 1..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Write, start));
+      }
+      assert!(failures.failed(Operation::Write, start));
+   }
+
+   #[test]
+   fn failures_far_apart_start_again() {
+      let mut failures = Failures::default();
+      let start = Instant::now();
+      for _ in 1..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Open, start));
+      }
+      let later = start + FAILURE_WINDOW;
+      assert!(!failures.failed(Operation::Open, later));
+      assert_eq!(failures.count(Operation::Open), 1);
+      for _ in 2..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Open, later));
+      }
+      assert!(failures.failed(Operation::Open, later));
+   }
+
+   #[test]
+   fn notices_cool_down() {
+      let mut failures = Failures::default();
+      let start = Instant::now();
+      for _ in 0..FAILURE_THRESHOLD {
+         failures.failed(Operation::Open, start);
+      }
+      // Failing more, or failing again after a success, isn't told again until the cooldown is over
+      assert!(!failures.failed(Operation::Open, start + Duration::from_secs(1)));
+      failures.succeeded(Operation::Open);
+      for _ in 0..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Open, start + Duration::from_secs(2)));
+      }
+      let later = start + NOTICE_COOLDOWN;
+      for _ in 1..FAILURE_THRESHOLD {
+         assert!(!failures.failed(Operation::Open, later));
+      }
+      assert!(failures.failed(Operation::Open, later));
+   }
+}
@@ -0,0 +1,214 @@
+//! The history file that `history_file` turns on: a record of every entry popped, one JSON object per line.
+//! Records are written by a thread of their own, so that a slow or failing disk never holds up a hotkey.
+
+use crate::export;
+use serde_json::json;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+struct Record {
+   text: String,
+   popped_at: SystemTime,
+}
+
+/// Writes records to the history file until it's dropped, which waits for those already given to be written
+pub struct History {
+   path: PathBuf,
+   max_bytes: Option<usize>,
+   sender: Option<Sender<Record>>,
+   thread: Option<JoinHandle<()>>,
+}
+
+impl History {
+   pub fn start(path: PathBuf, max_bytes: Option<usize>) -> io::Result<History> {
+      let (sender, receiver) = mpsc::channel();
+      let writer = Writer {
+         path: path.clone(),
+         max_bytes,
+         file: None,
+         size: 0,
+      };
+      let thread = thread::Builder::new()
+         .name(String::from("history"))
+         .spawn(move || writer.run(receiver))?;
+      Ok(History {
+         path,
+         max_bytes,
+         sender: Some(sender),
+         thread: Some(thread),
+      })
+   }
+
+   /// Whether this writes where and how the configuration given says to
+   pub fn matches(&self, path: &Path, max_bytes: Option<usize>) -> bool {
+      self.path == path && self.max_bytes == max_bytes
+   }
+
+   /// Queues a record of `text` being popped at `popped_at`, returning right away
+   pub fn record(&self, text: String, popped_at: SystemTime) {
+      if let Some(sender) = &self.sender {
+         // The thread only stops once we do, so this can't fail
+         let _ = sender.send(Record { text, popped_at });
+      }
+   }
+}
+
+impl Drop for History {
+   fn drop(&mut self) {
+      // Hanging up tells the thread to finish what's queued and stop
+      self.sender = None;
+      if let Some(thread) = self.thread.take() {
+         let _ = thread.join();
+      }
+   }
+}
+
+/// The name the history file is given once it's grown past `history_max_bytes`
+fn rotated_path(path: &Path) -> PathBuf {
+   let mut rotated = OsString::from(path.as_os_str());
+   rotated.push(".1");
+   PathBuf::from(rotated)
+}
+
+fn record_line(record: &Record) -> Vec<u8> {
+   let value = json!({
+      "popped_at": export::rfc3339(record.popped_at),
+      "text": record.text,
+   });
+   // Writing to memory can't fail
+   let mut line = serde_json::to_vec(&value).unwrap();
+   line.push(b'\n');
+   line
+}
+
+struct Writer {
+   path: PathBuf,
+   max_bytes: Option<usize>,
+   /// Opened when there's first something to write, and again after a failure
+   file: Option<BufWriter<File>>,
+   /// How large the file is, counting what's waiting to be flushed
+   size: u64,
+}
+
+impl Writer {
+   fn run(mut self, receiver: Receiver<Record>) {
+      // Whether the last write failed. Only the first of a run of failures is reported.
+      let mut failing = false;
+      while let Ok(record) = receiver.recv() {
+         let mut result = self.write(&record);
+         // A burst of pops is flushed together, once it's all been written
+         while result.is_ok() {
+            match receiver.try_recv() {
+               Ok(record) => result = self.write(&record),
+               Err(_) => break,
+            }
+         }
+         let result = result.and_then(|()| self.file.as_mut().map_or(Ok(()), |x| x.flush()));
+         match result {
+            Ok(()) if failing => {
+               info!("Writing to the history file {:#?} works again", self.path);
+               failing = false;
+            }
+            Ok(()) => (),
+            Err(e) => {
+               if !failing {
+                  warn!("Failed to write to the history file {:#?}: {}", self.path, e);
+                  failing = true;
+               }
+               // It's opened afresh next time, in case whatever went wrong has been fixed
+               self.file = None;
+            }
+         }
+      }
+   }
+
+   fn write(&mut self, record: &Record) -> io::Result<()> {
+      let line = record_line(record);
+      if self.file.is_none() {
+         let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+         self.size = file.metadata()?.len();
+         self.file = Some(BufWriter::new(file));
+      }
+      let full = self
+         .max_bytes
+         .is_some_and(|max| self.size > 0 && self.size + line.len() as u64 > max as u64);
+      if full {
+         self.rotate()?;
+      }
+      self.file.as_mut().unwrap().write_all(&line)?;
+      self.size += line.len() as u64;
+      Ok(())
+   }
+
+   /// Moves the history file aside, replacing the one moved aside before, and starts a new one
+   fn rotate(&mut self) -> io::Result<()> {
+      if let Some(mut file) = self.file.take() {
+         file.flush()?;
+      }
+      fs::rename(&self.path, rotated_path(&self.path))?;
+      self.file = Some(BufWriter::new(File::create(&self.path)?));
+      self.size = 0;
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use crate::test_files::write_test_files;
+   use std::time::{Duration, UNIX_EPOCH};
+
+   #[test]
+   fn appends_records_as_json_lines() {
+      let dir = write_test_files("history-append", &[]);
+      let path = dir.join("history.jsonl");
+      fs::write(&path, "earlier\n").unwrap();
+      {
+         let history = History::start(path.clone(), None).unwrap();
+         history.record(String::from("first"), UNIX_EPOCH + Duration::from_millis(1500));
+         history.record(String::from("two\nlines"), UNIX_EPOCH);
+      }
+      assert_eq!(
+         fs::read_to_string(&path).unwrap(),
+         "earlier\n\
+          {\"popped_at\":\"1970-01-01T00:00:01.500Z\",\"text\":\"first\"}\n\
+          {\"popped_at\":\"1970-01-01T00:00:00.000Z\",\"text\":\"two\\nlines\"}\n"
+      );
+      fs::remove_dir_all(&dir).unwrap();
+   }
+
+   #[test]
+   fn rotates_when_full() {
+      let dir = write_test_files("history-rotate", &[]);
+      let path = dir.join("history.jsonl");
+      let line_len = record_line(&Record {
+         text: String::from("a"),
+         popped_at: UNIX_EPOCH,
+      })
+      .len();
+      {
+         let history = History::start(path.clone(), Some(line_len * 2)).unwrap();
+         for text in ["a", "b", "c", "d", "e"].iter() {
+            history.record(String::from(*text), UNIX_EPOCH);
+         }
+      }
+      let texts = |path: &Path| -> Vec<String> {
+         fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|x| {
+               let record: serde_json::Value = serde_json::from_str(x).unwrap();
+               record["text"].as_str().unwrap().to_owned()
+            })
+            .collect()
+      };
+      assert_eq!(texts(&path), vec!["e"]);
+      assert_eq!(texts(&rotated_path(&path)), vec!["c", "d"]);
+      fs::remove_dir_all(&dir).unwrap();
+   }
+}
@@ -0,0 +1,153 @@
+//! Reading and writing the "HTML Format" clipboard format (CF_HTML): a header of `Name:value` lines giving the byte
+//! offsets of the HTML and of the fragment within it that was actually copied, followed by the HTML as UTF-8
+
+use std::ops::Range;
+
+/// How many digits each offset in a header written by `to_cf_html` takes, so that the header's length doesn't depend
+/// on the offsets in it
+const OFFSET_DIGITS: usize = 10;
+
+/// Copied HTML, along with the surrounding document it came from, which gives the fragment its context
+#[derive(Clone, Debug, PartialEq)]
+pub struct Html {
+   document: Vec<u8>,
+   /// Where the fragment is in `document`
+   fragment: Range<usize>,
+   source_url: Option<String>,
+}
+
+/// Reads an offset in a CF_HTML header, which is None for -1, meaning that it's left out
+fn parse_offset(value: &str) -> Option<usize> {
+   value.trim().parse().ok()
+}
+
+impl Html {
+   /// Reads CF_HTML, returning None if the header is missing or its offsets don't fit the data
+   pub fn parse(data: &[u8]) -> Option<Html> {
+      let mut start_html = None;
+      let mut end_html = None;
+      let mut start_fragment = None;
+      let mut end_fragment = None;
+      let mut source_url = None;
+      for line in data.split(|x| *x == b'\n') {
+         let line = match std::str::from_utf8(line) {
+            Ok(line) => line.trim_end_matches('\r'),
+            Err(_) => break,
+         };
+         // The HTML starts right after the header, usually with a tag or comment
+         let (name, value) = match line.split_once(':') {
+            Some(field) if !line.starts_with('<') => field,
+            _ => break,
+         };
+         match name {
+            "StartHTML" => start_html = parse_offset(value),
+            "EndHTML" => end_html = parse_offset(value),
+            "StartFragment" => start_fragment = parse_offset(value),
+            "EndFragment" => end_fragment = parse_offset(value),
+            "SourceURL" => source_url = Some(String::from(value)),
+            _ => (),
+         }
+      }
+      let fragment = start_fragment?..end_fragment?;
+      if fragment.start > fragment.end || fragment.end > data.len() {
+         return None;
+      }
+      match (start_html, end_html) {
+         (Some(start), Some(end)) if start <= fragment.start && fragment.end <= end && end <= data.len() => {
+            Some(Html {
+               document: data[start..end].to_vec(),
+               fragment: fragment.start - start..fragment.end - start,
+               source_url,
+            })
+         }
+         // Without the surrounding document, the fragment is all there is
+         (None, None) => Some(Html {
+            document: data[fragment.clone()].to_vec(),
+            fragment: 0..fragment.len(),
+            source_url,
+         }),
+         _ => None,
+      }
+   }
+
+   /// Writes the HTML as CF_HTML, null terminated, with a header giving where everything is in what's written
+   pub fn to_cf_html(&self) -> Vec<u8> {
+      let header = |start_html: usize| {
+         let mut header = format!(
+            "Version:0.9\r\nStartHTML:{:0width$}\r\nEndHTML:{:0width$}\r\n\
+             StartFragment:{:0width$}\r\nEndFragment:{:0width$}\r\n",
+            start_html,
+            start_html + self.document.len(),
+            start_html + self.fragment.start,
+            start_html + self.fragment.end,
+            width = OFFSET_DIGITS
+         );
+         if let Some(source_url) = &self.source_url {
+            header.push_str(&format!("SourceURL:{}\r\n", source_url));
+         }
+         header
+      };
+      // The offsets are all written at the same width, so the header is as long with them as without them
+      let header = header(header(0).len());
+      let mut cf_html = header.into_bytes();
+      cf_html.extend_from_slice(&self.document);
+      cf_html.push(0);
+      cf_html
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   const BROWSER_COPY: &[u8] = b"Version:0.9\r\nStartHTML:0000000137\r\nEndHTML:0000000226\r\n\
+StartFragment:0000000171\r\nEndFragment:0000000192\r\nSourceURL:https://example.com/\r\n\
+<html><body>\r\n<!--StartFragment--><b>bold</b> and plain<!--EndFragment-->\r\n</body></html>\0";
+
+   #[test]
+   fn reads_fragment_and_document() {
+      let html = Html::parse(BROWSER_COPY).unwrap();
+      assert_eq!(&html.document[html.fragment.clone()], b"<b>bold</b> and plain");
+      assert_eq!(html.source_url.as_deref(), Some("https://example.com/"));
+      assert!(html.document.starts_with(b"<html>"));
+      assert!(html.document.ends_with(b"</html>"));
+   }
+
+   #[test]
+   fn recomputes_offsets() {
+      let html = Html {
+         document: b"<html><body><!--StartFragment-->\xc3\xa9<!--EndFragment--></body></html>".to_vec(),
+         fragment: 32..34,
+         source_url: None,
+      };
+      let cf_html = html.to_cf_html();
+      assert_eq!(Html::parse(&cf_html), Some(html.clone()));
+      let start_html = cf_html.windows(6).position(|x| x == b"<html>").unwrap();
+      assert_eq!(&cf_html[..12], b"Version:0.9\r");
+      assert!(String::from_utf8_lossy(&cf_html).contains(&format!("StartHTML:{:010}\r\n", start_html)));
+      assert_eq!(cf_html.last(), Some(&0));
+      // Writing it again gives the same offsets
+      let rewritten = Html::parse(BROWSER_COPY).unwrap().to_cf_html();
+      assert_eq!(Html::parse(&rewritten), Html::parse(BROWSER_COPY));
+   }
+
+   #[test]
+   fn reads_fragment_without_document() {
+      let cf_html = b"Version:1.0\r\nStartHTML:-1\r\nEndHTML:-1\r\nStartFragment:73\r\nEndFragment:83\r\n<i>hi</i>\r\n";
+      let html = Html::parse(&cf_html[..]).unwrap();
+      assert_eq!(html.document, b"<i>hi</i>\r");
+      assert_eq!(html.fragment, 0..10);
+   }
+
+   #[test]
+   fn rejects_bad_offsets() {
+      assert_eq!(Html::parse(b""), None);
+      assert_eq!(Html::parse(b"<html><body>no header</body></html>"), None);
+      // The fragment ends past the data, or starts before the document
+      assert_eq!(Html::parse(b"StartFragment:0\r\nEndFragment:999\r\n<b>x</b>"), None);
+      assert_eq!(
+         Html::parse(b"StartHTML:40\r\nEndHTML:60\r\nStartFragment:30\r\nEndFragment:50\r\n<html><b>x</b></html>"),
+         None
+      );
+   }
+}
@@ -0,0 +1,101 @@
+//! Logger setup. Records go to stderr through pretty_env_logger, and optionally to a log file as well,
+//! since a windows subsystem app has no console for anyone to read stderr from.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+struct TeeLogger {
+   inner: env_logger::Logger,
+}
+
+impl Log for TeeLogger {
+   fn enabled(&self, metadata: &Metadata) -> bool {
+      self.inner.enabled(metadata)
+   }
+
+   fn log(&self, record: &Record) {
+      if !self.inner.matches(record) {
+         return;
+      }
+      self.inner.log(record);
+      if let Ok(mut log_file) = LOG_FILE.lock() {
+         if let Some(file) = log_file.as_mut() {
+            let timestamp = SystemTime::now()
+               .duration_since(UNIX_EPOCH)
+               .map(|x| x.as_secs_f64())
+               .unwrap_or(0.0);
+            // Nowhere to report a failure to write the log, so it's ignored
+            let _ = writeln!(
+               file,
+               "{:.3} {:<5} {} > {}",
+               timestamp,
+               record.level(),
+               record.target(),
+               record.args()
+            );
+         }
+      }
+   }
+
+   fn flush(&self) {
+      self.inner.flush();
+      if let Ok(mut log_file) = LOG_FILE.lock() {
+         if let Some(file) = log_file.as_mut() {
+            let _ = file.flush();
+         }
+      }
+   }
+}
+
+pub fn init() {
+   // Everything from ripclip passes the logger's own filter, so that the configured
+   // log level (applied through the global max level) is the only thing limiting output.
+   // RUST_LOG, if present, still takes precedence.
+   let mut builder = pretty_env_logger::formatted_builder();
+   builder.filter_module("ripclip", LevelFilter::Trace);
+   if let Ok(filters) = std::env::var("RUST_LOG") {
+      builder.parse_filters(&filters);
+   }
+   let logger = TeeLogger { inner: builder.build() };
+   let max_level = logger.inner.filter();
+   log::set_boxed_logger(Box::new(logger)).unwrap();
+   log::set_max_level(max_level);
+}
+
+pub fn apply_config(config: &crate::config::Config) {
+   if std::env::var_os("RUST_LOG").is_none() {
+      log::set_max_level(config.log_level);
+   }
+   set_log_file(config.log_file.as_deref());
+}
+
+fn set_log_file(path: Option<&Path>) {
+   let file = path.and_then(|path| {
+      if let Some(parent) = path.parent() {
+         // Errors are handled when we try to open the file
+         let _ = fs::create_dir_all(parent);
+      }
+      match OpenOptions::new().create(true).append(true).open(path) {
+         Ok(file) => Some(file),
+         Err(e) => {
+            warn!(
+               "Unable to open log file {:#?}; logging to stderr only.\n Error: {}",
+               path, e
+            );
+            None
+         }
+      }
+   });
+   if let Ok(mut log_file) = LOG_FILE.lock() {
+      *log_file = file;
+   }
+   if let Some(path) = path {
+      info!("Logging to {:#?}", path);
+   }
+}
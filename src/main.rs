@@ -1,41 +1,337 @@
 #![windows_subsystem = "windows"]
+// Thread locals are all written the same way, whether or not their initializer could be const
+#![allow(clippy::missing_const_for_thread_local)]
 
 #[macro_use]
 extern crate bitflags;
 extern crate dirs;
+extern crate env_logger;
 extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 extern crate winapi;
 
+mod capture;
 mod config;
+mod dib;
+mod entry;
+mod export;
+mod failures;
+mod history;
+mod html;
+mod logging;
+mod rtf;
+mod shutdown;
+mod snapshot;
+mod stack;
+#[cfg(test)]
+mod test_files;
+mod watch;
 mod win;
 
+use crate::export::Saved;
+use crate::stack::{Size, Text};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 const POP_MENU_ID: usize = 100;
 const SWAP_MENU_ID: usize = 101;
 const CLEAR_MENU_ID: usize = 102;
 const EXIT_MENU_ID: usize = 103;
 const RELOAD_MENU_ID: usize = 104;
+const PAUSE_MENU_ID: usize = 105;
+const HOTKEY_PROBLEMS_MENU_ID: usize = 106;
+const EXPORT_MENU_ID: usize = 107;
+const IMPORT_MENU_ID: usize = 108;
+const UNDO_CLEAR_MENU_ID: usize = 109;
+const PLAIN_TEXT_MENU_ID: usize = 110;
+const STATUS_MENU_ID: usize = 111;
+const OPEN_CONFIG_MENU_ID: usize = 112;
+/// The entries listed in the tray menu have this plus their depth in the stack
+const ENTRY_MENU_ID_BASE: usize = 1000;
+/// The most entries the tray menu lists, whatever `tray_menu_entries` says
+const MAX_TRAY_MENU_ENTRIES: usize = 100;
+/// How many characters of an entry the tray menu shows
+const TRAY_MENU_PREVIEW_CHARS: usize = 50;
+/// How many UTF-16 code units the tray icon's tooltip holds, besides its null terminator
+const TRAY_TOOLTIP_UNITS: usize = 127;
 
 const POP_HOTKEY_ID: u16 = 1;
 const SWAP_HOTKEY_ID: u16 = 2;
 const CLEAR_HOTKEY_ID: u16 = 3;
+const PUSH_HOTKEY_ID: u16 = 4;
+const PEEK_HOTKEY_ID: u16 = 5;
+const PAUSE_HOTKEY_ID: u16 = 6;
+const ROTATE_HOTKEY_ID: u16 = 7;
+const DROP_HOTKEY_ID: u16 = 8;
+const POP_ALL_HOTKEY_ID: u16 = 9;
+const DUPLICATE_TOP_HOTKEY_ID: u16 = 10;
+const UNDO_POP_HOTKEY_ID: u16 = 11;
+const SHOW_STACK_HOTKEY_ID: u16 = 12;
+const REVERSE_HOTKEY_ID: u16 = 13;
+const MOVE_TO_BOTTOM_HOTKEY_ID: u16 = 14;
+const COPY_ALL_HOTKEY_ID: u16 = 15;
+const SAVE_SNAPSHOT_HOTKEY_ID: u16 = 16;
+const RESTORE_SNAPSHOT_HOTKEY_ID: u16 = 17;
+const PASTE_SECOND_HOTKEY_ID: u16 = 18;
+const EXPORT_HOTKEY_ID: u16 = 19;
+const IMPORT_HOTKEY_ID: u16 = 20;
+const PIN_TOP_HOTKEY_ID: u16 = 21;
+const UNDO_CLEAR_HOTKEY_ID: u16 = 22;
+/// The hotkey ID of each keybinding option, in the order `Config::keybindings` lists them
+const KEYBINDING_IDS: [u16; config::KEYBINDING_COUNT] = [
+   POP_HOTKEY_ID,
+   CLEAR_HOTKEY_ID,
+   SWAP_HOTKEY_ID,
+   PUSH_HOTKEY_ID,
+   PEEK_HOTKEY_ID,
+   PAUSE_HOTKEY_ID,
+   ROTATE_HOTKEY_ID,
+   DROP_HOTKEY_ID,
+   POP_ALL_HOTKEY_ID,
+   DUPLICATE_TOP_HOTKEY_ID,
+   UNDO_POP_HOTKEY_ID,
+   SHOW_STACK_HOTKEY_ID,
+   REVERSE_HOTKEY_ID,
+   MOVE_TO_BOTTOM_HOTKEY_ID,
+   COPY_ALL_HOTKEY_ID,
+   SAVE_SNAPSHOT_HOTKEY_ID,
+   RESTORE_SNAPSHOT_HOTKEY_ID,
+   PASTE_SECOND_HOTKEY_ID,
+   EXPORT_HOTKEY_ID,
+   IMPORT_HOTKEY_ID,
+   PIN_TOP_HOTKEY_ID,
+   UNDO_CLEAR_HOTKEY_ID,
+];
+/// Every hotkey bound to an action gets its own ID, counting up from the action's ID in steps of this
+const HOTKEY_ID_STRIDE: u16 = 0x100;
+/// Indexed pop hotkeys take the IDs following this one, so the ID of each is this plus its number
+const INDEXED_POP_HOTKEY_ID_BASE: u16 = 100;
+/// The first step of each sequence takes the next ID from this one. These are shared by every sequence with that
+/// first step, so they don't belong to an action.
+const LEADER_HOTKEY_ID_BASE: u16 = 0x80;
+
+/// Posted by the stack viewer's window procedure when a key it handles is pressed
+const VIEWER_KEY_MESSAGE: u32 = winapi::um::winuser::WM_APP;
+/// Posted by the stack viewer's window procedure when it loses focus
+const VIEWER_CLOSE_MESSAGE: u32 = winapi::um::winuser::WM_APP + 1;
+/// Posted by the mouse or keyboard hook when a hotkey it handles is pressed. Like WM_HOTKEY, the w_param is the hotkey ID.
+const HOOK_BINDING_MESSAGE: u32 = winapi::um::winuser::WM_APP + 2;
+/// Posted by the sequence hook once a sequence is finished or cancelled. The w_param is 1 if it finished.
+const SEQUENCE_END_MESSAGE: u32 = winapi::um::winuser::WM_APP + 3;
+/// Posted to the main thread by the export thread once it's done writing
+const EXPORT_DONE_MESSAGE: u32 = winapi::um::winuser::WM_APP + 4;
+/// Posted by the main window's procedure once it's handled Windows asking whether the session can end, so that the
+/// message loop saves anything it read since
+const SESSION_ENDING_MESSAGE: u32 = winapi::um::winuser::WM_APP + 5;
+/// Posted by the main window's procedure once it's handled Windows saying whether the session is ending, so that the
+/// message loop stops if it is. Like WM_ENDSESSION, the w_param is 1 if it is.
+const SESSION_END_MESSAGE: u32 = winapi::um::winuser::WM_APP + 6;
+/// Posted to the main thread when ripclip is asked to exit, from the tray menu or otherwise
+const EXIT_MESSAGE: u32 = winapi::um::winuser::WM_APP + 7;
+/// Posted to the main thread by the capture thread once it's read the clipboard
+const CAPTURE_DONE_MESSAGE: u32 = winapi::um::winuser::WM_APP + 8;
+/// Posted to the hook thread to wait for the second step of the sequences led by the hotkey ID in the w_param
+const SEQUENCE_START_MESSAGE: u32 = winapi::um::winuser::WM_APP + 9;
+/// Posted to the hook thread to stop waiting for the second step of a sequence
+const SEQUENCE_STOP_MESSAGE: u32 = winapi::um::winuser::WM_APP + 10;
+/// Sent by Explorer when the tray icon is clicked
+const TRAY_CALLBACK_MESSAGE: u32 = 100;
+const VIEWER_WIDTH: i32 = 400;
+const VIEWER_HEIGHT: i32 = 300;
+/// How many characters of each entry to show in the stack viewer
+const VIEWER_PREVIEW_CHARS: usize = 80;
+/// Put before pinned entries in the stack viewer
+const VIEWER_PINNED_MARKER: &str = "[pinned] ";
+
+/// How many characters of the top of the stack to show when peeking
+const PEEK_PREVIEW_CHARS: usize = 80;
+
+/// The program that passes copies made in a remote desktop session on to the local clipboard, and back
+const REMOTE_CLIPBOARD_PROCESS: &str = "rdpclip.exe";
+
+const CONFIG_WATCH_TIMER_ID: usize = 1;
+const CONFIG_WATCH_INTERVAL_MS: u32 = 1000;
+
+const SEQUENCE_TIMER_ID: usize = 2;
+/// How long to wait for the second step of a sequence before giving up on it
+const SEQUENCE_TIMEOUT_MS: u32 = 2000;
+
+const EXPIRY_TIMER_ID: usize = 3;
+/// How often to look for stale entries, besides when popping or peeking
+const EXPIRY_INTERVAL_MS: u32 = 60 * 1000;
+
+const PERSIST_TIMER_ID: usize = 4;
+/// How long to wait after the stack changes before saving it, so that a burst of changes is saved once
+const PERSIST_DELAY_MS: u32 = 500;
+
+/// Fires `undo_clear_timeout` after a clear, to forget the entries it removed
+const UNDO_CLEAR_TIMER_ID: usize = 5;
+
+/// Fires once the clipboard has gone `capture_debounce_ms` without changing, to read what was copied
+const CAPTURE_DEBOUNCE_TIMER_ID: usize = 6;
+
+const FULLSCREEN_TIMER_ID: usize = 7;
+/// How often to check whether a fullscreen program is in the foreground, for `pause_in_fullscreen`
+const FULLSCREEN_CHECK_INTERVAL_MS: u32 = 1000;
+
+const LISTENER_WATCHDOG_TIMER_ID: usize = 8;
+/// How often to check that the clipboard hasn't changed without ripclip hearing about it
+const LISTENER_WATCHDOG_INTERVAL_MS: u32 = 3 * 60 * 1000;
+
+/// Command line arguments
+struct Args {
+   /// An alternate configuration file to use instead of the default one
+   config_file: Option<PathBuf>,
+   /// Validate the configuration and exit instead of running
+   check_config: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+   let mut args = Args {
+      config_file: None,
+      check_config: false,
+   };
+   let mut iter = std::env::args_os().skip(1);
+   while let Some(arg) = iter.next() {
+      match arg.to_str() {
+         Some("--config") => match iter.next() {
+            Some(path) => args.config_file = Some(PathBuf::from(path)),
+            None => return Err(String::from("--config requires a path")),
+         },
+         Some("--check-config") => args.check_config = true,
+         _ => return Err(format!("Unknown argument {:?}", arg)),
+      }
+   }
+   Ok(args)
+}
+
+/// The configuration file in use, if it can be determined
+fn config_file(args: &Args) -> Option<PathBuf> {
+   args.config_file.clone().or_else(config::config_path)
+}
+
+fn load_config(args: &Args) -> Result<(config::Config, Vec<PathBuf>), config::ParseError> {
+   match args.config_file.as_ref() {
+      Some(path) => config::load_config_from(path),
+      None => config::load_config(),
+   }
+}
+
+/// Parses the configuration, printing every problem found, and returns the exit code
+fn check_config(args: &Args) -> i32 {
+   // We're a windows subsystem application, so there's nowhere for output to go otherwise
+   if let Err(e) = win::attach_console() {
+      warn!("Failed to attach a console: {}", e);
+   }
+   let path = match config_file(args) {
+      Some(path) => path,
+      None => {
+         eprintln!("Unable to determine configuration directory");
+         return 1;
+      }
+   };
+   let file = match File::open(&path) {
+      Ok(file) => file,
+      Err(e) => {
+         eprintln!("Failed to open {}: {}", path.display(), e);
+         return 1;
+      }
+   };
+   match config::parse_config_all_errors(BufReader::new(file), Some(&path)) {
+      Ok((parsed, errors)) => {
+         for (warning, location) in parsed.warnings.iter() {
+            eprintln!("Warning at {}: {}", location, warning);
+         }
+         if !errors.is_empty() {
+            for error in errors.iter() {
+               eprintln!("{}", error);
+            }
+            return 1;
+         }
+         // Every line being fine isn't enough for ripclip to start with it
+         if let Err(e) = parsed.config.validate() {
+            eprintln!("Invalid configuration: {}", e);
+            return 1;
+         }
+         println!("OK");
+         let _ = config::write_config(&parsed.config, &mut io::stdout());
+         0
+      }
+      Err(e) => {
+         eprintln!("{}", e);
+         1
+      }
+   }
+}
 
 fn main() {
-   pretty_env_logger::init();
+   logging::init();
 
-   let mut config = match config::load_config() {
-      Ok(config) => config,
+   let args = match parse_args() {
+      Ok(args) => args,
       Err(e) => {
-         eprintln!("Failed to parse config: {}", e);
+         error!("{}", e);
+         eprintln!("{}", e);
          std::process::exit(-1);
       }
    };
 
+   if args.check_config {
+      std::process::exit(check_config(&args));
+   }
+
+   let (config, config_files) = match load_config(&args) {
+      Ok(loaded) => loaded,
+      Err(e) => {
+         error!("Failed to load config: {}", e);
+         let path = config_file(&args).map_or_else(|| String::from("(unknown)"), |x| x.display().to_string());
+         if let config::ParseError::Io(_) = e {
+            // Only an explicitly given configuration file can fail this way, and we don't want to carry on without it
+            let text = format!("Failed to read the configuration at {}:\n\n{}", path, e);
+            let _ = win::message_box(
+               None,
+               &text,
+               "ripclip",
+               winapi::um::winuser::MB_OK | winapi::um::winuser::MB_ICONERROR,
+            );
+            std::process::exit(-1);
+         }
+         let text = format!(
+            "Failed to parse the configuration at {}:\n\n{}\n\nPress OK to continue with the default configuration, or Cancel to exit.",
+            path, e
+         );
+         match win::message_box(
+            None,
+            &text,
+            "ripclip",
+            winapi::um::winuser::MB_OKCANCEL | winapi::um::winuser::MB_ICONERROR,
+         ) {
+            // Only the configuration file itself is known to be worth watching for a fix
+            Ok(winapi::um::winuser::IDOK) => (config::Config::default(), config_file(&args).into_iter().collect()),
+            _ => std::process::exit(-1),
+         }
+      }
+   };
+   logging::apply_config(&config);
+   // Shared with the capture thread, which is handed it with each read
+   let mut config = Arc::new(config);
+
    let module = win::get_module_handle_ex().unwrap();
    let class = win::register_class_ex(&module, Some(on_message), "ripclip_class").unwrap();
+   let viewer_class = win::register_class_ex(&module, Some(on_viewer_message), "ripclip_viewer_class").unwrap();
 
    let window = win::create_window_ex(
       0x0000_0000,
@@ -45,130 +341,625 @@ fn main() {
       0,
       0,
       0,
-      &win::WindowParent::MessageOnly,
+      // Hidden rather than message-only, since only top-level windows are told when the session is ending
+      &win::WindowParent::NoParent,
    )
    .unwrap();
+   allow_lower_integrity_messages(&window);
+   MAIN_THREAD.store(
+      unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() },
+      Ordering::SeqCst,
+   );
+   if let Err(e) = win::set_console_ctrl_handler(on_console_event) {
+      warn!(
+         "Failed to set console control handler, so Ctrl+C won't exit cleanly: {}",
+         e
+      );
+   }
 
-   let mut menu = win::create_popup_menu().unwrap();
-   menu.append_text(POP_MENU_ID, "Pop").unwrap();
-   menu.append_text(SWAP_MENU_ID, "Swap").unwrap();
-   menu.append_text(CLEAR_MENU_ID, "Clear").unwrap();
-   menu.append_line_break(1).unwrap();
-   menu.append_text(RELOAD_MENU_ID, "Reload Configuration").unwrap();
-   menu.append_text(EXIT_MENU_ID, "Exit").unwrap();
-
-   let mut clipboard_stack: VecDeque<win::ClipboardText> = if let Some(max_stack_size) = config.max_stack_size {
-      VecDeque::with_capacity(max_stack_size)
-   } else {
-      VecDeque::new()
-   };
+   let mut clipboard_stack = load_stack(&config);
+   let mut persistence = Persistence::new(&clipboard_stack, &config);
+   // Recently popped entries, kept so that a pop can be undone
+   let mut popped: VecDeque<entry::Entry> = VecDeque::new();
+   // The entries removed by the last clear, kept for `undo_clear_timeout` so that it can be undone
+   let mut last_clear: Option<stack::Cleared<entry::Entry>> = None;
+   // How far pops have stepped down the stack, when they leave entries on it
+   let mut pop_cursor: Option<stack::Cursor> = None;
    let mut managing_clipboard = false;
+   // The last copy pushed, for ignoring the same copy being announced again
+   let mut last_push: Option<stack::LastPush<entry::Entry>> = None;
+   // Whether the clipboard has changed and is waiting on `CAPTURE_DEBOUNCE_TIMER_ID` to be read
+   let mut capture_reader = start_capture_reader();
+   let mut capture_pending = false;
+   let mut viewer: Option<win::ListPopup> = None;
+   // What `clipboard_stack.changes()` was when the tray menu was last opened, so that choosing an entry from it
+   // doesn't pop whatever has taken that entry's place since. This relies on the stack never being assigned a new
+   // one, whose count would start over; `Stack::replace_all` is for swapping in other entries.
+   let mut tray_menu_changes: Option<u64> = None;
+   let mut paused = false;
+   let mut fullscreen_pause = FullscreenPause::default();
+   // Whether the user has been told that ripclip can't paste into windows run as administrator
+   let mut warned_elevated = false;
+   let mut export: Option<Export> = None;
+   let mut history = start_history(&config);
+   let mut shutdown = shutdown::Shutdown::new();
+   let mut tray_tooltip = TrayTooltip::default();
+   let mut tray_badge = TrayBadge::default();
 
-   let mut _trayicon = if config.show_tray_icon {
-      Some(win::add_tray_icon(&window, 0, 100, "ripclip", &module).unwrap())
+   let mut trayicon = if config.show_tray_icon {
+      Some(win::add_tray_icon(&window, 0, TRAY_CALLBACK_MESSAGE, "ripclip", &module).unwrap())
    } else {
       None
    };
 
-   win::add_clipboard_format_listener(&window).unwrap();
+   watch_clipboard(&window);
+   let mut listener_watchdog = watch::ListenerWatchdog::new(win::get_clipboard_sequence_number());
+   // Tells us when the workstation is locked, for `clear_on_lock`
+   let session_notifications = match win::wts_register_session_notification(&window) {
+      Ok(()) => true,
+      Err(e) => {
+         warn!(
+            "Failed to register for session notifications, so locking won't clear anything: {}",
+            e
+         );
+         false
+      }
+   };
+
+   let mut keybindings = set_keybindings(&config, &window);
+   report_hotkey_failures(&keybindings.failures, &trayicon);
 
-   set_keybindings(&config, &window);
+   let mut config_watcher = config::ConfigWatcher::new(config_files);
+   win::set_timer(&window, CONFIG_WATCH_TIMER_ID, CONFIG_WATCH_INTERVAL_MS).unwrap();
+   win::set_timer(&window, FULLSCREEN_TIMER_ID, FULLSCREEN_CHECK_INTERVAL_MS).unwrap();
+   win::set_timer(&window, EXPIRY_TIMER_ID, EXPIRY_INTERVAL_MS).unwrap();
+   win::set_timer(&window, LISTENER_WATCHDOG_TIMER_ID, LISTENER_WATCHDOG_INTERVAL_MS).unwrap();
 
    loop {
-      let message = win::get_message(Some(&window), 0, 0).unwrap();
+      persistence.update(&window, &clipboard_stack, &config);
+      tray_tooltip.update(&trayicon, &clipboard_stack, paused, fullscreen_pause.active, &config);
+      tray_badge.update(&trayicon, &module, clipboard_stack.len(), &config);
+      let message = wait_for_message(&mut |request| {
+         shutdown.handle(
+            request,
+            &mut ShutdownSteps::new(
+               &window,
+               &config,
+               &mut keybindings,
+               &clipboard_stack,
+               &mut persistence,
+               &mut trayicon,
+            ),
+         );
+      });
+      // CAPTURE_DONE_MESSAGE is dropped if it arrives while a menu or message box is open, so whatever the capture
+      // thread has read is picked up with any message
+      for captured in capture_reader.ready() {
+         apply_capture(
+            captured,
+            &mut clipboard_stack,
+            &mut last_push,
+            &mut managing_clipboard,
+            &trayicon,
+            &config,
+         );
+      }
+      // Left by whatever failed while handling the last message
+      if let Some(notice) = FAILURE_NOTICE.with(|x| x.borrow_mut().take()) {
+         notify(&trayicon, "ripclip", &notice);
+      }
+      // Hotkeys, the menu, the stack viewer, locking and exiting can all change the stack or the clipboard, so a copy
+      // still waiting to be read goes on the stack first. Otherwise a pop right after copying would pop what was
+      // there before it, and a copy made just before locking would be pushed after the stack was cleared.
+      if matches!(
+         message.message,
+         winapi::um::winuser::WM_HOTKEY
+            | HOOK_BINDING_MESSAGE
+            | winapi::um::winuser::WM_COMMAND
+            | winapi::um::winuser::WM_CONTEXTMENU
+            | VIEWER_KEY_MESSAGE
+            | winapi::um::winuser::WM_WTSSESSION_CHANGE
+            | SESSION_ENDING_MESSAGE
+            | EXIT_MESSAGE
+      ) {
+         if capture_pending {
+            trace!("Reading the clipboard early, before handling a hotkey or command");
+            let _ = win::kill_timer(&window, CAPTURE_DEBOUNCE_TIMER_ID);
+            capture_pending = false;
+            request_capture(&mut capture_reader, &window, &config);
+         }
+         if capture_reader.is_busy() {
+            trace!("Waiting for the clipboard to be read, before handling a hotkey or command");
+         }
+         for captured in capture_reader.finish() {
+            apply_capture(
+               captured,
+               &mut clipboard_stack,
+               &mut last_push,
+               &mut managing_clipboard,
+               &trayicon,
+               &config,
+            );
+         }
+      }
+      if message.message == VIEWER_KEY_MESSAGE || message.message == VIEWER_CLOSE_MESSAGE {
+         on_viewer_event(
+            &message,
+            &mut viewer,
+            &window,
+            &mut clipboard_stack,
+            &mut managing_clipboard,
+            &config,
+         );
+         continue;
+      }
+      if message.hwnd.is_some() && !message.is_for(&window) {
+         // Our message loop only handles the main window itself; the stack viewer is handled by its window procedure
+         win::dispatch_message(&message);
+         continue;
+      }
+      if message.message == winapi::um::winuser::WM_CLIPBOARDUPDATE {
+         listener_watchdog.updated(win::get_clipboard_sequence_number());
+      }
       match message.message {
-         winapi::um::winuser::WM_HOTKEY => match message.w_param as u16 {
-            POP_HOTKEY_ID => {
-               pop(&window, &mut clipboard_stack, &mut managing_clipboard);
-            }
-            SWAP_HOTKEY_ID => {
-               swap(&window, &mut clipboard_stack, managing_clipboard);
-            }
-            CLEAR_HOTKEY_ID => {
-               clear(&window, &mut clipboard_stack, &mut managing_clipboard);
+         winapi::um::winuser::WM_HOTKEY | HOOK_BINDING_MESSAGE => {
+            match message.w_param as u16 % HOTKEY_ID_STRIDE {
+               POP_HOTKEY_ID => {
+                  let placed = pop(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut popped,
+                     &mut pop_cursor,
+                     &mut managing_clipboard,
+                     &config,
+                     &history,
+                  );
+                  if placed && config.paste_after_pop {
+                     paste(&config, &trayicon, &mut warned_elevated);
+                  }
+               }
+               SWAP_HOTKEY_ID => {
+                  swap(&window, &mut clipboard_stack, &mut managing_clipboard, &config);
+               }
+               CLEAR_HOTKEY_ID => {
+                  clear(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut popped,
+                     &mut last_clear,
+                     &mut managing_clipboard,
+                     &config,
+                  );
+               }
+               UNDO_CLEAR_HOTKEY_ID => {
+                  undo_clear(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut last_clear,
+                     &mut managing_clipboard,
+                     &config,
+                  );
+               }
+               PUSH_HOTKEY_ID => {
+                  push(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut managing_clipboard,
+                     &config,
+                     &trayicon,
+                  );
+               }
+               PEEK_HOTKEY_ID => {
+                  peek(
+                     &mut clipboard_stack,
+                     &pop_cursor,
+                     &mut managing_clipboard,
+                     &config,
+                     &trayicon,
+                  );
+               }
+               PAUSE_HOTKEY_ID => {
+                  toggle_pause(&mut paused);
+               }
+               ROTATE_HOTKEY_ID => {
+                  rotate(&window, &mut clipboard_stack, managing_clipboard, &config);
+               }
+               DROP_HOTKEY_ID => {
+                  drop_top(&mut clipboard_stack, &mut managing_clipboard);
+               }
+               POP_ALL_HOTKEY_ID => {
+                  pop_all(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut managing_clipboard,
+                     &config,
+                     &history,
+                  );
+               }
+               DUPLICATE_TOP_HOTKEY_ID => {
+                  // The clipboard already holds the top of the stack, so there's nothing to write
+                  if stack::duplicate_top(&mut clipboard_stack, &config) {
+                     trace!("Duplicated top of stack");
+                  } else if clipboard_stack.is_empty() {
+                     trace!("Nothing on stack to duplicate");
+                  } else {
+                     debug!("Not duplicating the top of the stack because the stack is full");
+                  }
+               }
+               UNDO_POP_HOTKEY_ID => {
+                  undo_pop(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut popped,
+                     &mut managing_clipboard,
+                     &config,
+                  );
+               }
+               SHOW_STACK_HOTKEY_ID => {
+                  show_stack(&mut viewer, &viewer_class, &clipboard_stack);
+               }
+               REVERSE_HOTKEY_ID => {
+                  reverse(&window, &mut clipboard_stack, managing_clipboard, &config);
+               }
+               MOVE_TO_BOTTOM_HOTKEY_ID => {
+                  move_to_bottom(&window, &mut clipboard_stack, managing_clipboard, &config);
+               }
+               COPY_ALL_HOTKEY_ID => {
+                  copy_all(&window, &clipboard_stack, &mut managing_clipboard, &config);
+               }
+               SAVE_SNAPSHOT_HOTKEY_ID => {
+                  save_snapshot(&clipboard_stack, &config, &trayicon);
+               }
+               PASTE_SECOND_HOTKEY_ID => {
+                  paste_second(&window, &clipboard_stack, &mut managing_clipboard, &config);
+               }
+               EXPORT_HOTKEY_ID => {
+                  start_export(&clipboard_stack, &config, &mut export, &trayicon);
+               }
+               PIN_TOP_HOTKEY_ID => {
+                  pin_top(&mut clipboard_stack, &config);
+               }
+               IMPORT_HOTKEY_ID => {
+                  import_stack(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut managing_clipboard,
+                     &config,
+                     &trayicon,
+                  );
+               }
+               id if id > INDEXED_POP_HOTKEY_ID_BASE
+                  && id <= INDEXED_POP_HOTKEY_ID_BASE + config::INDEXED_POP_KEYS.len() as u16 =>
+               {
+                  pop_at_depth(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut popped,
+                     &mut managing_clipboard,
+                     usize::from(id - INDEXED_POP_HOTKEY_ID_BASE),
+                     &history,
+                     &config,
+                  );
+               }
+               RESTORE_SNAPSHOT_HOTKEY_ID => {
+                  restore_snapshot(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut managing_clipboard,
+                     &config,
+                     &trayicon,
+                  );
+               }
+               id if id >= LEADER_HOTKEY_ID_BASE => {
+                  start_sequence(&window, &keybindings, id);
+               }
+               x => {
+                  warn!("Unknown hotkey {}", x);
+               }
             }
-            x => {
-               warn!("Unknown hotkey {}", x);
+            refresh_viewer(&mut viewer, &clipboard_stack);
+         }
+         SEQUENCE_END_MESSAGE => {
+            if message.w_param == 0 {
+               trace!("Sequence cancelled");
             }
-         },
-         winapi::um::winuser::WM_CLIPBOARDUPDATE => {
-            trace!("Clipboard updated!");
-            if win::is_clipboard_format_available(win::ClipboardFormat::UnicodeText) {
-               trace!("Unicode text available");
-               win::remove_clipboard_format_listener(&window).unwrap();
-               let clipboard_text = {
-                  let clipboard = open_clipboard_with_backoff(&window).unwrap();
-                  let text_buf = clipboard.get_text().unwrap();
-                  let owned_clipboard = clipboard.empty().unwrap();
-                  owned_clipboard.set_text(text_buf.clone()).unwrap();
-                  text_buf
-               };
-               win::add_clipboard_format_listener(&window).unwrap();
-               if config.prevent_duplicate_push && Some(&clipboard_text) == clipboard_stack.back() {
-                  trace!("Ignoring push because it was a duplicate");
-               } else {
-                  if Some(clipboard_stack.len()) == config.max_stack_size {
-                     clipboard_stack.pop_front();
-                  }
-                  clipboard_stack.push_back(clipboard_text);
-                  trace!("Pushed clipboard contents onto stack");
+            end_sequence(&window, &keybindings);
+         }
+         EXPORT_DONE_MESSAGE => {
+            finish_export(&mut export, &trayicon);
+         }
+         CAPTURE_DONE_MESSAGE => {
+            // Already picked up above
+         }
+         winapi::um::winuser::WM_CLIPBOARDUPDATE if is_own_write() => {
+            trace!("Ignoring clipboard update caused by our own write");
+         }
+         winapi::um::winuser::WM_CLIPBOARDUPDATE if paused => {
+            log!(
+               skip_level(&config),
+               "Ignoring clipboard update because monitoring is paused"
+            );
+            // Whatever was copied isn't on the stack
+            managing_clipboard = false;
+         }
+         winapi::um::winuser::WM_CLIPBOARDUPDATE if fullscreen_pause.active => {
+            log!(
+               skip_level(&config),
+               "Ignoring clipboard update because a fullscreen program is in the foreground"
+            );
+            managing_clipboard = false;
+         }
+         winapi::um::winuser::WM_CLIPBOARDUPDATE if config.capture_debounce_ms > 0 => {
+            trace!("Clipboard updated, waiting for it to settle");
+            // Setting the timer again restarts it, so it fires once the clipboard stops changing
+            match win::set_timer(&window, CAPTURE_DEBOUNCE_TIMER_ID, config.capture_debounce_ms) {
+               Ok(()) => capture_pending = true,
+               Err(e) => {
+                  warn!(
+                     "Failed to wait for the clipboard to settle, reading it right away: {}",
+                     e
+                  );
+                  request_capture(&mut capture_reader, &window, &config);
                }
-               managing_clipboard = true;
-            } else {
-               managing_clipboard = false;
             }
          }
+         winapi::um::winuser::WM_CLIPBOARDUPDATE => {
+            request_capture(&mut capture_reader, &window, &config);
+         }
          winapi::um::winuser::WM_CONTEXTMENU => {
             let x = winapi::shared::windowsx::GET_X_LPARAM(message.w_param as isize);
             let y = winapi::shared::windowsx::GET_Y_LPARAM(message.w_param as isize);
+            let menu = tray_menu(
+               &clipboard_stack,
+               last_clear.is_some(),
+               paused,
+               &config,
+               config_file(&args).is_some(),
+               &keybindings.failures,
+            );
+            tray_menu_changes = Some(clipboard_stack.changes());
+            // Otherwise the menu stays open when clicking away from it
             win::set_foreground_window(&window).unwrap();
             win::draw_popup_menu(&menu, x, y, &window).unwrap();
          }
-         winapi::um::winuser::WM_QUIT => {
-            break;
+         winapi::um::winuser::WM_WTSSESSION_CHANGE => match message.w_param {
+            winapi::um::winuser::WTS_SESSION_LOCK => {
+               if config.clear_on_lock {
+                  clear_all(
+                     &window,
+                     &mut clipboard_stack,
+                     &mut popped,
+                     &mut last_clear,
+                     &mut pop_cursor,
+                     &mut managing_clipboard,
+                  );
+                  // Saved now, rather than after a delay, so the file doesn't keep what was cleared
+                  persistence.update(&window, &clipboard_stack, &config);
+               }
+               if config.clear_clipboard_on_lock && set_clipboard_entry(&window, None, &config) {
+                  managing_clipboard = false;
+                  trace!("Emptied the clipboard because the workstation was locked");
+               }
+            }
+            winapi::um::winuser::WTS_SESSION_UNLOCK => {
+               trace!("Workstation unlocked");
+            }
+            _ => (),
+         },
+         winapi::um::winuser::WM_INPUTLANGCHANGE => {
+            if refresh_layout_keybindings(&config, &window, &mut keybindings) {
+               report_hotkey_failures(&keybindings.failures, &trayicon);
+            }
+         }
+         winapi::um::winuser::WM_TIMER => match message.w_param {
+            CONFIG_WATCH_TIMER_ID => {
+               // Other applications changing keyboard layout don't tell us, so keep checking
+               if refresh_layout_keybindings(&config, &window, &mut keybindings) {
+                  report_hotkey_failures(&keybindings.failures, &trayicon);
+               }
+               if config_watcher.poll() {
+                  info!("Configuration file changed, reloading");
+                  let reloaded = reload_config(
+                     &args,
+                     &mut config,
+                     &window,
+                     &module,
+                     &mut trayicon,
+                     &mut keybindings,
+                     &mut clipboard_stack,
+                     &mut managing_clipboard,
+                     &mut config_watcher,
+                  );
+                  if reloaded {
+                     report_hotkey_failures(&keybindings.failures, &trayicon);
+                     // Reloading registered the hotkeys again, and may have made a new tray icon
+                     fullscreen_pause.hotkeys_suspended = false;
+                     tray_tooltip.invalidate();
+                     tray_badge.invalidate();
+                     fullscreen_pause.update(&window, &config, &mut keybindings);
+                     update_history(&mut history, &config);
+                  }
+               }
+            }
+            LISTENER_WATCHDOG_TIMER_ID => {
+               if listener_watchdog.check(win::get_clipboard_sequence_number(), is_own_write()) {
+                  warn!("The clipboard changed without ripclip hearing about it, so watching it again");
+                  unwatch_clipboard(&window);
+                  watch_clipboard(&window);
+                  info!(
+                     "Recovered watching the clipboard, {} times since starting",
+                     listener_watchdog.recoveries()
+                  );
+                  // Catches up on the copy that was missed, though any before it are lost
+                  if !paused && !fullscreen_pause.active {
+                     request_capture(&mut capture_reader, &window, &config);
+                  }
+               }
+            }
+            FULLSCREEN_TIMER_ID => {
+               fullscreen_pause.update(&window, &config, &mut keybindings);
+            }
+            SEQUENCE_TIMER_ID => {
+               trace!("Timed out waiting for the rest of a sequence");
+               end_sequence(&window, &keybindings);
+            }
+            EXPIRY_TIMER_ID => {
+               expire(&mut clipboard_stack, &mut managing_clipboard, &config);
+            }
+            PERSIST_TIMER_ID => {
+               persistence.save(&window, &clipboard_stack);
+            }
+            UNDO_CLEAR_TIMER_ID => {
+               trace!("Forgetting the entries removed by the last clear");
+               set_last_clear(&window, &mut last_clear, None, None);
+            }
+            CAPTURE_DEBOUNCE_TIMER_ID => {
+               let _ = win::kill_timer(&window, CAPTURE_DEBOUNCE_TIMER_ID);
+               // A hotkey may have already read the clipboard
+               if capture_pending {
+                  capture_pending = false;
+                  request_capture(&mut capture_reader, &window, &config);
+               }
+            }
+            x => {
+               warn!("Unknown timer {}", x);
+            }
+         },
+         winapi::um::winuser::WM_QUIT | EXIT_MESSAGE | SESSION_ENDING_MESSAGE | SESSION_END_MESSAGE => {
+            let request = match message.message {
+               SESSION_ENDING_MESSAGE => shutdown::Request::QueryEndSession,
+               SESSION_END_MESSAGE => shutdown::Request::EndSession(message.w_param != 0),
+               _ => shutdown::Request::Exit,
+            };
+            let mut steps = ShutdownSteps::new(
+               &window,
+               &config,
+               &mut keybindings,
+               &clipboard_stack,
+               &mut persistence,
+               &mut trayicon,
+            );
+            if shutdown.handle(request, &mut steps) {
+               break;
+            }
          }
          winapi::um::winuser::WM_COMMAND => {
             if message.w_param & 0xFFFF_FFFF_0000_0000 == 0 {
                // Menu event
                match message.w_param & 0x0000_0000_FFFF_FFFF {
                   POP_MENU_ID => {
-                     pop(&window, &mut clipboard_stack, &mut managing_clipboard);
+                     pop(
+                        &window,
+                        &mut clipboard_stack,
+                        &mut popped,
+                        &mut pop_cursor,
+                        &mut managing_clipboard,
+                        &config,
+                        &history,
+                     );
                   }
                   SWAP_MENU_ID => {
-                     swap(&window, &mut clipboard_stack, managing_clipboard);
+                     swap(&window, &mut clipboard_stack, &mut managing_clipboard, &config);
                   }
                   CLEAR_MENU_ID => {
-                     clear(&window, &mut clipboard_stack, &mut managing_clipboard);
+                     clear(
+                        &window,
+                        &mut clipboard_stack,
+                        &mut popped,
+                        &mut last_clear,
+                        &mut managing_clipboard,
+                        &config,
+                     );
+                  }
+                  UNDO_CLEAR_MENU_ID => {
+                     undo_clear(
+                        &window,
+                        &mut clipboard_stack,
+                        &mut last_clear,
+                        &mut managing_clipboard,
+                        &config,
+                     );
                   }
                   EXIT_MENU_ID => {
-                     break;
+                     // Exits the same way as everything else that asks to
+                     if unsafe { winapi::um::winuser::PostMessageW(ptr::null_mut(), EXIT_MESSAGE, 0, 0) } == 0 {
+                        warn!("Failed to post exit event");
+                     }
+                  }
+                  PAUSE_MENU_ID => {
+                     toggle_pause(&mut paused);
+                  }
+                  PLAIN_TEXT_MENU_ID => {
+                     // Only until the configuration is next loaded
+                     let plain_text_only = !config.plain_text_only;
+                     Arc::make_mut(&mut config).plain_text_only = plain_text_only;
+                     info!(
+                        "{} plain text only mode",
+                        if config.plain_text_only { "Entered" } else { "Left" }
+                     );
+                  }
+                  EXPORT_MENU_ID => {
+                     start_export(&clipboard_stack, &config, &mut export, &trayicon);
+                  }
+                  IMPORT_MENU_ID => {
+                     import_stack(
+                        &window,
+                        &mut clipboard_stack,
+                        &mut managing_clipboard,
+                        &config,
+                        &trayicon,
+                     );
+                  }
+                  id if id > ENTRY_MENU_ID_BASE && id <= ENTRY_MENU_ID_BASE + MAX_TRAY_MENU_ENTRIES => {
+                     if tray_menu_changes == Some(clipboard_stack.changes()) {
+                        pop_at_depth(
+                           &window,
+                           &mut clipboard_stack,
+                           &mut popped,
+                           &mut managing_clipboard,
+                           id - ENTRY_MENU_ID_BASE,
+                           &history,
+                           &config,
+                        );
+                     } else {
+                        debug!("Not popping the entry chosen from the tray menu; the stack changed while it was open");
+                        notify(
+                           &trayicon,
+                           "ripclip",
+                           "The stack changed while the menu was open, so nothing was popped",
+                        );
+                     }
+                  }
+                  OPEN_CONFIG_MENU_ID => {
+                     open_config_file(&args, &trayicon);
+                  }
+                  STATUS_MENU_ID => {
+                     show_status(&listener_watchdog);
+                  }
+                  HOTKEY_PROBLEMS_MENU_ID => {
+                     show_hotkey_failures(&keybindings.failures);
                   }
                   RELOAD_MENU_ID => {
-                     match config::load_config() {
-                        Ok(new_config) => {
-                           if config.pop_keybinding.is_some() {
-                              win::unregister_hotkey(Some(&window), POP_HOTKEY_ID).unwrap();
-                           }
-                           if config.swap_keybinding.is_some() {
-                              win::unregister_hotkey(Some(&window), SWAP_HOTKEY_ID).unwrap();
-                           }
-                           if config.clear_keybinding.is_some() {
-                              win::unregister_hotkey(Some(&window), CLEAR_HOTKEY_ID).unwrap();
-                           }
-                           config = new_config;
-                           set_keybindings(&config, &window);
-                           // It's important the destructor is run before we create the new tray icon,
-                           // and that we destroy the tray icon if the prior configuration had it enabled
-                           _trayicon = None;
-                           if config.show_tray_icon {
-                              _trayicon = Some(win::add_tray_icon(&window, 0, 100, "ripclip", &module).unwrap());
-                           }
-                           trace!("Successfully reloaded configuration");
-                        }
-                        Err(e) => {
-                           eprintln!("Failed to parse config: {}", e);
-                        }
-                     };
+                     let reloaded = reload_config(
+                        &args,
+                        &mut config,
+                        &window,
+                        &module,
+                        &mut trayicon,
+                        &mut keybindings,
+                        &mut clipboard_stack,
+                        &mut managing_clipboard,
+                        &mut config_watcher,
+                     );
+                     if reloaded {
+                        report_hotkey_failures(&keybindings.failures, &trayicon);
+                        fullscreen_pause.hotkeys_suspended = false;
+                        tray_tooltip.invalidate();
+                        tray_badge.invalidate();
+                        fullscreen_pause.update(&window, &config, &mut keybindings);
+                        update_history(&mut history, &config);
+                     }
+                     config_watcher.mark_loaded();
                   }
                   _ => {
                      warn!("Unknown menu command");
@@ -182,121 +973,3351 @@ fn main() {
          }
       }
    }
+   if session_notifications {
+      let _ = win::wts_unregister_session_notification(&window);
+   }
+   // Leaving before the export is written would cut it short
+   finish_export(&mut export, &trayicon);
+}
+
+/// Pauses capture while a fullscreen program is in the foreground, for `pause_in_fullscreen`, and with
+/// `pause_hotkeys_in_fullscreen` lets go of the hotkeys so that the program gets those keys instead
+#[derive(Default)]
+struct FullscreenPause {
+   /// Whether a fullscreen program was in the foreground when last checked
+   active: bool,
+   /// Whether the hotkeys are unregistered
+   hotkeys_suspended: bool,
+}
+
+impl FullscreenPause {
+   /// Checks the foreground window again, and lets go of or registers the hotkeys to match
+   fn update(&mut self, window: &win::WindowHandle, config: &config::Config, keybindings: &mut Keybindings) {
+      let active = config.pause_in_fullscreen
+         && match win::foreground_is_fullscreen() {
+            Ok(fullscreen) => fullscreen,
+            Err(e) => {
+               debug!("Failed to find out whether the foreground window is fullscreen: {}", e);
+               self.active
+            }
+         };
+      if active != self.active {
+         self.active = active;
+         if active {
+            info!("Paused clipboard monitoring while a fullscreen program is in the foreground");
+         } else {
+            info!("Resumed clipboard monitoring now that no fullscreen program is in the foreground");
+         }
+      }
+      let suspend = active && config.pause_hotkeys_in_fullscreen;
+      if suspend != self.hotkeys_suspended {
+         self.hotkeys_suspended = suspend;
+         if suspend {
+            unset_keybindings(config, keybindings, window);
+            keybindings.hooks = None;
+         } else {
+            *keybindings = set_keybindings(config, window);
+         }
+      }
+   }
+}
+
+/// What shutting down cleans up
+struct ShutdownSteps<'a, 'b> {
+   window: &'b win::WindowHandle<'a>,
+   config: &'b config::Config,
+   keybindings: &'b mut Keybindings,
+   clipboard_stack: &'b stack::Stack<entry::Entry>,
+   persistence: &'b mut Persistence,
+   trayicon: &'b mut Option<win::TrayIcon<'a>>,
+}
+
+impl<'a, 'b> ShutdownSteps<'a, 'b> {
+   fn new(
+      window: &'b win::WindowHandle<'a>,
+      config: &'b config::Config,
+      keybindings: &'b mut Keybindings,
+      clipboard_stack: &'b stack::Stack<entry::Entry>,
+      persistence: &'b mut Persistence,
+      trayicon: &'b mut Option<win::TrayIcon<'a>>,
+   ) -> ShutdownSteps<'a, 'b> {
+      ShutdownSteps {
+         window,
+         config,
+         keybindings,
+         clipboard_stack,
+         persistence,
+         trayicon,
+      }
+   }
 }
 
-fn set_keybindings(config: &config::Config, window: &win::WindowHandle) {
-   if let Some(hotkey) = &config.pop_keybinding {
-      win::register_hotkey(Some(&window), POP_HOTKEY_ID, hotkey.modifiers, hotkey.key).unwrap();
+impl shutdown::Steps for ShutdownSteps<'_, '_> {
+   fn save_stack(&mut self) {
+      self.persistence.save(self.window, self.clipboard_stack);
    }
-   if let Some(hotkey) = &config.swap_keybinding {
-      win::register_hotkey(Some(&window), SWAP_HOTKEY_ID, hotkey.modifiers, hotkey.key).unwrap();
+
+   fn unregister_hotkeys(&mut self) {
+      unset_keybindings(self.config, self.keybindings, self.window);
+      self.keybindings.hooks = None;
+   }
+
+   fn stop_watching_clipboard(&mut self) {
+      unwatch_clipboard(self.window);
    }
-   if let Some(hotkey) = &config.clear_keybinding {
-      win::register_hotkey(Some(&window), CLEAR_HOTKEY_ID, hotkey.modifiers, hotkey.key).unwrap();
+
+   fn remove_tray_icon(&mut self) {
+      *self.trayicon = None;
    }
 }
 
-fn pop(window: &win::WindowHandle, clipboard_stack: &mut VecDeque<win::ClipboardText>, managing_clipboard: &mut bool) {
-   if *managing_clipboard {
-      clipboard_stack.pop_back();
-      trace!("Popped element off clipboard stack")
+thread_local! {
+   // What the message loop does about the session ending, while it's waiting for a message. Windows sends
+   // WM_QUERYENDSESSION and WM_ENDSESSION to the main window's procedure, on the main thread, and may end ripclip as
+   // soon as it's handled WM_ENDSESSION, so the procedure can't leave them to the message loop.
+   static SESSION_END_HANDLER: Cell<Option<*mut dyn FnMut(shutdown::Request)>> = Cell::new(None);
+}
+
+/// Waits for the next message, handing any word from Windows of the session ending to `on_session_end` in the meantime
+fn wait_for_message(on_session_end: &mut dyn FnMut(shutdown::Request)) -> win::Message {
+   // The handler can only be reached until get_message returns, while it's still borrowed here, so how long it lives
+   // can be forgotten
+   let handler = unsafe {
+      std::mem::transmute::<*mut (dyn FnMut(shutdown::Request) + '_), *mut (dyn FnMut(shutdown::Request) + 'static)>(
+         on_session_end,
+      )
+   };
+   SESSION_END_HANDLER.with(|x| x.set(Some(handler)));
+   let message = win::get_message(None, 0, 0);
+   SESSION_END_HANDLER.with(|x| x.set(None));
+   message.unwrap()
+}
+
+/// Handles Windows asking whether the session can end, or saying whether it's ending, before the main window's
+/// procedure returns. The message loop is told afterwards, which is too late to count on.
+unsafe fn on_session_end(
+   handle: winapi::shared::windef::HWND,
+   request: shutdown::Request,
+   message: u32,
+   w_param: usize,
+) {
+   // Taken while it runs, so that a message dispatched in the meantime can't run it again
+   match SESSION_END_HANDLER.with(|x| x.take()) {
+      Some(handler) => {
+         (*handler)(request);
+         SESSION_END_HANDLER.with(|x| x.set(Some(handler)));
+      }
+      None => warn!(
+         "Told about the session ending while busy rather than waiting for a message, so it's left to the message loop"
+      ),
    }
-   *managing_clipboard = true;
+   if winapi::um::winuser::PostMessageW(handle, message, w_param, 0) == 0 {
+      warn!("Failed to post session end event");
+   }
+}
 
-   win::remove_clipboard_format_listener(window).unwrap();
-   {
-      let clipboard = open_clipboard_with_backoff(window).unwrap();
-      let owned_clipboard = clipboard.empty().unwrap();
-      if let Some(text) = clipboard_stack.back() {
-         owned_clipboard.set_text(text.clone()).unwrap();
-         trace!("Placed top of stack in clipboard");
-      } else {
-         trace!("Nothing on stack to place in clipboard");
+/// The thread running the message loop, for `on_console_event` to post to
+static MAIN_THREAD: AtomicU32 = AtomicU32::new(0);
+
+/// Asks the main thread to exit when Ctrl+C is pressed in or the user closes an attached console
+unsafe extern "system" fn on_console_event(event: u32) -> winapi::shared::minwindef::BOOL {
+   match event {
+      winapi::um::wincon::CTRL_C_EVENT
+      | winapi::um::wincon::CTRL_BREAK_EVENT
+      | winapi::um::wincon::CTRL_CLOSE_EVENT => {
+         winapi::um::winuser::PostThreadMessageW(MAIN_THREAD.load(Ordering::SeqCst), EXIT_MESSAGE, 0, 0);
+         if event == winapi::um::wincon::CTRL_CLOSE_EVENT {
+            // Windows ends the process as soon as this returns, so wait for the main thread to finish and exit
+            // first. Windows won't wait more than a few seconds regardless.
+            std::thread::sleep(Duration::from_secs(5));
+         }
+         1
       }
+      _ => 0,
    }
-   win::add_clipboard_format_listener(window).unwrap();
 }
 
-fn clear(
-   window: &win::WindowHandle,
-   clipboard_stack: &mut VecDeque<win::ClipboardText>,
+/// Applies the configuration on disk, or keeps the current configuration if the new one doesn't parse.
+/// Returns whether the configuration was applied.
+#[allow(clippy::too_many_arguments)] // Everything the configuration controls has to be handed over
+fn reload_config<'a>(
+   args: &Args,
+   config: &mut Arc<config::Config>,
+   window: &'a win::WindowHandle,
+   module: &'a win::ModuleHandle,
+   trayicon: &mut Option<win::TrayIcon<'a>>,
+   keybindings: &mut Keybindings,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
    managing_clipboard: &mut bool,
-) {
-   clipboard_stack.clear();
-   win::remove_clipboard_format_listener(window).unwrap();
-   {
-      let clipboard = open_clipboard_with_backoff(window).unwrap();
-      clipboard.empty().unwrap();
+   config_watcher: &mut config::ConfigWatcher,
+) -> bool {
+   let (new_config, files) = match load_config(args) {
+      Ok(loaded) => loaded,
+      Err(e) => {
+         error!("Failed to reload config, keeping the current configuration: {}", e);
+         return false;
+      }
+   };
+   // Includes may have been added or removed
+   config_watcher.watch(files);
+   unset_keybindings(config, keybindings, window);
+   keybindings.hooks = None;
+   let tray_icon_changed = new_config.show_tray_icon != config.show_tray_icon;
+   *config = Arc::new(new_config);
+   logging::apply_config(config);
+   *keybindings = set_keybindings(config, window);
+   if clipboard_stack.set_mode(config.mode) {
+      info!("Switched to {:?} mode", config.mode);
+      // The top is now the other end, while the clipboard still holds the old top
+      *managing_clipboard = false;
    }
-   win::add_clipboard_format_listener(window).unwrap();
-   *managing_clipboard = true;
-   trace!("Cleared stack");
+   stack::evict(clipboard_stack, config);
+   if tray_icon_changed {
+      // It's important the destructor is run before we create the new tray icon
+      *trayicon = None;
+      if config.show_tray_icon {
+         *trayicon = Some(win::add_tray_icon(window, 0, TRAY_CALLBACK_MESSAGE, "ripclip", module).unwrap());
+      }
+   }
+   trace!("Successfully reloaded configuration");
+   true
 }
 
-fn swap(window: &win::WindowHandle, clipboard_stack: &mut VecDeque<win::ClipboardText>, managing_clipboard: bool) {
-   if !managing_clipboard {
-      trace!("Can't swap when the clipboard is not being managed by ripclip (clipboard contains non-text)");
-      return;
+/// Registers the hotkeys again if the keyboard layout has moved a key bound by scancode or character
+/// since they were registered.
+/// Returns whether they were registered again.
+fn refresh_layout_keybindings(
+   config: &config::Config,
+   window: &win::WindowHandle,
+   keybindings: &mut Keybindings,
+) -> bool {
+   if config.layout_mapping(&ForegroundLayout) == keybindings.layout_mapping {
+      return false;
    }
+   info!("Keyboard layout changed, registering hotkeys that depend on it again");
+   unset_keybindings(config, keybindings, window);
+   keybindings.hooks = None;
+   *keybindings = set_keybindings(config, window);
+   true
+}
 
-   if clipboard_stack.len() >= 2 {
-      let last_index = clipboard_stack.len() - 1;
-      clipboard_stack.swap(last_index, last_index - 1);
-      win::remove_clipboard_format_listener(window).unwrap();
-      {
-         let clipboard = open_clipboard_with_backoff(window).unwrap();
-         let owned_clipboard = clipboard.empty().unwrap();
-         owned_clipboard
-            .set_text(clipboard_stack.back().unwrap().clone())
-            .unwrap();
+fn unset_keybindings(config: &config::Config, keybindings: &Keybindings, window: &win::WindowHandle) {
+   for (&(_, hotkeys), &id) in config.keybindings().iter().zip(KEYBINDING_IDS.iter()) {
+      unregister_keybinding(window, id, hotkeys);
+   }
+   if config.indexed_pop_modifiers.is_some() {
+      for id in indexed_pop_hotkey_ids() {
+         // Any that failed to register were already reported by set_keybindings, so failures are expected here
+         let _ = win::unregister_hotkey(Some(window), id);
       }
-      win::add_clipboard_format_listener(window).unwrap();
-      trace!("Swapped top 2 elements of stack");
-   } else {
-      trace!("Stack too small to swap");
+   }
+   let mut leader_ids: Vec<u16> = keybindings.sequences.iter().map(|x| x.leader_id).collect();
+   leader_ids.sort_unstable();
+   leader_ids.dedup();
+   for id in leader_ids {
+      let _ = win::unregister_hotkey(Some(window), id);
    }
 }
 
-fn open_clipboard_with_backoff(hwnd: &win::WindowHandle) -> Result<win::ClipboardHandle, win::ErrorCode> {
-   // On Windows, only one application may have the clipboard open at a time
-   // Some applications fight us for the clipboard (especially after an operation),
-   // and so to avoid crashing we try to access the clipboard several times in a short succession.
-   // If we still can't access the clipboard after a long time (couple of hundred ms),
-   // the clipboard stack will be in a confusing state for the user.
-   // Currently, we will panic in such scenarios, but in the future (TODO)
-   // we may give back control of the stack and use a notification to let the user
-   // know that there was an issue accessing the clipboard and try to recover.
+fn unregister_keybinding(window: &win::WindowHandle, id: u16, hotkeys: &[config::Hotkey]) {
+   for i in 0..hotkeys.len() as u16 {
+      // Any that failed to register were already reported by register_keybinding, so failures are expected here
+      let _ = win::unregister_hotkey(Some(window), id + i * HOTKEY_ID_STRIDE);
+   }
+}
 
-   use std::time::{Duration, Instant};
-   use rand::Rng;
-   use rand::rngs::OsRng;
-   use rand::distributions::uniform::Uniform;
+/// Registers a hotkey, asking that holding it down doesn't repeat it if `no_repeat` is set.
+/// Windows versions before 7 don't support that, so then the hotkey is registered without it.
+fn register_hotkey(
+   window: &win::WindowHandle,
+   id: u16,
+   modifiers: win::Modifiers,
+   key_code: u32,
+   no_repeat: bool,
+) -> Result<(), win::ErrorCode> {
+   if no_repeat {
+      match win::register_hotkey(Some(window), id, modifiers | win::Modifiers::NO_REPEAT, key_code) {
+         Err(win::ERROR_INVALID_FLAGS) => debug!("Hotkeys can't be kept from repeating here; registering without"),
+         result => return result,
+      }
+   }
+   win::register_hotkey(Some(window), id, modifiers, key_code)
+}
 
-   let mut sleep_duration = Duration::from_millis(1);
-   let mut open_result = win::open_clipboard(hwnd);
-   let start_time = Instant::now();
-   while start_time.elapsed() <= Duration::from_millis(500) {
-      // Try to open clipboard
-      match open_result {
-         Err(win::ERROR_ACCESS_DENIED) => trace!("Clipboard is locked, backing off"),
-         _ => break,
+/// Registers each hotkey in `hotkeys` for the action `id`. A hotkey that can't be registered,
+/// e.g. because another application (or keyboard software) already took it, is added to the failures.
+/// For a sequence, only its first step is registered, the first time it's seen.
+fn register_keybinding(
+   window: &win::WindowHandle,
+   id: u16,
+   name: &'static str,
+   hotkeys: &[config::Hotkey],
+   config: &config::Config,
+   registration: &mut Registration,
+) {
+   for (i, hotkey) in hotkeys.iter().enumerate() {
+      let hotkey_id = id + i as u16 * HOTKEY_ID_STRIDE;
+      let then = match hotkey.then {
+         Some(then) => then,
+         None => {
+            bind_hotkey(window, hotkey_id, name, *hotkey, config, registration);
+            continue;
+         }
+      };
+      let leader = hotkey.leader();
+      let leader_index = match registration.leaders.iter().position(|x| *x == leader) {
+         Some(index) => index,
+         None => {
+            let leader_id = LEADER_HOTKEY_ID_BASE + registration.leaders.len() as u16;
+            bind_hotkey(window, leader_id, name, leader, config, registration);
+            registration.leaders.push(leader);
+            registration.leaders.len() - 1
+         }
+      };
+      registration.sequences.push(SequenceStep {
+         leader_id: LEADER_HOTKEY_ID_BASE + leader_index as u16,
+         leader_key: leader.key,
+         key: then,
+         id: hotkey_id,
+      });
+   }
+}
+
+/// Registers a single hotkey as `id`. Mouse buttons can't be registered as hotkeys, so those are left for
+/// the hooks instead, as is everything if the configuration asks for a keyboard hook.
+/// Keys bound by scancode or character are registered as whichever key that is in the current keyboard layout.
+fn bind_hotkey(
+   window: &win::WindowHandle,
+   id: u16,
+   name: &'static str,
+   hotkey: config::Hotkey,
+   config: &config::Config,
+   registration: &mut Registration,
+) {
+   let (modifiers, code) = match hotkey.resolve(&ForegroundLayout) {
+      Ok(resolved) => resolved,
+      Err(e) => {
+         registration.failures.push(HotkeyFailure {
+            option: name,
+            hotkey,
+            error: HotkeyError::Layout(e),
+         });
+         return;
       }
-      std::thread::sleep(sleep_duration);
-      // "Decorrelated jitter"
-      let range = Uniform::new_inclusive(Duration::from_millis(1), sleep_duration * 3);
-      sleep_duration = std::cmp::min(Duration::from_millis(50), OsRng.sample(range));
-      open_result = win::open_clipboard(hwnd);
+   };
+   if hotkey.key.is_mouse_button() || config.use_keyboard_hook {
+      registration.hooked.push(HookBinding {
+         modifiers,
+         key: hotkey.key,
+         code,
+         id,
+      });
+      return;
+   }
+   if let Err(e) = register_hotkey(window, id, modifiers, code, config.hotkey_no_repeat) {
+      registration.failures.push(HotkeyFailure {
+         option: name,
+         hotkey,
+         error: HotkeyError::Register(e),
+      });
    }
-   open_result
 }
 
-unsafe extern "system" fn on_message(
-   handle: winapi::shared::windef::HWND,
-   umsg: u32,
-   w_param: usize,
-   l_param: isize,
-) -> winapi::shared::minwindef::LRESULT {
+fn indexed_pop_hotkey_ids() -> impl Iterator<Item = u16> {
+   (1..=config::INDEXED_POP_KEYS.len() as u16).map(|x| INDEXED_POP_HOTKEY_ID_BASE + x)
+}
+
+/// Registers the hotkeys in `config`. Every hotkey that can be registered is, even if others fail.
+fn set_keybindings(config: &config::Config, window: &win::WindowHandle) -> Keybindings {
+   let mut registration = Registration::default();
+   for (&(name, hotkeys), &id) in config.keybindings().iter().zip(KEYBINDING_IDS.iter()) {
+      register_keybinding(window, id, name, hotkeys, config, &mut registration);
+   }
+   if let Some(modifiers) = config.indexed_pop_modifiers {
+      // Each number is registered on its own, so one being taken by another application doesn't lose the rest
+      for (id, key) in indexed_pop_hotkey_ids().zip(config::INDEXED_POP_KEYS.iter()) {
+         let hotkey = config::Hotkey {
+            key: config::Key::Virtual(*key),
+            modifiers,
+            then: None,
+         };
+         register_keybinding(
+            window,
+            id,
+            "indexed_pop_modifiers",
+            &[hotkey],
+            config,
+            &mut registration,
+         );
+      }
+   }
+   for failure in registration.failures.iter() {
+      warn!("{}", failure);
+   }
+   Keybindings {
+      hooks: set_hook_bindings(
+         registration.hooked,
+         registration.sequences.clone(),
+         config.hotkey_no_repeat,
+      ),
+      sequences: registration.sequences,
+      failures: registration.failures,
+      layout_mapping: config.layout_mapping(&ForegroundLayout),
+   }
+}
+
+/// What's collected while registering the configured hotkeys
+#[derive(Default)]
+struct Registration {
+   hooked: Vec<HookBinding>,
+   /// The first steps of sequences, in the order of their IDs
+   leaders: Vec<config::Hotkey>,
+   sequences: Vec<SequenceStep>,
+   failures: Vec<HotkeyFailure>,
+}
+
+/// The keyboard layout of the foreground window, which is the one the user is typing with
+struct ForegroundLayout;
+
+impl config::KeyboardLayout for ForegroundLayout {
+   fn scancode_key(&self, scancode: u16) -> Option<u32> {
+      win::scancode_to_virtual_key(scancode)
+   }
+
+   fn character_key(&self, c: char) -> Option<(u32, win::Modifiers)> {
+      win::character_to_virtual_key(c)
+   }
+}
+
+/// A hotkey that couldn't be registered
+struct HotkeyFailure {
+   option: &'static str,
+   hotkey: config::Hotkey,
+   error: HotkeyError,
+}
+
+enum HotkeyError {
+   Register(win::ErrorCode),
+   /// The hotkey's key couldn't be found in the keyboard layout, so there was nothing to register
+   Layout(config::ResolveError),
+}
+
+impl fmt::Display for HotkeyFailure {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match &self.error {
+         HotkeyError::Register(e) if *e == win::ERROR_HOTKEY_ALREADY_REGISTERED => write!(
+            f,
+            "{} ({}) is already in use by another application",
+            self.hotkey, self.option
+         ),
+         HotkeyError::Register(e) => write!(f, "{} ({}) couldn't be registered: {}", self.hotkey, self.option, e),
+         HotkeyError::Layout(e) => write!(f, "{} ({}) {}", self.hotkey, self.option, e),
+      }
+   }
+}
+
+/// The state of the configured hotkeys, once registered
+struct Keybindings {
+   /// Handles hotkeys that aren't registered with Windows and the second steps of sequences, if there are any
+   hooks: Option<HookThread>,
+   sequences: Vec<SequenceStep>,
+   failures: Vec<HotkeyFailure>,
+   /// What the keys that depend on the keyboard layout were when registered, from `Config::layout_mapping`
+   layout_mapping: Vec<Result<(win::Modifiers, u32), config::ResolveError>>,
+}
+
+/// The second step of a sequence, waited for once the hotkey `leader_id` is pressed
+#[derive(Clone, Copy)]
+struct SequenceStep {
+   leader_id: u16,
+   leader_key: config::Key,
+   key: win::VirtualKey,
+   /// The hotkey ID to dispatch once `key` is pressed
+   id: u16,
+}
+
+/// Lets the user know about hotkeys that don't work, which would otherwise just silently do nothing
+fn report_hotkey_failures(failures: &[HotkeyFailure], trayicon: &Option<win::TrayIcon>) {
+   match (failures, trayicon) {
+      ([], _) => (),
+      // Without the tray icon there's no menu to find the details in later, so show them now
+      (_, None) => show_hotkey_failures(failures),
+      ([failure], _) => notify(trayicon, "A hotkey doesn't work", &failure.to_string()),
+      _ => notify(
+         trayicon,
+         "Some hotkeys don't work",
+         &format!(
+            "{} hotkeys couldn't be registered. Choose Hotkey Problems in the tray menu for details.",
+            failures.len()
+         ),
+      ),
+   }
+}
+
+fn show_hotkey_failures(failures: &[HotkeyFailure]) {
+   let text = if failures.is_empty() {
+      String::from("All hotkeys are working.")
+   } else {
+      let lines: Vec<String> = failures.iter().map(|x| x.to_string()).collect();
+      format!("These hotkeys won't do anything:\n\n{}", lines.join("\n"))
+   };
+   let _ = win::message_box(
+      None,
+      &text,
+      "ripclip",
+      winapi::um::winuser::MB_OK | winapi::um::winuser::MB_ICONWARNING,
+   );
+}
+
+/// Builds the tray menu afresh each time it's opened, so that it shows the state of things then
+fn tray_menu(
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   can_undo_clear: bool,
+   paused: bool,
+   config: &config::Config,
+   has_config_file: bool,
+   hotkey_failures: &[HotkeyFailure],
+) -> win::Menu {
+   let mut menu = win::create_popup_menu().unwrap();
+   let listed = config.tray_menu_entries.min(MAX_TRAY_MENU_ENTRIES);
+   if listed > 0 {
+      if clipboard_stack.is_empty() {
+         menu.append_text(ENTRY_MENU_ID_BASE, "Stack is empty").unwrap();
+         menu.set_enabled(ENTRY_MENU_ID_BASE, false).unwrap();
+      }
+      for (depth, entry) in clipboard_stack.iter().rev().take(listed).enumerate() {
+         let id = ENTRY_MENU_ID_BASE + depth + 1;
+         menu
+            .append_text(id, &entry.menu_label(TRAY_MENU_PREVIEW_CHARS))
+            .unwrap();
+      }
+      menu.append_line_break(1).unwrap();
+   }
+   let clear_label = match clipboard_stack.len() {
+      1 => String::from("Clear Stack (1 entry)"),
+      count => format!("Clear Stack ({} entries)", count),
+   };
+   menu.append_text(POP_MENU_ID, "Pop").unwrap();
+   menu.append_text(SWAP_MENU_ID, "Swap").unwrap();
+   menu.append_text(CLEAR_MENU_ID, &clear_label).unwrap();
+   menu.set_enabled(CLEAR_MENU_ID, !clipboard_stack.is_empty()).unwrap();
+   menu.append_text(UNDO_CLEAR_MENU_ID, "Undo Clear").unwrap();
+   menu.set_enabled(UNDO_CLEAR_MENU_ID, can_undo_clear).unwrap();
+   menu.append_text(PAUSE_MENU_ID, "Pause Capture").unwrap();
+   menu.set_checked(PAUSE_MENU_ID, paused).unwrap();
+   menu.append_text(PLAIN_TEXT_MENU_ID, "Plain Text Only").unwrap();
+   menu.set_checked(PLAIN_TEXT_MENU_ID, config.plain_text_only).unwrap();
+   menu.append_text(EXPORT_MENU_ID, "Export Stack").unwrap();
+   menu.append_text(IMPORT_MENU_ID, "Import Stack").unwrap();
+   menu.append_line_break(1).unwrap();
+   menu.append_text(STATUS_MENU_ID, "Status...").unwrap();
+   menu.append_text(HOTKEY_PROBLEMS_MENU_ID, "Hotkey Problems...").unwrap();
+   menu
+      .set_enabled(HOTKEY_PROBLEMS_MENU_ID, !hotkey_failures.is_empty())
+      .unwrap();
+   menu
+      .append_text(OPEN_CONFIG_MENU_ID, "Open Configuration File")
+      .unwrap();
+   menu.set_enabled(OPEN_CONFIG_MENU_ID, has_config_file).unwrap();
+   menu.append_text(RELOAD_MENU_ID, "Reload Configuration").unwrap();
+   menu.append_text(EXIT_MENU_ID, "Exit").unwrap();
+   menu
+}
+
+/// Opens the configuration file in whatever edits it, or Notepad if nothing is set to
+fn open_config_file(args: &Args, trayicon: &Option<win::TrayIcon>) {
+   let path = match config_file(args) {
+      Some(path) => path,
+      None => return,
+   };
+   let path = path.to_string_lossy();
+   let result = win::shell_open(&path).or_else(|e| {
+      debug!("Failed to open the configuration file, trying Notepad: {}", e);
+      win::shell_open_with("notepad.exe", &path)
+   });
+   if let Err(e) = result {
+      warn!("Failed to open the configuration file {}: {}", path, e);
+      notify(
+         trayicon,
+         "ripclip",
+         &format!("Failed to open the configuration file: {}", e.to_string().trim_end()),
+      );
+   }
+}
+
+/// Shows how ripclip is watching the clipboard, and how often that's had to be started again
+fn show_status(listener_watchdog: &watch::ListenerWatchdog) {
+   let watch = match CLIPBOARD_WATCH.with(Cell::get) {
+      Some(ClipboardWatch::Listener) => "a clipboard format listener",
+      Some(ClipboardWatch::ViewerChain { .. }) => "the clipboard viewer chain",
+      None => "nothing",
+   };
+   let text = format!(
+      "Watching the clipboard with {}.\n\nWatching stopped working and was started again {} times.",
+      watch,
+      listener_watchdog.recoveries()
+   );
+   let _ = win::message_box(
+      None,
+      &text,
+      "ripclip",
+      winapi::um::winuser::MB_OK | winapi::um::winuser::MB_ICONINFORMATION,
+   );
+}
+
+/// A hotkey handled by one of our hooks rather than `RegisterHotKey`
+struct HookBinding {
+   modifiers: win::Modifiers,
+   key: config::Key,
+   /// The virtual key code of `key`
+   code: u32,
+   id: u16,
+}
+
+#[derive(Default)]
+struct HookBindings {
+   bindings: Vec<HookBinding>,
+   no_repeat: bool,
+   /// Keys and buttons whose press triggered a binding. Their release is hidden too,
+   /// so applications don't see half a key press.
+   held: Vec<u32>,
+}
+
+impl HookBindings {
+   /// Takes a press or release of the virtual key `code`, returning whether it was for a binding,
+   /// in which case it's kept from other applications
+   fn on_key(&mut self, code: u32, down: bool) -> bool {
+      let held = self.held.iter().position(|x| *x == code);
+      if !down {
+         return match held {
+            Some(i) => {
+               self.held.swap_remove(i);
+               true
+            }
+            None => false,
+         };
+      }
+      if held.is_some() && self.no_repeat {
+         // The key is being held down and auto-repeating
+         return true;
+      }
+      let modifiers = win::held_modifiers();
+      let binding = match self
+         .bindings
+         .iter()
+         .find(|x| x.code == code && x.modifiers - win::Modifiers::NO_REPEAT == modifiers)
+      {
+         Some(binding) => binding,
+         None => return false,
+      };
+      let result = unsafe {
+         winapi::um::winuser::PostThreadMessageW(
+            MAIN_THREAD.load(Ordering::SeqCst),
+            HOOK_BINDING_MESSAGE,
+            binding.id as usize,
+            0,
+         )
+      };
+      if result == 0 {
+         warn!("Failed to post hooked hotkey event");
+      }
+      if held.is_none() {
+         self.held.push(code);
+      }
+      true
+   }
+}
+
+thread_local! {
+   // Hooks are called on the thread that set them, which is the hook thread
+   static HOOK_BINDINGS: RefCell<HookBindings> = RefCell::new(HookBindings::default());
+}
+
+/// The thread the hooks are set on, which does nothing but pump the messages they're called from. Every mouse and
+/// key event on the system waits on the hooks, so they can't share the main thread, which can be held up by the
+/// clipboard for a while.
+struct HookThread {
+   thread_id: u32,
+   thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl HookThread {
+   /// Posts `message` to the thread, returning whether it could be
+   fn post(&self, message: u32, w_param: usize) -> bool {
+      unsafe { winapi::um::winuser::PostThreadMessageW(self.thread_id, message, w_param, 0) != 0 }
+   }
+}
+
+impl Drop for HookThread {
+   fn drop(&mut self) {
+      if !self.post(winapi::um::winuser::WM_QUIT, 0) {
+         // Without its message loop ending, joining would never return
+         warn!("Failed to stop the hook thread, leaving it running");
+         return;
+      }
+      if let Some(thread) = self.thread.take() {
+         if thread.join().is_err() {
+            warn!("The hook thread panicked");
+         }
+      }
+   }
+}
+
+/// Hands `bindings` to the hooks on a new thread, setting whichever hooks are needed to see them. The thread also
+/// waits for the second step of `sequences` when told to with `SEQUENCE_START_MESSAGE`.
+/// Returns `None` if there are neither bindings nor sequences, or the thread has nothing it can do.
+fn set_hook_bindings(bindings: Vec<HookBinding>, sequences: Vec<SequenceStep>, no_repeat: bool) -> Option<HookThread> {
+   if bindings.is_empty() && sequences.is_empty() {
+      return None;
+   }
+   let (started, thread_started) = mpsc::channel();
+   let spawned = std::thread::Builder::new().name(String::from("hooks")).spawn(move || {
+      let module = match win::get_module_handle_ex() {
+         Ok(module) => module,
+         Err(e) => {
+            warn!("Failed to get module handle; keybindings won't work: {}", e);
+            return;
+         }
+      };
+      let hooks = set_hooks(bindings, no_repeat, &module);
+      if hooks.is_empty() && sequences.is_empty() {
+         return;
+      }
+      win::create_message_queue();
+      let thread_id = unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() };
+      if started.send(thread_id).is_err() {
+         return;
+      }
+      // Watches for the second step of a sequence, while waiting for one. It's only held, to be unhooked once replaced.
+      let mut _sequence_hook: Option<win::Hook> = None;
+      loop {
+         match win::get_message(None, 0, 0) {
+            Ok(message) if message.message == winapi::um::winuser::WM_QUIT => break,
+            Ok(message) if message.message == SEQUENCE_START_MESSAGE => {
+               _sequence_hook = set_sequence_hook(&module, &sequences, message.w_param as u16);
+            }
+            Ok(message) if message.message == SEQUENCE_STOP_MESSAGE => {
+               _sequence_hook = None;
+               SEQUENCE_CAPTURE.with(|x| *x.borrow_mut() = None);
+            }
+            Ok(message) => win::dispatch_message(&message),
+            Err(e) => {
+               warn!(
+                  "Failed to get message on the hook thread; keybindings won't work: {}",
+                  e
+               );
+               break;
+            }
+         }
+      }
+   });
+   let thread = match spawned {
+      Ok(thread) => thread,
+      Err(e) => {
+         warn!("Failed to start the hook thread; keybindings won't work: {}", e);
+         return None;
+      }
+   };
+   match thread_started.recv() {
+      Ok(thread_id) => Some(HookThread {
+         thread_id,
+         thread: Some(thread),
+      }),
+      Err(_) => {
+         // The thread gave up before its message loop, having said why
+         let _ = thread.join();
+         None
+      }
+   }
+}
+
+/// Sets whichever hooks are needed to see `bindings`, on this thread
+fn set_hooks(bindings: Vec<HookBinding>, no_repeat: bool, module: &win::ModuleHandle) -> Vec<win::Hook<'_>> {
+   let needs_mouse_hook = bindings.iter().any(|x| x.key.is_mouse_button());
+   let needs_keyboard_hook = bindings.iter().any(|x| !x.key.is_mouse_button());
+   HOOK_BINDINGS.with(|x| {
+      *x.borrow_mut() = HookBindings {
+         bindings,
+         no_repeat,
+         held: Vec::new(),
+      }
+   });
+   let mut hooks = Vec::new();
+   if needs_mouse_hook {
+      match win::set_mouse_hook(module, Some(on_mouse_event)) {
+         Ok(hook) => hooks.push(hook),
+         Err(e) => warn!(
+            "Failed to set mouse hook; keybindings on mouse buttons won't work: {}",
+            e
+         ),
+      }
+   }
+   if needs_keyboard_hook {
+      match win::set_keyboard_hook(module, Some(on_keyboard_event)) {
+         Ok(hook) => hooks.push(hook),
+         Err(e) => warn!("Failed to set keyboard hook; keybindings won't work: {}", e),
+      }
+   }
+   hooks
+}
+
+/// A sequence whose first step was pressed
+struct SequenceCapture {
+   /// The second keys that finish the sequence, with the hotkey ID each dispatches
+   steps: Vec<(u32, u16)>,
+   /// The key of the first step, until it's released. Until then it may repeat, which shouldn't count as the second step.
+   leader_key: Option<u32>,
+}
+
+thread_local! {
+   // Like the hook bindings, this is the hook thread's
+   static SEQUENCE_CAPTURE: RefCell<Option<SequenceCapture>> = RefCell::new(None);
+}
+
+/// Starts waiting for the second step of the sequences that begin with the hotkey `leader_id`. The hook thread
+/// watches for it with a keyboard hook, so that no window has to take focus.
+fn start_sequence(window: &win::WindowHandle, keybindings: &Keybindings, leader_id: u16) {
+   let started = keybindings
+      .hooks
+      .as_ref()
+      .is_some_and(|x| x.post(SEQUENCE_START_MESSAGE, leader_id as usize));
+   if !started {
+      warn!("Failed to tell the hook thread to wait for the rest of a sequence; sequences won't work");
+      return;
+   }
+   if let Err(e) = win::set_timer(window, SEQUENCE_TIMER_ID, SEQUENCE_TIMEOUT_MS) {
+      warn!("Failed to set sequence timeout: {}", e);
+   }
+   trace!("Waiting for the rest of a sequence");
+}
+
+fn end_sequence(window: &win::WindowHandle, keybindings: &Keybindings) {
+   if let Some(hook_thread) = &keybindings.hooks {
+      // Failing means the thread is gone, and the hook along with it
+      hook_thread.post(SEQUENCE_STOP_MESSAGE, 0);
+   }
+   // The timer is already gone if it's what ended the sequence, so failing here is expected
+   let _ = win::kill_timer(window, SEQUENCE_TIMER_ID);
+}
+
+/// Sets the keyboard hook that waits for the second step of the sequences that begin with the hotkey `leader_id`,
+/// returning it. Called on the hook thread.
+fn set_sequence_hook<'a>(
+   module: &'a win::ModuleHandle,
+   sequences: &[SequenceStep],
+   leader_id: u16,
+) -> Option<win::Hook<'a>> {
+   let steps: Vec<&SequenceStep> = sequences.iter().filter(|x| x.leader_id == leader_id).collect();
+   let leader_key = steps.first()?.leader_key;
+   SEQUENCE_CAPTURE.with(|x| {
+      *x.borrow_mut() = Some(SequenceCapture {
+         steps: steps.iter().map(|x| (x.key as u32, x.id)).collect(),
+         leader_key: leader_key.resolve(&ForegroundLayout).ok().map(|(code, _)| code),
+      })
+   });
+   match win::set_keyboard_hook(module, Some(on_sequence_event)) {
+      Ok(hook) => Some(hook),
+      Err(e) => {
+         warn!("Failed to set keyboard hook; sequences won't work: {}", e);
+         None
+      }
+   }
+}
+
+/// Discards entries older than `max_entry_age`
+fn expire(clipboard_stack: &mut stack::Stack<entry::Entry>, managing_clipboard: &mut bool, config: &config::Config) {
+   let count = clipboard_stack.len();
+   if stack::expire(clipboard_stack, SystemTime::now(), config) {
+      // The clipboard still holds the old top of the stack, which is gone
+      *managing_clipboard = false;
+   }
+   if clipboard_stack.len() != count {
+      debug!("Discarded {} stale entries", count - clipboard_stack.len());
+   }
+}
+
+/// Returns whether an entry was placed in the clipboard
+fn pop(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   popped: &mut VecDeque<entry::Entry>,
+   pop_cursor: &mut Option<stack::Cursor>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   history: &Option<history::History>,
+) -> bool {
+   // Stale entries are skipped over, rather than placed in the clipboard
+   expire(clipboard_stack, managing_clipboard, config);
+   if !config.pop_removes_entry {
+      return step_down(window, clipboard_stack, pop_cursor, managing_clipboard, config);
+   }
+   // When the clipboard holds the top, popping it leaves the one below it on top. The clipboard is written first, so
+   // that the stack is left as it was if that fails.
+   let next = if *managing_clipboard {
+      clipboard_stack.iter().rev().nth(1)
+   } else {
+      clipboard_stack.back()
+   };
+   let written = write_clipboard(window, config, |clipboard| {
+      if let Some(entry) = next {
+         place_entry(clipboard, entry, config)?;
+         trace!("Placed top of stack in clipboard");
+      } else {
+         trace!("Nothing on stack to place in clipboard");
+      }
+      Ok(())
+   });
+   if !written {
+      return false;
+   }
+   if *managing_clipboard {
+      if let Some(entry) = clipboard_stack.pop_back() {
+         record_pop(history, &entry);
+         stack::remember_popped(popped, entry);
+      }
+      trace!("Popped element off clipboard stack")
+   }
+   *managing_clipboard = true;
+   !clipboard_stack.is_empty()
+}
+
+/// Pastes the clipboard into the active window by typing `paste_keystroke`. Windows silently drops what's typed into
+/// a window run as administrator when ripclip isn't, so that's skipped, and the first time, explained.
+fn paste(config: &config::Config, trayicon: &Option<win::TrayIcon>, warned_elevated: &mut bool) {
+   if foreground_is_elevated() {
+      if *warned_elevated {
+         debug!("Not pasting, as the foreground window is running as administrator");
+      } else {
+         notify(
+            trayicon,
+            "ripclip can't paste here",
+            "This window is running as administrator, which stops ripclip from pasting into it. Paste with Ctrl+V, \
+             or run ripclip as administrator too.",
+         );
+         *warned_elevated = true;
+      }
+      return;
+   }
+   let (modifiers, code) = match config.paste_keystroke.resolve(&ForegroundLayout) {
+      Ok(resolved) => resolved,
+      Err(e) => {
+         warn!(
+            "Failed to paste, as {} couldn't be typed: {}",
+            config.paste_keystroke, e
+         );
+         return;
+      }
+   };
+   if let Err(e) = win::send_keystroke(modifiers, code) {
+      warn!("Failed to paste: {}", e);
+   } else {
+      trace!("Typed {} to paste", config.paste_keystroke);
+   }
+}
+
+/// Puts the next entry down the stack on the clipboard, leaving the stack as it is, or the top if the clipboard
+/// doesn't hold an entry. From the bottom, it's back to the top. Returns whether an entry was placed in the clipboard.
+fn step_down(
+   window: &win::WindowHandle,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   pop_cursor: &mut Option<stack::Cursor>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) -> bool {
+   let depth = if *managing_clipboard {
+      stack::advance_cursor(pop_cursor, clipboard_stack)
+   } else {
+      *pop_cursor = None;
+      Some(1).filter(|_| !clipboard_stack.is_empty())
+   };
+   let depth = match depth {
+      Some(depth) => depth,
+      None => {
+         trace!("Nothing on stack to place in clipboard");
+         return false;
+      }
+   };
+   if !set_clipboard_entry(window, clipboard_stack.get(clipboard_stack.len() - depth), config) {
+      return false;
+   }
+   *managing_clipboard = true;
+   trace!("Placed entry {} of {} in clipboard", depth, clipboard_stack.len());
+   true
+}
+
+/// Starts writing popped entries to `history_file`, if it's set
+fn start_history(config: &config::Config) -> Option<history::History> {
+   let path = config.history_file.as_ref()?;
+   match history::History::start(path.clone(), config.history_max_bytes) {
+      Ok(history) => Some(history),
+      Err(e) => {
+         warn!("Failed to start writing to the history file {:#?}: {}", path, e);
+         None
+      }
+   }
+}
+
+/// Starts writing to the history file again if the configuration has changed where or how
+fn update_history(history: &mut Option<history::History>, config: &config::Config) {
+   let unchanged = match (history.as_ref(), config.history_file.as_ref()) {
+      (Some(history), Some(path)) => history.matches(path, config.history_max_bytes),
+      (None, None) => true,
+      _ => false,
+   };
+   if !unchanged {
+      // The old one has to finish writing before the new one starts, in case they're the same file
+      *history = None;
+      *history = start_history(config);
+   }
+}
+
+/// Adds an entry just popped to the history file, if there is one. Images are left out, having no text.
+fn record_pop(history: &Option<history::History>, entry: &entry::Entry) {
+   match history {
+      Some(history) if entry.kind().has_text() => history.record(entry.chars().collect(), SystemTime::now()),
+      _ => (),
+   }
+}
+
+fn pop_at_depth(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   popped: &mut VecDeque<entry::Entry>,
+   managing_clipboard: &mut bool,
+   depth: usize,
+   history: &Option<history::History>,
+   config: &config::Config,
+) {
+   if depth == 0 || depth > clipboard_stack.len() {
+      debug!(
+         "Can't pop entry {}; the stack only has {} entries",
+         depth,
+         clipboard_stack.len()
+      );
+      return;
+   }
+   // The entry stays on the stack if it can't be placed in the clipboard
+   if !set_clipboard_entry(window, clipboard_stack.get(clipboard_stack.len() - depth), config) {
+      return;
+   }
+   let entry = stack::remove_at_depth(clipboard_stack, depth).unwrap();
+   // The clipboard now holds an entry that's no longer on the stack
+   *managing_clipboard = false;
+   record_pop(history, &entry);
+   stack::remember_popped(popped, entry);
+   trace!("Popped entry {} off clipboard stack", depth);
+}
+
+fn paste_second(
+   window: &win::WindowHandle,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   match clipboard_stack.iter().rev().nth(1) {
+      Some(second) => {
+         if !set_clipboard_entry(window, Some(second), config) {
+            return;
+         }
+         // The stack is untouched, but the clipboard no longer holds its top
+         *managing_clipboard = false;
+         trace!("Placed second entry of stack in clipboard");
+      }
+      None => trace!("Not enough entries on stack to paste the second"),
+   }
+}
+
+fn drop_top(clipboard_stack: &mut stack::Stack<entry::Entry>, managing_clipboard: &mut bool) {
+   if clipboard_stack.pop_back().is_some() {
+      // The clipboard is left alone, so it no longer holds the top of the stack
+      *managing_clipboard = false;
+      trace!("Dropped element off clipboard stack");
+   } else {
+      trace!("Nothing on stack to drop");
+   }
+}
+
+fn pop_all(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   history: &Option<history::History>,
+) {
+   if clipboard_stack.is_empty() {
+      trace!("Nothing on stack to pop");
+      return;
+   }
+
+   let entries = stack::in_join_order(clipboard_stack, config.join_order);
+   let (joined, _) = stack::join(entries.into_iter().map(|x| x.chars()), &config.join_separator, None);
+   let written = write_clipboard(window, config, |clipboard| {
+      clipboard.set_text(win::ClipboardText::from(joined.as_str()))
+   });
+   if !written {
+      return;
+   }
+   clipboard_stack.clear();
+   if let Some(history) = history {
+      history.record(joined, SystemTime::now());
+   }
+   // The joined text isn't on the stack
+   *managing_clipboard = false;
+   trace!("Popped entire stack into clipboard");
+}
+
+fn undo_pop(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   popped: &mut VecDeque<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   if !stack::undo_pop(clipboard_stack, popped, config) {
+      if popped.is_empty() {
+         trace!("Nothing popped to restore");
+      } else {
+         debug!("Not restoring the popped element because the stack is full");
+      }
+      return;
+   }
+
+   set_clipboard_to_top(window, clipboard_stack, config);
+   *managing_clipboard = true;
+   trace!("Restored popped element onto stack");
+}
+
+fn copy_all(
+   window: &win::WindowHandle,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   if clipboard_stack.is_empty() {
+      trace!("Nothing on stack to copy");
+      return;
+   }
+
+   let entries = stack::in_join_order(clipboard_stack, config.join_order);
+   let (joined, truncated) = stack::join(
+      entries.into_iter().map(|x| x.chars()),
+      &config.join_separator,
+      Some(config.copy_all_max_bytes),
+   );
+   if truncated {
+      warn!(
+         "The joined stack is larger than copy_all_max_bytes ({} bytes), so only the start of it was copied",
+         config.copy_all_max_bytes
+      );
+   }
+   let written = write_clipboard(window, config, |clipboard| {
+      clipboard.set_text(win::ClipboardText::from(joined.as_str()))
+   });
+   if !written {
+      return;
+   }
+   // The joined text isn't on the stack
+   *managing_clipboard = false;
+   trace!("Copied entire stack into clipboard");
+}
+
+fn clear(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   popped: &mut VecDeque<entry::Entry>,
+   last_clear: &mut Option<stack::Cleared<entry::Entry>>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   let cleared = stack::clear_unpinned(clipboard_stack);
+   // Popped entries aren't left behind to be restored; undoing the clear is the way back
+   popped.clear();
+   // A pinned top is still what's on the clipboard, so that's left alone
+   if cleared.top_removed() {
+      set_clipboard_to_top(window, clipboard_stack, config);
+      *managing_clipboard = true;
+   }
+   if clipboard_stack.is_empty() {
+      trace!("Cleared stack");
+   } else {
+      trace!("Cleared stack, keeping {} pinned entries", clipboard_stack.len());
+   }
+   // Clearing nothing, such as by pressing clear twice, mustn't lose the clear before it
+   if cleared.is_empty() {
+      return;
+   }
+   let kept = match config.undo_clear_timeout {
+      Some(timeout) if timeout.is_zero() => None,
+      _ if config.max_stack_memory.is_some_and(|max| cleared.bytes() > max) => {
+         debug!(
+            "Not keeping the {} entries cleared to be restored; they take up more than max_stack_memory",
+            cleared.len()
+         );
+         None
+      }
+      _ => Some(cleared),
+   };
+   set_last_clear(window, last_clear, kept, config.undo_clear_timeout);
+}
+
+/// Removes every entry, pinned ones included, along with the entries kept to undo pops and clears, for
+/// `clear_on_lock`
+fn clear_all(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   popped: &mut VecDeque<entry::Entry>,
+   last_clear: &mut Option<stack::Cleared<entry::Entry>>,
+   pop_cursor: &mut Option<stack::Cursor>,
+   managing_clipboard: &mut bool,
+) {
+   clipboard_stack.clear();
+   popped.clear();
+   *pop_cursor = None;
+   // The clipboard is left alone, so it holds something no longer on the stack
+   *managing_clipboard = false;
+   set_last_clear(window, last_clear, None, None);
+   trace!("Cleared everything from the stack because the workstation was locked");
+}
+
+/// Replaces the entries kept to undo the last clear, forgetting them after `timeout`
+fn set_last_clear(
+   window: &win::WindowHandle,
+   last_clear: &mut Option<stack::Cleared<entry::Entry>>,
+   cleared: Option<stack::Cleared<entry::Entry>>,
+   timeout: Option<Duration>,
+) {
+   *last_clear = cleared;
+   if let (Some(_), Some(timeout)) = (&last_clear, timeout) {
+      let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+      if let Err(e) = win::set_timer(window, UNDO_CLEAR_TIMER_ID, timeout_ms) {
+         // Without the timer they'd be kept for good, which undo_clear_timeout is there to prevent
+         warn!(
+            "Failed to set timer to forget cleared entries, so the clear can't be undone: {}",
+            e
+         );
+         *last_clear = None;
+      }
+   }
+   if last_clear.is_none() {
+      let _ = win::kill_timer(window, UNDO_CLEAR_TIMER_ID);
+   }
+}
+
+fn undo_clear(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   last_clear: &mut Option<stack::Cleared<entry::Entry>>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   let cleared = match last_clear.take() {
+      Some(cleared) => cleared,
+      None => {
+         trace!("Nothing cleared to restore");
+         return;
+      }
+   };
+   set_last_clear(window, last_clear, None, None);
+   let count = cleared.len();
+   if stack::undo_clear(clipboard_stack, cleared, config) {
+      set_clipboard_to_top(window, clipboard_stack, config);
+      *managing_clipboard = true;
+   }
+   trace!("Restored {} cleared entries", count);
+}
+
+fn pin_top(clipboard_stack: &mut stack::Stack<entry::Entry>, config: &config::Config) {
+   match stack::toggle_pin_top(clipboard_stack) {
+      Some(true) => trace!("Pinned top of stack"),
+      Some(false) => {
+         trace!("Unpinned top of stack");
+         // Counting against the limits again may put the stack over them
+         stack::evict(clipboard_stack, config);
+      }
+      None => trace!("Nothing on stack to pin"),
+   }
+}
+
+fn push(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   if !clipboard_has_entry(config) {
+      debug!("Nothing to push; the clipboard doesn't contain text, files or an image");
+      return;
+   }
+
+   let clipboard_entry = match open_clipboard_with_backoff(window, config) {
+      Ok(clipboard) => read_clipboard_entry(&clipboard, config),
+      // Already logged by open_clipboard_with_backoff
+      Err(_) => return,
+   };
+   match clipboard_entry {
+      Ok(None) => {
+         *managing_clipboard = false;
+      }
+      Ok(Some((clipboard_entry, _))) => {
+         let pushed = stack::push(clipboard_stack, clipboard_entry, config);
+         report_push(pushed, clipboard_stack, trayicon, config);
+         *managing_clipboard = pushed.on_top();
+      }
+      Err(e) => {
+         debug!("Nothing to push; failed to read the clipboard: {}", e);
+      }
+   }
+}
+
+/// What the capture thread made of a clipboard update
+enum Captured {
+   /// The clipboard hasn't changed since it was last read, or has had the same copy placed on it again
+   Unchanged,
+   /// Nothing on the clipboard can go on the stack, or it couldn't be read
+   Nothing,
+   /// The clipboard couldn't be opened to be read
+   OpenFailed(win::ErrorCode),
+   /// What was copied, and whether it was placed back on the clipboard
+   Entry(entry::Entry, Rewrite),
+}
+
+/// Whether the capture thread placed the text it read back on the clipboard, converted and transformed as it is on
+/// the stack, which also leaves ripclip owning the clipboard
+enum Rewrite {
+   /// It was cut short, which only happens on the stack, or isn't text, or ripclip can't take the clipboard from the
+   /// capture thread
+   Skipped,
+   /// The clipboard's sequence number once it was written
+   Written(u32),
+   /// The clipboard couldn't be emptied, so still holds the copy as it was
+   Failed(win::ErrorCode),
+   /// The clipboard was emptied, but the copy couldn't be placed on it, so it's been left empty
+   Lost(win::ErrorCode),
+}
+
+/// What the capture thread is handed with each read
+struct CaptureRequest {
+   config: Arc<config::Config>,
+   /// The main window, which the clipboard is opened for so that text can be placed back on it. None in the viewer
+   /// chain, where closing the clipboard after writing to it sends WM_DRAWCLIPBOARD to the main window, which may be
+   /// waiting on the capture thread.
+   owner: Option<win::ClipboardOwner>,
+}
+
+type CaptureReader = capture::Reader<CaptureRequest, Captured>;
+
+/// Starts the thread that reads the clipboard after it changes, which keeps what it's seen to itself
+fn start_capture_reader() -> CaptureReader {
+   let mut capture_gate = watch::CaptureGate::default();
+   capture::Reader::start(
+      move |request: CaptureRequest| read_capture(&mut capture_gate, &request),
+      || unsafe {
+         winapi::um::winuser::PostThreadMessageW(MAIN_THREAD.load(Ordering::SeqCst), CAPTURE_DONE_MESSAGE, 0, 0);
+      },
+   )
+   .unwrap()
+}
+
+/// Has the capture thread read the clipboard after it's changed, with the configuration as it is now
+fn request_capture(capture_reader: &mut CaptureReader, window: &win::WindowHandle, config: &Arc<config::Config>) {
+   trace!("Clipboard updated!");
+   let owner = match CLIPBOARD_WATCH.with(Cell::get) {
+      Some(ClipboardWatch::Listener) => Some(win::clipboard_owner(window)),
+      Some(ClipboardWatch::ViewerChain { .. }) | None => None,
+   };
+   let request = CaptureRequest {
+      config: Arc::clone(config),
+      owner,
+   };
+   if !capture_reader.request(request) {
+      trace!("Not asking for another read of the clipboard, as those already waiting will see this update");
+   }
+}
+
+/// Reads what was copied from the clipboard, on the capture thread
+fn read_capture(capture_gate: &mut watch::CaptureGate, request: &CaptureRequest) -> Captured {
+   let config = &request.config;
+   let sequence_number = win::get_clipboard_sequence_number();
+   if capture_gate.check_sequence(sequence_number) == watch::Gate::Unchanged {
+      trace!("Ignoring clipboard update because the clipboard hasn't changed since it was last read");
+      return Captured::Unchanged;
+   }
+   if config.ignore_remote_clipboard && clipboard_is_remote() {
+      log!(
+         skip_level(config),
+         "Ignoring clipboard update because it came from a remote desktop session"
+      );
+      return Captured::Nothing;
+   }
+   if !config.ignored_applications.is_empty() {
+      if let Some(path) = clipboard_source_path() {
+         if config.ignored_applications.iter().any(|x| x.matches(&path)) {
+            log!(
+               skip_level(config),
+               "Ignoring clipboard update because it came from {}, which is in ignored_applications",
+               path
+            );
+            return Captured::Nothing;
+         }
+      }
+   }
+   if !clipboard_has_entry(config) {
+      // Some apps empty the clipboard before copying, and password managers empty it to clear a password
+      match win::count_clipboard_formats() {
+         0 => log!(
+            skip_level(config),
+            "Ignoring clipboard update because the clipboard was emptied"
+         ),
+         count => log!(
+            skip_level(config),
+            "Ignoring clipboard update because none of the {} formats on the clipboard can go on the stack",
+            count
+         ),
+      }
+      return Captured::Nothing;
+   }
+   trace!("Text, files or an image available");
+   // Emptying the clipboard while the main window owns it would wait on the main window, which may be waiting on
+   // the capture thread
+   let owner = request.owner.filter(|x| !win::owns_clipboard(*x));
+   let open = || match owner {
+      Some(owner) => win::open_clipboard_for(owner),
+      None => win::open_clipboard_unowned(),
+   };
+   let clipboard = match retry_open_clipboard(open, config) {
+      Ok(clipboard) => clipboard,
+      // Already logged by retry_open_clipboard
+      Err(e) => return Captured::OpenFailed(e),
+   };
+   if let Some(hash) = clipboard.contents_hash(&config.captured_formats()) {
+      let duplicate_window = Duration::from_millis(config.duplicate_window_ms);
+      if capture_gate.check_contents(hash, Instant::now(), duplicate_window) == watch::Gate::SameContents {
+         log!(
+            skip_level(config),
+            "Ignoring clipboard update because the same copy was placed on the clipboard again"
+         );
+         return Captured::Unchanged;
+      }
+   }
+   // The clipboard can be emptied again between being updated and being read
+   match read_clipboard_entry(&clipboard, config) {
+      Ok(Some((entry, truncated))) => {
+         if config.persist_stack {
+            // Images are encoded as PNG now, off the main thread, rather than when the stack is next saved
+            entry.saved_bytes();
+         }
+         let rewrite = if !truncated && owner.is_some() && matches!(entry.contents(), win::ClipboardEntry::Text(_)) {
+            rewrite_clipboard(clipboard, &entry, config)
+         } else {
+            Rewrite::Skipped
+         };
+         if let Rewrite::Written(sequence_number) = rewrite {
+            capture_gate.wrote(sequence_number);
+         }
+         Captured::Entry(entry, rewrite)
+      }
+      Ok(None) => Captured::Nothing,
+      Err(e) => {
+         log!(
+            skip_level(config),
+            "Ignoring clipboard update because the clipboard couldn't be read: {}",
+            e
+         );
+         Captured::Nothing
+      }
+   }
+}
+
+/// Places the text the capture thread read back on the clipboard, which it still has open. Files are left as they are,
+/// as Explorer's other formats say whether they were cut or copied.
+fn rewrite_clipboard(clipboard: win::ClipboardHandle, entry: &entry::Entry, config: &config::Config) -> Rewrite {
+   let clipboard = match clipboard.empty() {
+      Ok(clipboard) => clipboard,
+      Err(e) => {
+         warn!("Failed to empty the clipboard to place the copy back on it: {}", e);
+         return Rewrite::Failed(e);
+      }
+   };
+   let result = clipboard.set_entry(entry, config.plain_text_only);
+   // Closed first, so that the sequence number is the one the update carries
+   drop(clipboard);
+   match result {
+      Ok(()) => Rewrite::Written(win::get_clipboard_sequence_number()),
+      Err(e) => {
+         error!(
+            "Failed to place the copy back on the clipboard, which has been left empty: {}",
+            e
+         );
+         Rewrite::Lost(e)
+      }
+   }
+}
+
+/// Pushes what the capture thread read onto the stack
+fn apply_capture(
+   captured: Captured,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   last_push: &mut Option<stack::LastPush<entry::Entry>>,
+   managing_clipboard: &mut bool,
+   trayicon: &Option<win::TrayIcon>,
+   config: &config::Config,
+) {
+   let (clipboard_entry, rewrite) = match captured {
+      Captured::Unchanged => return,
+      Captured::Nothing => {
+         *managing_clipboard = false;
+         return;
+      }
+      Captured::OpenFailed(e) => {
+         record_clipboard_failure(failures::Operation::Open, &e);
+         *managing_clipboard = false;
+         return;
+      }
+      Captured::Entry(clipboard_entry, rewrite) => {
+         record_clipboard_success(failures::Operation::Open);
+         (clipboard_entry, rewrite)
+      }
+   };
+   let lost = matches!(rewrite, Rewrite::Lost(_));
+   match rewrite {
+      Rewrite::Skipped => (),
+      Rewrite::Written(sequence_number) => {
+         SELF_WRITES.with(|x| x.borrow_mut().wrote(sequence_number));
+         record_clipboard_success(failures::Operation::Write);
+      }
+      Rewrite::Failed(e) => record_clipboard_failure(failures::Operation::Write, &e),
+      Rewrite::Lost(e) => {
+         record_clipboard_failure(failures::Operation::Write, &e);
+         // Shown by the main loop, unless a notice about failing to write is shown instead
+         FAILURE_NOTICE.with(|x| {
+            x.borrow_mut().get_or_insert_with(|| {
+               format!(
+                  "ripclip emptied the clipboard to place a copy back on it, but couldn't: {} The clipboard has \
+                   been left empty, so copy it again to paste it.",
+                  e.to_string().trim_end()
+               )
+            });
+         });
+      }
+   }
+   let pushed = match stack::augment_newest(clipboard_stack, &clipboard_entry, SystemTime::now(), config) {
+      Some(augmented) => augmented,
+      None => stack::push_copy(clipboard_stack, clipboard_entry, last_push, Instant::now(), config),
+   };
+   report_push(pushed, clipboard_stack, trayicon, config);
+   // An emptied clipboard doesn't hold the top of the stack, even with the copy on top
+   *managing_clipboard = pushed.on_top() && !lost;
+}
+
+/// Whether what's on the clipboard was placed there by remote desktop, passing on a copy made in a remote session. If
+/// what placed it can't be found out, it's taken not to be.
+fn clipboard_is_remote() -> bool {
+   match clipboard_owner_name() {
+      Ok(Some(name)) => name.eq_ignore_ascii_case(REMOTE_CLIPBOARD_PROCESS),
+      Ok(None) => false,
+      Err(e) => {
+         debug!("Failed to find out which program placed a copy on the clipboard: {}", e);
+         false
+      }
+   }
+}
+
+/// The full path to the program whose window placed what's on the clipboard. Copies placed without a window, or by
+/// a program that can't be looked into, are taken to come from the program in the foreground, which is where they
+/// were almost certainly made.
+fn clipboard_source_path() -> Option<String> {
+   let process_path = |process: Result<Option<u32>, win::ErrorCode>| process?.map(win::get_process_path).transpose();
+   match process_path(win::get_clipboard_owner_process()) {
+      Ok(Some(path)) => return Some(path),
+      Ok(None) => (),
+      Err(e) => debug!("Failed to find out which program placed a copy on the clipboard: {}", e),
+   }
+   match process_path(win::get_foreground_window_process()) {
+      Ok(path) => path,
+      Err(e) => {
+         debug!("Failed to find out which program is in the foreground: {}", e);
+         None
+      }
+   }
+}
+
+/// The file name of the program whose window placed what's on the clipboard, or None if no window did
+fn clipboard_owner_name() -> Result<Option<String>, win::ErrorCode> {
+   match win::get_clipboard_owner_process()? {
+      Some(process_id) => win::get_process_name(process_id).map(Some),
+      None => Ok(None),
+   }
+}
+
+/// Whether the clipboard holds something that can go on the stack
+fn clipboard_has_entry(config: &config::Config) -> bool {
+   clipboard_capture(config).is_some()
+}
+
+/// What the copy on the clipboard is to be read as, as `entry::capture` decides
+fn clipboard_capture(config: &config::Config) -> Option<entry::Capture> {
+   let mut available: Vec<entry::Format> = [
+      win::ClipboardFormat::Files,
+      win::ClipboardFormat::UnicodeText,
+      win::ClipboardFormat::Text,
+      win::ClipboardFormat::Dib,
+      win::ClipboardFormat::Bitmap,
+   ]
+   .iter()
+   .filter(|x| win::is_clipboard_format_available(**x))
+   .map(|x| entry::Format::Id(*x as u32))
+   .collect();
+   if win::is_format_available(&entry::Format::rtf()) {
+      available.push(entry::Format::rtf());
+   }
+   entry::capture(&available, config)
+}
+
+/// The level to log why a copy was skipped at, which is high enough to show by default with `log_skipped_updates`
+fn skip_level(config: &config::Config) -> log::Level {
+   if config.log_skipped_updates {
+      log::Level::Info
+   } else {
+      log::Level::Debug
+   }
+}
+
+/// Reads the files, text or image on the clipboard as `read_clipboard_contents` does, along with the other formats
+/// that `capture_formats` and the like ask for. Returns None if the copy is to be skipped, as it is when it's marked
+/// as sensitive and `respect_exclusion_formats` is set, or else the entry along
+/// with whether it was cut short.
+fn read_clipboard_entry(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(entry::Entry, bool)>, win::ErrorCode> {
+   if config.respect_exclusion_formats && clipboard_is_sensitive(clipboard) {
+      log!(
+         skip_level(config),
+         "Skipping a copy marked as sensitive, such as a password"
+      );
+      return Ok(None);
+   }
+   let (contents, truncated) = match read_clipboard_contents(clipboard, config)? {
+      Some(contents) => contents,
+      None => return Ok(None),
+   };
+   // The other formats hold the whole copy, and would paste all of it, so a copy that was cut short is kept without
+   let extras = if truncated {
+      Vec::new()
+   } else {
+      read_clipboard_extras(clipboard, &contents, config)
+   };
+   Ok(Some((entry::Entry::with_extras(contents, extras), truncated)))
+}
+
+/// Whether the copy on the clipboard is marked by a password manager or Windows as not to be kept
+fn clipboard_is_sensitive(clipboard: &win::ClipboardHandle) -> bool {
+   let marks: Vec<entry::Extra> = entry::PRIVACY_FORMATS
+      .iter()
+      .filter_map(|name| {
+         let format = entry::Format::Name(String::from(*name));
+         let data = clipboard.get_format(&format).ok()??;
+         Some(entry::Extra { format, data })
+      })
+      .collect();
+   entry::is_sensitive(&marks)
+}
+
+/// Reads the files, text or image on the clipboard, like `read_clipboard_text`. File lists and images are never cut
+/// short, so one too large to push is always skipped.
+fn read_clipboard_contents(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(win::ClipboardEntry, bool)>, win::ErrorCode> {
+   let capture = match clipboard_capture(config) {
+      Some(capture) => capture,
+      None => {
+         log!(
+            skip_level(config),
+            "Skipping a copy with nothing that can go on the stack"
+         );
+         return Ok(None);
+      }
+   };
+   let text = match capture {
+      entry::Capture::Files => {
+         let files = win::ClipboardEntry::Files(clipboard.get_files()?);
+         return Ok(Some((files, false)).filter(|(x, _)| entry_fits(x, config)));
+      }
+      entry::Capture::Text => match read_clipboard_text(clipboard, config) {
+         Ok(text) => text,
+         Err(e) if win::is_clipboard_format_available(win::ClipboardFormat::Text) => {
+            debug!("Reading a copy as ANSI text; failed to read it as Unicode text: {}", e);
+            read_clipboard_ansi_text(clipboard, config)?
+         }
+         Err(e) => return Err(e),
+      },
+      entry::Capture::AnsiText => read_clipboard_ansi_text(clipboard, config)?,
+      entry::Capture::Rtf => return read_clipboard_rtf(clipboard, config),
+      entry::Capture::Image => return read_clipboard_image(clipboard, config),
+   };
+   Ok(text.map(|(text, truncated)| (win::ClipboardEntry::Text(text), truncated)))
+}
+
+/// Reads the image on the clipboard, as `max_image_pixels`, `max_item_size` and `max_stack_memory` allow. Returns
+/// None if it's skipped.
+fn read_clipboard_image(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(win::ClipboardEntry, bool)>, win::ErrorCode> {
+   // Measuring the image doesn't copy it, so skipping a huge one costs little
+   let (size, dimensions) = clipboard.image_size()?;
+   let pixels = dimensions.map(|(width, height)| width as usize * height as usize);
+   if let (Some(max_image_pixels), Some(pixels)) = (config.max_image_pixels, pixels) {
+      if pixels > max_image_pixels {
+         let (width, height) = dimensions.unwrap();
+         if config.image_oversize_action == config::ImageOversizeAction::Skip {
+            log!(
+               skip_level(config),
+               "Skipping a {}×{} image, more than max_image_pixels allows ({} pixels)",
+               width,
+               height,
+               max_image_pixels
+            );
+            return Ok(None);
+         }
+         let image = match clipboard.get_image()?.downscale(max_image_pixels) {
+            Some(image) => image,
+            None => {
+               log!(
+                  skip_level(config),
+                  "Skipping a {}×{} image, which is in a format that can't be downscaled",
+                  width,
+                  height
+               );
+               return Ok(None);
+            }
+         };
+         debug!("Downscaled a {}×{} image to {}", width, height, image.describe());
+         let entry = win::ClipboardEntry::Image(image);
+         return Ok(if entry_fits(&entry, config) {
+            Some((entry, false))
+         } else {
+            None
+         });
+      }
+   }
+   if let Some(max_item_size) = stack::item_size_limit(config) {
+      if size > max_item_size {
+         log!(
+            skip_level(config),
+            "Skipping an image of {} bytes, more than max_item_size or max_stack_memory allow ({} bytes)",
+            size,
+            max_item_size
+         );
+         return Ok(None);
+      }
+   }
+   Ok(Some((win::ClipboardEntry::Image(clipboard.get_image()?), false)))
+}
+
+/// Whether a file list, image or text taken from RTF is small enough for `max_item_size` and `max_stack_memory`,
+/// logging it if not
+fn entry_fits(entry: &win::ClipboardEntry, config: &config::Config) -> bool {
+   match stack::item_size_limit(config) {
+      Some(max_item_size) if entry.size() > max_item_size => {
+         log!(
+            skip_level(config),
+            "Skipping {} of {} bytes, more than max_item_size or max_stack_memory allow ({} bytes)",
+            match entry {
+               win::ClipboardEntry::Text(_) => "text taken from RTF",
+               win::ClipboardEntry::Files(_) => "files",
+               win::ClipboardEntry::Image(_) => "an image",
+            },
+            entry.size(),
+            max_item_size
+         );
+         false
+      }
+      _ => true,
+   }
+}
+
+/// Reads the text on the clipboard, as `max_item_size`, `max_stack_memory` and `oversize_action` allow. Returns None
+/// if it's too large to push or has no text, or else the text along with whether it was cut short.
+fn read_clipboard_text(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(win::ClipboardText, bool)>, win::ErrorCode> {
+   let max_item_size = stack::item_size_limit(config).unwrap_or(usize::MAX);
+   if config.oversize_action == config::OversizeAction::Skip {
+      // Measuring the text doesn't copy it, so skipping a huge copy costs little
+      let size = clipboard.text_size()?;
+      if size > max_item_size {
+         log!(
+            skip_level(config),
+            "Ignoring a copy of {} bytes, which is larger than max_item_size or max_stack_memory allow ({} bytes)",
+            size,
+            max_item_size
+         );
+         return Ok(None);
+      }
+   }
+   let (units, replaced) = clipboard.get_text_units(max_item_size)?;
+   if replaced > 0 {
+      debug!("Replaced {} unpaired surrogates in a copy's text with U+FFFD", replaced);
+   }
+   Ok(limit_text(&units, max_item_size, config))
+}
+
+/// Reads the ANSI text on the clipboard, converted from the code page of its locale, as `read_clipboard_text` does.
+/// Its size as UTF-16 isn't known until it's converted, so it's converted whole before being measured.
+fn read_clipboard_ansi_text(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(win::ClipboardText, bool)>, win::ErrorCode> {
+   let units = clipboard.get_ansi_text_units()?;
+   let max_item_size = stack::item_size_limit(config).unwrap_or(usize::MAX);
+   Ok(limit_text(&units, max_item_size, config))
+}
+
+/// Gives text read from the clipboard the line endings `normalize_line_endings` asks for, and applies `transform`
+fn transform_text<'a>(units: &'a [u16], config: &config::Config) -> Cow<'a, [u16]> {
+   let units = stack::normalize_line_endings(units, config.normalize_line_endings);
+   if config.transform.is_empty() {
+      return units;
+   }
+   let text = String::from_utf16_lossy(&units);
+   match stack::transform(&text, &config.transform) {
+      Cow::Borrowed(_) => units,
+      Cow::Owned(transformed) => {
+         trace!("Transformed a copy's text");
+         Cow::Owned(transformed.encode_utf16().collect())
+      }
+   }
+}
+
+/// Makes text read from the clipboard into an entry's text, changing it as `transform_text` does, and skipping or
+/// truncating it as `oversize_action` says if it's larger than `max_item_size`. Returns None if it's skipped,
+/// including when it's empty, as it is when a transform removes all of it.
+fn limit_text(units: &[u16], max_item_size: usize, config: &config::Config) -> Option<(win::ClipboardText, bool)> {
+   if units.is_empty() {
+      log!(skip_level(config), "Skipping a copy with no text");
+      return None;
+   }
+   let units = &*transform_text(units, config);
+   if units.is_empty() {
+      log!(
+         skip_level(config),
+         "Skipping a copy whose text was all removed by transform"
+      );
+      return None;
+   }
+   if config.oversize_action == config::OversizeAction::Skip && units.len() * 2 > max_item_size {
+      log!(
+         skip_level(config),
+         "Ignoring a copy of {} bytes, which is larger than max_item_size or max_stack_memory allow ({} bytes)",
+         units.len() * 2,
+         max_item_size
+      );
+      return None;
+   }
+   let (text, truncated) = win::ClipboardText::from_units_prefix(units, max_item_size, stack::TRUNCATED_MARKER);
+   if truncated {
+      debug!("Truncated a copy to {} bytes", max_item_size);
+   }
+   Some((text, truncated))
+}
+
+/// Reads the formats on the clipboard that `config.captured_formats` asks for, to keep alongside `contents`. Any
+/// that would make the entry too large for `max_item_size` or `max_stack_memory` is left off.
+fn read_clipboard_extras(
+   clipboard: &win::ClipboardHandle,
+   contents: &win::ClipboardEntry,
+   config: &config::Config,
+) -> Vec<entry::Extra> {
+   let mut size = contents.size();
+   let mut extras = Vec::new();
+   for format in config.captured_formats() {
+      let data = match clipboard.get_format(&format) {
+         Ok(Some(data)) => data,
+         Ok(None) => continue,
+         Err(e) => {
+            debug!(
+               "Keeping a copy without its `{}` format; failed to read it: {}",
+               format, e
+            );
+            continue;
+         }
+      };
+      if format.is_html() && html::Html::parse(&data).is_none() {
+         debug!("Keeping a copy without its HTML, which isn't valid CF_HTML");
+         continue;
+      }
+      match stack::item_size_limit(config) {
+         Some(max_item_size) if size + data.len() > max_item_size => {
+            debug!(
+               "Leaving the `{}` format off a copy, as it would make it larger than max_item_size or \
+                max_stack_memory allow ({} bytes)",
+               format, max_item_size
+            );
+            continue;
+         }
+         _ => (),
+      }
+      size += data.len();
+      extras.push(entry::Extra { format, data });
+   }
+   extras
+}
+
+/// Reads a copy that's only on the clipboard as RTF, taking its text from the RTF, which is kept along with it. Like
+/// file lists, it's never cut short, and it's skipped if it has no text.
+fn read_clipboard_rtf(
+   clipboard: &win::ClipboardHandle,
+   config: &config::Config,
+) -> Result<Option<(win::ClipboardEntry, bool)>, win::ErrorCode> {
+   let rtf = clipboard.get_format(&entry::Format::rtf())?.unwrap_or_default();
+   let text = rtf::to_text(&rtf);
+   if text.trim().is_empty() {
+      log!(skip_level(config), "Skipping a copy of RTF without any text");
+      return Ok(None);
+   }
+   let units: Vec<u16> = text.encode_utf16().collect();
+   let units = transform_text(&units, config);
+   if units.is_empty() {
+      log!(
+         skip_level(config),
+         "Skipping a copy whose text was all removed by transform"
+      );
+      return Ok(None);
+   }
+   let text = win::ClipboardEntry::Text(win::ClipboardText::from_units(units.iter().copied()));
+   Ok(Some((text, false)).filter(|(x, _)| entry_fits(x, config)))
+}
+
+fn report_push(
+   pushed: stack::Push,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   trayicon: &Option<win::TrayIcon>,
+   config: &config::Config,
+) {
+   match pushed {
+      stack::Push::Pushed => trace!(
+         "Pushed clipboard contents onto stack, which now holds {} entries taking up {} bytes",
+         clipboard_stack.len(),
+         clipboard_stack.bytes()
+      ),
+      stack::Push::Queued => trace!(
+         "Queued clipboard contents at the bottom of the stack, which now holds {} entries taking up {} bytes",
+         clipboard_stack.len(),
+         clipboard_stack.bytes()
+      ),
+      stack::Push::DuplicateOnTop | stack::Push::Duplicate => {
+         log!(skip_level(config), "Ignoring push because it was a duplicate")
+      }
+      stack::Push::StackFull => {
+         log!(skip_level(config), "Ignoring push because the stack is full");
+         notify(trayicon, "ripclip", "The stack is full, so the copy wasn't pushed");
+      }
+      stack::Push::TooLarge => log!(
+         skip_level(config),
+         "Ignoring push because it's larger than max_stack_memory"
+      ),
+      stack::Push::Augmented { .. } => {
+         trace!("Added the formats of the same copy placed again to the newest entry, rather than pushing it")
+      }
+   }
+}
+
+fn tray_status(paused: bool, fullscreen: bool, mode: config::Mode) -> String {
+   let mut states = Vec::new();
+   if mode == config::Mode::Queue {
+      states.push("queue");
+   }
+   if paused {
+      states.push("capture paused");
+   } else if fullscreen {
+      states.push("capture paused for fullscreen");
+   }
+   if states.is_empty() {
+      String::from("ripclip")
+   } else {
+      format!("ripclip ({})", states.join(", "))
+   }
+}
+
+/// Keeps the tray icon showing how many entries there are, for `tray_badge`
+#[derive(Default)]
+struct TrayBadge {
+   /// The icons made so far, for each count up to `stack::MAX_BADGE_COUNT` and then one more for anything past it.
+   /// They're kept, rather than made anew each time the count changes, and destroyed along with this.
+   icons: Vec<Option<win::Icon>>,
+   /// Which of `icons` is shown, where 0 is the program's own icon, or None if that isn't known
+   shown: Option<usize>,
+}
+
+impl TrayBadge {
+   /// Shows the badge for a stack `depth` entries deep, if it isn't already shown
+   fn update(
+      &mut self,
+      trayicon: &Option<win::TrayIcon>,
+      module: &win::ModuleHandle,
+      depth: usize,
+      config: &config::Config,
+   ) {
+      let trayicon = match trayicon {
+         Some(trayicon) => trayicon,
+         None => return,
+      };
+      let index = if config.tray_badge {
+         depth.min(stack::MAX_BADGE_COUNT + 1)
+      } else {
+         0
+      };
+      if self.shown == Some(index) {
+         return;
+      }
+      if self.icons.len() <= index {
+         self.icons.resize_with(index + 1, || None);
+      }
+      if let (None, Some(badge)) = (&self.icons[index], stack::badge(index)) {
+         match win::badged_icon(module, &badge) {
+            Ok(icon) => self.icons[index] = Some(icon),
+            Err(e) => {
+               warn!("Failed to draw the tray icon's badge: {}", e);
+               // Not tried again until the count changes
+               self.shown = Some(index);
+               return;
+            }
+         }
+      }
+      match trayicon.set_icon(self.icons[index].as_ref()) {
+         Ok(()) => self.shown = Some(index),
+         Err(e) => warn!("Failed to update the tray icon: {}", e),
+      }
+   }
+
+   /// Has the next update set the icon whatever it was before, such as for a new tray icon. The icons are made
+   /// again too, in case the size the notification area shows them at has changed.
+   fn invalidate(&mut self) {
+      *self = TrayBadge::default();
+   }
+}
+
+/// Keeps the tray icon's tooltip showing how many entries there are and what's on top
+#[derive(Default)]
+struct TrayTooltip {
+   /// What the tooltip was last made from: `Stack::changes`, whether capture was paused and paused for fullscreen,
+   /// the mode, and `tray_tooltip_preview`
+   made_from: Option<(u64, bool, bool, config::Mode, bool)>,
+   /// The tooltip last shown
+   shown: String,
+}
+
+impl TrayTooltip {
+   /// Updates the tooltip if what it shows may have changed. This is cheap when nothing has, so it's done after every
+   /// message, rather than by everything that changes the stack.
+   fn update(
+      &mut self,
+      trayicon: &Option<win::TrayIcon>,
+      clipboard_stack: &stack::Stack<entry::Entry>,
+      paused: bool,
+      fullscreen: bool,
+      config: &config::Config,
+   ) {
+      let trayicon = match trayicon {
+         Some(trayicon) => trayicon,
+         None => return,
+      };
+      let made_from = (
+         clipboard_stack.changes(),
+         paused,
+         fullscreen,
+         config.mode,
+         config.tray_tooltip_preview,
+      );
+      if self.made_from == Some(made_from) {
+         return;
+      }
+      self.made_from = Some(made_from);
+      let top = match clipboard_stack.back() {
+         Some(top) if config.tray_tooltip_preview => Some(top.preview(TRAY_TOOLTIP_UNITS)),
+         _ => None,
+      };
+      let tooltip = stack::tooltip(
+         &tray_status(paused, fullscreen, config.mode),
+         clipboard_stack.len(),
+         top.as_deref(),
+         TRAY_TOOLTIP_UNITS,
+      );
+      // Such as when an entry further down was pinned
+      if tooltip == self.shown {
+         return;
+      }
+      match trayicon.set_tooltip(&tooltip) {
+         Ok(()) => self.shown = tooltip,
+         Err(e) => warn!("Failed to update the tray icon tooltip: {}", e),
+      }
+   }
+
+   /// Has the next update set the tooltip whatever it was before, such as for a new tray icon
+   fn invalidate(&mut self) {
+      *self = TrayTooltip::default();
+   }
+}
+
+fn toggle_pause(paused: &mut bool) {
+   *paused = !*paused;
+   if *paused {
+      info!("Paused clipboard monitoring");
+   } else {
+      info!("Resumed clipboard monitoring");
+   }
+}
+
+fn peek(
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   pop_cursor: &Option<stack::Cursor>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   expire(clipboard_stack, managing_clipboard, config);
+   // In queue mode the top is the oldest copy, at the front of the queue
+   let (top_name, empty) = match config.mode {
+      config::Mode::Stack => ("Top of stack", "The stack is empty"),
+      config::Mode::Queue => ("Front of queue", "The queue is empty"),
+   };
+   let depth = stack::cursor_depth(pop_cursor, clipboard_stack);
+   let (title, text) = match clipboard_stack.back() {
+      // Pops have stepped down to an entry below the top, so that's the one on the clipboard
+      Some(_) if depth > 1 && *managing_clipboard => {
+         let entry = &clipboard_stack[clipboard_stack.len() - depth];
+         (
+            format!("Entry {} of {}", depth, clipboard_stack.len()),
+            entry.preview(PEEK_PREVIEW_CHARS),
+         )
+      }
+      Some(top) => {
+         let title = if clipboard_stack.len() == 1 {
+            format!("{} (1 entry)", top_name)
+         } else {
+            format!("{} ({} entries)", top_name, clipboard_stack.len())
+         };
+         (title, top.preview(PEEK_PREVIEW_CHARS))
+      }
+      None => (String::from("ripclip"), String::from(empty)),
+   };
+   debug!("The stack takes up {} bytes", clipboard_stack.bytes());
+   notify(trayicon, &title, &text);
+}
+
+/// Whether the foreground window belongs to a process with a higher integrity level than ripclip, such as a program
+/// run as administrator when ripclip isn't. When that can't be told, it's taken not to be.
+fn foreground_is_elevated() -> bool {
+   let process_id = match win::get_foreground_window_process() {
+      Ok(Some(process_id)) => process_id,
+      Ok(None) => return false,
+      Err(e) => {
+         debug!(
+            "Failed to find out which program the foreground window belongs to: {}",
+            e
+         );
+         return false;
+      }
+   };
+   match (
+      win::get_process_integrity_level(process_id),
+      win::get_current_process_integrity_level(),
+   ) {
+      (Ok(theirs), Ok(ours)) => theirs > ours,
+      (Err(e), _) | (_, Err(e)) => {
+         debug!(
+            "Failed to compare the foreground window's integrity level with ripclip's: {}",
+            e
+         );
+         false
+      }
+   }
+}
+
+/// Lets messages that ripclip relies on from other programs through when it runs as administrator, which Windows
+/// would otherwise block from programs that aren't
+fn allow_lower_integrity_messages(window: &win::WindowHandle) {
+   let messages = [
+      TRAY_CALLBACK_MESSAGE,
+      winapi::um::winuser::WM_DRAWCLIPBOARD,
+      winapi::um::winuser::WM_CHANGECBCHAIN,
+   ];
+   for message in messages.iter() {
+      if let Err(e) = win::change_window_message_filter_ex(window, *message) {
+         debug!(
+            "Failed to let message {} through from lower integrity programs: {}",
+            message, e
+         );
+      }
+   }
+}
+
+/// Shows a notification from the tray icon, or just logs it if there's no tray icon
+fn notify(trayicon: &Option<win::TrayIcon>, title: &str, text: &str) {
+   match trayicon {
+      Some(trayicon) => {
+         if let Err(e) = trayicon.show_balloon(title, text) {
+            warn!("Failed to show notification \"{}: {}\": {}", title, text, e);
+         }
+      }
+      None => info!("{}: {}", title, text),
+   }
+}
+
+fn snapshot_path() -> Option<PathBuf> {
+   config::data_dir().map(|x| x.join("snapshot"))
+}
+
+/// Writes a file to the side first and then moves it into place, so that failing partway through doesn't lose
+/// what was there before
+fn write_file_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+   F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+   let temp_path = path.with_extension("tmp");
+   {
+      let mut output = BufWriter::new(File::create(&temp_path)?);
+      write(&mut output)?;
+      output.flush()?;
+   }
+   fs::rename(&temp_path, path)
+}
+
+/// Writes the snapshot, encrypted like the saved stack if `encrypted` is set
+fn write_snapshot_file(path: &Path, clipboard_stack: &stack::Stack<entry::Entry>, encrypted: bool) -> io::Result<()> {
+   write_file_atomically(path, |output| {
+      if encrypted {
+         let entries = clipboard_stack
+            .iter_as_stack()
+            .map(|(x, t, pinned)| (x.kind(), x.saved_bytes(), t, pinned));
+         snapshot::write_encrypted_stack(output, entries, |data| {
+            win::protect_data(data).map_err(|e| io::Error::other(format!("Failed to encrypt: {}", e)))
+         })
+      } else {
+         let entries = clipboard_stack
+            .iter_as_stack()
+            .map(|(x, _, _)| (x.kind(), x.saved_bytes()));
+         snapshot::write_snapshot(output, entries)
+      }
+   })
+}
+
+/// Turns an entry read back from the saved stack or a snapshot into one for the stack, decoding images saved as PNG.
+/// Returns None if it's malformed.
+fn saved_entry(kind: stack::Kind, data: Vec<u8>) -> Option<win::ClipboardEntry> {
+   // Stacks saved by earlier versions keep images as bitmaps
+   if kind == stack::Kind::Image && dib::is_png(&data) {
+      match win::Image::from_png(data) {
+         Some(image) => return Some(win::ClipboardEntry::Image(image)),
+         None => {
+            warn!("Skipping a saved image that's damaged");
+            return None;
+         }
+      }
+   }
+   win::ClipboardEntry::from_bytes(kind, data)
+}
+
+/// Where the stack is kept between runs with `persist_stack`
+fn stack_path() -> Option<PathBuf> {
+   config::data_dir().map(|x| x.join("stack"))
+}
+
+/// Reads back the stack kept by `persist_stack`, or gives an empty stack if it's off. A missing or damaged file
+/// also gives an empty stack; a damaged one is moved aside rather than crashing or being overwritten.
+fn load_stack(config: &config::Config) -> stack::Stack<entry::Entry> {
+   let mut clipboard_stack = stack::Stack::with_capacity(config.max_stack_size.unwrap_or(0));
+   if !config.persist_stack {
+      return clipboard_stack;
+   }
+   let path = match stack_path() {
+      Some(path) => path,
+      None => {
+         warn!("Unable to determine configuration directory; Can't restore the saved stack");
+         return clipboard_stack;
+      }
+   };
+   let decrypt = |data: &[u8]| {
+      win::unprotect_data(data)
+         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to decrypt: {}", e)))
+   };
+   let entries = match File::open(&path).and_then(|x| snapshot::read_any_stack(&mut BufReader::new(x), decrypt)) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return clipboard_stack,
+      Err(e) => {
+         let damaged_path = path.with_extension("damaged");
+         warn!(
+            "Failed to read the saved stack from {:#?}, starting with an empty stack instead: {}",
+            path, e
+         );
+         match fs::rename(&path, &damaged_path) {
+            Ok(()) => info!("Moved the damaged file to {:#?}", damaged_path),
+            Err(e) => warn!("Failed to move the damaged file aside: {}", e),
+         }
+         return clipboard_stack;
+      }
+   };
+   let entry_count = entries.len();
+   for (kind, entry, copied_at, pinned) in entries {
+      if let Some(contents) = saved_entry(kind, entry) {
+         clipboard_stack.push_back_at(entry::Entry::new(contents), copied_at);
+         clipboard_stack.set_pinned(clipboard_stack.len() - 1, pinned);
+      }
+   }
+   // The file is written as a stack would be, oldest copy first
+   clipboard_stack.set_mode(config.mode);
+   if clipboard_stack.len() != entry_count {
+      warn!(
+         "Skipped {} malformed entries in the saved stack",
+         entry_count - clipboard_stack.len()
+      );
+   }
+   stack::evict(&mut clipboard_stack, config);
+   stack::expire(&mut clipboard_stack, SystemTime::now(), config);
+   info!("Restored {} entries saved by the last run", clipboard_stack.len());
+   clipboard_stack
+}
+
+/// Keeps the file from `stack_path` up to date with the stack, while `persist_stack` is on
+struct Persistence {
+   /// What `Stack::changes` was when the stack was last saved, or None if the file needs saving regardless
+   saved_changes: Option<u64>,
+   /// Whether a save is waiting on `PERSIST_TIMER_ID`
+   pending: bool,
+   /// Whether `persist_stack` was on when last checked
+   enabled: bool,
+   /// Whether `persist_encrypted` was on when last checked
+   encrypted: bool,
+}
+
+impl Persistence {
+   /// Starts off with the stack just loaded by `load_stack`, which the file already holds
+   fn new(clipboard_stack: &stack::Stack<entry::Entry>, config: &config::Config) -> Persistence {
+      Persistence {
+         saved_changes: Some(clipboard_stack.changes()),
+         pending: false,
+         enabled: config.persist_stack,
+         encrypted: config.persist_encrypted,
+      }
+   }
+
+   /// Schedules a save if the stack has changed, or saves right away if it's been emptied, so that clearing the
+   /// stack doesn't leave its contents on disk. Turning `persist_stack` off deletes the file, and changing
+   /// `persist_encrypted` saves it again.
+   fn update(
+      &mut self,
+      window: &win::WindowHandle,
+      clipboard_stack: &stack::Stack<entry::Entry>,
+      config: &config::Config,
+   ) {
+      if config.persist_stack != self.enabled {
+         self.cancel(window);
+         self.enabled = config.persist_stack;
+         self.saved_changes = None;
+         if !self.enabled {
+            remove_stack_file();
+            return;
+         }
+      }
+      if config.persist_encrypted != self.encrypted {
+         self.encrypted = config.persist_encrypted;
+         self.saved_changes = None;
+      }
+      if !self.enabled || self.saved_changes == Some(clipboard_stack.changes()) {
+         return;
+      }
+      if clipboard_stack.is_empty() {
+         self.save(window, clipboard_stack);
+      } else if !self.pending {
+         match win::set_timer(window, PERSIST_TIMER_ID, PERSIST_DELAY_MS) {
+            Ok(()) => self.pending = true,
+            Err(e) => {
+               warn!("Failed to set timer for saving the stack, saving it now: {}", e);
+               self.save(window, clipboard_stack);
+            }
+         }
+      }
+   }
+
+   /// Saves the stack now if it's changed since it was last saved
+   fn save(&mut self, window: &win::WindowHandle, clipboard_stack: &stack::Stack<entry::Entry>) {
+      self.cancel(window);
+      if !self.enabled || self.saved_changes == Some(clipboard_stack.changes()) {
+         return;
+      }
+      save_stack(clipboard_stack, self.encrypted);
+      // A failed save isn't retried until the stack changes again, rather than on every message
+      self.saved_changes = Some(clipboard_stack.changes());
+   }
+
+   fn cancel(&mut self, window: &win::WindowHandle) {
+      if self.pending {
+         let _ = win::kill_timer(window, PERSIST_TIMER_ID);
+         self.pending = false;
+      }
+   }
+}
+
+fn save_stack(clipboard_stack: &stack::Stack<entry::Entry>, encrypted: bool) {
+   let path = match stack_path() {
+      Some(path) => path,
+      None => {
+         warn!("Unable to determine configuration directory; Can't save the stack");
+         return;
+      }
+   };
+   let result = write_file_atomically(&path, |output| {
+      let entries = clipboard_stack
+         .iter_as_stack()
+         .map(|(x, t, pinned)| (x.kind(), x.saved_bytes(), t, pinned));
+      if encrypted {
+         snapshot::write_encrypted_stack(output, entries, |data| {
+            win::protect_data(data).map_err(|e| io::Error::other(format!("Failed to encrypt: {}", e)))
+         })
+      } else {
+         snapshot::write_stack(output, entries)
+      }
+   });
+   match result {
+      Ok(()) => trace!("Saved {} entries to {:#?}", clipboard_stack.len(), path),
+      Err(e) => warn!("Failed to save the stack to {:#?}: {}", path, e),
+   }
+}
+
+fn remove_stack_file() {
+   let path = match stack_path() {
+      Some(path) => path,
+      None => return,
+   };
+   match fs::remove_file(&path) {
+      Ok(()) => info!("Deleted the saved stack at {:#?}", path),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+      Err(e) => warn!("Failed to delete the saved stack at {:#?}: {}", path, e),
+   }
+}
+
+fn save_snapshot(
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   config: &config::Config,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   let path = match snapshot_path() {
+      Some(path) => path,
+      None => {
+         warn!("Unable to determine configuration directory; Can't save snapshot");
+         return;
+      }
+   };
+   match write_snapshot_file(&path, clipboard_stack, config.persist_encrypted) {
+      Ok(()) => {
+         info!("Saved snapshot of {} entries to {:#?}", clipboard_stack.len(), path);
+         notify(
+            trayicon,
+            "ripclip",
+            &format!("Saved a snapshot of {} entries", clipboard_stack.len()),
+         );
+      }
+      Err(e) => {
+         warn!("Failed to save snapshot to {:#?}: {}", path, e);
+         notify(trayicon, "ripclip", &format!("Failed to save snapshot: {}", e));
+      }
+   }
+}
+
+fn restore_snapshot(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   let path = match snapshot_path() {
+      Some(path) => path,
+      None => {
+         warn!("Unable to determine configuration directory; Can't restore snapshot");
+         return;
+      }
+   };
+   let decrypt_failed = Cell::new(false);
+   let decrypt = |data: &[u8]| {
+      win::unprotect_data(data).map_err(|e| {
+         decrypt_failed.set(true);
+         io::Error::new(io::ErrorKind::InvalidData, format!("Failed to decrypt: {}", e))
+      })
+   };
+   let read = File::open(&path).and_then(|x| snapshot::read_any_snapshot(&mut BufReader::new(x), decrypt));
+   let entries = match read {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+         notify(trayicon, "ripclip", "There's no snapshot to restore");
+         return;
+      }
+      Err(e) if decrypt_failed.get() => {
+         // Such as one taken by another user, which is as good as none
+         warn!(
+            "Failed to decrypt snapshot at {:#?}, so there's none to restore: {}",
+            path, e
+         );
+         notify(trayicon, "ripclip", "There's no snapshot to restore");
+         return;
+      }
+      Err(e) => {
+         warn!("Failed to read snapshot from {:#?}: {}", path, e);
+         notify(trayicon, "ripclip", &format!("Failed to restore snapshot: {}", e));
+         return;
+      }
+   };
+   let entry_count = entries.len();
+   // Snapshots are written as a stack would be, oldest copy first
+   clipboard_stack.replace_all(
+      entries
+         .into_iter()
+         .filter_map(|(kind, contents)| saved_entry(kind, contents))
+         .map(entry::Entry::new),
+   );
+   if clipboard_stack.len() != entry_count {
+      warn!(
+         "Skipped {} malformed entries in snapshot",
+         entry_count - clipboard_stack.len()
+      );
+   }
+   stack::evict(clipboard_stack, config);
+   set_clipboard_to_top(window, clipboard_stack, config);
+   *managing_clipboard = true;
+   info!("Restored snapshot of {} entries", clipboard_stack.len());
+}
+
+/// An export being written on its own thread, so that a huge stack doesn't hold up the message loop
+struct Export {
+   path: PathBuf,
+   count: usize,
+   thread: std::thread::JoinHandle<io::Result<()>>,
+}
+
+/// Where an export made at `now` goes: `export_path` if it's set, otherwise a file named for the time in the
+/// configuration directory
+fn export_path(config: &config::Config, now: SystemTime) -> Option<PathBuf> {
+   match &config.export_path {
+      Some(path) => Some(path.clone()),
+      None => config::data_dir().map(|x| x.join(export::default_file_name(now))),
+   }
+}
+
+fn start_export(
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   config: &config::Config,
+   export: &mut Option<Export>,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   // A message box or menu being open when the export finished would have dropped its message
+   if export.as_ref().is_some_and(|x| x.thread.is_finished()) {
+      finish_export(export, trayicon);
+   }
+   if export.is_some() {
+      debug!("Not exporting the stack because the last export is still being written");
+      return;
+   }
+   let now = SystemTime::now();
+   let path = match export_path(config, now) {
+      Some(path) => path,
+      None => {
+         warn!("Unable to determine configuration directory; Can't export the stack");
+         return;
+      }
+   };
+   // Copying the entries is quick next to decoding and writing them, which is left to the thread
+   let entries: Vec<(entry::Entry, SystemTime)> = clipboard_stack
+      .iter_as_stack()
+      .map(|(x, t, _)| (x.clone(), t))
+      .collect();
+   let count = entries.len();
+   let main_thread = unsafe { winapi::um::processthreadsapi::GetCurrentThreadId() };
+   let thread_path = path.clone();
+   let spawned = std::thread::Builder::new().name(String::from("export")).spawn(move || {
+      let result = write_file_atomically(&thread_path, |output| {
+         export::write_export(output, entries.iter().map(|(x, t)| (x, *t)), now)
+      });
+      unsafe { winapi::um::winuser::PostThreadMessageW(main_thread, EXPORT_DONE_MESSAGE, 0, 0) };
+      result
+   });
+   match spawned {
+      Ok(thread) => {
+         trace!("Exporting {} entries to {:#?}", count, path);
+         *export = Some(Export { path, count, thread });
+      }
+      Err(e) => {
+         warn!("Failed to start exporting the stack: {}", e);
+         notify(trayicon, "ripclip", &format!("Failed to export the stack: {}", e));
+      }
+   }
+}
+
+/// Waits for the export being written, if there is one, and reports how it went
+fn finish_export(export: &mut Option<Export>, trayicon: &Option<win::TrayIcon>) {
+   let Export { path, count, thread } = match export.take() {
+      Some(export) => export,
+      None => return,
+   };
+   match thread.join() {
+      Ok(Ok(())) => {
+         info!("Exported {} entries to {:#?}", count, path);
+         notify(
+            trayicon,
+            "ripclip",
+            &format!("Exported {} entries to {}", count, path.display()),
+         );
+      }
+      Ok(Err(e)) => {
+         warn!("Failed to export the stack to {:#?}: {}", path, e);
+         notify(trayicon, "ripclip", &format!("Failed to export the stack: {}", e));
+      }
+      Err(_) => warn!("Export thread panicked while exporting to {:#?}", path),
+   }
+}
+
+/// Where an import reads from: `import_path` if it's set, then `export_path`, otherwise the newest export in the
+/// configuration directory
+fn import_path(config: &config::Config) -> Option<PathBuf> {
+   if let Some(path) = config.import_path.as_ref().or(config.export_path.as_ref()) {
+      return Some(path.clone());
+   }
+   let dir = config::data_dir()?;
+   // Export names sort in the order the exports were made
+   fs::read_dir(&dir)
+      .ok()?
+      .filter_map(|x| x.ok()?.file_name().into_string().ok())
+      .filter(|x| x.starts_with("stack-export-") && x.ends_with(".json"))
+      .max()
+      .map(|x| dir.join(x))
+}
+
+/// Turns an imported entry into one for the stack, applying `max_item_size` just as `read_clipboard_entry` does to a
+/// copy. Returns None if the entry is to be skipped.
+fn import_entry(contents: &export::Contents, config: &config::Config) -> Option<entry::Entry> {
+   let contents = match contents {
+      export::Contents::Text(text) => import_text(text, config).map(win::ClipboardEntry::Text),
+      export::Contents::Files(paths) => {
+         let files = win::ClipboardEntry::Files(win::FileList::from_paths(paths.lines())?);
+         Some(files).filter(|x| entry_fits(x, config))
+      }
+      export::Contents::Image(data) if config.capture_images => {
+         let image = win::ClipboardEntry::Image(win::Image::from_bytes(data.clone())?);
+         Some(image).filter(|x| entry_fits(x, config))
+      }
+      export::Contents::Image(_) => None,
+   };
+   contents.map(entry::Entry::new)
+}
+
+/// Turns imported text into an entry, as `import_entry` does
+fn import_text(text: &str, config: &config::Config) -> Option<win::ClipboardText> {
+   let max_item_size = match stack::item_size_limit(config) {
+      Some(max_item_size) => max_item_size,
+      None => return Some(win::ClipboardText::from(text)),
+   };
+   match config.oversize_action {
+      config::OversizeAction::Skip => {
+         let size = text.encode_utf16().count() * 2;
+         if size > max_item_size {
+            debug!(
+               "Not importing an entry of {} bytes, which is larger than max_item_size or max_stack_memory allow ({} bytes)",
+               size, max_item_size
+            );
+            return None;
+         }
+         Some(win::ClipboardText::from(text))
+      }
+      config::OversizeAction::Truncate => {
+         let units: Vec<u16> = text.encode_utf16().collect();
+         let (entry, truncated) = win::ClipboardText::from_units_prefix(&units, max_item_size, stack::TRUNCATED_MARKER);
+         if truncated {
+            debug!("Truncated an imported entry to {} bytes", max_item_size);
+         }
+         Some(entry)
+      }
+   }
+}
+
+/// Reads a stack written by `start_export` from `import_path`, and replaces the stack with it, or pushes its entries
+/// with `import_append`. A file that isn't entirely valid leaves the stack alone.
+fn import_stack(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+   trayicon: &Option<win::TrayIcon>,
+) {
+   let path = match import_path(config) {
+      Some(path) => path,
+      None => {
+         notify(trayicon, "ripclip", "There's no export to import");
+         return;
+      }
+   };
+   let entries = fs::read_to_string(&path)
+      .map_err(|e| e.to_string())
+      .and_then(|x| export::read_export(&x, SystemTime::now()).map_err(|e| e.to_string()));
+   let entries = match entries {
+      Ok(entries) => entries,
+      Err(e) => {
+         warn!("Failed to import the stack from {:#?}: {}", path, e);
+         notify(
+            trayicon,
+            "ripclip",
+            &format!("Failed to import {}: {}", path.display(), e),
+         );
+         return;
+      }
+   };
+   if !config.import_append {
+      clipboard_stack.clear();
+   }
+   // Entries go through the same checks as copies, so an import can't get around the limits on the stack
+   let mut imported = 0;
+   for (contents, copied_at) in entries.iter() {
+      let entry = match import_entry(contents, config) {
+         Some(entry) => entry,
+         None => continue,
+      };
+      if let stack::Push::Pushed | stack::Push::Queued = stack::push_at(clipboard_stack, entry, *copied_at, config) {
+         imported += 1;
+      }
+   }
+   expire(clipboard_stack, managing_clipboard, config);
+   set_clipboard_to_top(window, clipboard_stack, config);
+   *managing_clipboard = !clipboard_stack.is_empty();
+   info!("Imported {} of {} entries from {:#?}", imported, entries.len(), path);
+   let text = if imported == entries.len() {
+      format!("Imported {} entries from {}", imported, path.display())
+   } else {
+      format!(
+         "Imported {} of {} entries from {}; the rest were duplicates or didn't fit",
+         imported,
+         entries.len(),
+         path.display()
+      )
+   };
+   notify(trayicon, "ripclip", &text);
+}
+
+fn swap(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   match config.swap_behavior {
+      config::SwapBehavior::TopTwo => swap_top_two(window, clipboard_stack, *managing_clipboard, config),
+      config::SwapBehavior::Clipboard => swap_clipboard(window, clipboard_stack, *managing_clipboard, config),
+   }
+}
+
+fn swap_top_two(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: bool,
+   config: &config::Config,
+) {
+   if !managing_clipboard {
+      trace!("Can't swap when the clipboard is not being managed by ripclip (clipboard contains non-text)");
+      return;
+   }
+
+   if stack::swap_top_two(clipboard_stack) {
+      set_clipboard_to_top(window, clipboard_stack, config);
+      trace!("Swapped top 2 elements of stack");
+   } else {
+      trace!("Stack too small to swap");
+   }
+}
+
+/// Swaps text on the clipboard that isn't on the stack with the top of the stack. The clipboard still holds
+/// something that isn't the top afterwards, so ripclip goes on not managing it.
+fn swap_clipboard(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: bool,
+   config: &config::Config,
+) {
+   if managing_clipboard {
+      trace!("Nothing to swap; the clipboard already holds the top of the stack");
+      return;
+   }
+   if clipboard_stack.is_empty() {
+      trace!("Nothing on stack to swap with the clipboard");
+      return;
+   }
+   if !clipboard_has_entry(config) {
+      debug!("Nothing to swap; the clipboard doesn't contain text, files or an image");
+      return;
+   }
+
+   let clipboard_entry = match open_clipboard_with_backoff(window, config) {
+      Ok(clipboard) => read_clipboard_entry(&clipboard, config),
+      // Already logged by open_clipboard_with_backoff
+      Err(_) => return,
+   };
+   match clipboard_entry {
+      Ok(Some((clipboard_entry, _))) => {
+         // The top only leaves the stack once it's on the clipboard
+         if set_clipboard_to_top(window, clipboard_stack, config) {
+            stack::replace_top(clipboard_stack, clipboard_entry, SystemTime::now(), config).unwrap();
+            trace!("Swapped clipboard with top of stack");
+         }
+      }
+      // Already logged by read_clipboard_entry
+      Ok(None) => (),
+      Err(e) => {
+         debug!("Nothing to swap; failed to read the clipboard: {}", e);
+      }
+   }
+}
+
+/// Puts the top of the stack on the clipboard, without it being pushed as a new entry. Returns whether it was.
+fn set_clipboard_to_top(
+   window: &win::WindowHandle,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+   config: &config::Config,
+) -> bool {
+   set_clipboard_entry(window, clipboard_stack.back(), config)
+}
+
+/// Replaces the clipboard contents with `entry`, or empties it, without pushing onto the stack. Returns false, having
+/// logged why, if the clipboard couldn't be opened.
+fn set_clipboard_entry(window: &win::WindowHandle, entry: Option<&entry::Entry>, config: &config::Config) -> bool {
+   write_clipboard(window, config, |clipboard| match entry {
+      Some(entry) => place_entry(clipboard, entry, config),
+      None => Ok(()),
+   })
+}
+
+/// Places the entry on the emptied clipboard, or with `delayed_render`, promises it and keeps it to render later
+fn place_entry(
+   clipboard: &win::OwnedClipboardHandle,
+   entry: &entry::Entry,
+   config: &config::Config,
+) -> Result<(), win::ErrorCode> {
+   if config.delayed_render {
+      clipboard.promise_entry(entry, config.plain_text_only)?;
+      DELAYED_RENDER.with(|x| *x.borrow_mut() = Some((entry.clone(), config.plain_text_only)));
+      Ok(())
+   } else {
+      clipboard.set_entry(entry, config.plain_text_only)
+   }
+}
+
+thread_local! {
+   // The entry promised to the clipboard, and whether it's plain text only, until the clipboard is emptied. Windows
+   // asks the main window's procedure for it, on the main thread.
+   static DELAYED_RENDER: RefCell<Option<(entry::Entry, bool)>> = RefCell::new(None);
+}
+
+/// Answers WM_RENDERFORMAT, for when a program pastes a format of the promised entry
+fn render_promised_format(format: u32) {
+   DELAYED_RENDER.with(|x| match &*x.borrow() {
+      Some((entry, plain_text_only)) => {
+         if let Err(e) = win::render_format(format, entry, *plain_text_only) {
+            warn!("Failed to place clipboard format {} that was pasted: {}", format, e);
+         }
+      }
+      None => warn!("Asked for clipboard format {}, but no entry was promised", format),
+   });
+}
+
+/// Answers WM_RENDERALLFORMATS, for when ripclip exits with an entry still promised
+fn render_all_promised_formats(window: winapi::shared::windef::HWND) {
+   DELAYED_RENDER.with(|x| {
+      if let Some((entry, plain_text_only)) = &*x.borrow() {
+         match win::render_all_formats(window, entry, *plain_text_only) {
+            Ok(()) => trace!("Placed every promised format on the clipboard"),
+            Err(e) => warn!(
+               "Failed to place the promised entry on the clipboard before exiting: {}",
+               e
+            ),
+         }
+      }
+   });
+}
+
+thread_local! {
+   // Writes, and the updates they cause, are both handled on the main thread
+   static SELF_WRITES: RefCell<watch::SelfWrites> = RefCell::new(watch::SelfWrites::default());
+}
+
+/// Empties the clipboard and writes to it with `write`. The update this causes isn't pushed back onto the stack: the
+/// clipboard format listener is removed while writing, and `is_own_write` recognizes the update if it arrives anyway.
+/// Returns false, having logged why, if the clipboard couldn't be opened or written to.
+fn write_clipboard<F>(window: &win::WindowHandle, config: &config::Config, write: F) -> bool
+where
+   F: FnOnce(&win::OwnedClipboardHandle) -> Result<(), win::ErrorCode>,
+{
+   pause_clipboard_updates(window);
+   let (emptied, result) = match open_clipboard_with_backoff(window, config) {
+      Ok(clipboard) => match clipboard.empty() {
+         Ok(clipboard) => (true, write(&clipboard)),
+         Err(e) => (false, Err(e)),
+      },
+      // Already logged and counted by open_clipboard_with_backoff
+      Err(_) => (false, Ok(())),
+   };
+   if emptied {
+      record_own_write();
+   }
+   resume_clipboard_updates(window);
+   match result {
+      Ok(()) if emptied => {
+         record_clipboard_success(failures::Operation::Write);
+         true
+      }
+      Ok(()) => false,
+      Err(e) => {
+         error!("Failed to write to the clipboard: {}", e);
+         record_clipboard_failure(failures::Operation::Write, &e);
+         false
+      }
+   }
+}
+
+thread_local! {
+   // Counted on the main thread, which is told about failures on the capture thread
+   static CLIPBOARD_FAILURES: RefCell<failures::Failures> = RefCell::new(failures::Failures::default());
+   // What to tell the user about the last operation to keep failing, until the main loop does
+   static FAILURE_NOTICE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Counts a failed clipboard operation, and if it keeps failing, leaves a notice for the main loop to show
+fn record_clipboard_failure(operation: failures::Operation, error: &win::ErrorCode) {
+   let notice = CLIPBOARD_FAILURES.with(|x| x.borrow_mut().failed(operation, Instant::now()));
+   if !notice {
+      return;
+   }
+   let count = CLIPBOARD_FAILURES.with(|x| x.borrow().count(operation));
+   // Windows' descriptions end in a full stop and a line break
+   let mut text = format!(
+      "ripclip failed to {} {} times in a row: {}",
+      operation,
+      count,
+      error.to_string().trim_end()
+   );
+   if operation == failures::Operation::Open {
+      text.push_str(&format!(" The program with it open is {}.", clipboard_opener_name()));
+   }
+   warn!("{}", text);
+   FAILURE_NOTICE.with(|x| *x.borrow_mut() = Some(text));
+}
+
+fn record_clipboard_success(operation: failures::Operation) {
+   CLIPBOARD_FAILURES.with(|x| x.borrow_mut().succeeded(operation));
+}
+
+/// How the main window hears about changes to the clipboard
+#[derive(Clone, Copy)]
+enum ClipboardWatch {
+   /// A clipboard format listener, which is sent WM_CLIPBOARDUPDATE
+   Listener,
+   /// The clipboard viewer chain, for versions of Windows without listeners. `next` is the window after ours in the
+   /// chain, or null if there's none.
+   ViewerChain { next: winapi::shared::windef::HWND },
+}
+
+thread_local! {
+   // Changes to the viewer chain are sent to the main window's procedure, on the main thread
+   static CLIPBOARD_WATCH: Cell<Option<ClipboardWatch>> = Cell::new(None);
+}
+
+/// Starts hearing about changes to the clipboard, with a clipboard format listener, or where Windows doesn't have
+/// those, by joining the clipboard viewer chain. Either way, each change arrives as WM_CLIPBOARDUPDATE.
+fn watch_clipboard(window: &win::WindowHandle) {
+   let watch = match win::add_clipboard_format_listener(window) {
+      Ok(()) => {
+         info!("Watching the clipboard with a clipboard format listener");
+         ClipboardWatch::Listener
+      }
+      Err(win::ERROR_PROC_NOT_FOUND) => {
+         let next = win::set_clipboard_viewer(window).unwrap();
+         info!(
+            "Clipboard format listeners aren't available, so watching the clipboard with the clipboard viewer chain"
+         );
+         ClipboardWatch::ViewerChain { next }
+      }
+      Err(e) => panic!("Failed to watch the clipboard: {}", e),
+   };
+   CLIPBOARD_WATCH.with(|x| x.set(Some(watch)));
+}
+
+/// Stops hearing about changes to the clipboard, leaving the viewer chain whole for the windows left in it
+fn unwatch_clipboard(window: &win::WindowHandle) {
+   match CLIPBOARD_WATCH.with(|x| x.take()) {
+      Some(ClipboardWatch::Listener) => {
+         let _ = win::remove_clipboard_format_listener(window);
+      }
+      Some(ClipboardWatch::ViewerChain { next }) => win::change_clipboard_chain(window, next),
+      None => (),
+   }
+}
+
+/// Stops WM_CLIPBOARDUPDATE while ripclip changes the clipboard itself. The viewer chain isn't left and joined again,
+/// which would disturb the other windows in it; `is_own_write` recognizes those updates instead.
+fn pause_clipboard_updates(window: &win::WindowHandle) {
+   if let Some(ClipboardWatch::Listener) = CLIPBOARD_WATCH.with(Cell::get) {
+      win::remove_clipboard_format_listener(window).unwrap();
+   }
+}
+
+/// Undoes `pause_clipboard_updates`
+fn resume_clipboard_updates(window: &win::WindowHandle) {
+   if let Some(ClipboardWatch::Listener) = CLIPBOARD_WATCH.with(Cell::get) {
+      win::add_clipboard_format_listener(window).unwrap();
+   }
+}
+
+/// Answers WM_DRAWCLIPBOARD, which is passed down the clipboard viewer chain after every change to the clipboard, by
+/// passing it on and posting WM_CLIPBOARDUPDATE for the main loop
+fn on_draw_clipboard(window: winapi::shared::windef::HWND) {
+   // Joining the chain sends one before the next window is known, which isn't a change
+   if let Some(ClipboardWatch::ViewerChain { next }) = CLIPBOARD_WATCH.with(Cell::get) {
+      if !next.is_null() {
+         unsafe { winapi::um::winuser::SendMessageW(next, winapi::um::winuser::WM_DRAWCLIPBOARD, 0, 0) };
+      }
+      let result = unsafe { winapi::um::winuser::PostMessageW(window, winapi::um::winuser::WM_CLIPBOARDUPDATE, 0, 0) };
+      if result == 0 {
+         warn!("Failed to post clipboard update event");
+      }
+   }
+}
+
+/// Answers WM_CHANGECBCHAIN, which is passed down the clipboard viewer chain when `removed` leaves it, by linking up
+/// to the window after it if it was next, and otherwise passing it on
+fn on_change_clipboard_chain(removed: winapi::shared::windef::HWND, after_removed: winapi::shared::windef::HWND) {
+   match CLIPBOARD_WATCH.with(Cell::get) {
+      Some(ClipboardWatch::ViewerChain { next }) if next == removed => {
+         CLIPBOARD_WATCH.with(|x| x.set(Some(ClipboardWatch::ViewerChain { next: after_removed })));
+      }
+      Some(ClipboardWatch::ViewerChain { next }) if !next.is_null() => unsafe {
+         winapi::um::winuser::SendMessageW(
+            next,
+            winapi::um::winuser::WM_CHANGECBCHAIN,
+            removed as usize,
+            after_removed as isize,
+         );
+      },
+      _ => (),
+   }
+}
+
+/// Records that ripclip has just written to the clipboard and closed it
+fn record_own_write() {
+   let sequence_number = win::get_clipboard_sequence_number();
+   SELF_WRITES.with(|x| x.borrow_mut().wrote(sequence_number));
+}
+
+/// Whether the clipboard still holds what ripclip last wrote to it, rather than a copy made since
+fn is_own_write() -> bool {
+   let sequence_number = win::get_clipboard_sequence_number();
+   let has_marker = win::is_format_available(&entry::Format::marker());
+   SELF_WRITES.with(|x| x.borrow().is_own(sequence_number, has_marker))
+}
+
+fn rotate(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: bool,
+   config: &config::Config,
+) {
+   if !managing_clipboard {
+      trace!("Can't rotate when the clipboard is not being managed by ripclip (clipboard contains non-text)");
+      return;
+   }
+
+   if stack::rotate(clipboard_stack) {
+      set_clipboard_to_top(window, clipboard_stack, config);
+      trace!("Rotated stack");
+   } else {
+      trace!("Stack too small to rotate");
+   }
+}
+
+fn reverse(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: bool,
+   config: &config::Config,
+) {
+   if !managing_clipboard {
+      trace!("Can't reverse when the clipboard is not being managed by ripclip (clipboard contains non-text)");
+      return;
+   }
+
+   if stack::reverse(clipboard_stack) {
+      set_clipboard_to_top(window, clipboard_stack, config);
+      trace!("Reversed stack");
+   } else {
+      trace!("Stack too small to reverse");
+   }
+}
+
+fn move_to_bottom(
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: bool,
+   config: &config::Config,
+) {
+   if !managing_clipboard {
+      trace!("Can't move to bottom when the clipboard is not being managed by ripclip (clipboard contains non-text)");
+      return;
+   }
+
+   if !stack::move_to_bottom(clipboard_stack) {
+      trace!("Nothing on stack to move to bottom");
+      return;
+   }
+   // With only one entry, the top of the stack hasn't changed
+   if clipboard_stack.len() > 1 {
+      set_clipboard_to_top(window, clipboard_stack, config);
+   }
+   trace!("Moved top element to bottom of stack");
+}
+
+fn fill_viewer(viewer: &mut win::ListPopup, clipboard_stack: &stack::Stack<entry::Entry>) {
+   // Listed top down
+   let previews = clipboard_stack.iter_with_details().rev().map(|(x, _, pinned)| {
+      let preview = x.preview(VIEWER_PREVIEW_CHARS);
+      if pinned {
+         format!("{}{}", VIEWER_PINNED_MARKER, preview)
+      } else {
+         preview
+      }
+   });
+   if viewer.set_items(previews).is_err() {
+      warn!("Failed to list the stack in the stack viewer");
+   }
+}
+
+fn show_stack<'a>(
+   viewer: &mut Option<win::ListPopup<'a>>,
+   viewer_class: &'a win::ClassAtom,
+   clipboard_stack: &stack::Stack<entry::Entry>,
+) {
+   // Only one viewer is open at a time
+   *viewer = None;
+   let (x, y) = win::get_cursor_pos().unwrap_or((0, 0));
+   let mut list = match win::create_list_popup(viewer_class, x, y, VIEWER_WIDTH, VIEWER_HEIGHT) {
+      Ok(list) => list,
+      Err(e) => {
+         warn!("Failed to open the stack viewer: {}", e);
+         return;
+      }
+   };
+   fill_viewer(&mut list, clipboard_stack);
+   list.select(0);
+   list.show();
+   *viewer = Some(list);
+}
+
+/// Keeps an open stack viewer up to date after the stack has changed
+fn refresh_viewer(viewer: &mut Option<win::ListPopup>, clipboard_stack: &stack::Stack<entry::Entry>) {
+   if let Some(list) = viewer {
+      let selection = list.selection().unwrap_or(0);
+      fill_viewer(list, clipboard_stack);
+      list.select(selection.min(clipboard_stack.len().saturating_sub(1)));
+   }
+}
+
+fn on_viewer_event(
+   message: &win::Message,
+   viewer: &mut Option<win::ListPopup>,
+   window: &win::WindowHandle,
+   clipboard_stack: &mut stack::Stack<entry::Entry>,
+   managing_clipboard: &mut bool,
+   config: &config::Config,
+) {
+   let list = match viewer {
+      Some(list) if message.is_for(list.window()) => list,
+      // Left over from a viewer that's already closed
+      _ => return,
+   };
+   if message.message == VIEWER_CLOSE_MESSAGE {
+      *viewer = None;
+      return;
+   }
+   // The list is top down, while the stack is bottom up
+   let stack_index = list
+      .selection()
+      .filter(|x| *x < clipboard_stack.len())
+      .map(|x| clipboard_stack.len() - 1 - x);
+   match message.w_param as i32 {
+      winapi::um::winuser::VK_RETURN => {
+         if let Some(stack_index) = stack_index {
+            stack::move_to_top(clipboard_stack, stack_index);
+            set_clipboard_to_top(window, clipboard_stack, config);
+            *managing_clipboard = true;
+            trace!("Moved selected element to top of stack");
+         }
+         *viewer = None;
+      }
+      winapi::um::winuser::VK_DELETE => {
+         if let Some(stack_index) = stack_index {
+            clipboard_stack.remove(stack_index);
+            if stack_index == clipboard_stack.len() {
+               // The clipboard is left alone, so it no longer holds the top of the stack
+               *managing_clipboard = false;
+            }
+            trace!("Removed selected element from stack");
+         }
+         refresh_viewer(viewer, clipboard_stack);
+      }
+      winapi::um::winuser::VK_ESCAPE => {
+         *viewer = None;
+      }
+      x => {
+         warn!("Unknown stack viewer key {}", x);
+      }
+   }
+}
+
+fn open_clipboard_with_backoff(
+   hwnd: &win::WindowHandle,
+   config: &config::Config,
+) -> Result<win::ClipboardHandle, win::ErrorCode> {
+   let result = retry_open_clipboard(|| win::open_clipboard(hwnd), config);
+   match &result {
+      Ok(_) => record_clipboard_success(failures::Operation::Open),
+      Err(e) => record_clipboard_failure(failures::Operation::Open, e),
+   }
+   result
+}
+
+/// Opens the clipboard with `open`, like `open_clipboard_with_backoff`
+fn retry_open_clipboard(
+   open: impl Fn() -> Result<win::ClipboardHandle, win::ErrorCode>,
+   config: &config::Config,
+) -> Result<win::ClipboardHandle, win::ErrorCode> {
+   // On Windows, only one application may have the clipboard open at a time
+   // Some applications fight us for the clipboard (especially after an operation),
+   // and so to avoid failing we try to access the clipboard several times in a short succession.
+   // If we still can't access the clipboard after `clipboard_retry_ms`, we give up and log which
+   // program has it, and callers leave the stack as it was.
+
+   use std::time::{Duration, Instant};
+   use rand::Rng;
+   use rand::rngs::OsRng;
+   use rand::distributions::uniform::Uniform;
+
+   let mut sleep_duration = Duration::from_millis(1);
+   let mut open_result = open();
+   let start_time = Instant::now();
+   while start_time.elapsed() <= Duration::from_millis(u64::from(config.clipboard_retry_ms)) {
+      // Try to open clipboard
+      match open_result {
+         Err(win::ERROR_ACCESS_DENIED) => trace!("Clipboard is locked, backing off"),
+         _ => break,
+      }
+      std::thread::sleep(sleep_duration);
+      // "Decorrelated jitter"
+      let range = Uniform::new_inclusive(Duration::from_millis(1), sleep_duration * 3);
+      sleep_duration = std::cmp::min(Duration::from_millis(50), OsRng.sample(range));
+      open_result = open();
+   }
+   if let Err(e) = &open_result {
+      error!(
+         "Failed to open the clipboard after trying for {}ms: {}. The program with it open is {}",
+         config.clipboard_retry_ms,
+         e,
+         clipboard_opener_name()
+      );
+   }
+   open_result
+}
+
+/// Describes the program with the clipboard open, for saying who's holding it
+fn clipboard_opener_name() -> String {
+   match win::get_open_clipboard_process() {
+      Ok(Some(process_id)) => match win::get_process_name(process_id) {
+         Ok(name) => format!("{} (process {})", name, process_id),
+         Err(_) => format!("process {}", process_id),
+      },
+      Ok(None) => String::from("unknown, as no window has it open"),
+      Err(e) => format!("an unknown program ({})", e),
+   }
+}
+
+unsafe extern "system" fn on_message(
+   handle: winapi::shared::windef::HWND,
+   umsg: u32,
+   w_param: usize,
+   l_param: isize,
+) -> winapi::shared::minwindef::LRESULT {
    let l_param_u: usize = std::mem::transmute::<_, _>(l_param);
+   // Sent to whichever window owns the clipboard, which is ours after a `delayed_render` pop, and to the windows in
+   // the clipboard viewer chain
+   match umsg {
+      winapi::um::winuser::WM_RENDERFORMAT => {
+         render_promised_format(w_param as u32);
+         return 0;
+      }
+      winapi::um::winuser::WM_RENDERALLFORMATS => {
+         render_all_promised_formats(handle);
+         return 0;
+      }
+      winapi::um::winuser::WM_DRAWCLIPBOARD => {
+         on_draw_clipboard(handle);
+         return 0;
+      }
+      winapi::um::winuser::WM_CHANGECBCHAIN => {
+         on_change_clipboard_chain(
+            w_param as winapi::shared::windef::HWND,
+            l_param as winapi::shared::windef::HWND,
+         );
+         return 0;
+      }
+      winapi::um::winuser::WM_DESTROYCLIPBOARD => {
+         // The clipboard was emptied, so nothing promised can be asked for any more
+         DELAYED_RENDER.with(|x| *x.borrow_mut() = None);
+         return 0;
+      }
+      _ => (),
+   }
+   match umsg {
+      winapi::um::winuser::WM_QUERYENDSESSION => {
+         on_session_end(handle, shutdown::Request::QueryEndSession, SESSION_ENDING_MESSAGE, 0);
+         return 1;
+      }
+      winapi::um::winuser::WM_ENDSESSION => {
+         on_session_end(
+            handle,
+            shutdown::Request::EndSession(w_param != 0),
+            SESSION_END_MESSAGE,
+            w_param,
+         );
+         return 0;
+      }
+      winapi::um::winuser::WM_CLOSE => {
+         // Sent by `taskkill` without /F, which shouldn't destroy the window out from under the message loop
+         if winapi::um::winuser::PostMessageW(handle, EXIT_MESSAGE, 0, 0) == 0 {
+            warn!("Failed to post exit event");
+         }
+         return 0;
+      }
+      _ => (),
+   }
+   if umsg == winapi::um::winuser::WM_INPUTLANGCHANGE {
+      // Sent rather than posted, so our message loop wouldn't otherwise see it
+      let result = winapi::um::winuser::PostMessageW(handle, umsg, w_param, l_param);
+      if result == 0 {
+         warn!("Failed to post input language change event");
+      }
+   }
+   if umsg == winapi::um::winuser::WM_WTSSESSION_CHANGE {
+      // Likewise, when it's sent rather than posted
+      let result = winapi::um::winuser::PostMessageW(handle, umsg, w_param, l_param);
+      if result == 0 {
+         warn!("Failed to post session change event");
+      }
+   }
    if (l_param_u & 0x0000_0000_0000_ffff) as u32 == winapi::um::winuser::WM_CONTEXTMENU {
       let result = winapi::um::winuser::PostMessageW(handle, winapi::um::winuser::WM_CONTEXTMENU, w_param, l_param);
       if result == 0 {
@@ -305,3 +4326,178 @@ unsafe extern "system" fn on_message(
    }
    winapi::um::winuser::DefWindowProcW(handle, umsg, w_param, l_param)
 }
+
+unsafe extern "system" fn on_mouse_event(
+   code: i32,
+   w_param: usize,
+   l_param: isize,
+) -> winapi::shared::minwindef::LRESULT {
+   if code == winapi::um::winuser::HC_ACTION {
+      let message = w_param as u32;
+      let info = &*(l_param as *const winapi::um::winuser::MSLLHOOKSTRUCT);
+      let button = match message {
+         winapi::um::winuser::WM_MBUTTONDOWN | winapi::um::winuser::WM_MBUTTONUP => Some(win::VirtualKey::MiddleClick),
+         winapi::um::winuser::WM_XBUTTONDOWN | winapi::um::winuser::WM_XBUTTONUP => {
+            match winapi::shared::minwindef::HIWORD(info.mouseData) {
+               winapi::um::winuser::XBUTTON1 => Some(win::VirtualKey::XButton1),
+               winapi::um::winuser::XBUTTON2 => Some(win::VirtualKey::XButton2),
+               _ => None,
+            }
+         }
+         _ => None,
+      };
+      if let Some(button) = button {
+         let down = message == winapi::um::winuser::WM_MBUTTONDOWN || message == winapi::um::winuser::WM_XBUTTONDOWN;
+         if on_hooked_key(button as u32, down) {
+            return 1;
+         }
+      }
+   }
+   winapi::um::winuser::CallNextHookEx(ptr::null_mut(), code, w_param, l_param)
+}
+
+unsafe extern "system" fn on_keyboard_event(
+   code: i32,
+   w_param: usize,
+   l_param: isize,
+) -> winapi::shared::minwindef::LRESULT {
+   if code == winapi::um::winuser::HC_ACTION {
+      let info = &*(l_param as *const winapi::um::winuser::KBDLLHOOKSTRUCT);
+      let down = match w_param as u32 {
+         winapi::um::winuser::WM_KEYDOWN | winapi::um::winuser::WM_SYSKEYDOWN => Some(true),
+         winapi::um::winuser::WM_KEYUP | winapi::um::winuser::WM_SYSKEYUP => Some(false),
+         _ => None,
+      };
+      if let Some(down) = down {
+         if on_hooked_key(info.vkCode, down) {
+            return 1;
+         }
+      }
+   }
+   winapi::um::winuser::CallNextHookEx(ptr::null_mut(), code, w_param, l_param)
+}
+
+unsafe extern "system" fn on_sequence_event(
+   code: i32,
+   w_param: usize,
+   l_param: isize,
+) -> winapi::shared::minwindef::LRESULT {
+   if code == winapi::um::winuser::HC_ACTION {
+      let info = &*(l_param as *const winapi::um::winuser::KBDLLHOOKSTRUCT);
+      let down = match w_param as u32 {
+         winapi::um::winuser::WM_KEYDOWN | winapi::um::winuser::WM_SYSKEYDOWN => Some(true),
+         winapi::um::winuser::WM_KEYUP | winapi::um::winuser::WM_SYSKEYUP => Some(false),
+         _ => None,
+      };
+      if let Some(down) = down {
+         if on_sequence_key(info.vkCode, down) {
+            return 1;
+         }
+      }
+   }
+   winapi::um::winuser::CallNextHookEx(ptr::null_mut(), code, w_param, l_param)
+}
+
+/// Called from the sequence hook, returning whether the key was taken by the sequence
+fn on_sequence_key(code: u32, down: bool) -> bool {
+   SEQUENCE_CAPTURE.with(|x| {
+      let mut capture = match x.try_borrow_mut() {
+         Ok(capture) => capture,
+         Err(_) => return false,
+      };
+      let state = match capture.as_mut() {
+         Some(state) => state,
+         None => return false,
+      };
+      if state.leader_key == Some(code) {
+         if !down {
+            state.leader_key = None;
+         }
+         // The first step's key repeating is swallowed, but its release is left alone
+         return down;
+      }
+      // Modifiers are often still held from the first step
+      if !down || is_modifier_code(code) {
+         return false;
+      }
+      // Anything that isn't a second step, Escape included, cancels the sequence
+      let id = state.steps.iter().find(|(key, _)| *key == code).map(|(_, id)| *id);
+      *capture = None;
+      let main_thread = MAIN_THREAD.load(Ordering::SeqCst);
+      if let Some(id) = id {
+         let result =
+            unsafe { winapi::um::winuser::PostThreadMessageW(main_thread, HOOK_BINDING_MESSAGE, id as usize, 0) };
+         if result == 0 {
+            warn!("Failed to post sequence event");
+         }
+      }
+      let result = unsafe {
+         winapi::um::winuser::PostThreadMessageW(main_thread, SEQUENCE_END_MESSAGE, id.is_some() as usize, 0)
+      };
+      if result == 0 {
+         warn!("Failed to post sequence end");
+      }
+      true
+   })
+}
+
+/// Whether the virtual key `code`, as a keyboard hook sees it, is one of the modifier keys
+fn is_modifier_code(code: u32) -> bool {
+   matches!(
+      code as i32,
+      winapi::um::winuser::VK_SHIFT
+         | winapi::um::winuser::VK_CONTROL
+         | winapi::um::winuser::VK_MENU
+         | winapi::um::winuser::VK_LSHIFT
+         | winapi::um::winuser::VK_RSHIFT
+         | winapi::um::winuser::VK_LCONTROL
+         | winapi::um::winuser::VK_RCONTROL
+         | winapi::um::winuser::VK_LMENU
+         | winapi::um::winuser::VK_RMENU
+         | winapi::um::winuser::VK_LWIN
+         | winapi::um::winuser::VK_RWIN
+   )
+}
+
+/// Called from the hooks, returning whether the key was for a binding
+fn on_hooked_key(code: u32, down: bool) -> bool {
+   // A reentrant call, which shouldn't happen, is passed along untouched
+   HOOK_BINDINGS.with(|x| match x.try_borrow_mut() {
+      Ok(mut bindings) => bindings.on_key(code, down),
+      Err(_) => false,
+   })
+}
+
+unsafe extern "system" fn on_viewer_message(
+   handle: winapi::shared::windef::HWND,
+   umsg: u32,
+   w_param: usize,
+   l_param: isize,
+) -> winapi::shared::minwindef::LRESULT {
+   match umsg {
+      winapi::um::winuser::WM_VKEYTOITEM => {
+         let key = (w_param & 0xFFFF) as i32;
+         if key == winapi::um::winuser::VK_RETURN
+            || key == winapi::um::winuser::VK_DELETE
+            || key == winapi::um::winuser::VK_ESCAPE
+         {
+            let result = winapi::um::winuser::PostMessageW(handle, VIEWER_KEY_MESSAGE, key as usize, 0);
+            if result == 0 {
+               warn!("Failed to post stack viewer key event");
+            }
+            // We've handled the key ourselves
+            return -2;
+         }
+         // Let the list box handle the key
+         return -1;
+      }
+      winapi::um::winuser::WM_ACTIVATE if (w_param & 0xFFFF) as u16 == winapi::um::winuser::WA_INACTIVE => {
+         let result = winapi::um::winuser::PostMessageW(handle, VIEWER_CLOSE_MESSAGE, 0, 0);
+         if result == 0 {
+            warn!("Failed to post stack viewer close event");
+         }
+      }
+      _ => (),
+   }
+   winapi::um::winuser::DefWindowProcW(handle, umsg, w_param, l_param)
+}
@@ -0,0 +1,200 @@
+//! Getting plain text out of Rich Text Format, for copies that come only as RTF. This is best effort: the text is
+//! kept, and the formatting and anything that isn't text, such as pictures and the font table, is left out.
+
+/// Groups starting with these control words hold something other than the document's text
+const SKIPPED_DESTINATIONS: &[&str] = &[
+   "fonttbl",
+   "colortbl",
+   "stylesheet",
+   "listtable",
+   "listoverridetable",
+   "info",
+   "pict",
+   "object",
+   "header",
+   "footer",
+   "generator",
+   "themedata",
+   "datastore",
+];
+
+/// What's being read in a group
+#[derive(Clone, Copy)]
+struct Group {
+   skipped: bool,
+   /// How many characters follow a `\u` character for readers that don't understand it, set by `\uc`
+   fallback_len: usize,
+}
+
+/// Reads a control word's name and its numeric parameter, if it has one, starting just after the backslash. Returns
+/// them along with where the control word ends, past the space that can follow it.
+fn read_control_word(rtf: &[u8], start: usize) -> (&str, Option<i32>, usize) {
+   let mut i = start;
+   while i < rtf.len() && rtf[i].is_ascii_alphabetic() {
+      i += 1;
+   }
+   let name = std::str::from_utf8(&rtf[start..i]).unwrap_or("");
+   let param_start = i;
+   if i < rtf.len() && rtf[i] == b'-' {
+      i += 1;
+   }
+   while i < rtf.len() && rtf[i].is_ascii_digit() {
+      i += 1;
+   }
+   let param = std::str::from_utf8(&rtf[param_start..i])
+      .ok()
+      .and_then(|x| x.parse().ok());
+   if i < rtf.len() && rtf[i] == b' ' {
+      i += 1;
+   }
+   (name, param, i)
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+   (byte as char).to_digit(16).map(|x| x as u8)
+}
+
+/// The plain text of an RTF document. Paragraphs and line breaks become `\r\n`. Characters given by their code in
+/// the document's code page are read as Windows-1252, which they almost always are.
+pub fn to_text(rtf: &[u8]) -> String {
+   let mut text = String::new();
+   let mut units: Vec<u16> = Vec::new();
+   let mut groups = vec![Group {
+      skipped: false,
+      fallback_len: 1,
+   }];
+   // How many more characters to skip, after a `\u` character
+   let mut fallback_left = 0;
+   let mut i = 0;
+   while i < rtf.len() {
+      let group = *groups.last().unwrap();
+      let byte = rtf[i];
+      i += 1;
+      let c = match byte {
+         b'{' => {
+            groups.push(group);
+            // `{\*\name ...}` marks a destination that can be ignored by readers that don't know it
+            if rtf[i..].starts_with(b"\\*") {
+               groups.last_mut().unwrap().skipped = true;
+            }
+            continue;
+         }
+         b'}' => {
+            if groups.len() > 1 {
+               groups.pop();
+            }
+            continue;
+         }
+         b'\r' | b'\n' => continue,
+         // Some apps put more after the null that ends the RTF
+         b'\0' => break,
+         b'\\' if i == rtf.len() => continue,
+         b'\\' => {
+            let next = rtf[i];
+            i += 1;
+            match next {
+               b'\\' | b'{' | b'}' => next as char,
+               b'~' => '\u{A0}',
+               b'_' => '\u{2011}',
+               b'\r' | b'\n' => '\n',
+               b'\'' => {
+                  let code = rtf
+                     .get(i..i + 2)
+                     .and_then(|x| Some(hex_digit(x[0])? << 4 | hex_digit(x[1])?));
+                  i = (i + 2).min(rtf.len());
+                  match code {
+                     Some(code) => windows_1252(code),
+                     None => continue,
+                  }
+               }
+               x if x.is_ascii_alphabetic() => {
+                  let (name, param, end) = read_control_word(rtf, i - 1);
+                  i = end;
+                  match name {
+                     "par" | "line" | "row" => '\n',
+                     "tab" | "cell" => '\t',
+                     "uc" => {
+                        groups.last_mut().unwrap().fallback_len = param.unwrap_or(1).max(0) as usize;
+                        continue;
+                     }
+                     "u" => {
+                        // Negative codes are how writers without unsigned numbers give those above 32767
+                        let unit = param.unwrap_or(0) as i16 as u16;
+                        if !group.skipped {
+                           units.push(unit);
+                        }
+                        fallback_left = group.fallback_len;
+                        continue;
+                     }
+                     _ if SKIPPED_DESTINATIONS.contains(&name) => {
+                        groups.last_mut().unwrap().skipped = true;
+                        continue;
+                     }
+                     _ => continue,
+                  }
+               }
+               // Other control symbols, such as optional hyphens, aren't text
+               _ => continue,
+            }
+         }
+         _ => byte as char,
+      };
+      if fallback_left > 0 {
+         fallback_left -= 1;
+         continue;
+      }
+      if group.skipped {
+         continue;
+      }
+      // Characters given with `\u` come as UTF-16, so a pair of them can make up one character
+      text.extend(char::decode_utf16(units.drain(..)).map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER)));
+      if c == '\n' {
+         text.push('\r');
+      }
+      text.push(c);
+   }
+   text.extend(char::decode_utf16(units.drain(..)).map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER)));
+   text
+}
+
+/// The character a byte stands for in Windows-1252, which is Latin-1 apart from 0x80 to 0x9F
+fn windows_1252(byte: u8) -> char {
+   const HIGH: [char; 32] = [
+      '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘',
+      '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+   ];
+   match byte {
+      0x80..=0x9F => HIGH[usize::from(byte - 0x80)],
+      _ => char::from(byte),
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   #[test]
+   fn strips_formatting() {
+      let rtf = br"{\rtf1\ansi\deff0{\fonttbl{\f0\fswiss Calibri;}}{\colortbl;\red255\green0\blue0;}
+{\*\generator Riched20 10.0;}\pard\f0\fs22 Some \b bold\b0  and {\i italic} text\par
+Second line\tab tabbed\line\par}";
+      assert_eq!(to_text(rtf), "Some bold and italic text\r\nSecond line\ttabbed\r\n\r\n");
+   }
+
+   #[test]
+   fn reads_escapes_and_unicode() {
+      assert_eq!(to_text(br"{\rtf1 \{braces\} and \\ back}"), "{braces} and \\ back");
+      assert_eq!(to_text(br"{\rtf1 caf\'e9 \'93quoted\'94}"), "café “quoted”");
+      // Each \u character is followed by a fallback for older readers, which is skipped
+      assert_eq!(to_text(br"{\rtf1 \u1055?\u1088?\uc2\u-10178??\u-8832??}"), "Пр🦀");
+      assert_eq!(to_text(br"{\rtf1 \uc0\u20320\u22909}"), "你好");
+   }
+
+   #[test]
+   fn reads_unterminated_rtf() {
+      assert_eq!(to_text(b""), "");
+      assert_eq!(to_text(br"{\rtf1 cut \'e"), "cut ");
+      assert_eq!(to_text(br"plain}} text\"), "plain text");
+      assert_eq!(to_text(b"{\\rtf1 end}\0\0garbage"), "end");
+   }
+}
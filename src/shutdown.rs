@@ -0,0 +1,141 @@
+//! Shutting down cleanly, however ripclip is asked to: the tray menu, Ctrl+C or closing an attached console, or
+//! Windows ending the session. More than one can arrive, so the cleanup is only ever done once.
+
+/// What's asked of ripclip that might end it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+   /// Exit from the tray menu, WM_QUIT, or Ctrl+C or closing the console ripclip is attached to
+   Exit,
+   /// Windows asking whether the session can end. It might still be cancelled by another program, but once it
+   /// isn't there may be no time left to save anything.
+   QueryEndSession,
+   /// Windows saying whether the session is really ending, after asking
+   EndSession(bool),
+}
+
+/// The cleanup done when shutting down, in the order it's done
+pub trait Steps {
+   /// Saves the stack if it's persisted and has changed since it was last saved
+   fn save_stack(&mut self);
+   fn unregister_hotkeys(&mut self);
+   fn stop_watching_clipboard(&mut self);
+   /// Removes the tray icon, which would otherwise linger until the mouse passes over it
+   fn remove_tray_icon(&mut self);
+}
+
+/// Tracks whether the cleanup has been done
+#[derive(Debug, Default)]
+pub struct Shutdown {
+   done: bool,
+}
+
+impl Shutdown {
+   pub fn new() -> Shutdown {
+      Shutdown::default()
+   }
+
+   /// Handles a request, doing whatever of `steps` it calls for. Returns whether ripclip should stop.
+   pub fn handle<S: Steps>(&mut self, request: Request, steps: &mut S) -> bool {
+      match request {
+         Request::QueryEndSession => {
+            // Saved now in case the session ends without another chance, but everything else carries on in case
+            // it doesn't
+            if !self.done {
+               steps.save_stack();
+            }
+            false
+         }
+         Request::EndSession(false) => false,
+         Request::Exit | Request::EndSession(true) => {
+            self.run(steps);
+            true
+         }
+      }
+   }
+
+   /// Does all of the cleanup, unless it's already been done
+   pub fn run<S: Steps>(&mut self, steps: &mut S) {
+      if self.done {
+         return;
+      }
+      self.done = true;
+      steps.save_stack();
+      steps.unregister_hotkeys();
+      steps.stop_watching_clipboard();
+      steps.remove_tray_icon();
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   /// Records the steps taken, in order
+   #[derive(Default)]
+   struct Recorder {
+      calls: Vec<&'static str>,
+   }
+
+   impl Steps for Recorder {
+      fn save_stack(&mut self) {
+         self.calls.push("save_stack");
+      }
+
+      fn unregister_hotkeys(&mut self) {
+         self.calls.push("unregister_hotkeys");
+      }
+
+      fn stop_watching_clipboard(&mut self) {
+         self.calls.push("stop_watching_clipboard");
+      }
+
+      fn remove_tray_icon(&mut self) {
+         self.calls.push("remove_tray_icon");
+      }
+   }
+
+   const ALL_STEPS: [&str; 4] = [
+      "save_stack",
+      "unregister_hotkeys",
+      "stop_watching_clipboard",
+      "remove_tray_icon",
+   ];
+
+   #[test]
+   fn exit_does_every_step_in_order() {
+      let mut shutdown = Shutdown::new();
+      let mut steps = Recorder::default();
+      assert!(shutdown.handle(Request::Exit, &mut steps));
+      assert_eq!(steps.calls, ALL_STEPS);
+   }
+
+   #[test]
+   fn steps_are_only_done_once() {
+      let mut shutdown = Shutdown::new();
+      let mut steps = Recorder::default();
+      assert!(shutdown.handle(Request::EndSession(true), &mut steps));
+      assert!(shutdown.handle(Request::Exit, &mut steps));
+      shutdown.run(&mut steps);
+      assert!(!shutdown.handle(Request::QueryEndSession, &mut steps));
+      assert_eq!(steps.calls, ALL_STEPS);
+   }
+
+   #[test]
+   fn query_end_session_only_saves() {
+      let mut shutdown = Shutdown::new();
+      let mut steps = Recorder::default();
+      assert!(!shutdown.handle(Request::QueryEndSession, &mut steps));
+      assert_eq!(steps.calls, ["save_stack"]);
+   }
+
+   #[test]
+   fn cancelled_end_session_carries_on() {
+      let mut shutdown = Shutdown::new();
+      let mut steps = Recorder::default();
+      assert!(!shutdown.handle(Request::QueryEndSession, &mut steps));
+      assert!(!shutdown.handle(Request::EndSession(false), &mut steps));
+      assert_eq!(steps.calls, ["save_stack"]);
+      assert!(shutdown.handle(Request::Exit, &mut steps));
+      assert_eq!(steps.calls[1..], ALL_STEPS);
+   }
+}
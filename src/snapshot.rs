@@ -0,0 +1,364 @@
+//! Saving the stack to a file and reading it back
+
+use crate::stack::Kind;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime};
+
+const MAGIC: &[u8] = b"ripclip snapshot 2\n";
+/// Marks a snapshot taken before the stack could hold anything but text, which is read as all text
+const TEXT_MAGIC: &[u8] = b"ripclip snapshot 1\n";
+/// Marks the file the stack is kept in between runs, which unlike a snapshot also records when entries were copied
+/// and whether they're pinned
+const STACK_MAGIC: &[u8] = b"ripclip stack 2\n";
+/// Marks a stack saved before entries could be pinned, which is read as having none pinned
+const UNPINNED_STACK_MAGIC: &[u8] = b"ripclip stack 1\n";
+/// Set in an entry's flags when it's pinned
+const PINNED_FLAG: u8 = 1;
+/// Set in an entry's flags when it's a file list rather than text
+const FILES_FLAG: u8 = 2;
+/// Set in an entry's flags when it's an image rather than text
+const IMAGE_FLAG: u8 = 4;
+/// Marks a saved stack that's encrypted as a whole, which follows
+const ENCRYPTED_STACK_MAGIC: &[u8] = b"ripclip encrypted stack 1\n";
+
+/// An entry read back from a saved stack: what kind it is, its contents, when it was copied and whether it's pinned
+pub type SavedEntry = (Kind, Vec<u8>, SystemTime, bool);
+
+fn invalid_data(message: &str) -> io::Error {
+   io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+   let mut bytes = [0; 8];
+   input.read_exact(&mut bytes)?;
+   Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_entry<W: Write>(output: &mut W, entry: &[u8]) -> io::Result<()> {
+   output.write_all(&(entry.len() as u64).to_le_bytes())?;
+   output.write_all(entry)
+}
+
+fn read_entry<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+   let len = read_u64(input)?;
+   // The length isn't trusted for allocating up front, in case the file is corrupt
+   let mut entry = Vec::new();
+   input.take(len).read_to_end(&mut entry)?;
+   if entry.len() as u64 != len {
+      return Err(invalid_data("File ends in the middle of an entry"));
+   }
+   Ok(entry)
+}
+
+fn read_u8<R: Read>(input: &mut R) -> io::Result<u8> {
+   let mut byte = [0];
+   input.read_exact(&mut byte)?;
+   Ok(byte[0])
+}
+
+fn kind_flag(kind: Kind) -> u8 {
+   match kind {
+      Kind::Text => 0,
+      Kind::Files => FILES_FLAG,
+      Kind::Image => IMAGE_FLAG,
+   }
+}
+
+fn flag_kind(flags: u8) -> Kind {
+   if flags & FILES_FLAG != 0 {
+      Kind::Files
+   } else if flags & IMAGE_FLAG != 0 {
+      Kind::Image
+   } else {
+      Kind::Text
+   }
+}
+
+/// Writes the entries of a stack along with what kind each is, given from the bottom of the stack to the top
+pub fn write_snapshot<W, I, E>(output: &mut W, entries: I) -> io::Result<()>
+where
+   W: Write,
+   I: ExactSizeIterator<Item = (Kind, E)>,
+   E: AsRef<[u8]>,
+{
+   output.write_all(MAGIC)?;
+   output.write_all(&(entries.len() as u64).to_le_bytes())?;
+   for (kind, entry) in entries {
+      output.write_all(&[kind_flag(kind)])?;
+      write_entry(output, entry.as_ref())?;
+   }
+   Ok(())
+}
+
+/// Reads back entries written by `write_snapshot`, bottom of the stack first
+pub fn read_snapshot<R: Read>(input: &mut R) -> io::Result<Vec<(Kind, Vec<u8>)>> {
+   let mut magic = vec![0; MAGIC.len()];
+   input.read_exact(&mut magic)?;
+   let has_flags = match &magic[..] {
+      MAGIC => true,
+      TEXT_MAGIC => false,
+      _ => return Err(invalid_data("Not a ripclip snapshot")),
+   };
+   let count = read_u64(input)?;
+   let mut entries = Vec::new();
+   for _ in 0..count {
+      let flags = if has_flags { read_u8(input)? } else { 0 };
+      entries.push((flag_kind(flags), read_entry(input)?));
+   }
+   Ok(entries)
+}
+
+/// Writes the entries of a stack along with what kind each is, when it was copied and whether it's pinned, given
+/// from the bottom of the stack to the top
+pub fn write_stack<W, I, E>(output: &mut W, entries: I) -> io::Result<()>
+where
+   W: Write,
+   I: ExactSizeIterator<Item = (Kind, E, SystemTime, bool)>,
+   E: AsRef<[u8]>,
+{
+   output.write_all(STACK_MAGIC)?;
+   output.write_all(&(entries.len() as u64).to_le_bytes())?;
+   for (kind, entry, copied_at, pinned) in entries {
+      let copied_at = copied_at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+      output.write_all(&(copied_at.as_millis() as u64).to_le_bytes())?;
+      let pinned_flag = if pinned { PINNED_FLAG } else { 0 };
+      output.write_all(&[kind_flag(kind) | pinned_flag])?;
+      write_entry(output, entry.as_ref())?;
+   }
+   Ok(())
+}
+
+/// Reads back entries written by `write_stack`, bottom of the stack first. Anything after the last entry means
+/// the file is corrupt, so it's an error too.
+pub fn read_stack<R: Read>(input: &mut R) -> io::Result<Vec<SavedEntry>> {
+   let mut magic = vec![0; STACK_MAGIC.len()];
+   input.read_exact(&mut magic)?;
+   let has_flags = match &magic[..] {
+      STACK_MAGIC => true,
+      UNPINNED_STACK_MAGIC => false,
+      _ => return Err(invalid_data("Not a saved ripclip stack")),
+   };
+   let count = read_u64(input)?;
+   let mut entries = Vec::new();
+   for _ in 0..count {
+      let copied_at = SystemTime::UNIX_EPOCH + Duration::from_millis(read_u64(input)?);
+      let flags = if has_flags { read_u8(input)? } else { 0 };
+      entries.push((
+         flag_kind(flags),
+         read_entry(input)?,
+         copied_at,
+         flags & PINNED_FLAG != 0,
+      ));
+   }
+   if input.read(&mut [0])? != 0 {
+      return Err(invalid_data("Unexpected data after the last entry"));
+   }
+   Ok(entries)
+}
+
+/// Writes the entries like `write_stack`, but encrypted as a whole by `encrypt`
+pub fn write_encrypted_stack<W, I, E, F>(output: &mut W, entries: I, encrypt: F) -> io::Result<()>
+where
+   W: Write,
+   I: ExactSizeIterator<Item = (Kind, E, SystemTime, bool)>,
+   E: AsRef<[u8]>,
+   F: FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+{
+   let mut plain = Vec::new();
+   write_stack(&mut plain, entries)?;
+   let encrypted = encrypt(&plain)?;
+   output.write_all(ENCRYPTED_STACK_MAGIC)?;
+   output.write_all(&encrypted)
+}
+
+/// Reads back entries written by either `write_stack` or `write_encrypted_stack`, using `decrypt` for the latter
+pub fn read_any_stack<R, F>(input: &mut R, decrypt: F) -> io::Result<Vec<SavedEntry>>
+where
+   R: Read,
+   F: FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+{
+   let mut contents = Vec::new();
+   input.read_to_end(&mut contents)?;
+   match contents.strip_prefix(ENCRYPTED_STACK_MAGIC) {
+      Some(encrypted) => read_stack(&mut &decrypt(encrypted)?[..]),
+      None => read_stack(&mut &contents[..]),
+   }
+}
+
+/// Reads back entries written by `write_snapshot`, or by `write_encrypted_stack` for a snapshot taken with
+/// `persist_encrypted`, using `decrypt` for the latter. Snapshots don't keep when entries were copied or whether
+/// they're pinned, so that's left out of an encrypted one too.
+pub fn read_any_snapshot<R, F>(input: &mut R, decrypt: F) -> io::Result<Vec<(Kind, Vec<u8>)>>
+where
+   R: Read,
+   F: FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+{
+   let mut contents = Vec::new();
+   input.read_to_end(&mut contents)?;
+   if contents.starts_with(ENCRYPTED_STACK_MAGIC) {
+      let entries = read_any_stack(&mut &contents[..], decrypt)?;
+      Ok(entries.into_iter().map(|(kind, entry, _, _)| (kind, entry)).collect())
+   } else {
+      read_snapshot(&mut &contents[..])
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   #[test]
+   fn round_trips_entries() {
+      let entries = vec![
+         (Kind::Text, b"first".to_vec()),
+         (Kind::Text, Vec::new()),
+         (Kind::Files, vec![b'a', 0, 0, 0, 0, 0]),
+      ];
+      let mut output = Vec::new();
+      write_snapshot(&mut output, entries.iter().map(|(kind, entry)| (*kind, entry))).unwrap();
+      assert_eq!(read_snapshot(&mut &output[..]).unwrap(), entries);
+   }
+
+   #[test]
+   fn reads_snapshot_taken_before_file_lists() {
+      let mut output = TEXT_MAGIC.to_vec();
+      output.extend_from_slice(&1u64.to_le_bytes());
+      write_entry(&mut output, b"entry").unwrap();
+      assert_eq!(
+         read_snapshot(&mut &output[..]).unwrap(),
+         vec![(Kind::Text, b"entry".to_vec())]
+      );
+   }
+
+   #[test]
+   fn rejects_other_files() {
+      let error = read_snapshot(&mut &b"max_stack_size = 100\nshow_tray_icon = true\n"[..]).unwrap_err();
+      assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+   }
+
+   #[test]
+   fn rejects_truncated_snapshot() {
+      let mut output = Vec::new();
+      write_snapshot(&mut output, [(Kind::Text, b"entry")].iter().copied()).unwrap();
+      output.pop();
+      assert!(read_snapshot(&mut &output[..]).is_err());
+   }
+
+   #[test]
+   fn round_trips_stack_with_copy_times() {
+      let copied_at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_123);
+      let entries = vec![
+         (Kind::Text, b"first\nline".to_vec(), copied_at, true),
+         (Kind::Text, Vec::new(), copied_at + Duration::from_secs(60), false),
+         (Kind::Files, vec![b'a', 0, 0, 0, 0, 0], copied_at, true),
+         (
+            Kind::Image,
+            vec![12, 0, 0, 0, 1, 0, 1, 0, 1, 0, 24, 0],
+            copied_at,
+            false,
+         ),
+      ];
+      let mut output = Vec::new();
+      write_stack(
+         &mut output,
+         entries
+            .iter()
+            .map(|(kind, entry, at, pinned)| (*kind, entry, *at, *pinned)),
+      )
+      .unwrap();
+      assert_eq!(read_stack(&mut &output[..]).unwrap(), entries);
+      // A snapshot isn't a saved stack, nor the other way around
+      assert!(read_snapshot(&mut &output[..]).is_err());
+      let mut snapshot = Vec::new();
+      write_snapshot(&mut snapshot, [(Kind::Text, b"entry")].iter().copied()).unwrap();
+      assert_eq!(
+         read_stack(&mut &snapshot[..]).unwrap_err().kind(),
+         io::ErrorKind::InvalidData
+      );
+   }
+
+   #[test]
+   fn round_trips_encrypted_stack() {
+      let xor = |data: &[u8]| Ok(data.iter().map(|x| x ^ 0x5A).collect());
+      let entries = vec![(
+         Kind::Text,
+         b"secret".to_vec(),
+         SystemTime::UNIX_EPOCH + Duration::from_secs(5),
+         true,
+      )];
+      let saved = || {
+         entries
+            .iter()
+            .map(|(kind, entry, at, pinned)| (*kind, entry, *at, *pinned))
+      };
+      let mut output = Vec::new();
+      write_encrypted_stack(&mut output, saved(), xor).unwrap();
+      assert!(!output.windows(6).any(|x| x == b"secret"));
+      assert_eq!(read_any_stack(&mut &output[..], xor).unwrap(), entries);
+      // Failing to decrypt fails the read
+      let error = read_any_stack(&mut &output[..], |_| Err(invalid_data("Wrong user"))).unwrap_err();
+      assert_eq!(error.to_string(), "Wrong user");
+
+      // Stacks that aren't encrypted are read as they are
+      let mut output = Vec::new();
+      write_stack(&mut output, saved()).unwrap();
+      let read = read_any_stack(&mut &output[..], |_| panic!("Nothing to decrypt"));
+      assert_eq!(read.unwrap(), entries);
+   }
+
+   #[test]
+   fn reads_encrypted_snapshot() {
+      let xor = |data: &[u8]| Ok(data.iter().map(|x| x ^ 0x5A).collect());
+      let entries = vec![(Kind::Text, b"token".to_vec()), (Kind::Files, vec![b'a', 0, 0, 0])];
+      let mut output = Vec::new();
+      let saved = entries
+         .iter()
+         .map(|(kind, entry)| (*kind, entry, SystemTime::UNIX_EPOCH, false));
+      write_encrypted_stack(&mut output, saved, xor).unwrap();
+      assert!(!output.windows(5).any(|x| x == b"token"));
+      assert_eq!(read_any_snapshot(&mut &output[..], xor).unwrap(), entries);
+      assert!(read_any_snapshot(&mut &output[..], |_| Err(invalid_data("Wrong user"))).is_err());
+
+      let mut output = Vec::new();
+      write_snapshot(&mut output, entries.iter().map(|(kind, entry)| (*kind, entry))).unwrap();
+      let read = read_any_snapshot(&mut &output[..], |_| panic!("Nothing to decrypt"));
+      assert_eq!(read.unwrap(), entries);
+   }
+
+   #[test]
+   fn reads_stack_saved_before_pinning() {
+      let mut output = UNPINNED_STACK_MAGIC.to_vec();
+      output.extend_from_slice(&1u64.to_le_bytes());
+      output.extend_from_slice(&2000u64.to_le_bytes());
+      write_entry(&mut output, b"entry").unwrap();
+      assert_eq!(
+         read_stack(&mut &output[..]).unwrap(),
+         vec![(
+            Kind::Text,
+            b"entry".to_vec(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+            false
+         )]
+      );
+   }
+
+   #[test]
+   fn rejects_damaged_stack() {
+      let mut output = Vec::new();
+      write_stack(
+         &mut output,
+         [(Kind::Text, b"entry", SystemTime::UNIX_EPOCH, false)].iter().copied(),
+      )
+      .unwrap();
+      let mut truncated = output.clone();
+      truncated.pop();
+      assert!(read_stack(&mut &truncated[..]).is_err());
+      let mut extended = output.clone();
+      extended.push(0);
+      assert_eq!(
+         read_stack(&mut &extended[..]).unwrap_err().kind(),
+         io::ErrorKind::InvalidData
+      );
+      assert!(read_stack(&mut &output[..STACK_MAGIC.len()]).is_err());
+   }
+}
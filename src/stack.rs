@@ -0,0 +1,2276 @@
+use crate::config::{
+   Config, DedupeAction, DedupeScope, JoinOrder, LineEndings, Mode, OversizeAction, StackFullPolicy, Transform,
+};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::ops::Deref;
+use std::time::{Duration, Instant, SystemTime};
+
+// The top of the stack is the back of the deque. In stack mode copies go on top, so the oldest entries are at the
+// front, while in queue mode copies go on the bottom, so the oldest entries are at the back, to be popped first.
+
+/// Entries that take up memory, for `max_stack_memory`
+pub trait Size {
+   /// How many bytes the entry takes up
+   fn size(&self) -> usize;
+}
+
+impl Size for &str {
+   fn size(&self) -> usize {
+      self.len()
+   }
+}
+
+/// What the stack knows about an entry, besides the entry itself
+#[derive(Clone, Copy, Debug)]
+struct Details {
+   copied_at: SystemTime,
+   /// Pinned entries are kept when the stack is cleared, and are never discarded to make room
+   pinned: bool,
+   /// The entry's normalized text hashed, once it's been compared with the looser dedupe options, so it isn't
+   /// normalized again on every push
+   normalized: Option<NormalizedHash>,
+}
+
+/// A hash of an entry's text after `normalize`, along with the dedupe options it was normalized with
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NormalizedHash {
+   trim_whitespace: bool,
+   ignore_case: bool,
+   hash: u64,
+}
+
+impl NormalizedHash {
+   fn new(text: &str, config: &Config) -> NormalizedHash {
+      let mut hasher = DefaultHasher::new();
+      normalize(text, config).hash(&mut hasher);
+      NormalizedHash {
+         trim_whitespace: config.dedupe_trim_whitespace,
+         ignore_case: config.dedupe_ignore_case,
+         hash: hasher.finish(),
+      }
+   }
+
+   /// Whether this was normalized as the dedupe options in `config` ask
+   fn is_current(&self, config: &Config) -> bool {
+      self.trim_whitespace == config.dedupe_trim_whitespace && self.ignore_case == config.dedupe_ignore_case
+   }
+}
+
+/// The entries on the stack, along with when each was copied and their total size. The total is kept up to date as
+/// entries come and go, so checking it against `max_stack_memory` doesn't look at every entry.
+#[derive(Debug)]
+pub struct Stack<T> {
+   entries: VecDeque<T>,
+   /// The details of each entry, in the same order as `entries`
+   details: VecDeque<Details>,
+   bytes: usize,
+   /// How many entries are pinned, and their total size, which don't count towards the stack's limits
+   pinned: usize,
+   pinned_bytes: usize,
+   /// Counts changes to the stack, so that whether it's changed since some point can be told cheaply
+   changes: u64,
+   mode: Mode,
+}
+
+impl<T: Size> Stack<T> {
+   pub fn with_capacity(capacity: usize) -> Stack<T> {
+      Stack {
+         entries: VecDeque::with_capacity(capacity),
+         details: VecDeque::with_capacity(capacity),
+         bytes: 0,
+         pinned: 0,
+         pinned_bytes: 0,
+         changes: 0,
+         mode: Mode::Stack,
+      }
+   }
+
+   /// Switches between stack and queue mode, turning the stack upside down so that the entries stay in the order
+   /// they were copied. Returns whether the mode changed.
+   pub fn set_mode(&mut self, mode: Mode) -> bool {
+      if self.mode == mode {
+         return false;
+      }
+      self.mode = mode;
+      self.entries.make_contiguous().reverse();
+      self.details.make_contiguous().reverse();
+      self.changes += 1;
+      true
+   }
+
+   /// The total size of the entries in bytes
+   pub fn bytes(&self) -> usize {
+      self.bytes
+   }
+
+   /// A number that's different whenever the entries, their order, their copy times or which are pinned have changed
+   pub fn changes(&self) -> u64 {
+      self.changes
+   }
+
+   /// The entries, bottom of the stack first, along with when each was copied and whether it's pinned
+   pub fn iter_with_details(&self) -> impl DoubleEndedIterator<Item = (&T, SystemTime, bool)> + ExactSizeIterator {
+      self
+         .entries
+         .iter()
+         .zip(&self.details)
+         .map(|(entry, x)| (entry, x.copied_at, x.pinned))
+   }
+
+   /// The entries like `iter_with_details`, but bottom first as they'd be in stack mode, so the oldest copies come
+   /// first whatever the mode. Stacks are saved and exported in this order, so they can be read back in either mode.
+   pub fn iter_as_stack(&self) -> impl ExactSizeIterator<Item = (&T, SystemTime, bool)> {
+      let mut entries: Vec<_> = self.iter_with_details().collect();
+      if self.mode == Mode::Queue {
+         entries.reverse();
+      }
+      entries.into_iter()
+   }
+
+   /// Whether the entry at `index` (counting from the bottom) is pinned. There being no such entry counts as not.
+   pub fn is_pinned(&self, index: usize) -> bool {
+      self.details.get(index).is_some_and(|x| x.pinned)
+   }
+
+   /// Pins or unpins the entry at `index` (counting from the bottom), if there is one
+   pub fn set_pinned(&mut self, index: usize, pinned: bool) {
+      let details = match self.details.get_mut(index) {
+         Some(details) if details.pinned != pinned => details,
+         _ => return,
+      };
+      details.pinned = pinned;
+      let size = self.entries[index].size();
+      if pinned {
+         self.pinned += 1;
+         self.pinned_bytes += size;
+      } else {
+         self.pinned -= 1;
+         self.pinned_bytes -= size;
+      }
+      self.changes += 1;
+   }
+
+   /// Pushes an entry copied just now
+   pub fn push_back(&mut self, entry: T) {
+      self.push_back_at(entry, SystemTime::now());
+   }
+
+   /// Pushes an entry that isn't pinned
+   pub fn push_back_at(&mut self, entry: T, copied_at: SystemTime) {
+      self.bytes += entry.size();
+      self.entries.push_back(entry);
+      self.details.push_back(Details {
+         copied_at,
+         pinned: false,
+         normalized: None,
+      });
+      self.changes += 1;
+   }
+
+   /// Puts an entry that isn't pinned at the end of the stack the newest copies go on
+   fn push_newest_at(&mut self, entry: T, copied_at: SystemTime) {
+      match self.mode {
+         Mode::Stack => self.push_back_at(entry, copied_at),
+         Mode::Queue => self.push_front_at(entry, copied_at),
+      }
+   }
+
+   /// The index of the entry at the end of the stack the newest copies go on
+   fn newest_index(&self) -> Option<usize> {
+      match self.mode {
+         Mode::Stack => self.len().checked_sub(1),
+         Mode::Queue if self.is_empty() => None,
+         Mode::Queue => Some(0),
+      }
+   }
+
+   /// Moves the entry at `index` to the end of the stack the newest copies go on, as though it was copied again at
+   /// `copied_at`, returning its new index
+   fn renew(&mut self, index: usize, copied_at: SystemTime) -> usize {
+      let entry = self.entries.remove(index).unwrap();
+      let details = Details {
+         copied_at,
+         ..self.details.remove(index).unwrap()
+      };
+      self.changes += 1;
+      match self.mode {
+         Mode::Stack => {
+            self.entries.push_back(entry);
+            self.details.push_back(details);
+            self.len() - 1
+         }
+         Mode::Queue => {
+            self.entries.push_front(entry);
+            self.details.push_front(details);
+            0
+         }
+      }
+   }
+
+   /// Puts an entry that isn't pinned at the bottom of the stack
+   fn push_front_at(&mut self, entry: T, copied_at: SystemTime) {
+      self.bytes += entry.size();
+      self.entries.push_front(entry);
+      self.details.push_front(Details {
+         copied_at,
+         pinned: false,
+         normalized: None,
+      });
+      self.changes += 1;
+   }
+
+   /// Keeps the totals up to date with an entry that's just been taken off the stack
+   fn forget(&mut self, entry: &T, details: Details) {
+      self.bytes -= entry.size();
+      if details.pinned {
+         self.pinned -= 1;
+         self.pinned_bytes -= entry.size();
+      }
+      self.changes += 1;
+   }
+
+   pub fn pop_back(&mut self) -> Option<T> {
+      let entry = self.entries.pop_back()?;
+      let details = self.details.pop_back().unwrap();
+      self.forget(&entry, details);
+      Some(entry)
+   }
+
+   /// Puts `entry` in place of the entry at `index`, keeping when it was copied and whether it's pinned, and returns
+   /// the entry it replaced
+   fn replace(&mut self, index: usize, entry: T) -> T {
+      let size = entry.size();
+      let old = std::mem::replace(&mut self.entries[index], entry);
+      self.bytes = self.bytes - old.size() + size;
+      if self.details[index].pinned {
+         self.pinned_bytes = self.pinned_bytes - old.size() + size;
+      }
+      self.details[index].normalized = None;
+      self.changes += 1;
+      old
+   }
+
+   /// Changes the entry at `index` in place with `change`, keeping the totals up to date with its new size
+   fn modify(&mut self, index: usize, change: impl FnOnce(&mut T)) {
+      let old_size = self.entries[index].size();
+      change(&mut self.entries[index]);
+      let size = self.entries[index].size();
+      self.bytes = self.bytes - old_size + size;
+      if self.details[index].pinned {
+         self.pinned_bytes = self.pinned_bytes - old_size + size;
+      }
+      self.details[index].normalized = None;
+      self.changes += 1;
+   }
+
+   pub fn remove(&mut self, index: usize) -> Option<T> {
+      let entry = self.entries.remove(index)?;
+      let details = self.details.remove(index).unwrap();
+      self.forget(&entry, details);
+      Some(entry)
+   }
+
+   /// Removes every entry, pinned or not
+   pub fn clear(&mut self) {
+      self.entries.clear();
+      self.details.clear();
+      self.bytes = 0;
+      self.pinned = 0;
+      self.pinned_bytes = 0;
+      self.changes += 1;
+   }
+
+   /// Replaces every entry with `entries`, oldest copy first as `iter_as_stack` gives them, each copied just now and
+   /// unpinned. Unlike assigning a new stack, `changes` carries on counting, so the stack it replaced can't be
+   /// mistaken for it.
+   pub fn replace_all<I: IntoIterator<Item = T>>(&mut self, entries: I) {
+      self.clear();
+      let now = SystemTime::now();
+      for entry in entries {
+         self.push_newest_at(entry, now);
+      }
+   }
+
+   pub fn swap(&mut self, i: usize, j: usize) {
+      self.entries.swap(i, j);
+      self.details.swap(i, j);
+      self.changes += 1;
+   }
+}
+
+impl<T: Text> Stack<T> {
+   /// The hash of the normalized text of the entry at `index`, hashing it now if it hasn't been with the dedupe
+   /// options in `config`
+   fn normalized_hash(&mut self, index: usize, config: &Config) -> NormalizedHash {
+      match self.details[index].normalized {
+         Some(hash) if hash.is_current(config) => hash,
+         _ => {
+            let hash = NormalizedHash::new(&self.entries[index].to_text(), config);
+            self.details[index].normalized = Some(hash);
+            hash
+         }
+      }
+   }
+}
+
+impl<T: PartialEq> PartialEq for Stack<T> {
+   /// Stacks are equal if they hold the same entries, whenever those were copied
+   fn eq(&self, other: &Stack<T>) -> bool {
+      self.entries == other.entries
+   }
+}
+
+impl<T: Size> Default for Stack<T> {
+   fn default() -> Stack<T> {
+      Stack::with_capacity(0)
+   }
+}
+
+impl<T: Size> iter::FromIterator<T> for Stack<T> {
+   fn from_iter<I: IntoIterator<Item = T>>(entries: I) -> Stack<T> {
+      let mut stack = Stack::with_capacity(0);
+      for entry in entries {
+         stack.push_back(entry);
+      }
+      stack
+   }
+}
+
+impl<T> Deref for Stack<T> {
+   type Target = VecDeque<T>;
+
+   fn deref(&self) -> &VecDeque<T> {
+      &self.entries
+   }
+}
+
+/// What an entry holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+   Text,
+   /// Paths of files copied in Explorer, which paste as the files themselves
+   Files,
+   Image,
+}
+
+impl Kind {
+   /// Whether entries of this kind have any text. Images don't.
+   pub fn has_text(self) -> bool {
+      self != Kind::Image
+   }
+}
+
+/// Entries whose text can be looked at, for the dedupe options that ignore some differences in it
+pub trait Text {
+   /// The entry's text. A file list's text is its paths, one per line, and an image has none.
+   fn to_text(&self) -> String;
+   fn kind(&self) -> Kind;
+
+   /// A hash of how the entry's text is formatted, such as the HTML copied along with it, which is 0 for entries
+   /// without any. Entries are only told apart by this with `dedupe_formatting`.
+   fn formatting_hash(&self) -> u64 {
+      0
+   }
+}
+
+/// How the formats of an entry compare with those of the same copy placed on the clipboard again
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Formats {
+   /// The other has only formats this already has, though not all of them
+   Fewer,
+   /// The other has every format this has and more, which would add `added_bytes` to this
+   More { added_bytes: usize },
+}
+
+/// Entries that can take in another announcement of the same copy, for `augment_newest`
+pub trait Augment {
+   /// Compares the formats of this and `other`, if `other` is the same copy placed on the clipboard again with a
+   /// different set of formats, one holding all of the other's. Otherwise, returns None.
+   fn compare_formats(&self, other: &Self) -> Option<Formats>;
+   /// Adds the formats of `other` this doesn't have, once `compare_formats` found it has more
+   fn augment(&mut self, other: &Self);
+}
+
+impl Text for &str {
+   fn to_text(&self) -> String {
+      String::from(*self)
+   }
+
+   fn kind(&self) -> Kind {
+      Kind::Text
+   }
+}
+
+/// Normalizes `text` as the dedupe options ask, for comparing with other normalized text.
+/// Whitespace within the text is left alone, even when trimming.
+pub fn normalize<'a>(text: &'a str, config: &Config) -> Cow<'a, str> {
+   let text = if config.dedupe_trim_whitespace {
+      text.trim()
+   } else {
+      text
+   };
+   if config.dedupe_ignore_case {
+      Cow::Owned(text.to_lowercase())
+   } else {
+      Cow::Borrowed(text)
+   }
+}
+
+/// The hash `find_duplicate` compares `entry` by, if the dedupe options ask for its text to be normalized
+fn dedupe_hash<T: Text>(entry: &T, config: &Config) -> Option<NormalizedHash> {
+   if (config.dedupe_trim_whitespace || config.dedupe_ignore_case) && entry.kind().has_text() {
+      Some(NormalizedHash::new(&entry.to_text(), config))
+   } else {
+      None
+   }
+}
+
+/// Finds the index of an entry on the stack that `entry` duplicates, as far as the dedupe options look. `hash` is
+/// from `dedupe_hash`.
+fn find_duplicate<T: PartialEq + Size + Text>(
+   stack: &mut Stack<T>,
+   entry: &T,
+   hash: Option<NormalizedHash>,
+   config: &Config,
+) -> Option<usize> {
+   let candidates = match config.dedupe_scope {
+      DedupeScope::Consecutive if config.prevent_duplicate_push => match stack.newest_index() {
+         Some(newest) => newest..newest + 1,
+         None => return None,
+      },
+      DedupeScope::Consecutive => return None,
+      DedupeScope::Stack => 0..stack.len(),
+   };
+   let same_formatting = |other: &T| !config.dedupe_formatting || other.formatting_hash() == entry.formatting_hash();
+   if let Some(hash) = hash {
+      // Text that happens to read like a file list isn't the same as the files
+      candidates.rev().find(|i| {
+         stack[*i].kind() == entry.kind() && stack.normalized_hash(*i, config) == hash && same_formatting(&stack[*i])
+      })
+   } else {
+      candidates
+         .rev()
+         .find(|i| stack[*i] == *entry && same_formatting(&stack[*i]))
+   }
+}
+
+/// What became of an entry given to `push`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Push {
+   /// The entry is the new top of the stack
+   Pushed,
+   /// In queue mode, the entry went on the bottom, to be popped after the entries already on the stack
+   Queued,
+   /// The entry was already on the stack, and is now on top, either because it already was or because it was
+   /// promoted there
+   DuplicateOnTop,
+   /// The entry was already on the stack, and isn't on top, whether it was left where it was or promoted
+   Duplicate,
+   /// The stack is full and `stack_full_policy` rejects new entries, so the entry was discarded
+   StackFull,
+   /// The entry is larger than `max_stack_memory` on its own, so it was discarded
+   TooLarge,
+   /// The entry was the newest on the stack, placed on the clipboard again with a different set of formats, and was
+   /// taken into it where it is. `on_top` is whether that's the top of the stack.
+   Augmented { on_top: bool },
+}
+
+impl Push {
+   /// Whether the entry ended up on top of the stack
+   pub fn on_top(self) -> bool {
+      match self {
+         Push::Pushed | Push::DuplicateOnTop => true,
+         Push::Queued | Push::Duplicate | Push::StackFull | Push::TooLarge => false,
+         Push::Augmented { on_top } => on_top,
+      }
+   }
+}
+
+/// Whether `entry` can't be pushed, because it would overfill the stack and `stack_full_policy` rejects new entries
+pub fn rejects_new<T: Size>(stack: &Stack<T>, entry: &T, config: &Config) -> bool {
+   if config.stack_full_policy != StackFullPolicy::RejectNew {
+      return false;
+   }
+   config
+      .max_stack_size
+      .is_some_and(|max| stack.len() - stack.pinned >= max)
+      || config
+         .max_stack_memory
+         .is_some_and(|max| stack.bytes - stack.pinned_bytes + entry.size() > max)
+}
+
+/// Whether `entry` is larger than `max_stack_memory` by itself, so it can't go on the stack at all
+fn too_large<T: Size>(entry: &T, config: &Config) -> bool {
+   config.max_stack_memory.is_some_and(|max| entry.size() > max)
+}
+
+/// Pushes `entry` onto the stack, or onto the bottom in queue mode. If the stack is full, the oldest entries are
+/// discarded, or `entry` is, as `stack_full_policy` says. An entry already on the stack, as far as the configured
+/// dedupe scope looks, is ignored or moved to where it would have been pushed instead. Comparing against the whole stack compares every entry, so entries should be quick
+/// to tell apart, such as by comparing a hash first.
+pub fn push<T: PartialEq + Size + Text>(stack: &mut Stack<T>, entry: T, config: &Config) -> Push {
+   push_at(stack, entry, SystemTime::now(), config)
+}
+
+/// Pushes `entry` like `push`, as though it was copied at `copied_at`
+pub fn push_at<T: PartialEq + Size + Text>(
+   stack: &mut Stack<T>,
+   entry: T,
+   copied_at: SystemTime,
+   config: &Config,
+) -> Push {
+   if too_large(&entry, config) {
+      return Push::TooLarge;
+   }
+   let hash = dedupe_hash(&entry, config);
+   if let Some(mut index) = find_duplicate(stack, &entry, hash, config) {
+      if config.dedupe_action == DedupeAction::Promote {
+         // It was copied again, so it's as fresh as a new entry
+         index = stack.renew(index, copied_at);
+      }
+      return if index + 1 == stack.len() {
+         Push::DuplicateOnTop
+      } else {
+         Push::Duplicate
+      };
+   }
+   if rejects_new(stack, &entry, config) {
+      return Push::StackFull;
+   }
+   stack.push_newest_at(entry, copied_at);
+   if let Some(index) = stack.newest_index() {
+      stack.details[index].normalized = hash;
+   }
+   evict(stack, config);
+   if stack.mode == Mode::Queue && stack.len() > 1 {
+      Push::Queued
+   } else {
+      Push::Pushed
+   }
+}
+
+/// The entry most recently pushed by `push_copy`, and when
+pub struct LastPush<T> {
+   entry: T,
+   at: Instant,
+}
+
+/// Pushes an entry the clipboard announced at `now`, like `push`. Some applications announce one copy several times
+/// in quick succession, so an entry that's the same as the last one pushed, within `duplicate_window_ms` of it,
+/// is ignored whatever the dedupe options. `now` should come from a monotonic clock, such as `Instant::now()`.
+pub fn push_copy<T: Clone + PartialEq + Size + Text>(
+   stack: &mut Stack<T>,
+   entry: T,
+   last_push: &mut Option<LastPush<T>>,
+   now: Instant,
+   config: &Config,
+) -> Push {
+   if config.duplicate_window_ms == 0 {
+      *last_push = None;
+      return push(stack, entry, config);
+   }
+   if let Some(last) = last_push {
+      let window = Duration::from_millis(config.duplicate_window_ms);
+      if now.saturating_duration_since(last.at) < window && last.entry == entry {
+         return if stack.back() == Some(&entry) {
+            Push::DuplicateOnTop
+         } else {
+            Push::Duplicate
+         };
+      }
+   }
+   let copy = entry.clone();
+   let pushed = push(stack, entry, config);
+   if let Push::Pushed | Push::Queued = pushed {
+      *last_push = Some(LastPush { entry: copy, at: now });
+   }
+   pushed
+}
+
+/// How soon after an entry is copied it can take in the same copy placed on the clipboard again, for `augment_newest`
+pub const AUGMENT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Some applications, such as Office, place a single copy on the clipboard more than once, each time with a different
+/// set of formats, such as only the plain text and then everything. Given `entry`, copied at `now`, this takes it into
+/// the newest entry on the stack in place, rather than it being pushed as well, if the newest was copied within
+/// `AUGMENT_WINDOW` of it and has fewer formats than `entry`. If `entry` has fewer formats than the newest entry,
+/// the newest already holds all of it, so it's taken as a duplicate. Returns None if it's neither, to be pushed as
+/// usual.
+pub fn augment_newest<T: Augment + Size>(
+   stack: &mut Stack<T>,
+   entry: &T,
+   now: SystemTime,
+   config: &Config,
+) -> Option<Push> {
+   let index = stack.newest_index()?;
+   let recent = now
+      .duration_since(stack.details[index].copied_at)
+      .is_ok_and(|x| x < AUGMENT_WINDOW);
+   if !recent {
+      return None;
+   }
+   match stack[index].compare_formats(entry)? {
+      Formats::Fewer => Some(if index + 1 == stack.len() {
+         Push::DuplicateOnTop
+      } else {
+         Push::Duplicate
+      }),
+      Formats::More { added_bytes } => {
+         if config
+            .max_stack_memory
+            .is_some_and(|max| stack[index].size() + added_bytes > max)
+         {
+            return None;
+         }
+         stack.modify(index, |x| x.augment(entry));
+         // Growing the entry may have overfilled the stack
+         evict(stack, config);
+         Some(Push::Augmented {
+            on_top: stack.newest_index() == stack.len().checked_sub(1),
+         })
+      }
+   }
+}
+
+/// Pushes a copy of the top entry, even if duplicates are otherwise prevented.
+/// Returns false if there was nothing to copy, or the stack is full and rejects new entries.
+pub fn duplicate_top<T: Clone + Size>(stack: &mut Stack<T>, config: &Config) -> bool {
+   match stack.back() {
+      Some(top) if !rejects_new(stack, top, config) => {
+         stack.push_back(top.clone());
+         evict(stack, config);
+         true
+      }
+      _ => false,
+   }
+}
+
+/// How many popped entries are kept around to be restored
+pub const UNDO_LIMIT: usize = 10;
+
+/// Remembers a popped entry so that it can be restored, forgetting the oldest if there are too many
+pub fn remember_popped<T>(popped: &mut VecDeque<T>, entry: T) {
+   popped.push_back(entry);
+   let excess = popped.len().saturating_sub(UNDO_LIMIT);
+   popped.drain(..excess);
+}
+
+/// Pushes the most recently popped entry back onto the stack, returning false if there was none.
+/// If the stack is full and rejects new entries, or the entry is too large for it, the entry is kept to be restored
+/// later, and false is returned.
+pub fn undo_pop<T: Size>(stack: &mut Stack<T>, popped: &mut VecDeque<T>, config: &Config) -> bool {
+   let restorable = popped
+      .back()
+      .is_some_and(|entry| !too_large(entry, config) && !rejects_new(stack, entry, config));
+   if !restorable {
+      return false;
+   }
+   stack.push_back(popped.pop_back().unwrap());
+   evict(stack, config);
+   true
+}
+
+/// Where pops that leave entries on the stack (`pop_removes_entry = false`) have got to: the entry they last put on
+/// the clipboard. It only holds until the stack changes, after which it's back at the top.
+#[derive(Clone, Copy, Debug)]
+pub struct Cursor {
+   depth: usize,
+   changes: u64,
+}
+
+/// How far down the stack `cursor` is, where the top is at a depth of 1. Having no cursor, or one from before the
+/// stack last changed, is being at the top.
+pub fn cursor_depth<T>(cursor: &Option<Cursor>, stack: &Stack<T>) -> usize {
+   match cursor {
+      Some(cursor) if cursor.changes == stack.changes && cursor.depth <= stack.len() => cursor.depth,
+      _ => 1,
+   }
+}
+
+/// Moves `cursor` to the next entry down the stack, or back to the top from the bottom, returning its new depth.
+/// Returns None if the stack is empty.
+pub fn advance_cursor<T>(cursor: &mut Option<Cursor>, stack: &Stack<T>) -> Option<usize> {
+   if stack.is_empty() {
+      *cursor = None;
+      return None;
+   }
+   let depth = cursor_depth(cursor, stack) % stack.len() + 1;
+   *cursor = Some(Cursor {
+      depth,
+      changes: stack.changes,
+   });
+   Some(depth)
+}
+
+/// Swaps the top two entries of the stack, returning false if there aren't two
+pub fn swap_top_two<T: Size>(stack: &mut Stack<T>) -> bool {
+   let top = match stack.len().checked_sub(1) {
+      Some(top) if top > 0 => top,
+      _ => return false,
+   };
+   stack.swap(top, top - 1);
+   true
+}
+
+/// Puts `entry`, copied at `copied_at`, in place of the top of the stack, returning the entry it replaced. The new top
+/// is pinned if the old one was. The stack is left as it is if it's empty.
+pub fn replace_top<T: Size>(stack: &mut Stack<T>, entry: T, copied_at: SystemTime, config: &Config) -> Option<T> {
+   let index = stack.len().checked_sub(1)?;
+   let top = stack.replace(index, entry);
+   stack.details[index].copied_at = copied_at;
+   // The new top may be larger than the one it replaced
+   evict(stack, config);
+   Some(top)
+}
+
+/// Moves the top entry to the bottom of the stack, returning false if the stack is empty
+pub fn move_to_bottom<T>(stack: &mut Stack<T>) -> bool {
+   match (stack.entries.pop_back(), stack.details.pop_back()) {
+      (Some(top), Some(details)) => {
+         stack.entries.push_front(top);
+         stack.details.push_front(details);
+         stack.changes += 1;
+         true
+      }
+      _ => false,
+   }
+}
+
+/// Reverses the order of the stack, returning false if there weren't enough entries for that to do anything.
+/// Entries are moved, not copied, so this is cheap no matter how large they are.
+pub fn reverse<T>(stack: &mut Stack<T>) -> bool {
+   if stack.len() < 2 {
+      return false;
+   }
+   stack.entries.make_contiguous().reverse();
+   stack.details.make_contiguous().reverse();
+   stack.changes += 1;
+   true
+}
+
+/// Moves the entry at `index` (counting from the bottom) to the top of the stack.
+/// Returns false if there's no such entry.
+pub fn move_to_top<T>(stack: &mut Stack<T>, index: usize) -> bool {
+   match (stack.entries.remove(index), stack.details.remove(index)) {
+      (Some(entry), Some(details)) => {
+         stack.entries.push_back(entry);
+         stack.details.push_back(details);
+         stack.changes += 1;
+         true
+      }
+      _ => false,
+   }
+}
+
+/// Removes the entry `depth` places down the stack, where the top is at a depth of 1.
+/// Returns None if the stack isn't that deep.
+pub fn remove_at_depth<T: Size>(stack: &mut Stack<T>, depth: usize) -> Option<T> {
+   if depth == 0 {
+      return None;
+   }
+   let index = stack.len().checked_sub(depth)?;
+   stack.remove(index)
+}
+
+/// Discards the oldest entry that isn't pinned, if there is one
+fn discard_oldest_unpinned<T: Size>(stack: &mut Stack<T>) {
+   let oldest = match stack.mode {
+      Mode::Stack => stack.details.iter().position(|x| !x.pinned),
+      Mode::Queue => stack.details.iter().rposition(|x| !x.pinned),
+   };
+   if let Some(index) = oldest {
+      stack.remove(index);
+   }
+}
+
+/// Discards the oldest entries until the stack holds no more than `max_stack_size` entries, not counting pinned
+/// entries, which are never discarded
+pub fn truncate<T: Size>(stack: &mut Stack<T>, max_stack_size: Option<usize>) {
+   if let Some(max_stack_size) = max_stack_size {
+      while stack.len() - stack.pinned > max_stack_size {
+         discard_oldest_unpinned(stack);
+      }
+   }
+}
+
+/// Discards the oldest entries until the entries on the stack take up no more than `max_stack_memory` bytes, not
+/// counting pinned entries, which are never discarded. Only the entries discarded and the pinned entries before them
+/// are looked at.
+pub fn truncate_memory<T: Size>(stack: &mut Stack<T>, max_stack_memory: Option<usize>) {
+   if let Some(max_stack_memory) = max_stack_memory {
+      while stack.bytes - stack.pinned_bytes > max_stack_memory {
+         discard_oldest_unpinned(stack);
+      }
+   }
+}
+
+/// The entries taken off the stack by a clear, kept so that the clear can be undone
+pub struct Cleared<T> {
+   /// Bottom of the stack first, along with when each was copied
+   entries: Vec<(T, SystemTime)>,
+   bytes: usize,
+   top_removed: bool,
+}
+
+impl<T> Cleared<T> {
+   pub fn len(&self) -> usize {
+      self.entries.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.entries.is_empty()
+   }
+
+   /// The total size of the entries in bytes
+   pub fn bytes(&self) -> usize {
+      self.bytes
+   }
+
+   /// Whether the top of the stack was among the entries cleared
+   pub fn top_removed(&self) -> bool {
+      self.top_removed
+   }
+}
+
+/// Removes every entry that isn't pinned, returning them
+pub fn clear_unpinned<T: Size>(stack: &mut Stack<T>) -> Cleared<T> {
+   let top_removed = !stack.is_empty() && !stack.is_pinned(stack.len() - 1);
+   let mut entries = Vec::new();
+   let mut bytes = 0;
+   let mut index = 0;
+   while index < stack.len() {
+      if stack.is_pinned(index) {
+         index += 1;
+      } else {
+         let copied_at = stack.details[index].copied_at;
+         let entry = stack.remove(index).unwrap();
+         bytes += entry.size();
+         entries.push((entry, copied_at));
+      }
+   }
+   Cleared {
+      entries,
+      bytes,
+      top_removed,
+   }
+}
+
+/// Puts back the entries a clear removed, as the oldest on the stack: beneath everything on it now, so that anything
+/// pushed since stays on top, or in queue mode on top, so that they're popped before anything pushed since. If that
+/// puts the stack over its limits, the oldest entries are discarded as usual. Returns whether the top of the stack
+/// is now one of the entries put back.
+pub fn undo_clear<T: Size>(stack: &mut Stack<T>, cleared: Cleared<T>, config: &Config) -> bool {
+   let before = stack.len();
+   match stack.mode {
+      Mode::Stack => {
+         for (entry, copied_at) in cleared.entries.into_iter().rev() {
+            stack.push_front_at(entry, copied_at);
+         }
+      }
+      Mode::Queue => {
+         for (entry, copied_at) in cleared.entries {
+            stack.push_back_at(entry, copied_at);
+         }
+      }
+   }
+   // The entries put back are the oldest, so they're the first discarded
+   evict(stack, config);
+   stack.len() > before && (before == 0 || stack.mode == Mode::Queue)
+}
+
+/// Pins the top entry, or unpins it if it's already pinned.
+/// Returns whether it's pinned now, or None if the stack is empty.
+pub fn toggle_pin_top<T: Size>(stack: &mut Stack<T>) -> Option<bool> {
+   let top = stack.len().checked_sub(1)?;
+   let pinned = !stack.is_pinned(top);
+   stack.set_pinned(top, pinned);
+   Some(pinned)
+}
+
+/// Discards the oldest entries until the stack is within both `max_stack_size` and `max_stack_memory`
+pub fn evict<T: Size>(stack: &mut Stack<T>, config: &Config) {
+   truncate(stack, config.max_stack_size);
+   truncate_memory(stack, config.max_stack_memory);
+}
+
+/// Moves the top entry to the bottom of the stack, returning false if there weren't enough entries to do so
+pub fn rotate<T>(stack: &mut Stack<T>) -> bool {
+   if stack.len() < 2 {
+      return false;
+   }
+   stack.entries.rotate_right(1);
+   stack.details.rotate_right(1);
+   stack.changes += 1;
+   true
+}
+
+/// Discards entries copied more than `max_entry_age` before `now`, wherever they are on the stack, unless they're
+/// pinned. Returns whether the top entry was among them.
+pub fn expire<T: Size>(stack: &mut Stack<T>, now: SystemTime, config: &Config) -> bool {
+   let max_entry_age = match config.max_entry_age {
+      Some(max_entry_age) => max_entry_age,
+      None => return false,
+   };
+   // An entry from the future, as far as the clock is concerned now, isn't stale
+   let is_stale = |details: &Details| {
+      !details.pinned
+         && now
+            .duration_since(details.copied_at)
+            .is_ok_and(|age| age > max_entry_age)
+   };
+   let top_expired = stack.details.back().is_some_and(is_stale);
+   let mut index = 0;
+   while index < stack.len() {
+      if is_stale(&stack.details[index]) {
+         stack.remove(index);
+      } else {
+         index += 1;
+      }
+   }
+   top_expired
+}
+
+/// What's appended to an entry cut short for being larger than `max_item_size`
+pub const TRUNCATED_MARKER: &str = "…[truncated]";
+
+/// How many UTF-16 code units from the start of `units` fit in `max_bytes`, without splitting a surrogate pair
+pub fn utf16_prefix_len(units: &[u16], max_bytes: usize) -> usize {
+   let len = units.len().min(max_bytes / 2);
+   if len > 0 && len < units.len() && (0xD800..0xDC00).contains(&units[len - 1]) {
+      len - 1
+   } else {
+      len
+   }
+}
+
+/// Reads text from the memory holding it on the clipboard, as UTF-16 up to the first null, or all of it if there's no
+/// null. Some apps leave garbage after the null. Unpaired surrogates are replaced with U+FFFD, and how many were
+/// replaced is returned alongside the text.
+pub fn read_utf16(bytes: &[u8]) -> (Vec<u16>, usize) {
+   let units = bytes
+      .chunks_exact(2)
+      .map(|x| u16::from_le_bytes([x[0], x[1]]))
+      .take_while(|x| *x != 0);
+   let mut text = Vec::new();
+   let mut replaced = 0;
+   for c in char::decode_utf16(units) {
+      let c = c.unwrap_or_else(|_| {
+         replaced += 1;
+         char::REPLACEMENT_CHARACTER
+      });
+      text.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+   }
+   (text, replaced)
+}
+
+/// Gives UTF-16 text the line endings that `normalize_line_endings` asks for. A run of carriage returns followed by a
+/// line feed is one line ending, so converting to `lf` drops the whole run. Carriage returns that aren't followed by a
+/// line feed aren't line endings, and are left as they are.
+pub fn normalize_line_endings(units: &[u16], line_endings: LineEndings) -> Cow<'_, [u16]> {
+   const CR: u16 = b'\r' as u16;
+   const LF: u16 = b'\n' as u16;
+   if line_endings == LineEndings::Keep || !units.contains(&LF) {
+      return Cow::Borrowed(units);
+   }
+   let mut normalized = Vec::with_capacity(units.len());
+   for (i, unit) in units.iter().copied().enumerate() {
+      let follows_cr = i > 0 && units[i - 1] == CR;
+      match (unit, line_endings) {
+         (LF, LineEndings::Crlf) if !follows_cr => normalized.extend_from_slice(&[CR, LF]),
+         (LF, LineEndings::Lf) if follows_cr => {
+            while normalized.last() == Some(&CR) {
+               normalized.pop();
+            }
+            normalized.push(LF);
+         }
+         _ => normalized.push(unit),
+      }
+   }
+   Cow::Owned(normalized)
+}
+
+/// Applies each of `transforms` to `text` in turn, so that each one sees what the ones before it made
+pub fn transform<'a>(text: &'a str, transforms: &[Transform]) -> Cow<'a, str> {
+   let mut text = Cow::Borrowed(text);
+   for transform in transforms {
+      if let Cow::Owned(transformed) = transform.regex.replace_all(&text, transform.replacement.as_str()) {
+         text = Cow::Owned(transformed);
+      }
+   }
+   text
+}
+
+/// The most bytes of a copy's text to read from the clipboard: `max_item_size`, or less if the copy wouldn't fit in
+/// `max_stack_memory` otherwise. When truncating, room is left for `TRUNCATED_MARKER` within `max_stack_memory`.
+pub fn item_size_limit(config: &Config) -> Option<usize> {
+   let memory_limit = config.max_stack_memory.map(|max| match config.oversize_action {
+      OversizeAction::Skip => max,
+      OversizeAction::Truncate => max.saturating_sub(TRUNCATED_MARKER.encode_utf16().count() * 2),
+   });
+   match (config.max_item_size, memory_limit) {
+      (Some(item_limit), Some(memory_limit)) => Some(item_limit.min(memory_limit)),
+      (item_limit, memory_limit) => item_limit.or(memory_limit),
+   }
+}
+
+/// The entries of `stack` in the order `order` joins them in. Entries without text, such as images, are left out.
+pub fn in_join_order<T: Text>(stack: &Stack<T>, order: JoinOrder) -> Vec<&T> {
+   let entries = stack.iter().filter(|x| x.kind().has_text());
+   match order {
+      JoinOrder::TopFirst => entries.rev().collect(),
+      JoinOrder::BottomFirst => entries.collect(),
+   }
+}
+
+/// Joins `entries`, in the order given, with `separator` between each. If the result would be longer than
+/// `max_bytes`, it's cut short and the returned flag is set; nothing past that point is looked at.
+pub fn join<I, C>(entries: I, separator: &str, max_bytes: Option<usize>) -> (String, bool)
+where
+   I: Iterator<Item = C>,
+   C: Iterator<Item = char>,
+{
+   let max_bytes = max_bytes.unwrap_or(usize::MAX);
+   let mut joined = String::new();
+   for (i, entry) in entries.enumerate() {
+      let separator = if i > 0 { separator } else { "" };
+      for c in separator.chars().chain(entry) {
+         if joined.len() + c.len_utf8() > max_bytes {
+            return (joined, true);
+         }
+         joined.push(c);
+      }
+   }
+   (joined, false)
+}
+
+/// A single line summary of an entry: its first `max_chars` characters, followed by an ellipsis if
+/// there's more. Line breaks are collapsed into spaces, and nothing past the summary is looked at.
+pub fn preview<I: Iterator<Item = char>>(entry: I, max_chars: usize) -> String {
+   let mut preview = String::new();
+   let mut chars = 0;
+   let mut line_break = false;
+   for c in entry {
+      if c == '\r' || c == '\n' {
+         line_break = chars > 0;
+         continue;
+      }
+      let space = if line_break { Some(' ') } else { None };
+      for c in space.into_iter().chain(iter::once(c)) {
+         if chars == max_chars {
+            preview.push('…');
+            return preview;
+         }
+         preview.push(c);
+         chars += 1;
+      }
+      line_break = false;
+   }
+   preview
+}
+
+/// Describes the stack for the tray icon's tooltip, after `status`: how many entries it holds, and a preview of the
+/// top entry if one's given. The preview is cut short so that the whole fits in `max_units` UTF-16 code units, which
+/// is all a tooltip holds.
+pub fn tooltip(status: &str, depth: usize, top: Option<&str>, max_units: usize) -> String {
+   let mut tooltip = match depth {
+      0 => format!("{} — empty", status),
+      1 => format!("{} — 1 entry", status),
+      depth => format!("{} — {} entries", status, depth),
+   };
+   if let Some(top) = top {
+      let room = max_units.saturating_sub(tooltip.encode_utf16().count() + ". Top: ''".len());
+      // Too little room for anything but an ellipsis isn't worth showing
+      if room > 1 {
+         tooltip.push_str(&format!(". Top: '{}'", fit_utf16(&top.replace('\t', " "), room)));
+      }
+   }
+   tooltip
+}
+
+/// The most entries the tray icon's badge counts before showing `BADGE_OVERFLOW`
+pub const MAX_BADGE_COUNT: usize = 9;
+const BADGE_OVERFLOW: &str = "9+";
+
+/// What the tray icon's badge shows for a stack `depth` entries deep, or None for no badge, when the stack's empty
+pub fn badge(depth: usize) -> Option<String> {
+   match depth {
+      0 => None,
+      depth if depth > MAX_BADGE_COUNT => Some(String::from(BADGE_OVERFLOW)),
+      depth => Some(depth.to_string()),
+   }
+}
+
+/// Cuts `text` short with an ellipsis, if need be, to fit in `max_units` UTF-16 code units, without splitting a
+/// character
+fn fit_utf16(text: &str, max_units: usize) -> String {
+   if text.encode_utf16().count() <= max_units {
+      return String::from(text);
+   }
+   // Already cut short, as a preview may be
+   let text = text.strip_suffix('…').unwrap_or(text);
+   let mut fitted = String::new();
+   // The ellipsis takes a unit of its own
+   let mut units = 1;
+   for c in text.chars() {
+      units += c.len_utf16();
+      if units > max_units {
+         break;
+      }
+      fitted.push(c);
+   }
+   fitted.push('…');
+   fitted
+}
+
+/// Makes `text` show as written in a menu, where `&` would mark the next character as the item's access key and a tab
+/// would start a column
+pub fn menu_text(text: &str) -> String {
+   text.replace('&', "&&").replace('\t', " ")
+}
+
+/// A summary of a file list for previews: the name of the first file, and how many others there are
+pub fn files_summary<S: AsRef<str>>(paths: &[S]) -> String {
+   let first = match paths.first() {
+      Some(first) => first.as_ref(),
+      None => return String::from("No files"),
+   };
+   let name = first.trim_end_matches('\\').rsplit('\\').next().unwrap_or(first);
+   match paths.len() - 1 {
+      0 => String::from(name),
+      1 => format!("{} and 1 other file", name),
+      others => format!("{} and {} other files", name, others),
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+
+   fn stack_of(entries: &[&'static str]) -> Stack<&'static str> {
+      entries.iter().copied().collect()
+   }
+
+   fn copy_times<'a>(stack: &'a Stack<&'static str>) -> impl Iterator<Item = (&'a &'static str, SystemTime)> {
+      stack
+         .iter_with_details()
+         .map(|(entry, copied_at, _)| (entry, copied_at))
+   }
+
+   #[test]
+   fn push_discards_oldest_when_full() {
+      let config = Config {
+         max_stack_size: Some(2),
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b"]);
+      assert_eq!(push(&mut stack, "c", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["b", "c"]));
+   }
+
+   #[test]
+   fn push_rejects_new_when_full() {
+      let config = Config {
+         max_stack_size: Some(2),
+         stack_full_policy: StackFullPolicy::RejectNew,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a"]);
+      assert_eq!(push(&mut stack, "b", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "c", &config), Push::StackFull);
+      assert_eq!(stack, stack_of(&["a", "b"]));
+      // Duplicates are still promoted, since that doesn't grow the stack
+      let config = Config {
+         dedupe_scope: DedupeScope::Stack,
+         dedupe_action: DedupeAction::Promote,
+         ..config
+      };
+      assert_eq!(push(&mut stack, "a", &config), Push::DuplicateOnTop);
+      assert_eq!(stack, stack_of(&["b", "a"]));
+      assert!(!duplicate_top(&mut stack, &config));
+      let mut popped = VecDeque::from(vec!["c"]);
+      assert!(!undo_pop(&mut stack, &mut popped, &config));
+      assert_eq!(popped, ["c"]);
+      assert_eq!(stack, stack_of(&["b", "a"]));
+   }
+
+   #[test]
+   fn pinned_entries_are_never_discarded() {
+      let config = Config {
+         max_stack_size: Some(2),
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b"]);
+      stack.set_pinned(0, true);
+      // Pinned entries don't count towards the limit
+      assert_eq!(push(&mut stack, "c", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "b", "c"]));
+      assert_eq!(push(&mut stack, "d", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "c", "d"]));
+      let config = Config {
+         stack_full_policy: StackFullPolicy::RejectNew,
+         ..config
+      };
+      assert_eq!(push(&mut stack, "e", &config), Push::StackFull);
+      assert_eq!(toggle_pin_top(&mut stack), Some(true));
+      assert_eq!(push(&mut stack, "e", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "c", "d", "e"]));
+
+      let config = Config {
+         max_stack_memory: Some(2),
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["aa", "b"]);
+      stack.set_pinned(0, true);
+      assert_eq!(push(&mut stack, "c", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["aa", "b", "c"]));
+      assert_eq!(push(&mut stack, "dd", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["aa", "dd"]));
+      // Unpinning counts the entry again
+      stack.set_pinned(0, false);
+      evict(&mut stack, &config);
+      assert_eq!(stack, stack_of(&["dd"]));
+   }
+
+   #[test]
+   fn clear_unpinned_keeps_pinned_entries() {
+      let mut stack = stack_of(&["a", "b", "c", "d"]);
+      stack.set_pinned(1, true);
+      stack.set_pinned(3, true);
+      assert!(!clear_unpinned(&mut stack).top_removed());
+      assert_eq!(stack, stack_of(&["b", "d"]));
+      assert!(stack.is_pinned(0) && stack.is_pinned(1));
+      assert_eq!(toggle_pin_top(&mut stack), Some(false));
+      assert!(clear_unpinned(&mut stack).top_removed());
+      assert_eq!(stack, stack_of(&["b"]));
+      // Pinned entries are moved along with their entries
+      assert!(duplicate_top(&mut stack, &Config::default()));
+      assert!(rotate(&mut stack));
+      assert!(!stack.is_pinned(0) && stack.is_pinned(1));
+      assert_eq!(toggle_pin_top(&mut Stack::<&str>::default()), None);
+   }
+
+   #[test]
+   fn undo_clear_restores_beneath_new_entries() {
+      let config = Config {
+         max_stack_size: Some(4),
+         ..Config::default()
+      };
+      let copied_at = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+      let mut stack = Stack::default();
+      stack.push_back_at("a", copied_at);
+      stack.push_back("bb");
+      stack.push_back("c");
+      stack.set_pinned(2, true);
+      let cleared = clear_unpinned(&mut stack);
+      assert_eq!((cleared.len(), cleared.bytes(), cleared.top_removed()), (2, 3, false));
+      push(&mut stack, "d", &config);
+      push(&mut stack, "e", &config);
+      push(&mut stack, "f", &config);
+      undo_clear(&mut stack, cleared, &config);
+      // The pinned entry doesn't count toward max_stack_size, so only the oldest restored entry is discarded
+      assert_eq!(stack, stack_of(&["bb", "c", "d", "e", "f"]));
+      assert_eq!(stack.bytes(), 6);
+      assert!(stack.is_pinned(1) && !stack.is_pinned(0));
+      let mut stack = Stack::default();
+      stack.push_back_at("a", copied_at);
+      let cleared = clear_unpinned(&mut stack);
+      assert!(cleared.top_removed());
+      undo_clear(&mut stack, cleared, &config);
+      assert_eq!(copy_times(&stack).collect::<Vec<_>>(), vec![(&"a", copied_at)]);
+   }
+
+   #[test]
+   fn push_evicts_oldest_to_fit_memory() {
+      let config = Config {
+         max_stack_memory: Some(10),
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["aaa", "bbb", "cc"]);
+      assert_eq!(stack.bytes(), 8);
+      assert_eq!(push(&mut stack, "dddd", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["bbb", "cc", "dddd"]));
+      assert_eq!(stack.bytes(), 9);
+      // An entry filling the whole stack pushes out everything else
+      assert_eq!(push(&mut stack, "eeeeeeeeee", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["eeeeeeeeee"]));
+      assert_eq!(push(&mut stack, "fffffffffff", &config), Push::TooLarge);
+      assert_eq!(stack, stack_of(&["eeeeeeeeee"]));
+
+      let config = Config {
+         stack_full_policy: StackFullPolicy::RejectNew,
+         ..config
+      };
+      let mut stack = stack_of(&["aaa", "bbb"]);
+      assert_eq!(push(&mut stack, "cccc", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "d", &config), Push::StackFull);
+      assert_eq!(stack, stack_of(&["aaa", "bbb", "cccc"]));
+   }
+
+   #[test]
+   fn stack_keeps_track_of_memory() {
+      let config = Config {
+         max_stack_size: None,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "bb", "ccc"]);
+      assert_eq!(stack.bytes(), 6);
+      assert!(duplicate_top(&mut stack, &config));
+      assert_eq!(stack.bytes(), 9);
+      assert_eq!(remove_at_depth(&mut stack, 3), Some("bb"));
+      assert_eq!(stack.bytes(), 7);
+      assert!(rotate(&mut stack));
+      assert!(reverse(&mut stack));
+      assert!(move_to_top(&mut stack, 0));
+      assert_eq!(stack.bytes(), 7);
+      let mut popped = VecDeque::new();
+      remember_popped(&mut popped, stack.pop_back().unwrap());
+      assert_eq!(stack.bytes(), 4);
+      assert!(undo_pop(&mut stack, &mut popped, &config));
+      assert_eq!(stack.bytes(), 7);
+      truncate(&mut stack, Some(1));
+      assert_eq!(stack.bytes(), stack.back().unwrap().len());
+      stack.clear();
+      assert_eq!(stack.bytes(), 0);
+   }
+
+   #[test]
+   fn expire_discards_stale_entries() {
+      let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+      let minutes = |x: u64| start + Duration::from_secs(x * 60);
+      let mut stack = Stack::with_capacity(4);
+      stack.push_back_at("a", minutes(0));
+      stack.push_back_at("b", minutes(20));
+      stack.push_back_at("c", minutes(5));
+      stack.push_back_at("d", minutes(30));
+      let config = Config {
+         max_entry_age: Some(Duration::from_secs(30 * 60)),
+         ..Config::default()
+      };
+      assert!(!expire(&mut stack, minutes(30), &config));
+      assert_eq!(stack, stack_of(&["a", "b", "c", "d"]));
+      // Entries are discarded wherever they are on the stack
+      assert!(!expire(&mut stack, minutes(40), &config));
+      assert_eq!(stack, stack_of(&["b", "d"]));
+      assert_eq!(stack.bytes(), 2);
+      assert!(expire(&mut stack, minutes(70), &config));
+      assert!(stack.is_empty());
+
+      let mut stack = Stack::with_capacity(1);
+      stack.push_back_at("a", minutes(0));
+      assert!(!expire(&mut stack, minutes(1000), &Config::default()));
+      // The clock going backwards doesn't make anything stale
+      assert!(!expire(&mut stack, start - Duration::from_secs(3600), &config));
+      assert_eq!(stack, stack_of(&["a"]));
+      // Pinned entries never go stale
+      stack.set_pinned(0, true);
+      assert!(!expire(&mut stack, minutes(1000), &config));
+      assert_eq!(stack, stack_of(&["a"]));
+   }
+
+   #[test]
+   fn push_at_records_copy_time() {
+      let at = |x: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(x);
+      let mut stack = Stack::with_capacity(2);
+      let config = Config {
+         dedupe_scope: DedupeScope::Stack,
+         dedupe_action: DedupeAction::Promote,
+         ..Config::default()
+      };
+      assert_eq!(push_at(&mut stack, "a", at(1), &config), Push::Pushed);
+      assert_eq!(push_at(&mut stack, "b", at(2), &config), Push::Pushed);
+      // Promoting an entry takes the time it was copied again
+      assert_eq!(push_at(&mut stack, "a", at(3), &config), Push::DuplicateOnTop);
+      let copied: Vec<_> = copy_times(&stack).map(|(x, t)| (*x, t)).collect();
+      assert_eq!(copied, vec![("b", at(2)), ("a", at(3))]);
+   }
+
+   #[test]
+   fn reordering_keeps_copy_times() {
+      let start = SystemTime::UNIX_EPOCH;
+      let mut stack = Stack::with_capacity(3);
+      for (i, entry) in ["a", "b", "c"].iter().enumerate() {
+         stack.push_back_at(*entry, start + Duration::from_secs(i as u64));
+      }
+      assert!(rotate(&mut stack));
+      assert!(reverse(&mut stack));
+      assert!(move_to_top(&mut stack, 0));
+      assert!(move_to_bottom(&mut stack));
+      stack.swap(0, 2);
+      let times: Vec<_> = copy_times(&stack)
+         .map(|(entry, copied_at)| (*entry, copied_at.duration_since(start).unwrap().as_secs()))
+         .collect();
+      assert_eq!(stack, stack_of(&["c", "a", "b"]));
+      assert_eq!(times, [("c", 2), ("a", 0), ("b", 1)]);
+   }
+
+   fn queue() -> Stack<&'static str> {
+      let mut stack = Stack::default();
+      stack.set_mode(Mode::Queue);
+      stack
+   }
+
+   #[test]
+   fn queue_pops_in_copy_order() {
+      let config = Config {
+         max_stack_size: Some(3),
+         ..Config::default()
+      };
+      let mut stack = queue();
+      assert_eq!(push(&mut stack, "a", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "b", &config), Push::Queued);
+      assert_eq!(push(&mut stack, "c", &config), Push::Queued);
+      assert_eq!(stack, stack_of(&["c", "b", "a"]));
+      // The oldest copy is discarded to make room, which in a queue is the one about to be popped
+      assert_eq!(push(&mut stack, "d", &config), Push::Queued);
+      assert_eq!(stack.pop_back(), Some("b"));
+      assert_eq!(stack.pop_back(), Some("c"));
+      assert_eq!(stack.pop_back(), Some("d"));
+      assert_eq!(stack.pop_back(), None);
+   }
+
+   #[test]
+   fn queue_dedupes_against_newest_copy() {
+      let mut config = Config {
+         prevent_duplicate_push: true,
+         ..Config::default()
+      };
+      let mut stack = queue();
+      push(&mut stack, "a", &config);
+      push(&mut stack, "b", &config);
+      assert_eq!(push(&mut stack, "b", &config), Push::Duplicate);
+      assert_eq!(push(&mut stack, "a", &config), Push::Queued);
+      assert_eq!(stack, stack_of(&["a", "b", "a"]));
+      config.dedupe_scope = DedupeScope::Stack;
+      config.dedupe_action = DedupeAction::Promote;
+      let copied_at = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+      let mut stack = queue();
+      push(&mut stack, "a", &config);
+      push(&mut stack, "b", &config);
+      // Promoting moves the copy to the bottom, where new copies go
+      assert_eq!(push_at(&mut stack, "b", copied_at, &config), Push::Duplicate);
+      assert_eq!(push_at(&mut stack, "a", copied_at, &config), Push::Duplicate);
+      assert_eq!(stack, stack_of(&["a", "b"]));
+      assert_eq!(copy_times(&stack).next(), Some((&"a", copied_at)));
+      let mut stack = queue();
+      push(&mut stack, "a", &config);
+      assert_eq!(push(&mut stack, "a", &config), Push::DuplicateOnTop);
+   }
+
+   #[test]
+   fn set_mode_keeps_copy_order() {
+      let mut stack = stack_of(&["a", "b", "c"]);
+      stack.set_pinned(0, true);
+      let changes = stack.changes();
+      assert!(stack.set_mode(Mode::Queue));
+      assert_ne!(stack.changes(), changes);
+      assert_eq!(stack, stack_of(&["c", "b", "a"]));
+      assert!(stack.is_pinned(2));
+      let as_stack: Vec<_> = stack.iter_as_stack().map(|(x, _, pinned)| (*x, pinned)).collect();
+      assert_eq!(as_stack, vec![("a", true), ("b", false), ("c", false)]);
+      assert!(!stack.set_mode(Mode::Queue));
+      assert!(stack.set_mode(Mode::Stack));
+      assert_eq!(stack, stack_of(&["a", "b", "c"]));
+   }
+
+   #[test]
+   fn replace_all_counts_as_a_change() {
+      // The tray menu only pops an entry it listed if the count hasn't moved, so a stack swapped in by a snapshot
+      // or import, however alike, mustn't share it
+      let mut stack = stack_of(&["a", "b"]);
+      let changes = stack.changes();
+      stack.replace_all(vec!["c", "d"]);
+      assert!(stack.changes() > changes);
+      let changes = stack.changes();
+      stack.replace_all(Vec::new());
+      stack.replace_all(vec!["c", "d"]);
+      assert!(stack.changes() > changes);
+      assert_ne!(stack.changes(), stack_of(&["c", "d"]).changes());
+   }
+
+   #[test]
+   fn replace_all_keeps_copy_order() {
+      let mut stack = stack_of(&["a", "b"]);
+      stack.set_pinned(1, true);
+      stack.replace_all(vec!["x", "y", "z"]);
+      assert_eq!(stack, stack_of(&["x", "y", "z"]));
+      assert!(!stack.is_pinned(1));
+      assert_eq!(stack.bytes(), 3);
+      stack.set_mode(Mode::Queue);
+      stack.replace_all(vec!["x", "y", "z"]);
+      assert_eq!(stack, stack_of(&["z", "y", "x"]));
+   }
+
+   #[test]
+   fn queue_undo_clear_restores_on_top() {
+      let config = Config::default();
+      let mut stack = queue();
+      push(&mut stack, "a", &config);
+      push(&mut stack, "b", &config);
+      let cleared = clear_unpinned(&mut stack);
+      push(&mut stack, "c", &config);
+      assert!(undo_clear(&mut stack, cleared, &config));
+      assert_eq!(stack, stack_of(&["c", "b", "a"]));
+      let mut stack = stack_of(&["a"]);
+      let cleared = clear_unpinned(&mut stack);
+      push(&mut stack, "b", &config);
+      assert!(!undo_clear(&mut stack, cleared, &config));
+      assert_eq!(stack, stack_of(&["a", "b"]));
+   }
+
+   #[test]
+   fn cursor_wraps_and_resets_on_change() {
+      let config = Config::default();
+      let mut stack = stack_of(&["a", "b", "c"]);
+      let mut cursor = None;
+      assert_eq!(cursor_depth(&cursor, &stack), 1);
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(2));
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(3));
+      assert_eq!(cursor_depth(&cursor, &stack), 3);
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(1));
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(2));
+      // Any change to the stack, such as a push, puts the cursor back at the top
+      push(&mut stack, "d", &config);
+      assert_eq!(cursor_depth(&cursor, &stack), 1);
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(2));
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(3));
+      stack.clear();
+      assert_eq!(cursor_depth(&cursor, &stack), 1);
+      assert_eq!(advance_cursor(&mut cursor, &stack), None);
+      let mut stack = stack_of(&["a"]);
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(1));
+      assert_eq!(advance_cursor(&mut cursor, &stack), Some(1));
+      // Evicting entries past the cursor doesn't leave it pointing past the bottom
+      stack = stack_of(&["a", "b", "c"]);
+      advance_cursor(&mut cursor, &stack);
+      advance_cursor(&mut cursor, &stack);
+      truncate(&mut stack, Some(1));
+      assert_eq!(cursor_depth(&cursor, &stack), 1);
+   }
+
+   #[test]
+   fn swap_top_two_needs_two_entries() {
+      let mut stack = stack_of(&["a", "b", "c"]);
+      stack.set_pinned(2, true);
+      assert!(swap_top_two(&mut stack));
+      assert_eq!(stack, stack_of(&["a", "c", "b"]));
+      assert!(stack.is_pinned(1));
+      let mut stack = stack_of(&["a"]);
+      assert!(!swap_top_two(&mut stack));
+      assert_eq!(stack, stack_of(&["a"]));
+      assert!(!swap_top_two(&mut Stack::<&str>::default()));
+   }
+
+   #[test]
+   fn replace_top_returns_old_top() {
+      let config = Config {
+         max_stack_memory: Some(6),
+         ..Config::default()
+      };
+      let copied_at = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+      let mut stack = stack_of(&["aa", "bb", "cc"]);
+      assert_eq!(replace_top(&mut stack, "dd", copied_at, &config), Some("cc"));
+      assert_eq!(stack, stack_of(&["aa", "bb", "dd"]));
+      assert_eq!(copy_times(&stack).last(), Some((&"dd", copied_at)));
+      // A larger top pushes out the oldest entries to fit
+      assert_eq!(replace_top(&mut stack, "eeee", copied_at, &config), Some("dd"));
+      assert_eq!(stack, stack_of(&["bb", "eeee"]));
+      let mut stack = Stack::default();
+      assert_eq!(replace_top(&mut stack, "a", copied_at, &config), None);
+      assert!(stack.is_empty());
+      // A pinned top stays pinned, so it still can't be pushed out
+      let mut stack = stack_of(&["aa", "bb"]);
+      stack.set_pinned(1, true);
+      assert_eq!(replace_top(&mut stack, "cccccc", copied_at, &config), Some("bb"));
+      assert!(stack.is_pinned(1));
+      assert_eq!(stack, stack_of(&["aa", "cccccc"]));
+   }
+
+   #[test]
+   fn counts_changes() {
+      let mut stack = stack_of(&["a", "b"]);
+      let mut changes = stack.changes();
+      let mut assert_changed = |stack: &Stack<&str>| {
+         assert_ne!(stack.changes(), changes);
+         changes = stack.changes();
+      };
+      assert!(rotate(&mut stack));
+      assert_changed(&stack);
+      assert!(reverse(&mut stack));
+      assert_changed(&stack);
+      assert!(move_to_bottom(&mut stack));
+      assert_changed(&stack);
+      assert!(move_to_top(&mut stack, 0));
+      assert_changed(&stack);
+      stack.swap(0, 1);
+      assert_changed(&stack);
+      assert_eq!(push(&mut stack, "c", &Config::default()), Push::Pushed);
+      assert_changed(&stack);
+      assert_eq!(remove_at_depth(&mut stack, 1), Some("c"));
+      assert_changed(&stack);
+      assert_eq!(toggle_pin_top(&mut stack), Some(true));
+      assert_changed(&stack);
+      stack.clear();
+      assert_changed(&stack);
+      // Looking doesn't change anything
+      assert!(!rotate(&mut stack));
+      assert_eq!(stack.changes(), changes);
+   }
+
+   #[test]
+   fn item_size_limit_leaves_room_in_memory() {
+      let marker = TRUNCATED_MARKER.encode_utf16().count() * 2;
+      let config = Config::default();
+      assert_eq!(item_size_limit(&config), None);
+      let config = Config {
+         max_item_size: Some(100),
+         ..config
+      };
+      assert_eq!(item_size_limit(&config), Some(100));
+      let config = Config {
+         max_stack_memory: Some(1000),
+         ..config
+      };
+      assert_eq!(item_size_limit(&config), Some(100));
+      let config = Config {
+         max_item_size: None,
+         ..config
+      };
+      assert_eq!(item_size_limit(&config), Some(1000));
+      let config = Config {
+         oversize_action: OversizeAction::Truncate,
+         ..config
+      };
+      assert_eq!(item_size_limit(&config), Some(1000 - marker));
+   }
+
+   #[test]
+   fn push_with_single_entry_stack() {
+      let config = Config {
+         max_stack_size: Some(1),
+         ..Config::default()
+      };
+      let mut stack = stack_of(&[]);
+      assert_eq!(push(&mut stack, "a", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "b", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["b"]));
+
+      let config = Config {
+         stack_full_policy: StackFullPolicy::RejectNew,
+         ..config
+      };
+      assert_eq!(push(&mut stack, "c", &config), Push::StackFull);
+      assert_eq!(stack, stack_of(&["b"]));
+      stack.pop_back();
+      assert_eq!(push(&mut stack, "c", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["c"]));
+   }
+
+   #[test]
+   fn push_ignores_duplicate_of_top() {
+      let config = Config {
+         prevent_duplicate_push: true,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b"]);
+      assert_eq!(push(&mut stack, "b", &config), Push::DuplicateOnTop);
+      assert_eq!(push(&mut stack, "a", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "b", "a"]));
+   }
+
+   #[test]
+   fn push_checks_whole_stack_for_duplicates() {
+      let config = Config {
+         dedupe_scope: DedupeScope::Stack,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b", "c"]);
+      assert_eq!(push(&mut stack, "a", &config), Push::Duplicate);
+      assert_eq!(push(&mut stack, "c", &config), Push::DuplicateOnTop);
+      assert_eq!(stack, stack_of(&["a", "b", "c"]));
+      assert_eq!(push(&mut stack, "d", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "b", "c", "d"]));
+   }
+
+   #[test]
+   fn push_promotes_duplicates() {
+      let config = Config {
+         max_stack_size: Some(3),
+         dedupe_scope: DedupeScope::Stack,
+         dedupe_action: DedupeAction::Promote,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b", "c"]);
+      // A full stack loses nothing when a duplicate is promoted
+      assert_eq!(push(&mut stack, "a", &config), Push::DuplicateOnTop);
+      assert_eq!(stack, stack_of(&["b", "c", "a"]));
+      assert_eq!(push(&mut stack, "d", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["c", "a", "d"]));
+
+      // Only the top is checked when deduping consecutive copies
+      let config = Config {
+         prevent_duplicate_push: true,
+         dedupe_action: DedupeAction::Promote,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b"]);
+      assert_eq!(push(&mut stack, "b", &config), Push::DuplicateOnTop);
+      assert_eq!(push(&mut stack, "a", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["a", "b", "a"]));
+   }
+
+   #[test]
+   fn push_trims_whitespace_for_duplicates() {
+      let config = Config {
+         prevent_duplicate_push: true,
+         dedupe_trim_whitespace: true,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["foo"]);
+      assert_eq!(push(&mut stack, "foo ", &config), Push::DuplicateOnTop);
+      assert_eq!(push(&mut stack, "\r\n\tfoo", &config), Push::DuplicateOnTop);
+      // Whitespace within the text still matters
+      assert_eq!(push(&mut stack, "f oo", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "FOO", &config), Push::Pushed);
+      // The entry on the stack keeps its original text
+      assert_eq!(stack, stack_of(&["foo", "f oo", "FOO"]));
+   }
+
+   #[test]
+   fn push_ignores_case_for_duplicates() {
+      let config = Config {
+         dedupe_scope: DedupeScope::Stack,
+         dedupe_action: DedupeAction::Promote,
+         dedupe_ignore_case: true,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["Foo", "bar"]);
+      assert_eq!(push(&mut stack, "fOO", &config), Push::DuplicateOnTop);
+      assert_eq!(stack, stack_of(&["bar", "Foo"]));
+      assert_eq!(push(&mut stack, " bar", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["bar", "Foo", " bar"]));
+      assert_eq!(
+         normalize(
+            " Ünïcode  Text ",
+            &Config {
+               dedupe_trim_whitespace: true,
+               ..config
+            }
+         ),
+         "ünïcode  text"
+      );
+   }
+
+   #[test]
+   fn push_normalizes_again_when_dedupe_options_change() {
+      let mut config = Config {
+         dedupe_scope: DedupeScope::Stack,
+         dedupe_ignore_case: true,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["Foo"]);
+      assert_eq!(push(&mut stack, " bar", &config), Push::Pushed);
+      assert_eq!(push(&mut stack, "FOO", &config), Push::Duplicate);
+      // The hashes kept from the pushes above were normalized without trimming
+      config.dedupe_trim_whitespace = true;
+      assert_eq!(push(&mut stack, "BAR ", &config), Push::DuplicateOnTop);
+      config.dedupe_trim_whitespace = false;
+      config.dedupe_ignore_case = false;
+      assert_eq!(push(&mut stack, "FOO", &config), Push::Pushed);
+      assert_eq!(stack, stack_of(&["Foo", " bar", "FOO"]));
+   }
+
+   /// An entry of either kind, to check entries of different kinds are kept apart
+   #[derive(Clone, Copy, Debug, PartialEq)]
+   struct Mixed(Kind, &'static str);
+
+   impl Size for Mixed {
+      fn size(&self) -> usize {
+         self.1.len()
+      }
+   }
+
+   impl Text for Mixed {
+      fn to_text(&self) -> String {
+         String::from(self.1)
+      }
+
+      fn kind(&self) -> Kind {
+         self.0
+      }
+   }
+
+   #[test]
+   fn dedupe_keeps_kinds_apart() {
+      let config = Config {
+         prevent_duplicate_push: true,
+         dedupe_ignore_case: true,
+         ..Config::default()
+      };
+      let files = Mixed(Kind::Files, "C:\\a.txt");
+      let mut stack = Stack::default();
+      assert_eq!(push(&mut stack, files, &config), Push::Pushed);
+      assert_eq!(
+         push(&mut stack, Mixed(Kind::Files, "c:\\A.TXT"), &config),
+         Push::DuplicateOnTop
+      );
+      assert_eq!(push(&mut stack, Mixed(Kind::Text, "C:\\a.txt"), &config), Push::Pushed);
+      // Images have no text to normalize, so only the same image is a duplicate
+      assert_eq!(push(&mut stack, Mixed(Kind::Image, "1"), &config), Push::Pushed);
+      assert_eq!(push(&mut stack, Mixed(Kind::Image, "2"), &config), Push::Pushed);
+      assert_eq!(push(&mut stack, Mixed(Kind::Image, "2"), &config), Push::DuplicateOnTop);
+      // Mixed stacks pop in order
+      assert_eq!(stack.pop_back(), Some(Mixed(Kind::Image, "2")));
+      assert_eq!(stack.pop_back(), Some(Mixed(Kind::Image, "1")));
+      assert_eq!(stack.pop_back(), Some(Mixed(Kind::Text, "C:\\a.txt")));
+      assert_eq!(stack.pop_back(), Some(files));
+   }
+
+   /// Text with a hash of its formatting, which is the same text whatever its formatting
+   #[derive(Debug)]
+   struct Formatted(&'static str, u64);
+
+   impl PartialEq for Formatted {
+      fn eq(&self, other: &Formatted) -> bool {
+         self.0 == other.0
+      }
+   }
+
+   impl Size for Formatted {
+      fn size(&self) -> usize {
+         self.0.len()
+      }
+   }
+
+   impl Text for Formatted {
+      fn to_text(&self) -> String {
+         String::from(self.0)
+      }
+
+      fn kind(&self) -> Kind {
+         Kind::Text
+      }
+
+      fn formatting_hash(&self) -> u64 {
+         self.1
+      }
+   }
+
+   #[test]
+   fn dedupe_ignores_formatting_unless_asked() {
+      let mut config = Config {
+         prevent_duplicate_push: true,
+         ..Config::default()
+      };
+      let mut stack = Stack::default();
+      assert_eq!(push(&mut stack, Formatted("hi", 1), &config), Push::Pushed);
+      assert_eq!(push(&mut stack, Formatted("hi", 0), &config), Push::DuplicateOnTop);
+      config.dedupe_formatting = true;
+      assert_eq!(push(&mut stack, Formatted("hi", 0), &config), Push::Pushed);
+      assert_eq!(push(&mut stack, Formatted("hi", 0), &config), Push::DuplicateOnTop);
+      config.dedupe_ignore_case = true;
+      assert_eq!(push(&mut stack, Formatted("HI", 1), &config), Push::Pushed);
+      assert_eq!(push(&mut stack, Formatted("Hi", 1), &config), Push::DuplicateOnTop);
+   }
+
+   #[test]
+   fn join_leaves_out_images() {
+      let stack: Stack<Mixed> = vec![Mixed(Kind::Text, "a"), Mixed(Kind::Image, "1"), Mixed(Kind::Text, "b")]
+         .into_iter()
+         .collect();
+      let joined: Vec<_> = in_join_order(&stack, JoinOrder::TopFirst)
+         .into_iter()
+         .map(|x| x.1)
+         .collect();
+      assert_eq!(joined, vec!["b", "a"]);
+   }
+
+   #[test]
+   fn push_copy_ignores_repeats_within_window() {
+      let config = Config {
+         duplicate_window_ms: 100,
+         ..Config::default()
+      };
+      let start = Instant::now();
+      let at = |ms| start + Duration::from_millis(ms);
+      let mut stack = stack_of(&[]);
+      let mut last_push = None;
+      assert_eq!(push_copy(&mut stack, "a", &mut last_push, at(0), &config), Push::Pushed);
+      assert_eq!(
+         push_copy(&mut stack, "a", &mut last_push, at(10), &config),
+         Push::DuplicateOnTop
+      );
+      assert_eq!(
+         push_copy(&mut stack, "a", &mut last_push, at(99), &config),
+         Push::DuplicateOnTop
+      );
+      // A different copy isn't held back, and restarts the window
+      assert_eq!(
+         push_copy(&mut stack, "b", &mut last_push, at(50), &config),
+         Push::Pushed
+      );
+      assert_eq!(
+         push_copy(&mut stack, "a", &mut last_push, at(60), &config),
+         Push::Pushed
+      );
+      // Copying the same thing again later is a real copy
+      assert_eq!(
+         push_copy(&mut stack, "a", &mut last_push, at(160), &config),
+         Push::Pushed
+      );
+      assert_eq!(stack, stack_of(&["a", "b", "a", "a"]));
+   }
+
+   #[test]
+   fn push_copy_without_window() {
+      let config = Config::default();
+      let start = Instant::now();
+      let mut stack = stack_of(&[]);
+      let mut last_push = None;
+      assert_eq!(push_copy(&mut stack, "a", &mut last_push, start, &config), Push::Pushed);
+      assert_eq!(push_copy(&mut stack, "a", &mut last_push, start, &config), Push::Pushed);
+      assert!(last_push.is_none());
+      assert_eq!(stack, stack_of(&["a", "a"]));
+   }
+
+   #[test]
+   fn duplicate_top_ignores_duplicate_prevention() {
+      let config = Config {
+         max_stack_size: Some(3),
+         prevent_duplicate_push: true,
+         ..Config::default()
+      };
+      let mut stack = stack_of(&["a", "b", "c"]);
+      assert!(duplicate_top(&mut stack, &config));
+      assert_eq!(stack, stack_of(&["b", "c", "c"]));
+      let mut stack = stack_of(&[]);
+      assert!(!duplicate_top(&mut stack, &config));
+      assert!(stack.is_empty());
+   }
+
+   #[test]
+   fn undo_pop_walks_back_through_popped_entries() {
+      let config = Config::default();
+      let mut stack = stack_of(&["a"]);
+      let mut popped = VecDeque::new();
+      remember_popped(&mut popped, "c");
+      remember_popped(&mut popped, "b");
+      assert!(undo_pop(&mut stack, &mut popped, &config));
+      assert_eq!(stack, stack_of(&["a", "b"]));
+      assert!(undo_pop(&mut stack, &mut popped, &config));
+      assert_eq!(stack, stack_of(&["a", "b", "c"]));
+      assert!(!undo_pop(&mut stack, &mut popped, &config));
+   }
+
+   #[test]
+   fn remembers_limited_popped_entries() {
+      let mut popped = VecDeque::new();
+      for i in 0..UNDO_LIMIT + 5 {
+         remember_popped(&mut popped, i);
+      }
+      assert_eq!(popped.len(), UNDO_LIMIT);
+      assert_eq!(popped.back(), Some(&(UNDO_LIMIT + 4)));
+   }
+
+   #[test]
+   fn move_to_bottom_works_with_one_entry() {
+      let mut stack = stack_of(&["a", "b", "c"]);
+      assert!(move_to_bottom(&mut stack));
+      assert_eq!(stack, stack_of(&["c", "a", "b"]));
+      let mut stack = stack_of(&["a"]);
+      assert!(move_to_bottom(&mut stack));
+      assert_eq!(stack, stack_of(&["a"]));
+      let mut stack = stack_of(&[]);
+      assert!(!move_to_bottom(&mut stack));
+   }
+
+   #[test]
+   fn reverse_flips_stack() {
+      let mut stack = stack_of(&["a", "b", "c"]);
+      assert!(reverse(&mut stack));
+      assert_eq!(stack, stack_of(&["c", "b", "a"]));
+      let mut stack = stack_of(&["a"]);
+      assert!(!reverse(&mut stack));
+      assert_eq!(stack, stack_of(&["a"]));
+   }
+
+   #[test]
+   fn move_to_top_keeps_order_of_others() {
+      let mut stack = stack_of(&["a", "b", "c", "d"]);
+      assert!(move_to_top(&mut stack, 1));
+      assert_eq!(stack, stack_of(&["a", "c", "d", "b"]));
+      assert!(move_to_top(&mut stack, 3));
+      assert_eq!(stack, stack_of(&["a", "c", "d", "b"]));
+      assert!(!move_to_top(&mut stack, 4));
+   }
+
+   #[test]
+   fn removes_at_depth_from_top() {
+      let mut stack = stack_of(&["a", "b", "c", "d"]);
+      assert_eq!(remove_at_depth(&mut stack, 3), Some("b"));
+      assert_eq!(stack, stack_of(&["a", "c", "d"]));
+      assert_eq!(remove_at_depth(&mut stack, 1), Some("d"));
+      assert_eq!(stack, stack_of(&["a", "c"]));
+   }
+
+   #[test]
+   fn remove_at_depth_ignores_missing_entries() {
+      let mut stack = stack_of(&["a", "b"]);
+      assert_eq!(remove_at_depth(&mut stack, 3), None);
+      assert_eq!(remove_at_depth(&mut stack, 0), None);
+      assert_eq!(stack, stack_of(&["a", "b"]));
+   }
+
+   #[test]
+   fn utf16_prefix_keeps_surrogate_pairs_whole() {
+      let units: Vec<u16> = "ab😀c".encode_utf16().collect();
+      assert_eq!(units.len(), 5);
+      assert_eq!(utf16_prefix_len(&units, 100), 5);
+      assert_eq!(utf16_prefix_len(&units, 10), 5);
+      assert_eq!(utf16_prefix_len(&units, 9), 4);
+      assert_eq!(utf16_prefix_len(&units, 8), 4);
+      // Half of the emoji doesn't fit, so it's left out entirely
+      assert_eq!(utf16_prefix_len(&units, 6), 2);
+      assert_eq!(utf16_prefix_len(&units, 5), 2);
+      assert_eq!(utf16_prefix_len(&units, 1), 0);
+      assert_eq!(utf16_prefix_len(&[], 4), 0);
+   }
+
+   fn utf16_bytes(units: &[u16]) -> Vec<u8> {
+      units.iter().copied().flat_map(u16::to_le_bytes).collect()
+   }
+
+   #[test]
+   fn reads_utf16_to_the_first_null() {
+      let units: Vec<u16> = "hi😀".encode_utf16().collect();
+      // Without a null, all of it is text, but for a stray odd byte
+      let mut bytes = utf16_bytes(&units);
+      assert_eq!(read_utf16(&bytes), (units.clone(), 0));
+      bytes.push(b'x');
+      assert_eq!(read_utf16(&bytes), (units.clone(), 0));
+      // Anything after the null is left out
+      let bytes = utf16_bytes(&[0x68, 0x69, 0, 0x67, 0x61, 0x72, 0x62]);
+      assert_eq!(read_utf16(&bytes), (vec![0x68, 0x69], 0));
+      assert_eq!(read_utf16(&utf16_bytes(&[0, 0x68, 0x69])), (Vec::new(), 0));
+      assert_eq!(read_utf16(&[]), (Vec::new(), 0));
+   }
+
+   fn normalized(text: &str, line_endings: LineEndings) -> String {
+      let units: Vec<u16> = text.encode_utf16().collect();
+      String::from_utf16(&normalize_line_endings(&units, line_endings)).unwrap()
+   }
+
+   #[test]
+   fn normalizes_line_endings() {
+      let mixed = "one\r\ntwo\nthree\r\n\nfour";
+      assert_eq!(normalized(mixed, LineEndings::Crlf), "one\r\ntwo\r\nthree\r\n\r\nfour");
+      assert_eq!(normalized(mixed, LineEndings::Lf), "one\ntwo\nthree\n\nfour");
+      assert_eq!(normalized(mixed, LineEndings::Keep), mixed);
+      // A trailing newline stays, in the new form
+      assert_eq!(normalized("end\n", LineEndings::Crlf), "end\r\n");
+      assert_eq!(normalized("end\r\n", LineEndings::Lf), "end\n");
+      assert_eq!(normalized("\n", LineEndings::Crlf), "\r\n");
+      // Carriage returns on their own aren't line endings
+      assert_eq!(normalized("a\rb\r", LineEndings::Crlf), "a\rb\r");
+      assert_eq!(normalized("a\rb\r\r\nc", LineEndings::Lf), "a\rb\nc");
+      assert_eq!(normalized("a\r\r\nb\n", LineEndings::Crlf), "a\r\r\nb\r\n");
+      // Normalizing twice changes nothing more, so text normalized once still dedupes against the same text
+      for text in [mixed, "a\rb\r\r\nc", "\r\r\r\n\r"] {
+         for line_endings in [LineEndings::Crlf, LineEndings::Lf] {
+            let once = normalized(text, line_endings);
+            assert_eq!(normalized(&once, line_endings), once);
+         }
+      }
+   }
+
+   fn transforms(rules: &[(&str, &str)]) -> Vec<Transform> {
+      rules
+         .iter()
+         .map(|(pattern, replacement)| Transform {
+            regex: regex::Regex::new(pattern).unwrap(),
+            replacement: String::from(*replacement),
+         })
+         .collect()
+   }
+
+   #[test]
+   fn transforms_in_order() {
+      let rules = transforms(&[("^mailto:", ""), ("[ \t]+", " "), ("@example\\.com$", "@example.org")]);
+      assert_eq!(transform("mailto:a  b\t@example.com", &rules), "a b @example.org");
+      // Each transform sees what the last made
+      let rules = transforms(&[("cat", "dog"), ("dog", "bird")]);
+      assert_eq!(transform("cat dog", &rules), "bird bird");
+      let rules = transforms(&[("dog", "bird"), ("cat", "dog")]);
+      assert_eq!(transform("cat dog", &rules), "dog bird");
+      // Text that no transform matches is left as it is
+      assert!(matches!(transform("fish", &rules), Cow::Borrowed("fish")));
+      assert_eq!(transform("fish", &[]), "fish");
+   }
+
+   #[test]
+   fn transforms_with_capture_groups() {
+      let rules = transforms(&[
+         ("(\\w+)@(\\w+)", "$2 at ${1}x"),
+         ("token=(?P<token>[a-z0-9]+)", "token=[redacted]"),
+      ]);
+      assert_eq!(transform("me@home", &rules), "home at mex");
+      assert_eq!(transform("?token=abc123&x=1", &rules), "?token=[redacted]&x=1");
+      assert_eq!(transform("secret", &transforms(&[(".*", "")])), "");
+   }
+
+   #[test]
+   fn replaces_unpaired_surrogates() {
+      // A high surrogate without its low half, then a low surrogate on its own
+      let bytes = utf16_bytes(&[0x61, 0xD83D, 0x62, 0xDE00, 0]);
+      assert_eq!(read_utf16(&bytes), (vec![0x61, 0xFFFD, 0x62, 0xFFFD], 2));
+      assert_eq!(read_utf16(&utf16_bytes(&[0xD83D])), (vec![0xFFFD], 1));
+   }
+
+   #[test]
+   fn truncate_keeps_newest_entries() {
+      let mut stack = stack_of(&["a", "b", "c", "d"]);
+      truncate(&mut stack, Some(2));
+      assert_eq!(stack, stack_of(&["c", "d"]));
+      truncate(&mut stack, None);
+      assert_eq!(stack, stack_of(&["c", "d"]));
+      truncate(&mut stack, Some(5));
+      assert_eq!(stack, stack_of(&["c", "d"]));
+   }
+
+   #[test]
+   fn rotate_moves_top_to_bottom() {
+      let mut stack = stack_of(&["a", "b", "c"]);
+      assert!(rotate(&mut stack));
+      assert_eq!(stack, stack_of(&["c", "a", "b"]));
+      assert!(rotate(&mut stack));
+      assert!(rotate(&mut stack));
+      assert_eq!(stack, stack_of(&["a", "b", "c"]));
+   }
+
+   #[test]
+   fn rotate_needs_two_entries() {
+      let mut stack = stack_of(&["a"]);
+      assert!(!rotate(&mut stack));
+      assert_eq!(stack, stack_of(&["a"]));
+      let mut stack = stack_of(&[]);
+      assert!(!rotate(&mut stack));
+   }
+
+   #[test]
+   fn join_puts_oldest_entry_first() {
+      let stack = stack_of(&["oldest", "middle", "newest"]);
+      assert_eq!(
+         join(stack.iter().map(|x| x.chars()), "\r\n", None),
+         (String::from("oldest\r\nmiddle\r\nnewest"), false)
+      );
+      assert_eq!(
+         join(stack_of(&["only"]).iter().map(|x| x.chars()), ", ", None),
+         (String::from("only"), false)
+      );
+   }
+
+   #[test]
+   fn joins_in_join_order() {
+      let joined =
+         |stack: &Stack<&str>, order| join(in_join_order(stack, order).into_iter().map(|x| x.chars()), "\n", None).0;
+      // The top of the stack is the last copied
+      let stack = stack_of(&["bottom", "middle", "top"]);
+      assert_eq!(joined(&stack, JoinOrder::BottomFirst), "bottom\nmiddle\ntop");
+      assert_eq!(joined(&stack, JoinOrder::TopFirst), "top\nmiddle\nbottom");
+      // In a queue, the top is the first copied
+      let mut stack = queue();
+      for entry in &["first", "second", "third"] {
+         push(&mut stack, *entry, &Config::default());
+      }
+      assert_eq!(joined(&stack, JoinOrder::BottomFirst), "third\nsecond\nfirst");
+      assert_eq!(joined(&stack, JoinOrder::TopFirst), "first\nsecond\nthird");
+   }
+
+   #[test]
+   fn join_stops_at_byte_limit() {
+      let stack = stack_of(&["ab", "cd"]);
+      assert_eq!(
+         join(stack.iter().map(|x| x.chars()), ", ", Some(5)),
+         (String::from("ab, c"), true)
+      );
+      assert_eq!(
+         join(stack.iter().map(|x| x.chars()), ", ", Some(6)),
+         (String::from("ab, cd"), false)
+      );
+      // Characters aren't split
+      let stack = stack_of(&["aé"]);
+      assert_eq!(
+         join(stack.iter().map(|x| x.chars()), "", Some(2)),
+         (String::from("a"), true)
+      );
+      // Only as much as fits is read
+      assert_eq!(
+         join(iter::once(iter::repeat('x')), "", Some(3)),
+         (String::from("xxx"), true)
+      );
+   }
+
+   #[test]
+   fn files_summary_names_first_file() {
+      assert_eq!(files_summary(&["C:\\Users\\me\\report.pdf"]), "report.pdf");
+      assert_eq!(
+         files_summary(&["C:\\photos\\a.jpg", "C:\\photos\\b.jpg"]),
+         "a.jpg and 1 other file"
+      );
+      assert_eq!(
+         files_summary(&["C:\\projects\\ripclip", "C:\\a.txt", "C:\\b.txt"]),
+         "ripclip and 2 other files"
+      );
+      // A whole drive has no name past its root
+      assert_eq!(files_summary(&["D:\\"]), "D:");
+   }
+
+   #[test]
+   fn preview_collapses_line_breaks() {
+      assert_eq!(
+         preview("\r\nfirst\r\n\r\nsecond\nthird\n".chars(), 80),
+         "first second third"
+      );
+   }
+
+   #[test]
+   fn preview_truncates_long_entries() {
+      assert_eq!(preview("abcdef".chars(), 3), "abc…");
+      assert_eq!(preview("abc".chars(), 3), "abc");
+      assert_eq!(preview("ab\ncd".chars(), 3), "ab …");
+      assert_eq!(preview(iter::repeat('x'), 5), "xxxxx…");
+   }
+
+   #[test]
+   fn badge_stops_counting_at_nine() {
+      assert_eq!(badge(0), None);
+      assert_eq!(badge(1).as_deref(), Some("1"));
+      assert_eq!(badge(9).as_deref(), Some("9"));
+      assert_eq!(badge(10).as_deref(), Some("9+"));
+      assert_eq!(badge(5000).as_deref(), Some("9+"));
+   }
+
+   #[test]
+   fn tooltip_fits_in_limit() {
+      assert_eq!(tooltip("ripclip", 0, None, 127), "ripclip — empty");
+      assert_eq!(tooltip("ripclip", 1, Some("hi"), 127), "ripclip — 1 entry. Top: 'hi'");
+      assert_eq!(
+         tooltip("ripclip (capture paused)", 12, None, 127),
+         "ripclip (capture paused) — 12 entries"
+      );
+      let long = tooltip("ripclip", 12, Some("SELECT * FROM users WHERE id = 1"), 40);
+      assert_eq!(long, "ripclip — 12 entries. Top: 'SELECT * F…'");
+      assert_eq!(long.encode_utf16().count(), 40);
+      // Characters outside the Basic Multilingual Plane take two units, and aren't split
+      let emoji = tooltip("ripclip", 2, Some("😀😀😀😀"), 34);
+      assert_eq!(emoji, "ripclip — 2 entries. Top: '😀😀…'");
+      assert!(emoji.encode_utf16().count() <= 34);
+      // A preview that's already cut short isn't given a second ellipsis
+      assert_eq!(fit_utf16("abcdef…", 4), "abc…");
+      // With no room for the preview, it's left out
+      assert_eq!(tooltip("ripclip", 3, Some("abc"), 20), "ripclip — 3 entries");
+   }
+}
@@ -0,0 +1,16 @@
+//! Files on disk for tests to read and write, shared by the tests of every module that needs them
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Writes `files`, each a name and its contents, to a directory of their own for the test `test_name`, so tests
+/// don't trip over each other. Anything left there by an earlier run is removed first.
+pub fn write_test_files(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+   let dir = std::env::temp_dir().join(format!("ripclip-{}-{}", test_name, std::process::id()));
+   let _ = fs::remove_dir_all(&dir);
+   fs::create_dir_all(&dir).unwrap();
+   for (name, contents) in files {
+      fs::write(dir.join(name), contents).unwrap();
+   }
+   dir
+}
@@ -0,0 +1,255 @@
+//! Watching the clipboard for copies: telling the updates caused by ripclip's own writes apart from copies, skipping
+//! updates that don't change what's on the clipboard, and noticing when Windows stops sending updates at all
+
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// How much of each clipboard format `hash_sample` hashes. Formats this size or smaller are hashed whole.
+const HASH_SAMPLE_BYTES: usize = 64 * 1024;
+/// How many evenly spaced pieces a larger format is sampled in
+const HASH_SAMPLE_PIECES: usize = 16;
+
+/// Tells the clipboard updates caused by ripclip's own writes apart from copies made by other apps
+#[derive(Debug, Default)]
+pub struct SelfWrites {
+   /// The clipboard's sequence number right after ripclip last wrote to it
+   last_write: Option<u32>,
+}
+
+impl SelfWrites {
+   /// Records a write to the clipboard, given the clipboard's sequence number once it's done
+   pub fn wrote(&mut self, sequence_number: u32) {
+      self.last_write = Some(sequence_number);
+   }
+
+   /// Whether an update leaves the clipboard holding what ripclip wrote, given the clipboard's sequence number and
+   /// whether `entry::MARKER_FORMAT` is on it. Windows changes the sequence number whenever anything is copied, so a
+   /// copy made by another app right after a write, however soon, isn't mistaken for it. The marker catches writes
+   /// whose sequence number wasn't recorded before their update arrived.
+   pub fn is_own(&self, sequence_number: u32, has_marker: bool) -> bool {
+      has_marker || self.last_write == Some(sequence_number)
+   }
+}
+
+/// Whether a clipboard update is worth reading, as `CaptureGate` decides
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gate {
+   Capture,
+   /// The clipboard's sequence number hasn't changed since it was last read, so neither has what's on it
+   Unchanged,
+   /// What's on the clipboard is the same as when it was last read, just placed there again
+   SameContents,
+}
+
+/// Keeps the clipboard from being read again when it hasn't changed, which can be costly for a large copy. Some
+/// programs announce one change more than once, and some place the same copy on the clipboard again and again.
+#[derive(Debug, Default)]
+pub struct CaptureGate {
+   /// The clipboard's sequence number when it was last read
+   last_sequence: Option<u32>,
+   /// A quick hash of what was on the clipboard when it was last read, and when that was
+   last_contents: Option<(u64, Instant)>,
+}
+
+impl CaptureGate {
+   /// Checks the clipboard's sequence number, before opening the clipboard
+   pub fn check_sequence(&mut self, sequence_number: u32) -> Gate {
+      if self.last_sequence == Some(sequence_number) {
+         return Gate::Unchanged;
+      }
+      self.last_sequence = Some(sequence_number);
+      Gate::Capture
+   }
+
+   /// Records that the capture thread placed what it read back on the clipboard, leaving it at `sequence_number`, so
+   /// that the update this causes isn't read again
+   pub fn wrote(&mut self, sequence_number: u32) {
+      self.last_sequence = Some(sequence_number);
+   }
+
+   /// Checks a quick hash of what's on the clipboard at `now`, once its sequence number has changed. The same
+   /// contents placed again are only skipped within `window` of being read, as copying the same thing again later is
+   /// a copy of its own, as `stack::push_copy` takes it.
+   pub fn check_contents(&mut self, hash: u64, now: Instant, window: Duration) -> Gate {
+      if let Some((last_hash, at)) = self.last_contents {
+         if last_hash == hash && now.saturating_duration_since(at) < window {
+            return Gate::SameContents;
+         }
+      }
+      self.last_contents = Some((hash, now));
+      Gate::Capture
+   }
+}
+
+/// Hashes `bytes` for `CaptureGate::check_contents` in the same time however large they are: their length along with
+/// evenly spaced pieces of them, the first at the start and the last at the end. Copies of the same size that differ
+/// only between the pieces hash the same, which is why the gate only trusts a hash for a moment after a read.
+pub fn hash_sample<H: Hasher>(bytes: &[u8], hasher: &mut H) {
+   bytes.len().hash(hasher);
+   if bytes.len() <= HASH_SAMPLE_BYTES {
+      bytes.hash(hasher);
+      return;
+   }
+   let piece = HASH_SAMPLE_BYTES / HASH_SAMPLE_PIECES;
+   let last_start = bytes.len() - piece;
+   for i in 0..HASH_SAMPLE_PIECES {
+      let start = last_start * i / (HASH_SAMPLE_PIECES - 1);
+      bytes[start..start + piece].hash(hasher);
+   }
+}
+
+/// Notices when the clipboard changes without an update arriving, as happens when Windows quietly drops the
+/// clipboard listener, so that ripclip can start listening again rather than miss every copy from then on
+#[derive(Debug)]
+pub struct ListenerWatchdog {
+   /// The clipboard's sequence number when the last update arrived, or when the listener was last checked on
+   last_seen: u32,
+   /// How many times the listener has been found broken
+   recoveries: u32,
+}
+
+impl ListenerWatchdog {
+   /// Starts watching, given the clipboard's sequence number when the listener was added
+   pub fn new(sequence_number: u32) -> ListenerWatchdog {
+      ListenerWatchdog {
+         last_seen: sequence_number,
+         recoveries: 0,
+      }
+   }
+
+   /// Records that an update arrived, given the clipboard's sequence number then
+   pub fn updated(&mut self, sequence_number: u32) {
+      self.last_seen = sequence_number;
+   }
+
+   /// Checks on the listener, given the clipboard's sequence number and whether ripclip wrote what's on the
+   /// clipboard, as its own writes don't send updates. Returns whether the clipboard has changed since the last
+   /// update, in which case the listener is taken to be broken and counted as recovered.
+   pub fn check(&mut self, sequence_number: u32, own_write: bool) -> bool {
+      let broken = !own_write && sequence_number != self.last_seen;
+      self.last_seen = sequence_number;
+      if broken {
+         self.recoveries += 1;
+      }
+      broken
+   }
+
+   /// How many times the listener has been found broken
+   pub fn recoveries(&self) -> u32 {
+      self.recoveries
+   }
+}
+
+#[cfg(test)]
+mod test {
+   use super::*;
+   use std::collections::hash_map::DefaultHasher;
+
+   #[test]
+   fn recognizes_own_writes() {
+      let mut writes = SelfWrites::default();
+      assert!(!writes.is_own(5, false));
+      writes.wrote(7);
+      assert!(writes.is_own(7, false));
+      // Another app copied something after the write
+      assert!(!writes.is_own(8, false));
+      assert!(!writes.is_own(6, false));
+      // A write that hasn't been recorded yet is still marked
+      assert!(writes.is_own(8, true));
+      writes.wrote(8);
+      assert!(writes.is_own(8, false));
+      assert!(!writes.is_own(7, false));
+      // Sequence numbers wrap around
+      writes.wrote(u32::MAX);
+      assert!(!writes.is_own(0, false));
+   }
+
+   #[test]
+   fn gates_unchanged_clipboard() {
+      let mut gate = CaptureGate::default();
+      let start = Instant::now();
+      let window = Duration::from_millis(100);
+      assert_eq!(gate.check_sequence(5), Gate::Capture);
+      assert_eq!(gate.check_contents(1, start, window), Gate::Capture);
+      // The same update announced again
+      assert_eq!(gate.check_sequence(5), Gate::Unchanged);
+      // The same contents placed again soon after
+      assert_eq!(gate.check_sequence(6), Gate::Capture);
+      assert_eq!(
+         gate.check_contents(1, start + Duration::from_millis(50), window),
+         Gate::SameContents
+      );
+      // Something else
+      assert_eq!(gate.check_sequence(7), Gate::Capture);
+      assert_eq!(
+         gate.check_contents(2, start + Duration::from_millis(60), window),
+         Gate::Capture
+      );
+      assert_eq!(
+         gate.check_contents(1, start + Duration::from_millis(70), window),
+         Gate::Capture
+      );
+      // The same thing copied again later
+      assert_eq!(
+         gate.check_contents(1, start + Duration::from_millis(170), window),
+         Gate::Capture
+      );
+      // With no window, only the sequence number can skip an update
+      assert_eq!(
+         gate.check_contents(1, start + Duration::from_millis(170), Duration::from_millis(0)),
+         Gate::Capture
+      );
+      // Sequence numbers wrap around
+      assert_eq!(gate.check_sequence(u32::MAX), Gate::Capture);
+      assert_eq!(gate.check_sequence(0), Gate::Capture);
+      // What was read placed back on the clipboard
+      gate.wrote(1);
+      assert_eq!(gate.check_sequence(1), Gate::Unchanged);
+   }
+
+   fn sample_hash(bytes: &[u8]) -> u64 {
+      let mut hasher = DefaultHasher::new();
+      hash_sample(bytes, &mut hasher);
+      hasher.finish()
+   }
+
+   #[test]
+   fn samples_large_copies() {
+      let small = vec![7u8; 1000];
+      assert_eq!(sample_hash(&small), sample_hash(&small.clone()));
+      let mut changed = small.clone();
+      changed[500] = 8;
+      assert_ne!(sample_hash(&small), sample_hash(&changed));
+      let large = vec![7u8; 10 * 1024 * 1024];
+      // The start, the end and the size are always hashed
+      for index in [0, large.len() - 1] {
+         let mut changed = large.clone();
+         changed[index] = 8;
+         assert_ne!(sample_hash(&large), sample_hash(&changed));
+      }
+      assert_ne!(sample_hash(&large), sample_hash(&large[1..]));
+      // The middle of a large copy is only sampled
+      let mut changed = large.clone();
+      changed[large.len() / 2] = 8;
+      assert_eq!(sample_hash(&large), sample_hash(&changed));
+   }
+
+   #[test]
+   fn notices_broken_listener() {
+      let mut watchdog = ListenerWatchdog::new(10);
+      assert!(!watchdog.check(10, false));
+      watchdog.updated(11);
+      watchdog.updated(12);
+      assert!(!watchdog.check(12, false));
+      // ripclip's own writes don't send updates
+      assert!(!watchdog.check(13, true));
+      assert!(!watchdog.check(13, false));
+      // A copy made without an update arriving
+      assert!(watchdog.check(14, false));
+      assert_eq!(watchdog.recoveries(), 1);
+      // Once caught up, it isn't counted again
+      assert!(!watchdog.check(14, false));
+      assert!(watchdog.check(15, false));
+      assert_eq!(watchdog.recoveries(), 2);
+   }
+}
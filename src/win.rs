@@ -1,9 +1,13 @@
 //! "Safe" "wrapper" around a smattering of the windows API
 
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 use std::iter;
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::ptr::{self, NonNull};
+use std::slice;
 use std::str::FromStr;
 use std::string::FromUtf16Error;
 use std::{fmt, mem, num};
@@ -62,6 +66,51 @@ impl Menu {
 
       Ok(())
    }
+
+   pub fn set_checked(&mut self, id: usize, checked: bool) -> Result<(), ()> {
+      let check = if checked {
+         winapi::um::winuser::MF_CHECKED
+      } else {
+         winapi::um::winuser::MF_UNCHECKED
+      };
+      let result = unsafe {
+         winapi::um::winuser::CheckMenuItem(
+            self.inner.as_ptr(),
+            id as u32,
+            winapi::um::winuser::MF_BYCOMMAND | check,
+         )
+      };
+
+      // The previous state of the item is returned, or -1 if the item doesn't exist
+      if result == u32::MAX {
+         return Err(());
+      }
+
+      Ok(())
+   }
+
+   /// Greys out the item if `enabled` is false
+   pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<(), ()> {
+      let enable = if enabled {
+         winapi::um::winuser::MF_ENABLED
+      } else {
+         winapi::um::winuser::MF_GRAYED
+      };
+      let result = unsafe {
+         winapi::um::winuser::EnableMenuItem(
+            self.inner.as_ptr(),
+            id as u32,
+            winapi::um::winuser::MF_BYCOMMAND | enable,
+         )
+      };
+
+      // The previous state of the item is returned, or -1 if the item doesn't exist
+      if result == -1 {
+         return Err(());
+      }
+
+      Ok(())
+   }
 }
 
 pub fn create_popup_menu() -> Result<Menu, ErrorCode> {
@@ -96,6 +145,9 @@ pub fn draw_popup_menu(menu: &Menu, x: i32, y: i32, hwnd: &WindowHandle) -> Resu
       return Err(ErrorCode(code));
    }
 
+   // Switches back to the window, so that the menu opens properly next time
+   unsafe { winapi::um::winuser::PostMessageW(hwnd.inner.as_ptr(), winapi::um::winuser::WM_NULL, 0, 0) };
+
    Ok(())
 }
 
@@ -109,6 +161,18 @@ pub fn set_foreground_window(hwnd: &WindowHandle) -> Result<(), ()> {
    Ok(())
 }
 
+pub fn get_cursor_pos() -> Result<(i32, i32), ErrorCode> {
+   let mut point = winapi::shared::windef::POINT { x: 0, y: 0 };
+   let result = unsafe { winapi::um::winuser::GetCursorPos(&mut point) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok((point.x, point.y))
+}
+
 pub struct WindowHandle<'a> {
    inner: NonNull<winapi::shared::windef::HWND__>,
    class: PhantomData<&'a ClassAtom<'a>>,
@@ -164,6 +228,12 @@ fn unregister_class<'a>(class_atom: &mut ClassAtom<'a>) -> Result<(), ErrorCode>
 pub struct ErrorCode(u32);
 
 pub const ERROR_ACCESS_DENIED: ErrorCode = ErrorCode(5);
+pub const ERROR_PROC_NOT_FOUND: ErrorCode = ErrorCode(127);
+pub const ERROR_INVALID_FLAGS: ErrorCode = ErrorCode(1004);
+pub const ERROR_NOT_FOUND: ErrorCode = ErrorCode(1168);
+pub const ERROR_HOTKEY_ALREADY_REGISTERED: ErrorCode = ErrorCode(1409);
+const ERROR_INVALID_PARAMETER: ErrorCode = ErrorCode(87);
+const ERROR_INVALID_DATA: ErrorCode = ErrorCode(13);
 
 impl fmt::Display for ErrorCode {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -231,6 +301,29 @@ impl FromStr for Modifiers {
    }
 }
 
+impl fmt::Display for Modifiers {
+   /// Writes each modifier joined with `+`, as they would appear in a hotkey
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let names = [
+         (Modifiers::CONTROL, "Control"),
+         (Modifiers::ALT, "Alt"),
+         (Modifiers::SHIFT, "Shift"),
+         (Modifiers::WIN, "Win"),
+      ];
+      let mut first = true;
+      for (modifier, name) in names.iter() {
+         if self.contains(*modifier) {
+            if !first {
+               write!(f, " + ")?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+         }
+      }
+      Ok(())
+   }
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum VirtualKey {
@@ -238,6 +331,8 @@ pub enum VirtualKey {
    RightClick,
    Cancel,
    MiddleClick,
+   XButton1,
+   XButton2,
    Backspace = 0x08,
    Tab,
    Enter = 0x0D,
@@ -313,7 +408,13 @@ pub enum VirtualKey {
    NumpadSeven,
    NumpadEight,
    NumpadNine,
-   F1 = 0x70,
+   NumpadMultiply,
+   NumpadAdd,
+   NumpadSeparator,
+   NumpadSubtract,
+   NumpadDecimal,
+   NumpadDivide,
+   F1,
    F2,
    F3,
    F4,
@@ -344,6 +445,18 @@ pub enum VirtualKey {
    RightControl,
    LeftAlt,
    RightAlt,
+   BrowserBack = 0xA6,
+   BrowserForward,
+   BrowserRefresh,
+   MediaNextTrack = 0xB0,
+   MediaPreviousTrack,
+   MediaStop,
+   MediaPlayPause,
+   LaunchMail,
+   LaunchMediaSelect,
+   LaunchApp1,
+   LaunchApp2,
+   Minus = 0xBD,
    Play = 0xFA,
    Zoom,
 }
@@ -360,7 +473,9 @@ impl FromStr for VirtualKey {
          "left click" | "left button" => VirtualKey::LeftClick,
          "right click" | "right button" => VirtualKey::RightClick,
          "cancel" => VirtualKey::Cancel,
-         "middle click" | "middle button" => VirtualKey::MiddleClick,
+         "middle click" | "middle button" | "mouse middle" | "mouse_middle" => VirtualKey::MiddleClick,
+         "mouse x1" | "mouse_x1" => VirtualKey::XButton1,
+         "mouse x2" | "mouse_x2" => VirtualKey::XButton2,
          "backspace" | "back" => VirtualKey::Backspace,
          "tab" => VirtualKey::Tab,
          "enter" => VirtualKey::Enter,
@@ -426,16 +541,26 @@ impl FromStr for VirtualKey {
          "right windows" | "right win" | "right super" => VirtualKey::RightWindows,
          "applications" | "apps" => VirtualKey::Applications,
          "sleep" => VirtualKey::Sleep,
-         "numpad zero" | "numpad 0" => VirtualKey::NumpadZero,
-         "numpad one" | "numpad 1" => VirtualKey::NumpadOne,
-         "numpad two" | "numpad 2" => VirtualKey::NumpadTwo,
-         "numpad three" | "numpad 3" => VirtualKey::NumpadThree,
-         "numpad four" | "numpad 4" => VirtualKey::NumpadFour,
-         "numpad five" | "numpad 5" => VirtualKey::NumpadFive,
-         "numpad six" | "numpad 6" => VirtualKey::NumpadSix,
-         "numpad seven" | "numpad 7" => VirtualKey::NumpadSeven,
-         "numpad eight" | "numpad 8" => VirtualKey::NumpadEight,
-         "numpad nine" | "numpad 9" => VirtualKey::NumpadNine,
+         "numpad zero" | "numpad 0" | "numpad0" => VirtualKey::NumpadZero,
+         "numpad one" | "numpad 1" | "numpad1" => VirtualKey::NumpadOne,
+         "numpad two" | "numpad 2" | "numpad2" => VirtualKey::NumpadTwo,
+         "numpad three" | "numpad 3" | "numpad3" => VirtualKey::NumpadThree,
+         "numpad four" | "numpad 4" | "numpad4" => VirtualKey::NumpadFour,
+         "numpad five" | "numpad 5" | "numpad5" => VirtualKey::NumpadFive,
+         "numpad six" | "numpad 6" | "numpad6" => VirtualKey::NumpadSix,
+         "numpad seven" | "numpad 7" | "numpad7" => VirtualKey::NumpadSeven,
+         "numpad eight" | "numpad 8" | "numpad8" => VirtualKey::NumpadEight,
+         "numpad nine" | "numpad 9" | "numpad9" => VirtualKey::NumpadNine,
+         "numpad multiply" | "numpad_multiply" | "numpadmultiply" | "numpad *" | "numpad*" => {
+            VirtualKey::NumpadMultiply
+         }
+         "numpad add" | "numpad_add" | "numpadadd" | "numpad plus" | "numpad_plus" => VirtualKey::NumpadAdd,
+         "numpad separator" | "numpad_separator" | "numpadseparator" => VirtualKey::NumpadSeparator,
+         "numpad subtract" | "numpad_subtract" | "numpadsubtract" | "numpad minus" | "numpad_minus" => {
+            VirtualKey::NumpadSubtract
+         }
+         "numpad decimal" | "numpad_decimal" | "numpaddecimal" | "numpad ." | "numpad." => VirtualKey::NumpadDecimal,
+         "numpad divide" | "numpad_divide" | "numpaddivide" | "numpad /" | "numpad/" => VirtualKey::NumpadDivide,
          "f1" => VirtualKey::F1,
          "f2" => VirtualKey::F2,
          "f3" => VirtualKey::F3,
@@ -467,6 +592,18 @@ impl FromStr for VirtualKey {
          "right control" => VirtualKey::RightControl,
          "left alt" => VirtualKey::LeftAlt,
          "right alt" => VirtualKey::RightAlt,
+         "browser back" | "browser_back" => VirtualKey::BrowserBack,
+         "browser forward" | "browser_forward" => VirtualKey::BrowserForward,
+         "browser refresh" | "browser_refresh" => VirtualKey::BrowserRefresh,
+         "media next track" | "media_next_track" => VirtualKey::MediaNextTrack,
+         "media previous track" | "media_previous_track" => VirtualKey::MediaPreviousTrack,
+         "media stop" | "media_stop" => VirtualKey::MediaStop,
+         "media play pause" | "media_play_pause" => VirtualKey::MediaPlayPause,
+         "launch mail" | "launch_mail" => VirtualKey::LaunchMail,
+         "launch media select" | "launch_media_select" => VirtualKey::LaunchMediaSelect,
+         "launch app1" | "launch_app1" => VirtualKey::LaunchApp1,
+         "launch app2" | "launch_app2" => VirtualKey::LaunchApp2,
+         "minus" | "-" => VirtualKey::Minus,
          "play" => VirtualKey::Play,
          "zoom" => VirtualKey::Zoom,
          _ => return Err(ParseVirtualKeyError::UnknownKey(s.into())),
@@ -474,7 +611,162 @@ impl FromStr for VirtualKey {
    }
 }
 
+impl fmt::Display for VirtualKey {
+   /// Writes a name that can be parsed back into the same key
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let name = match self {
+         VirtualKey::LeftClick => "Left Click",
+         VirtualKey::RightClick => "Right Click",
+         VirtualKey::Cancel => "Cancel",
+         VirtualKey::MiddleClick => "Middle Click",
+         VirtualKey::XButton1 => "Mouse X1",
+         VirtualKey::XButton2 => "Mouse X2",
+         VirtualKey::Backspace => "Backspace",
+         VirtualKey::Tab => "Tab",
+         VirtualKey::Enter => "Enter",
+         VirtualKey::Shift => "Shift",
+         VirtualKey::Control => "Control",
+         VirtualKey::Alt => "Alt",
+         VirtualKey::Pause => "Pause",
+         VirtualKey::CapsLock => "Caps Lock",
+         VirtualKey::Escape => "Escape",
+         VirtualKey::Space => "Space",
+         VirtualKey::PageUp => "Page Up",
+         VirtualKey::PageDown => "Page Down",
+         VirtualKey::End => "End",
+         VirtualKey::Home => "Home",
+         VirtualKey::Left => "Left",
+         VirtualKey::Up => "Up",
+         VirtualKey::Right => "Right",
+         VirtualKey::Down => "Down",
+         VirtualKey::Select => "Select",
+         VirtualKey::Print => "Print",
+         VirtualKey::Execute => "Execute",
+         VirtualKey::PrintScreen => "Print Screen",
+         VirtualKey::Insert => "Insert",
+         VirtualKey::Delete => "Delete",
+         VirtualKey::Help => "Help",
+         VirtualKey::Zero => "0",
+         VirtualKey::One => "1",
+         VirtualKey::Two => "2",
+         VirtualKey::Three => "3",
+         VirtualKey::Four => "4",
+         VirtualKey::Five => "5",
+         VirtualKey::Six => "6",
+         VirtualKey::Seven => "7",
+         VirtualKey::Eight => "8",
+         VirtualKey::Nine => "9",
+         VirtualKey::A => "A",
+         VirtualKey::B => "B",
+         VirtualKey::C => "C",
+         VirtualKey::D => "D",
+         VirtualKey::E => "E",
+         VirtualKey::F => "F",
+         VirtualKey::G => "G",
+         VirtualKey::H => "H",
+         VirtualKey::I => "I",
+         VirtualKey::J => "J",
+         VirtualKey::K => "K",
+         VirtualKey::L => "L",
+         VirtualKey::M => "M",
+         VirtualKey::N => "N",
+         VirtualKey::O => "O",
+         VirtualKey::P => "P",
+         VirtualKey::Q => "Q",
+         VirtualKey::R => "R",
+         VirtualKey::S => "S",
+         VirtualKey::T => "T",
+         VirtualKey::U => "U",
+         VirtualKey::V => "V",
+         VirtualKey::W => "W",
+         VirtualKey::X => "X",
+         VirtualKey::Y => "Y",
+         VirtualKey::Z => "Z",
+         VirtualKey::LeftWindows => "Left Windows",
+         VirtualKey::RightWindows => "Right Windows",
+         VirtualKey::Applications => "Applications",
+         VirtualKey::Sleep => "Sleep",
+         VirtualKey::NumpadZero => "Numpad 0",
+         VirtualKey::NumpadOne => "Numpad 1",
+         VirtualKey::NumpadTwo => "Numpad 2",
+         VirtualKey::NumpadThree => "Numpad 3",
+         VirtualKey::NumpadFour => "Numpad 4",
+         VirtualKey::NumpadFive => "Numpad 5",
+         VirtualKey::NumpadSix => "Numpad 6",
+         VirtualKey::NumpadSeven => "Numpad 7",
+         VirtualKey::NumpadEight => "Numpad 8",
+         VirtualKey::NumpadNine => "Numpad 9",
+         VirtualKey::NumpadMultiply => "Numpad Multiply",
+         VirtualKey::NumpadAdd => "Numpad Add",
+         VirtualKey::NumpadSeparator => "Numpad Separator",
+         VirtualKey::NumpadSubtract => "Numpad Subtract",
+         VirtualKey::NumpadDecimal => "Numpad Decimal",
+         VirtualKey::NumpadDivide => "Numpad Divide",
+         VirtualKey::F1 => "F1",
+         VirtualKey::F2 => "F2",
+         VirtualKey::F3 => "F3",
+         VirtualKey::F4 => "F4",
+         VirtualKey::F5 => "F5",
+         VirtualKey::F6 => "F6",
+         VirtualKey::F7 => "F7",
+         VirtualKey::F8 => "F8",
+         VirtualKey::F9 => "F9",
+         VirtualKey::F10 => "F10",
+         VirtualKey::F11 => "F11",
+         VirtualKey::F12 => "F12",
+         VirtualKey::F13 => "F13",
+         VirtualKey::F14 => "F14",
+         VirtualKey::F15 => "F15",
+         VirtualKey::F16 => "F16",
+         VirtualKey::F17 => "F17",
+         VirtualKey::F18 => "F18",
+         VirtualKey::F19 => "F19",
+         VirtualKey::F20 => "F20",
+         VirtualKey::F21 => "F21",
+         VirtualKey::F22 => "F22",
+         VirtualKey::F23 => "F23",
+         VirtualKey::F24 => "F24",
+         VirtualKey::Numlock => "Num Lock",
+         VirtualKey::LeftShift => "Left Shift",
+         VirtualKey::RightShift => "Right Shift",
+         VirtualKey::LeftControl => "Left Control",
+         VirtualKey::RightControl => "Right Control",
+         VirtualKey::LeftAlt => "Left Alt",
+         VirtualKey::RightAlt => "Right Alt",
+         VirtualKey::BrowserBack => "Browser Back",
+         VirtualKey::BrowserForward => "Browser Forward",
+         VirtualKey::BrowserRefresh => "Browser Refresh",
+         VirtualKey::MediaNextTrack => "Media Next Track",
+         VirtualKey::MediaPreviousTrack => "Media Previous Track",
+         VirtualKey::MediaStop => "Media Stop",
+         VirtualKey::MediaPlayPause => "Media Play Pause",
+         VirtualKey::LaunchMail => "Launch Mail",
+         VirtualKey::LaunchMediaSelect => "Launch Media Select",
+         VirtualKey::LaunchApp1 => "Launch App1",
+         VirtualKey::LaunchApp2 => "Launch App2",
+         VirtualKey::Minus => "Minus",
+         VirtualKey::Play => "Play",
+         VirtualKey::Zoom => "Zoom",
+      };
+      write!(f, "{}", name)
+   }
+}
+
 impl VirtualKey {
+   /// Whether this is a function key past F12, or a browser, media or launch key. These aren't used for typing,
+   /// so they make fine hotkeys without any modifiers
+   pub fn is_spare(self) -> bool {
+      let code = self as u8;
+      (VirtualKey::F13 as u8..=VirtualKey::F24 as u8).contains(&code)
+         || (VirtualKey::BrowserBack as u8..=VirtualKey::BrowserRefresh as u8).contains(&code)
+         || (VirtualKey::MediaNextTrack as u8..=VirtualKey::LaunchApp2 as u8).contains(&code)
+   }
+
+   /// The mouse buttons that can be bound, which `RegisterHotKey` doesn't accept
+   pub fn is_mouse_button(self) -> bool {
+      self == VirtualKey::MiddleClick || self == VirtualKey::XButton1 || self == VirtualKey::XButton2
+   }
+
    pub fn is_modifier(self) -> bool {
       self == VirtualKey::Alt
          || self == VirtualKey::Control
@@ -494,14 +786,14 @@ pub fn register_hotkey(
    hwnd: Option<&WindowHandle>,
    id: u16,
    modifiers: Modifiers,
-   key: VirtualKey,
+   key_code: u32,
 ) -> Result<(), ErrorCode> {
    let result = unsafe {
       winapi::um::winuser::RegisterHotKey(
          hwnd.map_or(ptr::null_mut(), |x| x.inner.as_ptr()),
          mem::transmute::<u32, i32>(u32::from(id)),
          modifiers.bits(),
-         key as u32,
+         key_code,
       )
    };
 
@@ -529,6 +821,226 @@ pub fn unregister_hotkey(hwnd: Option<&WindowHandle>, id: u16) -> Result<(), Err
    Ok(())
 }
 
+/// The keyboard layout of the foreground window, which is the layout the user is typing with
+fn foreground_keyboard_layout() -> winapi::shared::minwindef::HKL {
+   unsafe {
+      let foreground = winapi::um::winuser::GetForegroundWindow();
+      let thread = winapi::um::winuser::GetWindowThreadProcessId(foreground, ptr::null_mut());
+      winapi::um::winuser::GetKeyboardLayout(thread)
+   }
+}
+
+/// Looks up the virtual key at `scancode` in the keyboard layout the user is typing with.
+/// `None` if that layout has no key there.
+pub fn scancode_to_virtual_key(scancode: u16) -> Option<u32> {
+   let code = unsafe {
+      winapi::um::winuser::MapVirtualKeyExW(
+         u32::from(scancode),
+         winapi::um::winuser::MAPVK_VSC_TO_VK,
+         foreground_keyboard_layout(),
+      )
+   };
+   if code == 0 {
+      None
+   } else {
+      Some(code)
+   }
+}
+
+/// Looks up the virtual key that types `c` in the keyboard layout the user is typing with, along with the
+/// modifiers that have to be held for it. `None` if no key in that layout types it.
+pub fn character_to_virtual_key(c: char) -> Option<(u32, Modifiers)> {
+   let mut utf16 = [0; 2];
+   let utf16 = c.encode_utf16(&mut utf16);
+   if utf16.len() != 1 {
+      // Only characters that fit in a single UTF-16 unit can be looked up
+      return None;
+   }
+   let result = unsafe { winapi::um::winuser::VkKeyScanExW(utf16[0], foreground_keyboard_layout()) };
+   if result == -1 {
+      return None;
+   }
+   let [code, shift_state] = (result as u16).to_le_bytes();
+   let mut modifiers = Modifiers::empty();
+   for (bit, modifier) in [(1, Modifiers::SHIFT), (2, Modifiers::CONTROL), (4, Modifiers::ALT)].iter() {
+      if shift_state & bit != 0 {
+         modifiers |= *modifier;
+      }
+   }
+   if shift_state & !0x7 != 0 {
+      // Keys needing the Hankaku key, or other layout specific states, can't be part of a hotkey
+      return None;
+   }
+   Some((u32::from(code), modifiers))
+}
+
+/// A key pressed or released by `send_input`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyInput {
+   /// The virtual key code
+   pub key: u16,
+   pub up: bool,
+}
+
+/// Each modifier, with its left and right keys
+const MODIFIER_KEYS: [(Modifiers, [u16; 2]); 4] = [
+   (
+      Modifiers::CONTROL,
+      [
+         winapi::um::winuser::VK_LCONTROL as u16,
+         winapi::um::winuser::VK_RCONTROL as u16,
+      ],
+   ),
+   (
+      Modifiers::SHIFT,
+      [
+         winapi::um::winuser::VK_LSHIFT as u16,
+         winapi::um::winuser::VK_RSHIFT as u16,
+      ],
+   ),
+   (
+      Modifiers::ALT,
+      [
+         winapi::um::winuser::VK_LMENU as u16,
+         winapi::um::winuser::VK_RMENU as u16,
+      ],
+   ),
+   (
+      Modifiers::WIN,
+      [winapi::um::winuser::VK_LWIN as u16, winapi::um::winuser::VK_RWIN as u16],
+   ),
+];
+
+/// A virtual key code with no key assigned to it, pressed to keep a lone Alt or Windows key from opening a menu
+const UNASSIGNED_KEY: u16 = 0xE8;
+
+/// Keys that send the extended key flag, which apps tell apart from their twins on the numpad or the left side by
+fn is_extended_key(key: u16) -> bool {
+   use winapi::um::winuser::*;
+   [
+      VK_RCONTROL,
+      VK_RMENU,
+      VK_LWIN,
+      VK_RWIN,
+      VK_APPS,
+      VK_INSERT,
+      VK_DELETE,
+      VK_HOME,
+      VK_END,
+      VK_PRIOR,
+      VK_NEXT,
+      VK_LEFT,
+      VK_UP,
+      VK_RIGHT,
+      VK_DOWN,
+      VK_DIVIDE,
+      VK_NUMLOCK,
+      VK_SNAPSHOT,
+   ]
+   .iter()
+   .any(|x| *x as u16 == key)
+}
+
+/// Whether the key with the virtual key code given is held down right now
+fn is_key_down(key: u16) -> bool {
+   let state = unsafe { winapi::um::winuser::GetAsyncKeyState(i32::from(key)) };
+   state as u16 & 0x8000 != 0
+}
+
+/// Synthesizes the key presses and releases given, in order, as though typed into the foreground window
+pub fn send_input(keys: &[KeyInput]) -> Result<(), ErrorCode> {
+   let mut inputs: Vec<winapi::um::winuser::INPUT> = keys
+      .iter()
+      .map(|key| {
+         let mut flags = 0;
+         if key.up {
+            flags |= winapi::um::winuser::KEYEVENTF_KEYUP;
+         }
+         if is_extended_key(key.key) {
+            flags |= winapi::um::winuser::KEYEVENTF_EXTENDEDKEY;
+         }
+         let scancode =
+            unsafe { winapi::um::winuser::MapVirtualKeyW(u32::from(key.key), winapi::um::winuser::MAPVK_VK_TO_VSC) };
+         let mut input = winapi::um::winuser::INPUT {
+            type_: winapi::um::winuser::INPUT_KEYBOARD,
+            u: unsafe { std::mem::zeroed() },
+         };
+         unsafe {
+            *input.u.ki_mut() = winapi::um::winuser::KEYBDINPUT {
+               wVk: key.key,
+               wScan: scancode as u16,
+               dwFlags: flags,
+               time: 0,
+               dwExtraInfo: 0,
+            };
+         }
+         input
+      })
+      .collect();
+
+   let sent = unsafe {
+      winapi::um::winuser::SendInput(
+         inputs.len() as u32,
+         inputs.as_mut_ptr(),
+         std::mem::size_of::<winapi::um::winuser::INPUT>() as i32,
+      )
+   };
+
+   if (sent as usize) < inputs.len() {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+/// Types `key` (a virtual key code) with `modifiers` held into the foreground window. Modifier keys still held down,
+/// such as those of the hotkey that led here, would be combined with it, so any that aren't part of `modifiers` are
+/// released around it and pressed again afterwards.
+pub fn send_keystroke(modifiers: Modifiers, key: u32) -> Result<(), ErrorCode> {
+   let held: Vec<u16> = MODIFIER_KEYS
+      .iter()
+      .flat_map(|(_, keys)| keys.iter().copied())
+      .filter(|x| is_key_down(*x))
+      .collect();
+   send_input(&keystroke_inputs(modifiers, key as u16, &held))
+}
+
+/// The presses and releases that type `key` with `modifiers`, given the modifier keys held down
+fn keystroke_inputs(modifiers: Modifiers, key: u16, held: &[u16]) -> Vec<KeyInput> {
+   let down = |key| KeyInput { key, up: false };
+   let up = |key| KeyInput { key, up: true };
+   // The keystroke's modifiers that aren't held already, and held modifiers that aren't the keystroke's
+   let mut pressed = Vec::new();
+   let mut in_the_way = Vec::new();
+   for (modifier, keys) in MODIFIER_KEYS.iter() {
+      let held_keys = keys.iter().copied().filter(|x| held.contains(x));
+      if !modifiers.contains(*modifier) {
+         in_the_way.extend(held_keys);
+      } else if held_keys.count() == 0 {
+         pressed.push(keys[0]);
+      }
+   }
+   let mut inputs = Vec::new();
+   // Pressing the keystroke's modifiers first means releasing a held Alt isn't taken as tapping it
+   inputs.extend(pressed.iter().map(|x| down(*x)));
+   inputs.extend(in_the_way.iter().map(|x| up(*x)));
+   inputs.push(down(key));
+   inputs.push(up(key));
+   inputs.extend(in_the_way.iter().map(|x| down(*x)));
+   inputs.extend(pressed.iter().rev().map(|x| up(*x)));
+   // Otherwise letting go of the Alt or Windows key pressed again would open the menu or the Start menu
+   let alt_or_win = [Modifiers::ALT, Modifiers::WIN]
+      .iter()
+      .flat_map(|x| MODIFIER_KEYS.iter().filter(move |(modifier, _)| modifier == x))
+      .any(|(_, keys)| keys.iter().any(|x| in_the_way.contains(x)));
+   if alt_or_win {
+      inputs.push(down(UNASSIGNED_KEY));
+      inputs.push(up(UNASSIGNED_KEY));
+   }
+   inputs
+}
+
 pub fn get_module_handle_ex() -> Result<ModuleHandle, ErrorCode> {
    let mut module_handle: MaybeUninit<winapi::shared::minwindef::HMODULE> = MaybeUninit::uninit();
 
@@ -542,6 +1054,167 @@ pub fn get_module_handle_ex() -> Result<ModuleHandle, ErrorCode> {
    unsafe { Ok(ModuleHandle(NonNull::new_unchecked(module_handle.assume_init()))) }
 }
 
+/// The modifiers held down right now, for hotkeys that aren't handled by `register_hotkey`
+pub fn held_modifiers() -> Modifiers {
+   let is_down = |key: VirtualKey| unsafe { winapi::um::winuser::GetAsyncKeyState(key as i32) } as u16 & 0x8000 != 0;
+   let mut modifiers = Modifiers::empty();
+   if is_down(VirtualKey::Control) {
+      modifiers |= Modifiers::CONTROL;
+   }
+   if is_down(VirtualKey::Alt) {
+      modifiers |= Modifiers::ALT;
+   }
+   if is_down(VirtualKey::Shift) {
+      modifiers |= Modifiers::SHIFT;
+   }
+   if is_down(VirtualKey::LeftWindows) || is_down(VirtualKey::RightWindows) {
+      modifiers |= Modifiers::WIN;
+   }
+   modifiers
+}
+
+pub struct Hook<'a> {
+   inner: NonNull<winapi::shared::windef::HHOOK__>,
+   module: PhantomData<&'a ModuleHandle>,
+}
+
+impl<'a> Drop for Hook<'a> {
+   fn drop(&mut self) {
+      unhook_windows_hook(self).unwrap();
+   }
+}
+
+fn unhook_windows_hook(hook: &mut Hook) -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::winuser::UnhookWindowsHookEx(hook.inner.as_ptr()) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+/// Installs a low level mouse hook. `hook_fn` sees every mouse event on the system, and is called on this thread
+/// while it waits in `get_message`, so it must return quickly.
+pub fn set_mouse_hook<'a>(
+   module_handle: &'a ModuleHandle,
+   hook_fn: winapi::um::winuser::HOOKPROC,
+) -> Result<Hook<'a>, ErrorCode> {
+   set_windows_hook(winapi::um::winuser::WH_MOUSE_LL, module_handle, hook_fn)
+}
+
+/// Installs a low level keyboard hook. Like with `set_mouse_hook`, `hook_fn` sees every key press on the system,
+/// and must return quickly; Windows skips hooks that take too long.
+pub fn set_keyboard_hook<'a>(
+   module_handle: &'a ModuleHandle,
+   hook_fn: winapi::um::winuser::HOOKPROC,
+) -> Result<Hook<'a>, ErrorCode> {
+   set_windows_hook(winapi::um::winuser::WH_KEYBOARD_LL, module_handle, hook_fn)
+}
+
+fn set_windows_hook<'a>(
+   id: i32,
+   module_handle: &'a ModuleHandle,
+   hook_fn: winapi::um::winuser::HOOKPROC,
+) -> Result<Hook<'a>, ErrorCode> {
+   let result = unsafe { winapi::um::winuser::SetWindowsHookExW(id, hook_fn, module_handle.0.as_ptr(), 0) };
+
+   match NonNull::new(result) {
+      Some(inner) => Ok(Hook {
+         inner,
+         module: PhantomData,
+      }),
+      None => {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         Err(ErrorCode(code))
+      }
+   }
+}
+
+/// Shows a modal message box, returning which button was pressed (`IDOK`, `IDCANCEL`, ...)
+pub fn message_box(hwnd: Option<&WindowHandle>, text: &str, caption: &str, style: u32) -> Result<i32, ErrorCode> {
+   let text = to_win_utf16(text);
+   let caption = to_win_utf16(caption);
+   let hwnd = hwnd.map_or(ptr::null_mut(), |x| x.inner.as_ptr());
+
+   let result = unsafe { winapi::um::winuser::MessageBoxW(hwnd, text.as_ptr(), caption.as_ptr(), style) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(result)
+}
+
+/// Opens `file` with the program Windows has for its type, as double-clicking it would
+pub fn shell_open(file: &str) -> Result<(), ErrorCode> {
+   shell_execute(file, None)
+}
+
+/// Opens `file` with `program`, which is looked for where Windows looks for programs if it isn't a full path
+pub fn shell_open_with(program: &str, file: &str) -> Result<(), ErrorCode> {
+   shell_execute(program, Some(&format!("\"{}\"", file)))
+}
+
+fn shell_execute(file: &str, parameters: Option<&str>) -> Result<(), ErrorCode> {
+   let operation = to_win_utf16("open");
+   let file = to_win_utf16(file);
+   let parameters = parameters.map(to_win_utf16);
+   let result = unsafe {
+      winapi::um::shellapi::ShellExecuteW(
+         ptr::null_mut(),
+         operation.as_ptr(),
+         file.as_ptr(),
+         parameters.as_ref().map_or(ptr::null(), |x| x.as_ptr()),
+         ptr::null(),
+         winapi::um::winuser::SW_SHOWNORMAL,
+      )
+   };
+
+   // Anything above 32 means success
+   if result as usize <= 32 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+/// Attaches to the console of the parent process, or creates a new console if there isn't one
+pub fn attach_console() -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS) };
+
+   if result != 0 {
+      return Ok(());
+   }
+
+   let result = unsafe { winapi::um::consoleapi::AllocConsole() };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+/// Has `handler` called, on a thread of its own, when Ctrl+C or Ctrl+Break is pressed in or the user closes the
+/// console this process is attached to
+pub fn set_console_ctrl_handler(
+   handler: unsafe extern "system" fn(u32) -> winapi::shared::minwindef::BOOL,
+) -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(Some(handler), 1) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
 fn to_win_utf16(inp: &str) -> Vec<u16> {
    inp.encode_utf16().chain(iter::once(0)).collect()
 }
@@ -582,9 +1255,8 @@ pub fn register_class_ex<'a>(
 }
 
 pub enum WindowParent<'a> {
-   _NoParent,
+   NoParent,
    _SomeParent(&'a WindowHandle<'a>),
-   MessageOnly,
 }
 
 #[allow(clippy::too_many_arguments)] // We're roughly trying to mirror the Windows API, so the number of arguments is what it is
@@ -599,9 +1271,8 @@ pub fn create_window_ex<'a>(
    parent: &WindowParent,
 ) -> Result<WindowHandle<'a>, ErrorCode> {
    let parent_ptr = match parent {
-      WindowParent::_NoParent => ptr::null_mut(),
+      WindowParent::NoParent => ptr::null_mut(),
       WindowParent::_SomeParent(handle) => handle.inner.as_ptr(),
-      WindowParent::MessageOnly => winapi::um::winuser::HWND_MESSAGE,
    };
 
    let handle = unsafe {
@@ -634,8 +1305,156 @@ pub fn create_window_ex<'a>(
    }
 }
 
+/// A popup window filled by a list box. The list box is destroyed along with the window.
+pub struct ListPopup<'a> {
+   window: WindowHandle<'a>,
+   list: NonNull<winapi::shared::windef::HWND__>,
+}
+
+pub fn create_list_popup<'a>(
+   class_atom: &'a ClassAtom,
+   x: i32,
+   y: i32,
+   width: i32,
+   height: i32,
+) -> Result<ListPopup<'a>, ErrorCode> {
+   let window = create_window_ex(
+      winapi::um::winuser::WS_EX_TOPMOST | winapi::um::winuser::WS_EX_TOOLWINDOW,
+      class_atom,
+      winapi::um::winuser::WS_POPUP | winapi::um::winuser::WS_BORDER,
+      x,
+      y,
+      width,
+      height,
+      &WindowParent::NoParent,
+   )?;
+
+   let mut client_rect = winapi::shared::windef::RECT {
+      left: 0,
+      top: 0,
+      right: 0,
+      bottom: 0,
+   };
+   let result = unsafe { winapi::um::winuser::GetClientRect(window.inner.as_ptr(), &mut client_rect) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   let list_class = to_win_utf16("LISTBOX");
+   let list = unsafe {
+      winapi::um::winuser::CreateWindowExW(
+         0,
+         list_class.as_ptr(),
+         ptr::null(),
+         winapi::um::winuser::WS_CHILD
+            | winapi::um::winuser::WS_VISIBLE
+            | winapi::um::winuser::WS_VSCROLL
+            | winapi::um::winuser::LBS_NOTIFY
+            | winapi::um::winuser::LBS_NOINTEGRALHEIGHT
+            | winapi::um::winuser::LBS_WANTKEYBOARDINPUT,
+         0,
+         0,
+         client_rect.right,
+         client_rect.bottom,
+         window.inner.as_ptr(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+      )
+   };
+
+   match NonNull::new(list) {
+      Some(list) => Ok(ListPopup { window, list }),
+      None => {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         Err(ErrorCode(code))
+      }
+   }
+}
+
+impl<'a> ListPopup<'a> {
+   pub fn window(&self) -> &WindowHandle<'a> {
+      &self.window
+   }
+
+   fn send_list_message(&self, message: u32, w_param: usize, l_param: isize) -> isize {
+      unsafe { winapi::um::winuser::SendMessageW(self.list.as_ptr(), message, w_param, l_param) }
+   }
+
+   /// Replaces the contents of the list
+   pub fn set_items<I, S>(&mut self, items: I) -> Result<(), ()>
+   where
+      I: Iterator<Item = S>,
+      S: AsRef<str>,
+   {
+      self.send_list_message(winapi::um::winuser::LB_RESETCONTENT, 0, 0);
+      for item in items {
+         let text = to_win_utf16(item.as_ref());
+         let result = self.send_list_message(winapi::um::winuser::LB_ADDSTRING, 0, text.as_ptr() as isize);
+         // LB_ERR or LB_ERRSPACE
+         if result < 0 {
+            return Err(());
+         }
+      }
+      Ok(())
+   }
+
+   pub fn selection(&self) -> Option<usize> {
+      let result = self.send_list_message(winapi::um::winuser::LB_GETCURSEL, 0, 0);
+      if result < 0 {
+         None
+      } else {
+         Some(result as usize)
+      }
+   }
+
+   pub fn select(&mut self, index: usize) {
+      self.send_list_message(winapi::um::winuser::LB_SETCURSEL, index, 0);
+   }
+
+   /// Shows the window and gives the list keyboard focus
+   pub fn show(&self) {
+      unsafe {
+         winapi::um::winuser::ShowWindow(self.window.inner.as_ptr(), winapi::um::winuser::SW_SHOW);
+      }
+      let _ = set_foreground_window(&self.window);
+      unsafe {
+         winapi::um::winuser::SetFocus(self.list.as_ptr());
+      }
+   }
+}
+
+/// AddClipboardFormatListener and RemoveClipboardFormatListener
+type ClipboardListenerFn = unsafe extern "system" fn(winapi::shared::windef::HWND) -> winapi::shared::minwindef::BOOL;
+
+/// Finds a function in user32 by its null terminated name, for functions that older versions of Windows don't have.
+/// They're looked up rather than linked so that ripclip still starts without them.
+fn user32_function(name: &[u8]) -> Result<winapi::shared::minwindef::FARPROC, ErrorCode> {
+   let user32 = unsafe { winapi::um::libloaderapi::GetModuleHandleW(to_win_utf16("user32.dll").as_ptr()) };
+   if user32.is_null() {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+   let function = unsafe { winapi::um::libloaderapi::GetProcAddress(user32, name.as_ptr() as *const i8) };
+   if function.is_null() {
+      return Err(ERROR_PROC_NOT_FOUND);
+   }
+   Ok(function)
+}
+
+/// Finds one of user32's clipboard listener functions, which Windows only has from Vista on, so that ripclip can fall
+/// back to the clipboard viewer chain without them
+fn clipboard_listener_function(name: &[u8]) -> Result<ClipboardListenerFn, ErrorCode> {
+   let function = user32_function(name)?;
+   Ok(unsafe { mem::transmute::<winapi::shared::minwindef::FARPROC, ClipboardListenerFn>(function) })
+}
+
+/// Fails with ERROR_PROC_NOT_FOUND on versions of Windows without clipboard format listeners
 pub fn add_clipboard_format_listener(hwnd: &WindowHandle) -> Result<(), ErrorCode> {
-   let result = unsafe { winapi::um::winuser::AddClipboardFormatListener(hwnd.inner.as_ptr()) };
+   let add = clipboard_listener_function(b"AddClipboardFormatListener\0")?;
+   let result = unsafe { add(hwnd.inner.as_ptr()) };
 
    if result == 0 {
       let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
@@ -646,8 +1465,9 @@ pub fn add_clipboard_format_listener(hwnd: &WindowHandle) -> Result<(), ErrorCod
 }
 
 pub fn remove_clipboard_format_listener(hwnd: &WindowHandle) -> Result<(), ErrorCode> {
+   let remove = clipboard_listener_function(b"RemoveClipboardFormatListener\0")?;
    let success = unsafe {
-      let success_int = winapi::um::winuser::RemoveClipboardFormatListener(hwnd.inner.as_ptr());
+      let success_int = remove(hwnd.inner.as_ptr());
       success_int == 1
    };
 
@@ -659,51 +1479,979 @@ pub fn remove_clipboard_format_listener(hwnd: &WindowHandle) -> Result<(), Error
    Ok(())
 }
 
-#[repr(u32)]
-#[derive(Copy, Clone)]
-pub enum ClipboardFormat {
-   UnicodeText = 13,
-}
+/// Joins the clipboard viewer chain, the way to hear about clipboard changes before clipboard format listeners. The
+/// window is sent WM_DRAWCLIPBOARD right away, and after every change. Returns the next window in the chain, which
+/// WM_DRAWCLIPBOARD and WM_CHANGECBCHAIN are to be passed on to, or null if it's the only one.
+pub fn set_clipboard_viewer(hwnd: &WindowHandle) -> Result<winapi::shared::windef::HWND, ErrorCode> {
+   // Null is what's given back both on failure and when the chain was empty, so only the last error tells them apart
+   unsafe { winapi::um::errhandlingapi::SetLastError(0) };
+   let next = unsafe { winapi::um::winuser::SetClipboardViewer(hwnd.inner.as_ptr()) };
+   let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
 
-pub fn is_clipboard_format_available(format: ClipboardFormat) -> bool {
-   unsafe { winapi::um::winuser::IsClipboardFormatAvailable(format as u32) != 0 }
-}
+   if next.is_null() && code != 0 {
+      return Err(ErrorCode(code));
+   }
 
-pub struct ClipboardHandle {
-   _inner: (),
+   Ok(next)
 }
 
-pub struct OwnedClipboardHandle {
-   _inner: (),
+/// Leaves the clipboard viewer chain, linking the window before this one to `next`
+pub fn change_clipboard_chain(hwnd: &WindowHandle, next: winapi::shared::windef::HWND) {
+   // What this gives back says nothing about whether it worked
+   unsafe { winapi::um::winuser::ChangeClipboardChain(hwnd.inner.as_ptr(), next) };
 }
 
-#[derive(Clone, PartialEq)]
-pub struct ClipboardText(Vec<u8>);
+/// Has `ChangeWindowMessageFilterEx` let a message through
+const MSGFLT_ALLOW: u32 = 1;
 
-impl ClipboardHandle {
-   pub fn get_text(&self) -> Result<ClipboardText, ErrorCode> {
-      let handle = unsafe { winapi::um::winuser::GetClipboardData(ClipboardFormat::UnicodeText as u32) };
+/// ChangeWindowMessageFilterEx
+type MessageFilterFn = unsafe extern "system" fn(
+   winapi::shared::windef::HWND,
+   u32,
+   u32,
+   *mut winapi::um::winuser::CHANGEFILTERSTRUCT,
+) -> winapi::shared::minwindef::BOOL;
 
-      if handle.is_null() {
-         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
-         return Err(ErrorCode(code));
-      }
+/// Lets `message` through to the window from processes with a lower integrity level. Windows blocks most messages from
+/// those when ripclip runs as administrator. Fails with ERROR_PROC_NOT_FOUND before Windows 7.
+pub fn change_window_message_filter_ex(hwnd: &WindowHandle, message: u32) -> Result<(), ErrorCode> {
+   let function = user32_function(b"ChangeWindowMessageFilterEx\0")?;
+   let change_filter = unsafe { mem::transmute::<winapi::shared::minwindef::FARPROC, MessageFilterFn>(function) };
+   let result = unsafe { change_filter(hwnd.inner.as_ptr(), message, MSGFLT_ALLOW, ptr::null_mut()) };
 
-      unsafe {
-         let str_len = {
-            let mut index = 1;
-            while *((handle as *const u8).offset(index - 1)) != 0 || *((handle as *const u8).offset(index)) != 0 {
-               index += 2;
-            }
-            index as usize + 1
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+// Missing from winapi's wtsapi32 module
+#[link(name = "wtsapi32")]
+extern "system" {
+   fn WTSRegisterSessionNotification(hwnd: winapi::shared::windef::HWND, flags: u32)
+      -> winapi::shared::minwindef::BOOL;
+   fn WTSUnRegisterSessionNotification(hwnd: winapi::shared::windef::HWND) -> winapi::shared::minwindef::BOOL;
+}
+
+/// Has `WTSRegisterSessionNotification` only tell about the session the window is in
+const NOTIFY_FOR_THIS_SESSION: u32 = 0;
+
+/// Has the window sent WM_WTSSESSION_CHANGE when the session it's in is locked, unlocked, or otherwise changes
+pub fn wts_register_session_notification(hwnd: &WindowHandle) -> Result<(), ErrorCode> {
+   let result = unsafe { WTSRegisterSessionNotification(hwnd.inner.as_ptr(), NOTIFY_FOR_THIS_SESSION) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+pub fn wts_unregister_session_notification(hwnd: &WindowHandle) -> Result<(), ErrorCode> {
+   let result = unsafe { WTSUnRegisterSessionNotification(hwnd.inner.as_ptr()) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone)]
+pub enum ClipboardFormat {
+   /// Text in the code page of the clipboard's locale, which Windows provides for Unicode text and vice versa
+   Text = 1,
+   Bitmap = 2,
+   /// A device-independent bitmap. Windows provides it for images placed only as a bitmap, and vice versa.
+   Dib = 8,
+   UnicodeText = 13,
+   /// Files copied in Explorer, as a DROPFILES header followed by their paths
+   Files = 15,
+   /// The locale of the text on the clipboard, as an LCID
+   Locale = 16,
+}
+
+/// A number that changes whenever the clipboard's contents do
+pub fn get_clipboard_sequence_number() -> u32 {
+   unsafe { winapi::um::winuser::GetClipboardSequenceNumber() }
+}
+
+/// How many formats are on the clipboard, which is 0 once it's been emptied
+pub fn count_clipboard_formats() -> usize {
+   unsafe { winapi::um::winuser::CountClipboardFormats() as usize }
+}
+
+pub fn is_clipboard_format_available(format: ClipboardFormat) -> bool {
+   unsafe { winapi::um::winuser::IsClipboardFormatAvailable(format as u32) != 0 }
+}
+
+/// The id of the clipboard format with the given name, such as "HTML Format". Every program that registers the same
+/// name gets the same id.
+pub fn register_clipboard_format(name: &str) -> Result<u32, ErrorCode> {
+   let name = to_win_utf16(name);
+   let format = unsafe { winapi::um::winuser::RegisterClipboardFormatW(name.as_ptr()) };
+
+   if format == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(format)
+}
+
+/// The id of a format, registering it if it's given by name
+fn format_id(format: &crate::entry::Format) -> Result<u32, ErrorCode> {
+   match format {
+      crate::entry::Format::Id(id) => Ok(*id),
+      crate::entry::Format::Name(name) => register_clipboard_format(name),
+   }
+}
+
+/// Whether the clipboard holds something in `format`
+pub fn is_format_available(format: &crate::entry::Format) -> bool {
+   match format_id(format) {
+      Ok(id) => unsafe { winapi::um::winuser::IsClipboardFormatAvailable(id) != 0 },
+      Err(_) => false,
+   }
+}
+
+/// The id of the process whose window last placed something on the clipboard, or None if no window did
+pub fn get_clipboard_owner_process() -> Result<Option<u32>, ErrorCode> {
+   window_process(unsafe { winapi::um::winuser::GetClipboardOwner() })
+}
+
+/// The id of the process whose window has the clipboard open, or None if no window does
+pub fn get_open_clipboard_process() -> Result<Option<u32>, ErrorCode> {
+   window_process(unsafe { winapi::um::winuser::GetOpenClipboardWindow() })
+}
+
+/// The id of the process that made `window`, or None if there's no window
+fn window_process(window: winapi::shared::windef::HWND) -> Result<Option<u32>, ErrorCode> {
+   if window.is_null() {
+      return Ok(None);
+   }
+
+   let mut process_id = 0;
+   let thread_id = unsafe { winapi::um::winuser::GetWindowThreadProcessId(window, &mut process_id) };
+
+   if thread_id == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(Some(process_id))
+}
+
+/// Whether the window in the foreground covers the whole of its monitor, taskbar included, as fullscreen games and
+/// videos do. The desktop covers its monitor too, but doesn't count.
+pub fn foreground_is_fullscreen() -> Result<bool, ErrorCode> {
+   let window = unsafe { winapi::um::winuser::GetForegroundWindow() };
+   if window.is_null() || is_desktop(window) {
+      return Ok(false);
+   }
+   // A maximized window reaches past the edges of the monitor when the taskbar hides itself, but keeps its title
+   // bar, which fullscreen windows don't have
+   let style = unsafe { winapi::um::winuser::GetWindowLongW(window, winapi::um::winuser::GWL_STYLE) } as u32;
+   if style & winapi::um::winuser::WS_CAPTION == winapi::um::winuser::WS_CAPTION {
+      return Ok(false);
+   }
+   let window_rect = get_window_rect(window)?;
+   let monitor_rect = get_monitor_rect(monitor_from_window(window))?;
+   Ok(window_rect.left <= monitor_rect.left
+      && window_rect.top <= monitor_rect.top
+      && window_rect.right >= monitor_rect.right
+      && window_rect.bottom >= monitor_rect.bottom)
+}
+
+/// Whether `window` is the desktop, which Explorer draws with a `Progman` window, or a `WorkerW` window once the
+/// desktop has been clicked or while the wallpaper is a slideshow
+fn is_desktop(window: winapi::shared::windef::HWND) -> bool {
+   if window == unsafe { winapi::um::winuser::GetShellWindow() }
+      || window == unsafe { winapi::um::winuser::GetDesktopWindow() }
+   {
+      return true;
+   }
+   let mut class_name: [u16; 16] = [0; 16];
+   let len = unsafe { winapi::um::winuser::GetClassNameW(window, class_name.as_mut_ptr(), class_name.len() as i32) };
+   let class_name = String::from_utf16_lossy(&class_name[..len.max(0) as usize]);
+   class_name == "Progman" || class_name == "WorkerW"
+}
+
+/// The monitor that most of `window` is on, or the nearest one if it's on none
+fn monitor_from_window(window: winapi::shared::windef::HWND) -> winapi::shared::windef::HMONITOR {
+   unsafe { winapi::um::winuser::MonitorFromWindow(window, winapi::um::winuser::MONITOR_DEFAULTTONEAREST) }
+}
+
+/// Where a monitor is on the virtual screen, taskbar included
+fn get_monitor_rect(monitor: winapi::shared::windef::HMONITOR) -> Result<winapi::shared::windef::RECT, ErrorCode> {
+   let mut info: winapi::um::winuser::MONITORINFO = unsafe { mem::zeroed() };
+   info.cbSize = mem::size_of::<winapi::um::winuser::MONITORINFO>() as u32;
+   let result = unsafe { winapi::um::winuser::GetMonitorInfoW(monitor, &mut info) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(info.rcMonitor)
+}
+
+/// Where a window is on the virtual screen
+fn get_window_rect(window: winapi::shared::windef::HWND) -> Result<winapi::shared::windef::RECT, ErrorCode> {
+   let mut rect: winapi::shared::windef::RECT = unsafe { mem::zeroed() };
+   let result = unsafe { winapi::um::winuser::GetWindowRect(window, &mut rect) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(rect)
+}
+
+/// The id of the process whose window is in the foreground, or None if no window is
+pub fn get_foreground_window_process() -> Result<Option<u32>, ErrorCode> {
+   window_process(unsafe { winapi::um::winuser::GetForegroundWindow() })
+}
+
+/// How much Windows trusts a process, as the last part of its integrity level's SID, such as 0x2000 for medium
+/// integrity. Processes can't send input or most messages to windows of processes with a higher level, such as
+/// programs run as administrator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IntegrityLevel(u32);
+
+/// The integrity level of a process
+pub fn get_process_integrity_level(process_id: u32) -> Result<IntegrityLevel, ErrorCode> {
+   open_process(process_id)?.integrity_level()
+}
+
+/// The integrity level ripclip runs at
+pub fn get_current_process_integrity_level() -> Result<IntegrityLevel, ErrorCode> {
+   token_integrity_level(unsafe { winapi::um::processthreadsapi::GetCurrentProcess() })
+}
+
+/// Reads the integrity level from the access token of `process`
+fn token_integrity_level(process: winapi::um::winnt::HANDLE) -> Result<IntegrityLevel, ErrorCode> {
+   let mut token = ptr::null_mut();
+   let result =
+      unsafe { winapi::um::processthreadsapi::OpenProcessToken(process, winapi::um::winnt::TOKEN_QUERY, &mut token) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   let level = read_integrity_level(token);
+   unsafe { winapi::um::handleapi::CloseHandle(token) };
+   level
+}
+
+/// Reads the integrity level from an access token opened for querying
+fn read_integrity_level(token: winapi::um::winnt::HANDLE) -> Result<IntegrityLevel, ErrorCode> {
+   let mut len = 0;
+   unsafe {
+      winapi::um::securitybaseapi::GetTokenInformation(
+         token,
+         winapi::um::winnt::TokenIntegrityLevel,
+         ptr::null_mut(),
+         0,
+         &mut len,
+      )
+   };
+   // Held as u64s so that the label, which starts with a pointer, is aligned
+   let mut label = vec![0u64; (len as usize).div_ceil(8)];
+   let result = unsafe {
+      winapi::um::securitybaseapi::GetTokenInformation(
+         token,
+         winapi::um::winnt::TokenIntegrityLevel,
+         label.as_mut_ptr() as *mut winapi::ctypes::c_void,
+         len,
+         &mut len,
+      )
+   };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   let level = unsafe {
+      let sid = (*(label.as_ptr() as *const winapi::um::winnt::TOKEN_MANDATORY_LABEL))
+         .Label
+         .Sid;
+      let count = *winapi::um::securitybaseapi::GetSidSubAuthorityCount(sid);
+      *winapi::um::securitybaseapi::GetSidSubAuthority(sid, u32::from(count) - 1)
+   };
+   Ok(IntegrityLevel(level))
+}
+
+/// Another process, opened only to ask about it
+pub struct ProcessHandle(NonNull<winapi::ctypes::c_void>);
+
+impl Drop for ProcessHandle {
+   fn drop(&mut self) {
+      unsafe { winapi::um::handleapi::CloseHandle(self.0.as_ptr()) };
+   }
+}
+
+pub fn open_process(process_id: u32) -> Result<ProcessHandle, ErrorCode> {
+   let handle = unsafe {
+      winapi::um::processthreadsapi::OpenProcess(winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id)
+   };
+
+   match NonNull::new(handle) {
+      Some(handle) => Ok(ProcessHandle(handle)),
+      None => {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         Err(ErrorCode(code))
+      }
+   }
+}
+
+impl ProcessHandle {
+   pub fn integrity_level(&self) -> Result<IntegrityLevel, ErrorCode> {
+      token_integrity_level(self.0.as_ptr())
+   }
+
+   /// The full path of the program the process is running
+   pub fn image_path(&self) -> Result<String, ErrorCode> {
+      let mut path = vec![0u16; winapi::shared::minwindef::MAX_PATH];
+      loop {
+         let mut len = path.len() as u32;
+         let result =
+            unsafe { winapi::um::winbase::QueryFullProcessImageNameW(self.0.as_ptr(), 0, path.as_mut_ptr(), &mut len) };
+
+         if result != 0 {
+            return Ok(String::from_utf16_lossy(&path[..len as usize]));
+         }
+
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         // Paths can be longer than MAX_PATH, up to the 32767 characters Windows allows
+         if code != winapi::shared::winerror::ERROR_INSUFFICIENT_BUFFER || path.len() >= 0x8000 {
+            return Err(ErrorCode(code));
+         }
+         path.resize(path.len() * 4, 0);
+      }
+   }
+}
+
+/// The full path to the program a process is running
+pub fn get_process_path(process_id: u32) -> Result<String, ErrorCode> {
+   open_process(process_id)?.image_path()
+}
+
+/// The file name of the program a process is running, such as `rdpclip.exe`
+pub fn get_process_name(process_id: u32) -> Result<String, ErrorCode> {
+   let path = get_process_path(process_id)?;
+   Ok(match path.rfind('\\') {
+      Some(i) => String::from(&path[i + 1..]),
+      None => path,
+   })
+}
+
+/// Asks `GetLocaleInfoW` for the locale's ANSI code page
+const LOCALE_IDEFAULTANSICODEPAGE: u32 = 0x1004;
+/// Has `GetLocaleInfoW` give a number, rather than a string
+const LOCALE_RETURN_NUMBER: u32 = 0x2000_0000;
+
+/// The ANSI code page of a locale, which is 0 (`CP_ACP`) for locales that only use Unicode
+fn locale_code_page(locale: u32) -> Result<u32, ErrorCode> {
+   let mut code_page: u32 = 0;
+   let result = unsafe {
+      winapi::um::winnls::GetLocaleInfoW(
+         locale,
+         LOCALE_IDEFAULTANSICODEPAGE | LOCALE_RETURN_NUMBER,
+         &mut code_page as *mut u32 as *mut u16,
+         (mem::size_of::<u32>() / mem::size_of::<u16>()) as i32,
+      )
+   };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(code_page)
+}
+
+/// Converts text in `code_page` to UTF-16. Fails if Windows doesn't have the code page.
+pub fn multi_byte_to_wide(code_page: u32, bytes: &[u8]) -> Result<Vec<u16>, ErrorCode> {
+   if bytes.is_empty() {
+      return Ok(Vec::new());
+   }
+   let len = i32::try_from(bytes.len()).map_err(|_| ERROR_INVALID_PARAMETER)?;
+   let convert = |units: &mut [u16]| unsafe {
+      winapi::um::stringapiset::MultiByteToWideChar(
+         code_page,
+         0,
+         bytes.as_ptr() as *const i8,
+         len,
+         units.as_mut_ptr(),
+         units.len() as i32,
+      )
+   };
+
+   // Given no room, it says how much is needed
+   let needed = convert(&mut []);
+   if needed == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   let mut units = vec![0; needed as usize];
+   let written = convert(&mut units);
+   if written == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+   units.truncate(written as usize);
+
+   Ok(units)
+}
+
+pub struct ClipboardHandle {
+   _inner: (),
+}
+
+pub struct OwnedClipboardHandle {
+   _inner: (),
+}
+
+/// Text on (or headed for) the clipboard. A hash of the text is kept alongside it, so that
+/// telling different texts apart rarely needs to compare the texts themselves.
+#[derive(Clone)]
+pub struct ClipboardText {
+   bytes: Vec<u8>,
+   hash: u64,
+}
+
+impl PartialEq for ClipboardText {
+   fn eq(&self, other: &ClipboardText) -> bool {
+      self.hash == other.hash && self.bytes == other.bytes
+   }
+}
+
+impl ClipboardText {
+   fn new(bytes: Vec<u8>) -> ClipboardText {
+      let mut hasher = DefaultHasher::new();
+      bytes.hash(&mut hasher);
+      ClipboardText {
+         bytes,
+         hash: hasher.finish(),
+      }
+   }
+
+   /// Takes text as UTF-16, without a null terminator
+   pub fn from_units(units: impl Iterator<Item = u16>) -> ClipboardText {
+      let bytes = units.chain(iter::once(0)).flat_map(u16::to_le_bytes).collect();
+      ClipboardText::new(bytes)
+   }
+
+   /// Takes as much of the start of `units`, which is UTF-16 text without a null terminator, as fits in `max_bytes`,
+   /// without splitting a character. If some was left out, `marker` is appended and true is returned alongside.
+   pub fn from_units_prefix(units: &[u16], max_bytes: usize, marker: &str) -> (ClipboardText, bool) {
+      let len = crate::stack::utf16_prefix_len(units, max_bytes);
+      if len == units.len() {
+         return (ClipboardText::from_units(units.iter().copied()), false);
+      }
+      let text = ClipboardText::from_units(units[..len].iter().copied().chain(marker.encode_utf16()));
+      (text, true)
+   }
+
+   /// Takes text in the form given by `as_ref`, returning None if it isn't null terminated UTF-16
+   pub fn from_bytes(bytes: Vec<u8>) -> Option<ClipboardText> {
+      if bytes.len() % 2 == 1 || !bytes.ends_with(&[0, 0]) {
+         return None;
+      }
+      Some(ClipboardText::new(bytes))
+   }
+
+   /// Decodes the text, stopping at the null terminator. Invalid UTF-16 is replaced with U+FFFD.
+   pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+      let units = self
+         .bytes
+         .chunks_exact(2)
+         .map(|x| u16::from_le_bytes([x[0], x[1]]))
+         .take_while(|x| *x != 0);
+      char::decode_utf16(units).map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER))
+   }
+}
+
+impl crate::stack::Size for ClipboardText {
+   /// The size of the text, not counting the null terminator
+   fn size(&self) -> usize {
+      self.bytes.len() - 2
+   }
+}
+
+impl crate::stack::Text for ClipboardText {
+   fn to_text(&self) -> String {
+      self.chars().collect()
+   }
+
+   fn kind(&self) -> crate::stack::Kind {
+      crate::stack::Kind::Text
+   }
+}
+
+impl AsRef<[u8]> for ClipboardText {
+   /// The text as null terminated UTF-16, the way it's stored on the clipboard
+   fn as_ref(&self) -> &[u8] {
+      &self.bytes
+   }
+}
+
+impl From<&str> for ClipboardText {
+   fn from(text: &str) -> ClipboardText {
+      ClipboardText::from_units(text.encode_utf16())
+   }
+}
+
+/// Paths of files on (or headed for) the clipboard, as copied in Explorer. Two lists are the same if they hold the
+/// same paths in the same order.
+#[derive(Clone, PartialEq)]
+pub struct FileList {
+   /// The paths as UTF-16, each null terminated, with another null after the last, the way CF_HDROP holds them
+   bytes: Vec<u8>,
+}
+
+impl FileList {
+   /// Takes each path as UTF-16, without a null terminator. Returns None if there are no paths, or one is empty.
+   fn from_units(paths: impl Iterator<Item = Vec<u16>>) -> Option<FileList> {
+      let mut bytes = Vec::new();
+      for path in paths {
+         if path.is_empty() {
+            return None;
+         }
+         bytes.extend(path.into_iter().chain(iter::once(0)).flat_map(u16::to_le_bytes));
+      }
+      if bytes.is_empty() {
+         return None;
+      }
+      bytes.extend_from_slice(&[0, 0]);
+      Some(FileList { bytes })
+   }
+
+   /// Returns None if there are no paths, or one is empty
+   pub fn from_paths<'a>(paths: impl Iterator<Item = &'a str>) -> Option<FileList> {
+      FileList::from_units(paths.map(|x| x.encode_utf16().collect()))
+   }
+
+   /// Takes a file list in the form given by `as_ref`, returning None if it isn't one
+   pub fn from_bytes(bytes: Vec<u8>) -> Option<FileList> {
+      if bytes.len() % 2 == 1 || bytes.len() < 6 || !bytes.ends_with(&[0, 0, 0, 0]) {
+         return None;
+      }
+      let list = FileList { bytes };
+      // Two nulls in a row end the list, so an empty path would end it early
+      let units = list.units();
+      if units[0] == 0 || units[..units.len() - 1].windows(2).any(|x| x == [0, 0]) {
+         return None;
+      }
+      Some(list)
+   }
+
+   fn units(&self) -> Vec<u16> {
+      self
+         .bytes
+         .chunks_exact(2)
+         .map(|x| u16::from_le_bytes([x[0], x[1]]))
+         .collect()
+   }
+
+   /// The paths, one per line. Invalid UTF-16 is replaced with U+FFFD.
+   pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+      // The nulls after the last path are left off, and the ones between paths become line breaks
+      let units = self.units().into_iter().take(self.bytes.len() / 2 - 2);
+      char::decode_utf16(units).flat_map(|x| {
+         let (c, then) = match x {
+            Ok('\0') => ('\r', Some('\n')),
+            Ok(c) => (c, None),
+            Err(_) => (char::REPLACEMENT_CHARACTER, None),
          };
-         let mut buffer: Vec<u8> = Vec::with_capacity(str_len);
-         buffer.set_len(str_len);
+         iter::once(c).chain(then)
+      })
+   }
+
+   /// Each path, with invalid UTF-16 replaced with U+FFFD
+   pub fn paths(&self) -> Vec<String> {
+      self
+         .units()
+         .split(|x| *x == 0)
+         .filter(|x| !x.is_empty())
+         .map(String::from_utf16_lossy)
+         .collect()
+   }
+}
+
+impl AsRef<[u8]> for FileList {
+   /// The paths as they follow the DROPFILES header on the clipboard
+   fn as_ref(&self) -> &[u8] {
+      &self.bytes
+   }
+}
+
+/// An image on (or headed for) the clipboard, as a device-independent bitmap. A hash of the pixels is kept alongside
+/// it, as with `ClipboardText`, since images are large to compare.
+#[derive(Clone)]
+pub struct Image {
+   bytes: Vec<u8>,
+   hash: u64,
+   /// The width and height the image was copied at, if it's since been downscaled
+   original_size: Option<(u32, u32)>,
+   /// The image encoded as PNG, or None if it can't be, once it's first saved. Clones share it, so that saving the
+   /// stack again doesn't encode every image again.
+   png: std::sync::Arc<std::sync::OnceLock<Option<Vec<u8>>>>,
+}
+
+impl PartialEq for Image {
+   fn eq(&self, other: &Image) -> bool {
+      self.hash == other.hash && self.bytes == other.bytes
+   }
+}
+
+impl Image {
+   /// Takes an image in the form given by `as_ref`, returning None if it doesn't start with a bitmap header
+   pub fn from_bytes(bytes: Vec<u8>) -> Option<Image> {
+      crate::dib::dimensions(&bytes)?;
+      let mut hasher = DefaultHasher::new();
+      bytes.hash(&mut hasher);
+      Some(Image {
+         bytes,
+         hash: hasher.finish(),
+         original_size: None,
+         png: Default::default(),
+      })
+   }
+
+   /// Decodes an image saved as PNG, keeping the PNG so that it isn't encoded again when it's next saved. Returns None
+   /// if it's damaged.
+   pub fn from_png(png: Vec<u8>) -> Option<Image> {
+      let image = Image::from_bytes(crate::dib::from_png(&png)?)?;
+      let _ = image.png.set(Some(png));
+      Some(image)
+   }
+
+   /// What the image is saved as in the saved stack, snapshots and exports: PNG, or the bitmap itself if it can't be
+   /// encoded. Encoding a large image takes a while, so it's best done first off the main thread.
+   pub fn saved_bytes(&self) -> &[u8] {
+      match self.png.get_or_init(|| crate::dib::to_png(&self.bytes)) {
+         Some(png) => png,
+         None => &self.bytes,
+      }
+   }
+
+   /// The image shrunk to at most `max_pixels` pixels, as `dib::downscale` does, or None if it can't be
+   pub fn downscale(&self, max_pixels: usize) -> Option<Image> {
+      let mut image = Image::from_bytes(crate::dib::downscale(&self.bytes, max_pixels)?)?;
+      image.original_size = self.original_size.or_else(|| crate::dib::dimensions(&self.bytes));
+      Some(image)
+   }
+
+   /// A description of the image for previews, like "1920×1080 image", which says what size it was copied at if
+   /// it's been downscaled
+   pub fn describe(&self) -> String {
+      let description = crate::dib::describe(&self.bytes);
+      match self.original_size {
+         Some((width, height)) => format!("{} (downscaled from {}×{})", description, width, height),
+         None => description,
+      }
+   }
+}
+
+impl AsRef<[u8]> for Image {
+   /// The image as CF_DIB holds it
+   fn as_ref(&self) -> &[u8] {
+      &self.bytes
+   }
+}
+
+/// Something on the stack, in whichever form it was copied
+#[derive(Clone, PartialEq)]
+pub enum ClipboardEntry {
+   Text(ClipboardText),
+   Files(FileList),
+   Image(Image),
+}
+
+impl ClipboardEntry {
+   /// Takes an entry in the form given by `as_ref`, returning None if it isn't an entry of that kind
+   pub fn from_bytes(kind: crate::stack::Kind, bytes: Vec<u8>) -> Option<ClipboardEntry> {
+      match kind {
+         crate::stack::Kind::Text => ClipboardText::from_bytes(bytes).map(ClipboardEntry::Text),
+         crate::stack::Kind::Files => FileList::from_bytes(bytes).map(ClipboardEntry::Files),
+         crate::stack::Kind::Image => Image::from_bytes(bytes).map(ClipboardEntry::Image),
+      }
+   }
+
+   /// What the entry is saved as, which is the form given by `as_ref` for all but images, as `Image::saved_bytes`
+   /// says
+   pub fn saved_bytes(&self) -> &[u8] {
+      match self {
+         ClipboardEntry::Image(image) => image.saved_bytes(),
+         _ => self.as_ref(),
+      }
+   }
+
+   /// The entry's text, which for a file list is its paths, one per line. Images have none.
+   pub fn chars(&self) -> Box<dyn Iterator<Item = char> + '_> {
+      match self {
+         ClipboardEntry::Text(text) => Box::new(text.chars()),
+         ClipboardEntry::Files(files) => Box::new(files.chars()),
+         ClipboardEntry::Image(_) => Box::new(iter::empty()),
+      }
+   }
+
+   /// A single line summary of the entry, of at most `max_chars` characters and an ellipsis. File lists are
+   /// summarized by the name of the first file, and how many others there are, and images by their dimensions.
+   pub fn preview(&self, max_chars: usize) -> String {
+      match self {
+         ClipboardEntry::Text(text) => crate::stack::preview(text.chars(), max_chars),
+         ClipboardEntry::Files(files) => {
+            crate::stack::preview(crate::stack::files_summary(&files.paths()).chars(), max_chars)
+         }
+         ClipboardEntry::Image(image) => crate::stack::preview(image.describe().chars(), max_chars),
+      }
+   }
+}
+
+impl crate::stack::Size for ClipboardEntry {
+   fn size(&self) -> usize {
+      match self {
+         ClipboardEntry::Text(text) => text.size(),
+         ClipboardEntry::Files(files) => files.bytes.len(),
+         ClipboardEntry::Image(image) => image.bytes.len(),
+      }
+   }
+}
+
+impl crate::stack::Text for ClipboardEntry {
+   fn to_text(&self) -> String {
+      self.chars().collect()
+   }
+
+   fn kind(&self) -> crate::stack::Kind {
+      match self {
+         ClipboardEntry::Text(_) => crate::stack::Kind::Text,
+         ClipboardEntry::Files(_) => crate::stack::Kind::Files,
+         ClipboardEntry::Image(_) => crate::stack::Kind::Image,
+      }
+   }
+}
+
+impl AsRef<[u8]> for ClipboardEntry {
+   fn as_ref(&self) -> &[u8] {
+      match self {
+         ClipboardEntry::Text(text) => text.as_ref(),
+         ClipboardEntry::Files(files) => files.as_ref(),
+         ClipboardEntry::Image(image) => image.as_ref(),
+      }
+   }
+}
+
+impl From<&str> for ClipboardEntry {
+   fn from(text: &str) -> ClipboardEntry {
+      ClipboardEntry::Text(ClipboardText::from(text))
+   }
+}
+
+/// The header of CF_HDROP, which the list of paths follows
+#[repr(C)]
+struct DropFiles {
+   /// Where the paths start, from the start of the header
+   files_offset: u32,
+   point: winapi::shared::windef::POINT,
+   non_client: winapi::shared::minwindef::BOOL,
+   wide: winapi::shared::minwindef::BOOL,
+}
+
+/// CF_HDROP as it's placed on the clipboard: a DROPFILES header, followed by the paths as wide characters
+fn drop_files_bytes(files: &FileList) -> Vec<u8> {
+   let header = DropFiles {
+      files_offset: mem::size_of::<DropFiles>() as u32,
+      point: winapi::shared::windef::POINT { x: 0, y: 0 },
+      non_client: 0,
+      wide: 1,
+   };
+   let header_bytes =
+      unsafe { slice::from_raw_parts(&header as *const DropFiles as *const u8, mem::size_of::<DropFiles>()) };
+   header_bytes.iter().chain(files.bytes.iter()).copied().collect()
+}
+
+/// Copies `bytes` into memory allocated with GlobalAlloc, as SetClipboardData wants
+fn global_alloc_bytes(bytes: &[u8]) -> Result<winapi::shared::minwindef::HGLOBAL, ErrorCode> {
+   unsafe {
+      let handle = winapi::um::winbase::GlobalAlloc(winapi::um::winbase::GMEM_MOVEABLE, bytes.len());
+      if handle.is_null() {
+         return Err(ErrorCode(winapi::um::errhandlingapi::GetLastError()));
+      }
+      let memory = winapi::um::winbase::GlobalLock(handle) as *mut u8;
+      if memory.is_null() {
+         let code = winapi::um::errhandlingapi::GetLastError();
+         winapi::um::winbase::GlobalFree(handle);
+         return Err(ErrorCode(code));
+      }
+      ptr::copy_nonoverlapping(bytes.as_ptr(), memory, bytes.len());
+      winapi::um::winbase::GlobalUnlock(handle);
+      Ok(handle)
+   }
+}
+
+impl ClipboardHandle {
+   /// The files on the clipboard. A drop of no files is an error, as there's nothing to paste.
+   pub fn get_files(&self) -> Result<FileList, ErrorCode> {
+      let handle = unsafe { winapi::um::winuser::GetClipboardData(ClipboardFormat::Files as u32) };
+
+      if handle.is_null() {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         return Err(ErrorCode(code));
+      }
+
+      // DragQueryFileW reads the paths whether the list holds wide characters or not
+      let hdrop = handle as winapi::um::shellapi::HDROP;
+      let count = unsafe { winapi::um::shellapi::DragQueryFileW(hdrop, 0xFFFF_FFFF, ptr::null_mut(), 0) };
+      let paths = (0..count).map(|i| unsafe {
+         let len = winapi::um::shellapi::DragQueryFileW(hdrop, i, ptr::null_mut(), 0);
+         let mut path = vec![0; len as usize + 1];
+         let copied = winapi::um::shellapi::DragQueryFileW(hdrop, i, path.as_mut_ptr(), len + 1);
+         path.truncate(copied as usize);
+         path
+      });
+      FileList::from_units(paths).ok_or(ERROR_INVALID_DATA)
+   }
+
+   /// What's on the clipboard in `format`. Nothing is copied, and the memory is locked until `GlobalUnlock` is called
+   /// on the handle.
+   fn locked_bytes(&self, format: u32) -> Result<(winapi::shared::minwindef::HGLOBAL, &[u8]), ErrorCode> {
+      let handle = unsafe { winapi::um::winuser::GetClipboardData(format) };
+
+      if handle.is_null() {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         return Err(ErrorCode(code));
+      }
+
+      unsafe {
+         let memory = winapi::um::winbase::GlobalLock(handle) as *const u8;
+         if memory.is_null() {
+            let code = winapi::um::errhandlingapi::GetLastError();
+            return Err(ErrorCode(code));
+         }
+         let size = winapi::um::winbase::GlobalSize(handle);
+         Ok((handle, slice::from_raw_parts(memory, size)))
+      }
+   }
+
+   /// A hash of what's on the clipboard in the formats that can go on the stack, along with `extras`, for telling
+   /// whether it's changed without copying it. Each format is only sampled, as `watch::hash_sample` does, so that
+   /// this stays quick for a large copy. None if there's nothing in any of those formats.
+   pub fn contents_hash(&self, extras: &[crate::entry::Format]) -> Option<u64> {
+      let mut hasher = DefaultHasher::new();
+      let mut hashed = false;
+      let formats = [
+         ClipboardFormat::Files,
+         ClipboardFormat::UnicodeText,
+         ClipboardFormat::Dib,
+      ]
+      .iter()
+      .map(|x| *x as u32)
+      .chain(extras.iter().filter_map(|x| format_id(x).ok()));
+      for format in formats {
+         if unsafe { winapi::um::winuser::IsClipboardFormatAvailable(format) } == 0 {
+            continue;
+         }
+         if let Ok((handle, bytes)) = self.locked_bytes(format) {
+            format.hash(&mut hasher);
+            crate::watch::hash_sample(bytes, &mut hasher);
+            unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+            hashed = true;
+         }
+      }
+      if hashed {
+         Some(hasher.finish())
+      } else {
+         None
+      }
+   }
+
+   /// The size of the image on the clipboard in bytes, as a device-independent bitmap, along with its width and
+   /// height if they can be read. Nothing is copied.
+   pub fn image_size(&self) -> Result<(usize, Option<(u32, u32)>), ErrorCode> {
+      let (handle, bytes) = self.locked_bytes(ClipboardFormat::Dib as u32)?;
+      let size = bytes.len();
+      let dimensions = crate::dib::dimensions(bytes);
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      Ok((size, dimensions))
+   }
 
-         ptr::copy(handle as *const u8, buffer.as_mut_ptr(), str_len);
+   /// The image on the clipboard. Images copied only as a bitmap are converted by Windows.
+   pub fn get_image(&self) -> Result<Image, ErrorCode> {
+      let (handle, bytes) = self.locked_bytes(ClipboardFormat::Dib as u32)?;
+      let image = Image::from_bytes(bytes.to_vec());
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      image.ok_or(ERROR_INVALID_DATA)
+   }
 
-         Ok(ClipboardText(buffer))
+   /// A copy of what's on the clipboard in `format`, or None if there's nothing in that format
+   pub fn get_format(&self, format: &crate::entry::Format) -> Result<Option<Vec<u8>>, ErrorCode> {
+      if !is_format_available(format) {
+         return Ok(None);
       }
+      let (handle, bytes) = self.locked_bytes(format_id(format)?)?;
+      let data = bytes.to_vec();
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      Ok(Some(data))
+   }
+
+   /// The code page that the clipboard's ANSI text is in: that of the clipboard's locale, or the system's if the
+   /// copy didn't give one
+   fn ansi_code_page(&self) -> u32 {
+      let locale = match self.locked_bytes(ClipboardFormat::Locale as u32) {
+         Ok((handle, bytes)) => {
+            let locale = bytes.get(..4).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]));
+            unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+            locale
+         }
+         Err(_) => None,
+      };
+      locale
+         .and_then(|x| locale_code_page(x).ok())
+         .unwrap_or(winapi::um::winnls::CP_ACP)
+   }
+
+   /// The ANSI text (CF_TEXT) on the clipboard, converted to UTF-16 and without the null terminator. Apps that only
+   /// place ANSI text leave Windows to provide Unicode text from it, which it sometimes can't.
+   pub fn get_ansi_text_units(&self) -> Result<Vec<u16>, ErrorCode> {
+      let code_page = self.ansi_code_page();
+      let (handle, bytes) = self.locked_bytes(ClipboardFormat::Text as u32)?;
+      let len = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+      let units = multi_byte_to_wide(code_page, &bytes[..len]);
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      units
+   }
+
+   /// The text on the clipboard as UTF-16, as `stack::read_utf16` reads it, along with how many unpaired surrogates
+   /// were replaced. Only as much as `max_bytes` asks for is copied, along with a little more to tell whether there's
+   /// more.
+   pub fn get_text_units(&self, max_bytes: usize) -> Result<(Vec<u16>, usize), ErrorCode> {
+      let (handle, bytes) = self.locked_bytes(ClipboardFormat::UnicodeText as u32)?;
+      let len = bytes.len().min(max_bytes.saturating_add(4));
+      let text = crate::stack::read_utf16(&bytes[..len]);
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      Ok(text)
+   }
+
+   /// The size of the text on the clipboard in bytes, as UTF-16 and not counting the null terminator. Nothing is
+   /// copied.
+   pub fn text_size(&self) -> Result<usize, ErrorCode> {
+      let (handle, bytes) = self.locked_bytes(ClipboardFormat::UnicodeText as u32)?;
+      let len = bytes.chunks_exact(2).take_while(|x| *x != [0, 0]).count();
+      unsafe { winapi::um::winbase::GlobalUnlock(handle) };
+      Ok(len * 2)
    }
 
    // Set clipboard content
@@ -715,6 +2463,10 @@ impl ClipboardHandle {
          return Err(ErrorCode(code));
       }
 
+      // Everything ripclip writes is marked as its own. Failing to mark it leaves the sequence number to tell. Only
+      // the marker being there matters, but the clipboard doesn't take empty data, so it holds a single byte.
+      let _ = format_id(&crate::entry::Format::marker()).and_then(|x| set_clipboard_data(x, &[1]));
+
       mem::forget(self);
 
       Ok(OwnedClipboardHandle { _inner: () })
@@ -743,7 +2495,7 @@ impl OwnedClipboardHandle {
       let result = unsafe {
          winapi::um::winuser::SetClipboardData(
             ClipboardFormat::UnicodeText as u32,
-            clipboard_text.0.as_mut_ptr() as *mut winapi::ctypes::c_void,
+            clipboard_text.bytes.as_mut_ptr() as *mut winapi::ctypes::c_void,
          )
       };
 
@@ -757,6 +2509,125 @@ impl OwnedClipboardHandle {
 
       Ok(())
    }
+
+   /// Places the files on the clipboard, so that pasting in Explorer copies them
+   pub fn set_files(&self, files: &FileList) -> Result<(), ErrorCode> {
+      set_clipboard_data(ClipboardFormat::Files as u32, &drop_files_bytes(files))
+   }
+
+   /// Places the image on the clipboard. Windows provides it as a bitmap too, for programs that only paste those.
+   pub fn set_image(&self, image: &Image) -> Result<(), ErrorCode> {
+      set_clipboard_data(ClipboardFormat::Dib as u32, &image.bytes)
+   }
+
+   /// Places the entry on the clipboard in every format it has, so that each program can paste the one it likes best.
+   /// If `plain_text_only` is set, only its text, files or image are placed, and none of its other formats.
+   pub fn set_entry(&self, entry: &crate::entry::Entry, plain_text_only: bool) -> Result<(), ErrorCode> {
+      let extras = if plain_text_only { &[][..] } else { entry.extras() };
+      for extra in extras {
+         set_clipboard_data(format_id(&extra.format)?, &extra.data_to_set())?;
+      }
+      match entry.contents() {
+         ClipboardEntry::Text(text) => self.set_text(text.clone()),
+         ClipboardEntry::Files(files) => self.set_files(files),
+         ClipboardEntry::Image(image) => self.set_image(image),
+      }
+   }
+
+   /// Promises the entry to the clipboard in every format `set_entry` would place it in, without placing any of its
+   /// data. Windows asks for a format with WM_RENDERFORMAT the first time it's pasted, which `render_format` answers.
+   /// The `RipclipMarker` format isn't promised: `empty` has already set it, data and all, so an update caused by a
+   /// promise is told apart from other copies by the marker and not just the sequence number.
+   pub fn promise_entry(&self, entry: &crate::entry::Entry, plain_text_only: bool) -> Result<(), ErrorCode> {
+      for format in entry_formats(entry, plain_text_only)? {
+         // A promise gives back a null handle even when it succeeds, so only the last error tells
+         unsafe { winapi::um::errhandlingapi::SetLastError(0) };
+         unsafe { winapi::um::winuser::SetClipboardData(format, ptr::null_mut()) };
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         if code != 0 {
+            return Err(ErrorCode(code));
+         }
+      }
+      Ok(())
+   }
+}
+
+/// The formats `set_entry` places the entry in, in the order it places them. The marker that `empty` sets isn't one.
+fn entry_formats(entry: &crate::entry::Entry, plain_text_only: bool) -> Result<Vec<u32>, ErrorCode> {
+   let extras = if plain_text_only { &[][..] } else { entry.extras() };
+   let mut formats = extras
+      .iter()
+      .map(|x| format_id(&x.format))
+      .collect::<Result<Vec<u32>, ErrorCode>>()?;
+   formats.push(match entry.contents() {
+      ClipboardEntry::Text(_) => ClipboardFormat::UnicodeText as u32,
+      ClipboardEntry::Files(_) => ClipboardFormat::Files as u32,
+      ClipboardEntry::Image(_) => ClipboardFormat::Dib as u32,
+   });
+   Ok(formats)
+}
+
+/// Places the entry's data in `format` on the clipboard, answering WM_RENDERFORMAT for a format promised by
+/// `promise_entry`. Windows has already opened the clipboard when it asks.
+pub fn render_format(format: u32, entry: &crate::entry::Entry, plain_text_only: bool) -> Result<(), ErrorCode> {
+   let extras = if plain_text_only { &[][..] } else { entry.extras() };
+   for extra in extras {
+      if format_id(&extra.format)? == format {
+         return set_clipboard_data(format, &extra.data_to_set());
+      }
+   }
+   match entry.contents() {
+      ClipboardEntry::Text(text) if format == ClipboardFormat::UnicodeText as u32 => {
+         set_clipboard_data(format, text.as_ref())
+      }
+      ClipboardEntry::Files(files) if format == ClipboardFormat::Files as u32 => {
+         set_clipboard_data(format, &drop_files_bytes(files))
+      }
+      ClipboardEntry::Image(image) if format == ClipboardFormat::Dib as u32 => set_clipboard_data(format, &image.bytes),
+      _ => Err(ERROR_NOT_FOUND),
+   }
+}
+
+/// Places every format promised by `promise_entry`, answering WM_RENDERALLFORMATS. Windows sends that when `window`
+/// is destroyed while the clipboard still holds its promises, such as when ripclip exits, and the data has to be
+/// placed then for it to be pasted afterwards.
+pub fn render_all_formats(
+   window: winapi::shared::windef::HWND,
+   entry: &crate::entry::Entry,
+   plain_text_only: bool,
+) -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::winuser::OpenClipboard(window) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   // Closes the clipboard when dropped
+   let _clipboard = ClipboardHandle { _inner: () };
+   // Another program may have emptied the clipboard, and with it the promises, before it was opened
+   if unsafe { winapi::um::winuser::GetClipboardOwner() } != window {
+      return Ok(());
+   }
+   for format in entry_formats(entry, plain_text_only)? {
+      render_format(format, entry, plain_text_only)?;
+   }
+   Ok(())
+}
+
+/// Places a copy of `bytes` on the clipboard in `format`, which must already be open and emptied
+fn set_clipboard_data(format: u32, bytes: &[u8]) -> Result<(), ErrorCode> {
+   let handle = global_alloc_bytes(bytes)?;
+   let result = unsafe { winapi::um::winuser::SetClipboardData(format, handle) };
+
+   if result.is_null() {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      // The clipboard only takes ownership of the memory when it succeeds
+      unsafe { winapi::um::winbase::GlobalFree(handle) };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
 }
 
 impl Drop for OwnedClipboardHandle {
@@ -765,8 +2636,40 @@ impl Drop for OwnedClipboardHandle {
    }
 }
 
+/// Opens the clipboard without a window, which is enough to read it and can be done from any thread. Emptying it
+/// would leave it without an owner, which keeps anything from being placed on it.
+pub fn open_clipboard_unowned() -> Result<ClipboardHandle, ErrorCode> {
+   let result = unsafe { winapi::um::winuser::OpenClipboard(ptr::null_mut()) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(ClipboardHandle { _inner: () })
+}
+
 pub fn open_clipboard(hwnd: &WindowHandle) -> Result<ClipboardHandle, ErrorCode> {
-   let result = unsafe { winapi::um::winuser::OpenClipboard(hwnd.inner.as_ptr()) };
+   open_clipboard_for(clipboard_owner(hwnd))
+}
+
+/// A window that the clipboard can be opened for from another thread, so that emptying it there leaves the window
+/// as its owner. Whatever the clipboard sends the owner still goes to the thread that made the window.
+#[derive(Clone, Copy)]
+pub struct ClipboardOwner {
+   inner: NonNull<winapi::shared::windef::HWND__>,
+}
+
+// Only ever passed to OpenClipboard and compared with GetClipboardOwner, which take windows from any thread
+unsafe impl Send for ClipboardOwner {}
+
+pub fn clipboard_owner(hwnd: &WindowHandle) -> ClipboardOwner {
+   ClipboardOwner { inner: hwnd.inner }
+}
+
+/// Opens the clipboard for `owner`, which can be done from any thread
+pub fn open_clipboard_for(owner: ClipboardOwner) -> Result<ClipboardHandle, ErrorCode> {
+   let result = unsafe { winapi::um::winuser::OpenClipboard(owner.inner.as_ptr()) };
 
    if result == 0 {
       let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
@@ -776,11 +2679,116 @@ pub fn open_clipboard(hwnd: &WindowHandle) -> Result<ClipboardHandle, ErrorCode>
    Ok(ClipboardHandle { _inner: () })
 }
 
+/// Whether `owner` last placed something on the clipboard
+pub fn owns_clipboard(owner: ClipboardOwner) -> bool {
+   unsafe { winapi::um::winuser::GetClipboardOwner() == owner.inner.as_ptr() }
+}
+
+pub fn set_timer(hwnd: &WindowHandle, id: usize, elapse_ms: u32) -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::winuser::SetTimer(hwnd.inner.as_ptr(), id, elapse_ms, None) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+pub fn kill_timer(hwnd: &WindowHandle, id: usize) -> Result<(), ErrorCode> {
+   let result = unsafe { winapi::um::winuser::KillTimer(hwnd.inner.as_ptr(), id) };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(())
+}
+
+fn data_blob(data: &[u8]) -> Result<winapi::um::wincrypt::DATA_BLOB, ErrorCode> {
+   Ok(winapi::um::wincrypt::DATA_BLOB {
+      cbData: u32::try_from(data.len()).map_err(|_| ERROR_INVALID_PARAMETER)?,
+      pbData: data.as_ptr() as *mut u8,
+   })
+}
+
+/// Copies out a blob that DPAPI allocated, then frees it. An empty blob may not have been allocated at all.
+unsafe fn take_blob(blob: winapi::um::wincrypt::DATA_BLOB) -> Vec<u8> {
+   if blob.pbData.is_null() {
+      return Vec::new();
+   }
+   let data = slice::from_raw_parts(blob.pbData, blob.cbData as usize).to_vec();
+   winapi::um::winbase::LocalFree(blob.pbData as winapi::shared::minwindef::HLOCAL);
+   data
+}
+
+/// Encrypts `data` with DPAPI, so that only the current user can decrypt it with `unprotect_data`
+pub fn protect_data(data: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+   let mut input = data_blob(data)?;
+   let mut output = winapi::um::wincrypt::DATA_BLOB {
+      cbData: 0,
+      pbData: ptr::null_mut(),
+   };
+   let result = unsafe {
+      winapi::um::dpapi::CryptProtectData(
+         &mut input,
+         ptr::null(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+         winapi::um::dpapi::CRYPTPROTECT_UI_FORBIDDEN,
+         &mut output,
+      )
+   };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(unsafe { take_blob(output) })
+}
+
+/// Decrypts data encrypted by `protect_data`. This fails for data encrypted by another user, or damaged data.
+pub fn unprotect_data(data: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+   let mut input = data_blob(data)?;
+   let mut output = winapi::um::wincrypt::DATA_BLOB {
+      cbData: 0,
+      pbData: ptr::null_mut(),
+   };
+   let result = unsafe {
+      winapi::um::dpapi::CryptUnprotectData(
+         &mut input,
+         ptr::null_mut(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+         ptr::null_mut(),
+         winapi::um::dpapi::CRYPTPROTECT_UI_FORBIDDEN,
+         &mut output,
+      )
+   };
+
+   if result == 0 {
+      let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+      return Err(ErrorCode(code));
+   }
+
+   Ok(unsafe { take_blob(output) })
+}
+
 pub struct Message {
    pub hwnd: Option<NonNull<winapi::shared::windef::HWND__>>,
    pub message: u32,
    pub w_param: usize,
    pub l_param: isize,
+   inner: winapi::um::winuser::MSG,
+}
+
+impl Message {
+   pub fn is_for(&self, hwnd: &WindowHandle) -> bool {
+      self.hwnd == Some(hwnd.inner)
+   }
 }
 
 impl From<winapi::um::winuser::MSG> for Message {
@@ -795,10 +2803,34 @@ impl From<winapi::um::winuser::MSG> for Message {
          message: msg.message,
          w_param: msg.wParam,
          l_param: msg.lParam,
+         inner: msg,
       }
    }
 }
 
+/// Sends a message on to the window procedure of the window it's for
+pub fn dispatch_message(message: &Message) {
+   unsafe {
+      winapi::um::winuser::TranslateMessage(&message.inner);
+      winapi::um::winuser::DispatchMessageW(&message.inner);
+   }
+}
+
+/// Makes sure this thread has a message queue, so messages can be posted to it before it first waits in
+/// `get_message`
+pub fn create_message_queue() {
+   let mut message: MaybeUninit<winapi::um::winuser::MSG> = MaybeUninit::uninit();
+   unsafe {
+      winapi::um::winuser::PeekMessageW(
+         message.as_mut_ptr(),
+         ptr::null_mut(),
+         winapi::um::winuser::WM_USER,
+         winapi::um::winuser::WM_USER,
+         winapi::um::winuser::PM_NOREMOVE,
+      )
+   };
+}
+
 pub fn get_message(hwnd: Option<&WindowHandle>, min_value: u32, max_value: u32) -> Result<Message, ErrorCode> {
    let mut message: MaybeUninit<winapi::um::winuser::MSG> = MaybeUninit::uninit();
    let result = unsafe {
@@ -821,6 +2853,8 @@ pub fn get_message(hwnd: Option<&WindowHandle>, min_value: u32, max_value: u32)
 pub struct TrayIcon<'a> {
    id: u32,
    hwnd: &'a WindowHandle<'a>,
+   /// The program's own icon, which is shared and so never destroyed
+   icon: NonNull<winapi::shared::windef::HICON__>,
 }
 
 impl<'a> Drop for TrayIcon<'a> {
@@ -829,6 +2863,133 @@ impl<'a> Drop for TrayIcon<'a> {
    }
 }
 
+impl<'a> TrayIcon<'a> {
+   pub fn set_tooltip(&self, tooltip: &str) -> Result<(), ErrorCode> {
+      let mut tooltip_text: [u16; 128] = [0; 128];
+      fill_utf16(&mut tooltip_text, tooltip);
+
+      let mut tooltip_options = winapi::um::shellapi::NOTIFYICONDATAW {
+         cbSize: mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32,
+         hWnd: self.hwnd.inner.as_ptr(),
+         uID: self.id,
+         uFlags: winapi::um::shellapi::NIF_TIP | winapi::um::shellapi::NIF_SHOWTIP,
+         uCallbackMessage: 0,
+         hIcon: ptr::null_mut(),
+         szTip: tooltip_text,
+         dwState: 0,
+         dwStateMask: 0,
+         szInfo: [0; 256],
+         u: unsafe { mem::zeroed() },
+         szInfoTitle: [0; 64],
+         dwInfoFlags: 0,
+         guidItem: winapi::shared::guiddef::GUID {
+            Data1: 0,
+            Data2: 0,
+            Data3: 0,
+            Data4: [0; 8],
+         },
+         hBalloonIcon: ptr::null_mut(),
+      };
+      let result =
+         unsafe { winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut tooltip_options) };
+
+      if result == 0 {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         return Err(ErrorCode(code));
+      }
+
+      Ok(())
+   }
+
+   /// Shows `icon` in place of the program's own, or the program's own again if None. The icon has to outlive its
+   /// being shown.
+   pub fn set_icon(&self, icon: Option<&Icon>) -> Result<(), ErrorCode> {
+      let mut icon_options = winapi::um::shellapi::NOTIFYICONDATAW {
+         cbSize: mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32,
+         hWnd: self.hwnd.inner.as_ptr(),
+         uID: self.id,
+         uFlags: winapi::um::shellapi::NIF_ICON,
+         uCallbackMessage: 0,
+         hIcon: icon.map_or(self.icon, |x| x.inner).as_ptr(),
+         szTip: [0; 128],
+         dwState: 0,
+         dwStateMask: 0,
+         szInfo: [0; 256],
+         u: unsafe { mem::zeroed() },
+         szInfoTitle: [0; 64],
+         dwInfoFlags: 0,
+         guidItem: winapi::shared::guiddef::GUID {
+            Data1: 0,
+            Data2: 0,
+            Data3: 0,
+            Data4: [0; 8],
+         },
+         hBalloonIcon: ptr::null_mut(),
+      };
+      let result =
+         unsafe { winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut icon_options) };
+
+      if result == 0 {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         return Err(ErrorCode(code));
+      }
+
+      Ok(())
+   }
+
+   /// Shows a notification balloon next to the tray icon. Text that doesn't fit is cut off.
+   pub fn show_balloon(&self, title: &str, text: &str) -> Result<(), ErrorCode> {
+      let mut info_title: [u16; 64] = [0; 64];
+      fill_utf16(&mut info_title, title);
+      let mut info: [u16; 256] = [0; 256];
+      fill_utf16(&mut info, text);
+
+      let mut balloon_options = winapi::um::shellapi::NOTIFYICONDATAW {
+         cbSize: mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32,
+         hWnd: self.hwnd.inner.as_ptr(),
+         uID: self.id,
+         uFlags: winapi::um::shellapi::NIF_INFO,
+         uCallbackMessage: 0,
+         hIcon: ptr::null_mut(),
+         szTip: [0; 128],
+         dwState: 0,
+         dwStateMask: 0,
+         szInfo: info,
+         u: unsafe { mem::zeroed() },
+         szInfoTitle: info_title,
+         dwInfoFlags: winapi::um::shellapi::NIIF_INFO,
+         guidItem: winapi::shared::guiddef::GUID {
+            Data1: 0,
+            Data2: 0,
+            Data3: 0,
+            Data4: [0; 8],
+         },
+         hBalloonIcon: ptr::null_mut(),
+      };
+      let result =
+         unsafe { winapi::um::shellapi::Shell_NotifyIconW(winapi::um::shellapi::NIM_MODIFY, &mut balloon_options) };
+
+      if result == 0 {
+         let code = unsafe { winapi::um::errhandlingapi::GetLastError() };
+         return Err(ErrorCode(code));
+      }
+
+      Ok(())
+   }
+}
+
+/// Copies as much of `text` as fits into `dest`, leaving room for the null terminator
+/// and never splitting a surrogate pair
+fn fill_utf16(dest: &mut [u16], text: &str) {
+   let mut len = 0;
+   for c in text.chars() {
+      if len + c.len_utf16() >= dest.len() {
+         break;
+      }
+      len += c.encode_utf16(&mut dest[len..]).len();
+   }
+}
+
 fn remove_tray_icon<'a>(tray_icon: &mut TrayIcon<'a>) -> Result<(), ErrorCode> {
    let mut remove_tray_icon_options = winapi::um::shellapi::NOTIFYICONDATAW {
       cbSize: mem::size_of::<winapi::um::shellapi::NOTIFYICONDATAW>() as u32,
@@ -932,5 +3093,306 @@ pub fn add_tray_icon<'a>(
       return Err(ErrorCode(code));
    }
 
-   Ok(TrayIcon { id, hwnd })
+   Ok(TrayIcon {
+      id,
+      hwnd,
+      icon: NonNull::new(icon).unwrap(),
+   })
+}
+
+/// An icon made while running, which is destroyed when dropped
+pub struct Icon {
+   inner: NonNull<winapi::shared::windef::HICON__>,
+}
+
+impl Drop for Icon {
+   fn drop(&mut self) {
+      unsafe { winapi::um::winuser::DestroyIcon(self.inner.as_ptr()) };
+   }
+}
+
+/// A GDI object, deleted when dropped
+struct GdiObject(winapi::shared::windef::HGDIOBJ);
+
+impl Drop for GdiObject {
+   fn drop(&mut self) {
+      unsafe { winapi::um::wingdi::DeleteObject(self.0) };
+   }
+}
+
+/// A device context for drawing into a bitmap, deleted when dropped
+struct MemoryDc(winapi::shared::windef::HDC);
+
+impl Drop for MemoryDc {
+   fn drop(&mut self) {
+      unsafe { winapi::um::wingdi::DeleteDC(self.0) };
+   }
+}
+
+fn last_error() -> ErrorCode {
+   ErrorCode(unsafe { winapi::um::errhandlingapi::GetLastError() })
+}
+
+/// Has `DrawIconEx` draw the icon with its transparency, which winapi's winuser module is missing
+const DI_NORMAL: u32 = 0x0003;
+
+/// Makes the program's icon, at the size the notification area shows icons, with `badge` drawn over its bottom right
+/// corner in white on red, such as a count
+pub fn badged_icon(module_handle: &ModuleHandle, badge: &str) -> Result<Icon, ErrorCode> {
+   let width = unsafe { winapi::um::winuser::GetSystemMetrics(winapi::um::winuser::SM_CXSMICON) };
+   let height = unsafe { winapi::um::winuser::GetSystemMetrics(winapi::um::winuser::SM_CYSMICON) };
+
+   let base = unsafe {
+      winapi::um::winuser::LoadImageW(
+         module_handle.0.as_ptr(),
+         winapi::um::winuser::MAKEINTRESOURCEW(1),
+         winapi::um::winuser::IMAGE_ICON,
+         width,
+         height,
+         winapi::um::winuser::LR_DEFAULTCOLOR,
+      )
+   } as winapi::shared::windef::HICON;
+   let base = NonNull::new(base).map(|inner| Icon { inner }).ok_or_else(last_error)?;
+
+   let dc = MemoryDc(unsafe { winapi::um::wingdi::CreateCompatibleDC(ptr::null_mut()) });
+   if dc.0.is_null() {
+      return Err(last_error());
+   }
+
+   // A 32 bit bitmap keeps the icon's transparency, drawn top row first so that rows can be found in `pixels`
+   let mut info: winapi::um::wingdi::BITMAPINFO = unsafe { mem::zeroed() };
+   info.bmiHeader.biSize = mem::size_of::<winapi::um::wingdi::BITMAPINFOHEADER>() as u32;
+   info.bmiHeader.biWidth = width;
+   info.bmiHeader.biHeight = -height;
+   info.bmiHeader.biPlanes = 1;
+   info.bmiHeader.biBitCount = 32;
+   info.bmiHeader.biCompression = winapi::um::wingdi::BI_RGB;
+   let mut bits: *mut winapi::ctypes::c_void = ptr::null_mut();
+   let color = unsafe {
+      winapi::um::wingdi::CreateDIBSection(
+         dc.0,
+         &info,
+         winapi::um::wingdi::DIB_RGB_COLORS,
+         &mut bits,
+         ptr::null_mut(),
+         0,
+      )
+   };
+   if color.is_null() || bits.is_null() {
+      return Err(last_error());
+   }
+   let color = GdiObject(color as winapi::shared::windef::HGDIOBJ);
+   // Every pixel is given by the color bitmap's alpha, so the mask is left empty
+   let mask_bits = vec![0u8; (width as usize).div_ceil(16) * 2 * height as usize];
+   let mask = unsafe { winapi::um::wingdi::CreateBitmap(width, height, 1, 1, mask_bits.as_ptr() as *const _) };
+   if mask.is_null() {
+      return Err(last_error());
+   }
+   let mask = GdiObject(mask as winapi::shared::windef::HGDIOBJ);
+
+   let badge_height = height * 5 / 8;
+   let font_name = to_win_utf16("Segoe UI");
+   let font = unsafe {
+      winapi::um::wingdi::CreateFontW(
+         -badge_height,
+         0,
+         0,
+         0,
+         winapi::um::wingdi::FW_BOLD,
+         0,
+         0,
+         0,
+         winapi::um::wingdi::DEFAULT_CHARSET,
+         winapi::um::wingdi::OUT_DEFAULT_PRECIS,
+         winapi::um::wingdi::CLIP_DEFAULT_PRECIS,
+         winapi::um::wingdi::ANTIALIASED_QUALITY,
+         winapi::um::wingdi::DEFAULT_PITCH,
+         font_name.as_ptr(),
+      )
+   };
+   if font.is_null() {
+      return Err(last_error());
+   }
+   let font = GdiObject(font as winapi::shared::windef::HGDIOBJ);
+   let brush = unsafe { winapi::um::wingdi::CreateSolidBrush(winapi::um::wingdi::RGB(0xD0, 0x20, 0x20)) };
+   if brush.is_null() {
+      return Err(last_error());
+   }
+   let brush = GdiObject(brush as winapi::shared::windef::HGDIOBJ);
+
+   let old_bitmap = unsafe { winapi::um::wingdi::SelectObject(dc.0, color.0) };
+   let old_font = unsafe { winapi::um::wingdi::SelectObject(dc.0, font.0) };
+   let text: Vec<u16> = badge.encode_utf16().collect();
+   let badge_rect = unsafe {
+      winapi::um::winuser::DrawIconEx(
+         dc.0,
+         0,
+         0,
+         base.inner.as_ptr(),
+         width,
+         height,
+         0,
+         ptr::null_mut(),
+         DI_NORMAL,
+      );
+      // Measures the text, to make the badge wide enough for it
+      let mut measured = winapi::shared::windef::RECT {
+         left: 0,
+         top: 0,
+         right: 0,
+         bottom: 0,
+      };
+      winapi::um::winuser::DrawTextW(
+         dc.0,
+         text.as_ptr(),
+         text.len() as i32,
+         &mut measured,
+         winapi::um::winuser::DT_CALCRECT | winapi::um::winuser::DT_SINGLELINE,
+      );
+      let badge_width = (measured.right + 2).clamp(badge_height, width);
+      let mut badge_rect = winapi::shared::windef::RECT {
+         left: width - badge_width,
+         top: height - badge_height,
+         right: width,
+         bottom: height,
+      };
+      winapi::um::winuser::FillRect(dc.0, &badge_rect, brush.0 as winapi::shared::windef::HBRUSH);
+      winapi::um::wingdi::SetBkMode(dc.0, winapi::um::wingdi::TRANSPARENT as i32);
+      winapi::um::wingdi::SetTextColor(dc.0, winapi::um::wingdi::RGB(0xFF, 0xFF, 0xFF));
+      winapi::um::winuser::DrawTextW(
+         dc.0,
+         text.as_ptr(),
+         text.len() as i32,
+         &mut badge_rect,
+         winapi::um::winuser::DT_CENTER | winapi::um::winuser::DT_VCENTER | winapi::um::winuser::DT_SINGLELINE,
+      );
+      winapi::um::wingdi::SelectObject(dc.0, old_font);
+      winapi::um::wingdi::SelectObject(dc.0, old_bitmap);
+      winapi::um::wingdi::GdiFlush();
+      badge_rect
+   };
+
+   // GDI leaves what it draws transparent, so the badge is made opaque by hand
+   let pixels = unsafe { slice::from_raw_parts_mut(bits as *mut u32, (width * height) as usize) };
+   for y in badge_rect.top..badge_rect.bottom {
+      for x in badge_rect.left..badge_rect.right {
+         pixels[(y * width + x) as usize] |= 0xFF00_0000;
+      }
+   }
+
+   let mut icon_info = winapi::um::winuser::ICONINFO {
+      fIcon: 1,
+      xHotspot: 0,
+      yHotspot: 0,
+      hbmMask: mask.0 as winapi::shared::windef::HBITMAP,
+      hbmColor: color.0 as winapi::shared::windef::HBITMAP,
+   };
+   // The bitmaps are copied into the icon, so they're deleted once it's made
+   let icon = unsafe { winapi::um::winuser::CreateIconIndirect(&mut icon_info) };
+   NonNull::new(icon).map(|inner| Icon { inner }).ok_or_else(last_error)
+}
+
+#[cfg(all(test, windows))]
+mod test {
+   use super::*;
+
+   #[test]
+   fn protected_data_round_trips() {
+      for data in [&b""[..], b"hunter2", &[0; 4096][..]] {
+         let protected = protect_data(data).unwrap();
+         assert_ne!(protected, data);
+         assert_eq!(unprotect_data(&protected).unwrap(), data);
+      }
+   }
+
+   #[test]
+   fn converts_ansi_text() {
+      // "Привет" in Windows-1251
+      let units = multi_byte_to_wide(1251, &[0xCF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2]).unwrap();
+      assert_eq!(String::from_utf16(&units).unwrap(), "Привет");
+      assert_eq!(
+         multi_byte_to_wide(1252, b"caf\xe9").unwrap(),
+         "café".encode_utf16().collect::<Vec<_>>()
+      );
+      assert_eq!(multi_byte_to_wide(1251, b""), Ok(Vec::new()));
+      // No such code page
+      assert!(multi_byte_to_wide(12345, b"abc").is_err());
+      // Russian
+      assert_eq!(locale_code_page(0x0419), Ok(1251));
+   }
+
+   #[test]
+   fn file_list_round_trips() {
+      let files = FileList::from_paths(["C:\\a.txt", "D:\\photos"].iter().copied()).unwrap();
+      assert_eq!(files.paths(), vec!["C:\\a.txt", "D:\\photos"]);
+      assert_eq!(files.chars().collect::<String>(), "C:\\a.txt\r\nD:\\photos");
+      assert!(FileList::from_bytes(files.as_ref().to_vec()) == Some(files.clone()));
+      // The paths follow the header, which says where they start and that they're wide characters
+      let hdrop = drop_files_bytes(&files);
+      assert_eq!(hdrop[..4], 20u32.to_le_bytes());
+      assert_eq!(hdrop[16..20], 1u32.to_le_bytes());
+      assert_eq!(&hdrop[20..], files.as_ref());
+
+      assert!(FileList::from_paths(iter::empty()).is_none());
+      assert!(FileList::from_paths(["C:\\a.txt", ""].iter().copied()).is_none());
+      // Text isn't a file list, nor is a list with an empty path in it
+      assert!(FileList::from_bytes(ClipboardText::from("C:\\a.txt").as_ref().to_vec()).is_none());
+      assert!(FileList::from_bytes(vec![b'a', 0, 0, 0, 0, 0, b'b', 0, 0, 0, 0, 0]).is_none());
+   }
+
+   #[test]
+   fn image_entries_describe_their_size() {
+      let mut dib = vec![40, 0, 0, 0];
+      dib.extend_from_slice(&1920i32.to_le_bytes());
+      dib.extend_from_slice(&1080i32.to_le_bytes());
+      dib.resize(44, 0xFF);
+      let image = ClipboardEntry::from_bytes(crate::stack::Kind::Image, dib.clone()).unwrap();
+      assert_eq!(image.preview(50), "1920×1080 image");
+      assert_eq!(image.chars().count(), 0);
+      assert_eq!(crate::stack::Size::size(&image), 44);
+      assert!(image.as_ref() == &dib[..]);
+      // Images that differ in a single pixel are different entries
+      dib[43] = 0;
+      assert!(ClipboardEntry::from_bytes(crate::stack::Kind::Image, dib).unwrap() != image);
+      assert!(Image::from_bytes(ClipboardText::from("not an image").as_ref().to_vec()).is_none());
+   }
+
+   #[test]
+   fn keystroke_releases_held_modifiers_around_key() {
+      use winapi::um::winuser::{VK_LCONTROL, VK_LSHIFT, VK_RMENU};
+      let (control, shift, alt, v) = (VK_LCONTROL as u16, VK_LSHIFT as u16, VK_RMENU as u16, b'V' as u16);
+      let down = |key| KeyInput { key, up: false };
+      let up = |key| KeyInput { key, up: true };
+      // Control + Shift held from the hotkey; Control stays down, while Shift is let go for the paste
+      assert_eq!(
+         keystroke_inputs(Modifiers::CONTROL, u16::from(b'V'), &[control, shift]),
+         vec![up(shift), down(v), up(v), down(shift)]
+      );
+      assert_eq!(
+         keystroke_inputs(Modifiers::CONTROL, u16::from(b'V'), &[]),
+         vec![down(control), down(v), up(v), up(control)]
+      );
+      assert_eq!(
+         keystroke_inputs(Modifiers::CONTROL, u16::from(b'V'), &[alt]),
+         vec![
+            down(control),
+            up(alt),
+            down(v),
+            up(v),
+            down(alt),
+            up(control),
+            down(UNASSIGNED_KEY),
+            up(UNASSIGNED_KEY)
+         ]
+      );
+   }
+
+   #[test]
+   fn unprotect_rejects_damaged_data() {
+      let mut protected = protect_data(b"hunter2").unwrap();
+      let last = protected.len() - 1;
+      protected[last] ^= 0xFF;
+      assert!(unprotect_data(&protected).is_err());
+      assert!(unprotect_data(b"not protected").is_err());
+   }
 }